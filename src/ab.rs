@@ -0,0 +1,296 @@
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    sync::{LazyLock, Mutex},
+    time::Duration,
+};
+
+use serde::Serialize;
+
+use crate::{
+    probe::{CommonArgs, Probe, Runner},
+    report,
+};
+
+/// Fewest pairs a difference is tested for significance with, below which the normal
+/// approximation the tests rely on does not hold.
+const MIN_PAIRS: u64 = 30;
+
+/// Fewest pairs where only one target failed that error rates are tested for significance with.
+const MIN_DISCORDANT: u64 = 10;
+
+/// Critical value of a two-sided test at the 95% confidence level.
+const Z_95: f64 = 1.96;
+
+/// Pairs recorded for each comparison, keyed by probe kind, name and the two targets.
+static PAIRS: LazyLock<Mutex<BTreeMap<Key, Pairs>>> = LazyLock::new(Default::default);
+
+type Key = (&'static str, Option<String>, String, String);
+
+/// Outcomes of a worker not paired yet, with the latency of those that succeeded.
+type Outcomes = VecDeque<Option<Duration>>;
+
+#[derive(Default)]
+struct Pairs {
+    pairs: u64,
+    a_failures: u64,
+    b_failures: u64,
+    /// Pairs where only one of the targets failed, which are what tell their error rates apart.
+    only_a_failed: u64,
+    only_b_failed: u64,
+    a_latencies: Vec<Duration>,
+    b_latencies: Vec<Duration>,
+    /// Latency of `b` minus that of `a`, in milliseconds, for pairs where both succeeded.
+    differences: Vec<f64>,
+}
+
+/// Paired differences between the attempts made against two targets.
+#[derive(Serialize)]
+pub struct Comparison {
+    pub probe: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    pub a: String,
+    pub b: String,
+    /// Number of attempts made against both targets by the same worker on the same tick.
+    pub pairs: u64,
+    pub a_error_rate: f64,
+    pub b_error_rate: f64,
+    /// Whether the error rates differ at the 95% confidence level, by McNemar's test, or `None`
+    /// with too few pairs where only one target failed to tell.
+    pub error_rate_significant: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub a_mean_ms: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub b_mean_ms: Option<f64>,
+    /// Latency of `b` minus that of `a` over the pairs where both succeeded.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub difference_ms: Option<Difference>,
+}
+
+#[derive(Serialize)]
+pub struct Difference {
+    pub mean: f64,
+    pub p50: f64,
+    pub p90: f64,
+    /// Mean difference over its standard error, for a paired t-test, and infinite when every
+    /// pair differs by the same nonzero amount.
+    pub t: f64,
+    /// Whether the mean difference is nonzero at the 95% confidence level, or `None` with too
+    /// few pairs to tell.
+    pub significant: Option<bool>,
+}
+
+/// Runs the two probes side by side from the same number of workers on the same schedule,
+/// reporting every attempt as usual and recording the attempts of each worker on each tick as a
+/// pair, until both are done.
+pub async fn run<P: Probe>(a: P, b: P, args: &CommonArgs) {
+    let key = (a.kind(), args.name.clone(), a.target(), b.target());
+    let mut a = Runner::start(a, args);
+    let mut b = Runner::start(b, args);
+    let mut pending: HashMap<usize, (Outcomes, Outcomes)> = HashMap::new();
+
+    loop {
+        let (result, first) = tokio::select! {
+            Some(result) = a.next() => (result, true),
+            Some(result) = b.next() => (result, false),
+            else => break,
+        };
+        if result.skipped > 0 {
+            report::skipped(&result.origin, result.skipped);
+        }
+        if result.warmup {
+            report::warmup(&result.origin, &result.attempt);
+            continue;
+        }
+        report::report(&result.origin, &result.attempt);
+
+        let outcome = match result.attempt.error {
+            None => Some(result.attempt.duration),
+            Some(_) => None,
+        };
+        let (a, b) = pending.entry(result.origin.worker).or_default();
+        match first {
+            true => a.push_back(outcome),
+            false => b.push_back(outcome),
+        }
+        if !a.is_empty() && !b.is_empty() {
+            let pair = (a.pop_front().unwrap(), b.pop_front().unwrap());
+            PAIRS
+                .lock()
+                .unwrap()
+                .entry(key.clone())
+                .or_default()
+                .record(pair);
+        }
+    }
+}
+
+impl Pairs {
+    fn record(&mut self, pair: (Option<Duration>, Option<Duration>)) {
+        self.pairs += 1;
+        match pair {
+            (Some(a), Some(b)) => {
+                self.a_latencies.push(a);
+                self.b_latencies.push(b);
+                self.differences
+                    .push((b.as_secs_f64() - a.as_secs_f64()) * 1000.0);
+            }
+            (None, Some(b)) => {
+                self.a_failures += 1;
+                self.only_a_failed += 1;
+                self.b_latencies.push(b);
+            }
+            (Some(a), None) => {
+                self.b_failures += 1;
+                self.only_b_failed += 1;
+                self.a_latencies.push(a);
+            }
+            (None, None) => {
+                self.a_failures += 1;
+                self.b_failures += 1;
+            }
+        }
+    }
+
+    fn summarize(&self, key: &Key) -> Comparison {
+        let rate = |failures| 100.0 * failures as f64 / self.pairs.max(1) as f64;
+        let mean = |latencies: &[Duration]| {
+            (!latencies.is_empty()).then(|| {
+                latencies.iter().map(Duration::as_secs_f64).sum::<f64>() * 1000.0
+                    / latencies.len() as f64
+            })
+        };
+
+        Comparison {
+            probe: key.0,
+            name: key.1.clone(),
+            a: key.2.clone(),
+            b: key.3.clone(),
+            pairs: self.pairs,
+            a_error_rate: rate(self.a_failures),
+            b_error_rate: rate(self.b_failures),
+            error_rate_significant: mcnemar(self.only_a_failed, self.only_b_failed),
+            a_mean_ms: mean(&self.a_latencies),
+            b_mean_ms: mean(&self.b_latencies),
+            difference_ms: difference(&self.differences),
+        }
+    }
+}
+
+/// Whether error rates differ given the pairs where only one target failed, by McNemar's test
+/// with continuity correction against the chi-squared critical value.
+fn mcnemar(only_a_failed: u64, only_b_failed: u64) -> Option<bool> {
+    let discordant = only_a_failed + only_b_failed;
+    (discordant >= MIN_DISCORDANT).then(|| {
+        let diff = only_a_failed.abs_diff(only_b_failed) as f64;
+        (diff - 1.0).max(0.0).powi(2) / discordant as f64 > Z_95 * Z_95
+    })
+}
+
+fn difference(differences: &[f64]) -> Option<Difference> {
+    if differences.is_empty() {
+        return None;
+    }
+    let n = differences.len() as f64;
+    let mean = differences.iter().sum::<f64>() / n;
+    let variance = differences.iter().map(|d| (d - mean).powi(2)).sum::<f64>() / (n - 1.0).max(1.0);
+    let t = if variance > 0.0 {
+        mean / (variance / n).sqrt()
+    } else if mean != 0.0 {
+        // Every pair differs by the same amount, like with a hop added to one of the paths.
+        f64::INFINITY.copysign(mean)
+    } else {
+        0.0
+    };
+
+    let mut sorted = differences.to_vec();
+    sorted.sort_by(f64::total_cmp);
+    let percentile = |q: f64| sorted[((sorted.len() - 1) as f64 * q).round() as usize];
+    Some(Difference {
+        mean,
+        p50: percentile(0.5),
+        p90: percentile(0.9),
+        t,
+        significant: (differences.len() as u64 >= MIN_PAIRS).then_some(t.abs() > Z_95),
+    })
+}
+
+/// Paired differences of every comparison run.
+pub fn comparisons() -> Vec<Comparison> {
+    PAIRS
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|(key, pairs)| pairs.summarize(key))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mcnemar_needs_enough_discordant_pairs() {
+        assert_eq!(mcnemar(MIN_DISCORDANT - 1, 0), None);
+        assert_eq!(mcnemar(0, MIN_DISCORDANT - 1), None);
+        assert!(mcnemar(MIN_DISCORDANT, 0).is_some());
+    }
+
+    #[test]
+    fn mcnemar_tells_lopsided_failures_apart() {
+        assert_eq!(mcnemar(20, 0), Some(true));
+        assert_eq!(mcnemar(0, 20), Some(true));
+        assert_eq!(mcnemar(5, 5), Some(false));
+        // (|12 - 8| - 1)^2 / 20 = 0.45, well below 1.96^2.
+        assert_eq!(mcnemar(12, 8), Some(false));
+    }
+
+    #[test]
+    fn difference_needs_enough_pairs() {
+        let differences = vec![5.0; MIN_PAIRS as usize - 1];
+        assert_eq!(difference(&differences).unwrap().significant, None);
+        assert!(difference(&[]).is_none());
+    }
+
+    #[test]
+    fn constant_nonzero_difference_is_significant() {
+        let difference = difference(&vec![2.5; MIN_PAIRS as usize]).unwrap();
+        assert_eq!(difference.mean, 2.5);
+        assert_eq!(difference.t, f64::INFINITY);
+        assert_eq!(difference.significant, Some(true));
+
+        let difference = super::difference(&vec![-2.5; MIN_PAIRS as usize]).unwrap();
+        assert_eq!(difference.t, f64::NEG_INFINITY);
+        assert_eq!(difference.significant, Some(true));
+    }
+
+    #[test]
+    fn no_difference_is_not_significant() {
+        let difference = difference(&vec![0.0; MIN_PAIRS as usize]).unwrap();
+        assert_eq!(difference.t, 0.0);
+        assert_eq!(difference.significant, Some(false));
+    }
+
+    #[test]
+    fn noisy_differences_around_zero_are_not_significant() {
+        let differences: Vec<_> = (0..40)
+            .map(|i| if i % 2 == 0 { 3.0 } else { -3.0 })
+            .collect();
+        let difference = difference(&differences).unwrap();
+        assert_eq!(difference.mean, 0.0);
+        assert_eq!(difference.significant, Some(false));
+    }
+
+    #[test]
+    fn shifted_differences_are_significant() {
+        // A mean of 2ms against a standard deviation of about 1ms over 40 pairs.
+        let differences: Vec<_> = (0..40)
+            .map(|i| if i % 2 == 0 { 3.0 } else { 1.0 })
+            .collect();
+        let difference = difference(&differences).unwrap();
+        assert_eq!(difference.mean, 2.0);
+        assert!(difference.t > 10.0);
+        assert_eq!(difference.significant, Some(true));
+        assert_eq!(difference.p50, 3.0);
+    }
+}
@@ -0,0 +1,432 @@
+use std::{
+    collections::{BTreeMap, VecDeque},
+    convert::Infallible,
+    net::SocketAddr,
+    sync::{LazyLock, Mutex, OnceLock},
+    time::{Duration, SystemTime},
+};
+
+use clap::{Args, Parser};
+use hyper::{
+    header::CONTENT_TYPE,
+    service::{make_service_fn, service_fn},
+    Body, Client, Method, Request, Response, Server, StatusCode, Uri,
+};
+use log::{error, warn};
+use serde::{Deserialize, Serialize};
+use tokio::time;
+
+use crate::{
+    http::{self, TimingConnector},
+    report::{self, Attempt, Labels, Origin},
+    stats::{Latencies, Percentiles},
+};
+
+/// How often agents push the results buffered since the last push.
+const PUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Time to wait for the collector to accept a push.
+const PUSH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Most results an agent buffers while the collector is unreachable, beyond which the oldest are
+/// dropped.
+const MAX_BUFFERED: usize = 100_000;
+
+static AGENT: OnceLock<Agent> = OnceLock::new();
+
+/// Results aggregated by the collector, keyed by probe kind, name and target.
+static COLLECTED: LazyLock<Mutex<Collected>> = LazyLock::new(Default::default);
+
+/// Options for pushing results to a collector as an agent.
+#[derive(Args, Debug, Default, Serialize, Deserialize)]
+pub struct AgentArgs {
+    /// Push the result of every attempt to the `artemiss collector` at this URL, e.g.
+    /// `http://collector:9900`, which aggregates the results of agents in several locations.
+    #[arg(long, global = true)]
+    collector: Option<String>,
+
+    /// Location results are pushed from, e.g. the availability zone, to tell agents apart at the
+    /// collector. Defaults to the host name.
+    #[arg(long, global = true, requires = "collector")]
+    location: Option<String>,
+}
+
+#[derive(Parser, Debug, Serialize, Deserialize)]
+pub struct CollectorArgs {
+    /// Address to accept results from agents on at `/ingest`, and to serve their aggregated
+    /// statistics on at `/status`.
+    #[arg(long, default_value = "0.0.0.0:9900")]
+    listen: SocketAddr,
+
+    /// Print the statistics of every target by location this often.
+    #[arg(long, default_value_t = 10)]
+    interval_s: u64,
+
+    /// Consider a location failing against a target over an interval when at least this
+    /// percentage of its attempts failed.
+    #[arg(long, default_value_t = 50.0)]
+    failing_error_rate: f64,
+}
+
+struct Agent {
+    uri: Uri,
+    client: Client<TimingConnector>,
+    location: String,
+    /// Results not pushed yet.
+    buffer: Mutex<VecDeque<Pushed>>,
+}
+
+/// Results pushed by an agent at once.
+#[derive(Serialize, Deserialize)]
+struct Batch {
+    location: String,
+    results: Vec<Pushed>,
+}
+
+/// Result of an attempt, as pushed to the collector.
+#[derive(Serialize, Deserialize)]
+struct Pushed {
+    probe: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+    target: String,
+    latency_ms: f64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    error_kind: Option<String>,
+}
+
+type Key = (String, Option<String>, String);
+
+#[derive(Default)]
+struct Collected {
+    total: BTreeMap<Key, BTreeMap<String, Stats>>,
+    interval: BTreeMap<Key, BTreeMap<String, Stats>>,
+    /// When each location last pushed results.
+    agents: BTreeMap<String, SystemTime>,
+    failing_error_rate: f64,
+}
+
+/// Attempts against a target from one location.
+#[derive(Default)]
+struct Stats {
+    attempts: u64,
+    latencies: Latencies,
+    errors: BTreeMap<String, u64>,
+}
+
+/// Statistics of a target over every location, and how widely it is failing.
+#[derive(Serialize)]
+struct TargetSummary {
+    probe: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+    target: String,
+    /// `healthy` when no location is failing against the target, `partial` when only some are
+    /// and `global` when all of them are.
+    scope: &'static str,
+    failing_locations: Vec<String>,
+    locations: BTreeMap<String, LocationSummary>,
+}
+
+#[derive(Serialize)]
+struct LocationSummary {
+    attempts: u64,
+    failures: u64,
+    /// Percentage of attempts that succeeded.
+    success_rate: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    latency_ms: Option<Percentiles>,
+    errors: BTreeMap<String, u64>,
+}
+
+/// Starts pushing results to the collector, if there is one.
+pub(crate) fn init(args: AgentArgs) {
+    let Some(url) = args.collector else {
+        return;
+    };
+    let base: Uri = url.parse().expect("invalid collector url");
+    let uri = format!("{}/ingest", url.trim_end_matches('/'))
+        .parse()
+        .expect("invalid collector url");
+    let origin = Origin {
+        probe: "agent",
+        name: None,
+        labels: Labels::default(),
+        target: report::redact(&url),
+        worker: 0,
+    };
+    let agent = Agent {
+        client: http::client(&base, origin),
+        uri,
+        location: args.location.unwrap_or_else(hostname),
+        buffer: Mutex::new(VecDeque::new()),
+    };
+    if AGENT.set(agent).is_err() {
+        panic!("agent already initialised");
+    }
+    tokio::spawn(async {
+        let mut interval = time::interval(PUSH_INTERVAL);
+        loop {
+            interval.tick().await;
+            push().await;
+        }
+    });
+}
+
+fn hostname() -> String {
+    let mut name = [0u8; 256];
+    // SAFETY: the buffer is valid for its whole length, which gethostname is told.
+    let result = unsafe { libc::gethostname(name.as_mut_ptr().cast(), name.len()) };
+    let len = name.iter().position(|&b| b == 0).unwrap_or(name.len());
+    match result {
+        0 if len > 0 => String::from_utf8_lossy(&name[..len]).into_owned(),
+        _ => "unknown".to_string(),
+    }
+}
+
+/// Buffers the result of an attempt to push to the collector.
+pub fn observe(origin: &Origin, attempt: &Attempt) {
+    let Some(agent) = AGENT.get() else {
+        return;
+    };
+    let mut buffer = agent.buffer.lock().unwrap();
+    if buffer.len() >= MAX_BUFFERED {
+        buffer.pop_front();
+    }
+    buffer.push_back(Pushed {
+        probe: origin.probe.to_string(),
+        name: origin.name.clone(),
+        target: origin.target.clone(),
+        latency_ms: attempt.duration.as_secs_f64() * 1000.0,
+        error_kind: attempt.error.as_ref().map(|e| e.kind.to_string()),
+    });
+}
+
+/// Pushes the buffered results to the collector, keeping them to push again if it fails.
+async fn push() {
+    let Some(agent) = AGENT.get() else {
+        return;
+    };
+    let results = std::mem::take(&mut *agent.buffer.lock().unwrap());
+    if results.is_empty() {
+        return;
+    }
+    let batch = Batch {
+        location: agent.location.clone(),
+        results: results.into(),
+    };
+    let request = Request::post(agent.uri.clone())
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(serde_json::to_vec(&batch).unwrap()))
+        .expect("invalid push request");
+    let failure = match time::timeout(PUSH_TIMEOUT, agent.client.request(request)).await {
+        Ok(Ok(res)) if res.status().is_success() => return,
+        Ok(Ok(res)) => format!("collector returned {}", res.status()),
+        Ok(Err(e)) => format!("error pushing to collector: {}", e),
+        Err(_) => "collector timed out".to_string(),
+    };
+    warn!(
+        "{}, keeping {} results to push again",
+        failure,
+        batch.results.len()
+    );
+    let mut buffer = agent.buffer.lock().unwrap();
+    let mut results = VecDeque::from(batch.results);
+    results.append(&mut buffer);
+    let excess = results.len().saturating_sub(MAX_BUFFERED);
+    results.drain(..excess);
+    *buffer = results;
+}
+
+/// Pushes the results still buffered before exiting.
+pub(crate) async fn flush() {
+    push().await;
+}
+
+/// Accepts results from agents and reports them by target and location.
+pub async fn collector_main(args: CollectorArgs) {
+    COLLECTED.lock().unwrap().failing_error_rate = args.failing_error_rate;
+    let server = Server::try_bind(&args.listen)
+        .expect("error binding collector listen address")
+        .serve(make_service_fn(|_| async {
+            Ok::<_, Infallible>(service_fn(handle))
+        }));
+    tokio::spawn(async move {
+        if let Err(e) = server.await {
+            error!("collector server error: {}", e);
+        }
+    });
+
+    let period = Duration::from_secs(args.interval_s);
+    let mut interval = time::interval_at(time::Instant::now() + period, period);
+    loop {
+        interval.tick().await;
+        let collected = std::mem::take(&mut COLLECTED.lock().unwrap().interval);
+        if collected.is_empty() {
+            continue;
+        }
+        println!("--- artemiss collector last {}s ---", period.as_secs());
+        for summary in summarise(&collected, args.failing_error_rate) {
+            println!("{}", summary_line(&summary));
+            for (location, stats) in &summary.locations {
+                println!("  {}", location_line(location, stats));
+            }
+        }
+    }
+}
+
+async fn handle(req: Request<Body>) -> Result<Response<Body>, Infallible> {
+    let res = match (req.method(), req.uri().path()) {
+        (&Method::POST, "/ingest") => match hyper::body::to_bytes(req.into_body()).await {
+            Ok(body) => match serde_json::from_slice::<Batch>(&body) {
+                Ok(batch) => {
+                    ingest(batch);
+                    status(StatusCode::NO_CONTENT, String::new())
+                }
+                Err(e) => status(StatusCode::BAD_REQUEST, format!("invalid batch: {}\n", e)),
+            },
+            Err(e) => status(StatusCode::BAD_REQUEST, format!("{}\n", e)),
+        },
+        (&Method::GET, "/status") => {
+            #[derive(Serialize)]
+            struct Status {
+                /// When each location last pushed results.
+                agents: BTreeMap<String, String>,
+                targets: Vec<TargetSummary>,
+            }
+            let collected = COLLECTED.lock().unwrap();
+            let agents = collected
+                .agents
+                .iter()
+                .map(|(location, at)| {
+                    let at = humantime::format_rfc3339_seconds(*at).to_string();
+                    (location.clone(), at)
+                })
+                .collect();
+            let targets = summarise(&collected.total, collected.failing_error_rate);
+            let mut res = Response::new(Body::from(
+                serde_json::to_vec(&Status { agents, targets }).unwrap(),
+            ));
+            res.headers_mut()
+                .insert(CONTENT_TYPE, "application/json".parse().unwrap());
+            res
+        }
+        (&Method::GET, "/healthz") => status(StatusCode::OK, "ok\n".to_string()),
+        _ => status(StatusCode::NOT_FOUND, String::new()),
+    };
+    Ok(res)
+}
+
+fn status(code: StatusCode, body: String) -> Response<Body> {
+    let mut res = Response::new(Body::from(body));
+    *res.status_mut() = code;
+    res
+}
+
+fn ingest(batch: Batch) {
+    let mut collected = COLLECTED.lock().unwrap();
+    let collected = &mut *collected;
+    collected
+        .agents
+        .insert(batch.location.clone(), SystemTime::now());
+    for result in batch.results {
+        let key = (result.probe, result.name, result.target);
+        for stats in [&mut collected.total, &mut collected.interval] {
+            let stats = stats
+                .entry(key.clone())
+                .or_default()
+                .entry(batch.location.clone())
+                .or_default();
+            stats.attempts += 1;
+            match &result.error_kind {
+                None => stats
+                    .latencies
+                    .record(Duration::from_secs_f64(result.latency_ms.max(0.0) / 1000.0)),
+                Some(kind) => *stats.errors.entry(kind.clone()).or_default() += 1,
+            }
+        }
+    }
+}
+
+fn summarise(
+    collected: &BTreeMap<Key, BTreeMap<String, Stats>>,
+    failing_error_rate: f64,
+) -> Vec<TargetSummary> {
+    collected
+        .iter()
+        .map(|((probe, name, target), locations)| {
+            let locations: BTreeMap<_, _> = locations
+                .iter()
+                .map(|(location, stats)| (location.clone(), stats.summarise()))
+                .collect();
+            let failing_locations: Vec<_> = locations
+                .iter()
+                .filter(|(_, stats)| 100.0 - stats.success_rate >= failing_error_rate)
+                .map(|(location, _)| location.clone())
+                .collect();
+            let scope = match failing_locations.len() {
+                0 => "healthy",
+                n if n == locations.len() => "global",
+                _ => "partial",
+            };
+            TargetSummary {
+                probe: probe.clone(),
+                name: name.clone(),
+                target: target.clone(),
+                scope,
+                failing_locations,
+                locations,
+            }
+        })
+        .collect()
+}
+
+impl Stats {
+    fn summarise(&self) -> LocationSummary {
+        let failures = self.errors.values().sum();
+        LocationSummary {
+            attempts: self.attempts,
+            failures,
+            success_rate: if self.attempts == 0 {
+                0.0
+            } else {
+                (self.attempts - failures) as f64 * 100.0 / self.attempts as f64
+            },
+            latency_ms: self.latencies.percentiles(),
+            errors: self.errors.clone(),
+        }
+    }
+}
+
+fn summary_line(summary: &TargetSummary) -> String {
+    let mut line = format!("{} ", summary.probe);
+    if let Some(name) = &summary.name {
+        line.push_str(name);
+        line.push(' ');
+    }
+    line.push_str(&summary.target);
+    line.push_str(": ");
+    line.push_str(summary.scope);
+    if summary.scope != "healthy" {
+        line.push_str(", failing from ");
+        line.push_str(&summary.failing_locations.join(", "));
+    }
+    line
+}
+
+fn location_line(location: &str, stats: &LocationSummary) -> String {
+    let mut line = format!(
+        "{}: attempts={} failures={} success={:.2}%",
+        location, stats.attempts, stats.failures, stats.success_rate
+    );
+    if let Some(latency) = &stats.latency_ms {
+        line.push_str(&format!(
+            " p50={:.3}ms p99={:.3}ms",
+            latency.p50, latency.p99
+        ));
+    }
+    for (kind, count) in &stats.errors {
+        line.push_str(&format!(" {}={}", kind, count));
+    }
+    line
+}
@@ -0,0 +1,486 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt::Write,
+    net::IpAddr,
+    sync::{Mutex, OnceLock},
+    time::{Duration, Instant, SystemTime},
+};
+
+use clap::{Args, ValueEnum};
+use hyper::{header::CONTENT_TYPE, Body, Client, Request, Uri};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tokio::{task::JoinSet, time};
+use url::Url;
+
+use crate::{
+    http::{self, one_or_many},
+    report::{self, Attempt, Labels, Origin, ProbeError},
+    resolve::Resolver,
+    stats::HealthSummary,
+    traceroute::{self, Hop},
+};
+
+/// Time to wait for a webhook to accept an alert.
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+
+static ALERTS: OnceLock<Alerts> = OnceLock::new();
+
+/// Options for alerting when targets become unhealthy or recover.
+#[derive(Args, Debug, Serialize, Deserialize)]
+pub struct AlertArgs {
+    /// POST an alert to this URL whenever a target becomes unhealthy or recovers. Can be repeated.
+    #[arg(long, global = true)]
+    #[serde(default, deserialize_with = "one_or_many")]
+    alert_webhook: Vec<String>,
+
+    /// Format of the alerts sent to webhooks.
+    #[arg(long, global = true, value_enum, default_value_t)]
+    alert_format: AlertFormat,
+
+    /// Consider a target unhealthy after this many consecutive failed attempts. The health of
+    /// every target is also shown in its summaries.
+    #[arg(long, global = true, default_value_t = 3)]
+    alert_after_failures: u32,
+
+    /// Consider an unhealthy target recovered after this many consecutive successful attempts.
+    #[arg(long, global = true, default_value_t = 1)]
+    alert_after_successes: u32,
+
+    /// Consider a target flapping once it became unhealthy or recovered this many times within
+    /// `--flap-window-s`, alerting once that it is rather than on every change, until a whole
+    /// window passes without one. `0` never does.
+    #[arg(long, global = true, default_value_t = 4)]
+    flap_transitions: usize,
+
+    /// Window to count the times a target became unhealthy or recovered within, to tell whether
+    /// it is flapping.
+    #[arg(long, global = true, default_value_t = 600)]
+    flap_window_s: u64,
+
+    /// When a target becomes unhealthy, trace the path to it and include the hops in the alert
+    /// and the log line reporting it.
+    #[arg(long, global = true)]
+    traceroute_on_failure: bool,
+
+    /// Maximum number of hops to trace the path to an unhealthy target over.
+    #[arg(long, global = true, default_value_t = 30)]
+    traceroute_max_hops: u8,
+
+    /// Time to wait for each hop on the path to an unhealthy target to answer.
+    #[arg(long, global = true, default_value_t = 500)]
+    traceroute_timeout_ms: u64,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AlertFormat {
+    /// A JSON object describing the transition.
+    #[default]
+    Json,
+    /// A Slack incoming webhook message, with the description as its text.
+    Slack,
+}
+
+struct Alerts {
+    /// Whether changes in health are logged and sent, rather than only shown in summaries.
+    notify: bool,
+    webhooks: Vec<(Uri, Client<http::TimingConnector>)>,
+    format: AlertFormat,
+    after_failures: u32,
+    after_successes: u32,
+    /// Number of changes in health within the window that make a target flapping, if any do.
+    flap: Option<(usize, Duration)>,
+    /// Maximum hops and timeout of each, if the path to targets is traced when they become
+    /// unhealthy.
+    traceroute: Option<(u8, Duration)>,
+    targets: Mutex<HashMap<Key, Health>>,
+    /// Alerts still being delivered.
+    deliveries: Mutex<JoinSet<()>>,
+}
+
+/// Keyed by probe kind, name and target, like the statistics.
+type Key = (&'static str, Option<String>, String);
+
+#[derive(Default)]
+struct Health {
+    failures: u32,
+    successes: u32,
+    /// When the target became unhealthy, if it is.
+    unhealthy_since: Option<Instant>,
+    /// Address of the target last connected to, to trace the path to.
+    remote_addr: Option<IpAddr>,
+    /// When the target became unhealthy or recovered within the flap window, oldest first.
+    transitions: VecDeque<Instant>,
+    /// Whether the target is flapping, which it stays until a whole window passes without a
+    /// change in health.
+    flapping: bool,
+}
+
+/// Starts tracking the health of targets, alerting the webhooks, if any, when it changes.
+pub(crate) fn init(args: AlertArgs) {
+    assert!(
+        args.alert_after_failures > 0 && args.alert_after_successes > 0,
+        "--alert-after-failures and --alert-after-successes must be at least 1"
+    );
+
+    let webhooks = args
+        .alert_webhook
+        .iter()
+        .map(|url| {
+            let uri: Uri = url.parse().expect("invalid alert webhook url");
+            let origin = Origin {
+                probe: "alert",
+                name: None,
+                labels: Labels::default(),
+                target: report::redact(url),
+                worker: 0,
+            };
+            let client = http::client(&uri, origin);
+            (uri, client)
+        })
+        .collect();
+
+    let alerts = Alerts {
+        notify: !args.alert_webhook.is_empty() || args.traceroute_on_failure,
+        webhooks,
+        format: args.alert_format,
+        after_failures: args.alert_after_failures,
+        after_successes: args.alert_after_successes,
+        flap: (args.flap_transitions > 0).then(|| {
+            assert!(args.flap_window_s > 0, "--flap-window-s must be positive");
+            (
+                args.flap_transitions,
+                Duration::from_secs(args.flap_window_s),
+            )
+        }),
+        traceroute: args.traceroute_on_failure.then(|| {
+            let timeout = Duration::from_millis(args.traceroute_timeout_ms);
+            (args.traceroute_max_hops, timeout)
+        }),
+        targets: Mutex::new(HashMap::new()),
+        deliveries: Mutex::new(JoinSet::new()),
+    };
+    if ALERTS.set(alerts).is_err() {
+        panic!("alerting already initialised");
+    }
+}
+
+/// Tracks the health of the target of a probe attempt, alerting when it changes.
+pub fn observe(origin: &Origin, attempt: &Attempt) {
+    let Some(alerts) = ALERTS.get() else {
+        return;
+    };
+
+    let key = (origin.probe, origin.name.clone(), origin.target.clone());
+    let mut targets = alerts.targets.lock().unwrap();
+    let health = targets.entry(key).or_default();
+    let remote_addr = attempt
+        .details
+        .iter()
+        .find(|(name, _)| *name == "remote_addr")
+        .and_then(|(_, addr)| addr.parse::<std::net::SocketAddr>().ok());
+    if let Some(addr) = remote_addr {
+        health.remote_addr = Some(addr.ip());
+    }
+    let now = Instant::now();
+    let mut transition = None;
+    match &attempt.error {
+        Some(error) => {
+            health.failures += 1;
+            health.successes = 0;
+            if health.unhealthy_since.is_none() && health.failures == alerts.after_failures {
+                health.unhealthy_since = Some(now);
+                transition = Some(Transition::Unhealthy(health.failures, error.clone()));
+            }
+        }
+        None => {
+            health.failures = 0;
+            health.successes += 1;
+            if let Some(since) = health.unhealthy_since {
+                if health.successes == alerts.after_successes {
+                    health.unhealthy_since = None;
+                    transition = Some(Transition::Recovered(since.elapsed()));
+                }
+            }
+        }
+    }
+
+    if let Some((transitions, window)) = alerts.flap {
+        while (health.transitions.front()).is_some_and(|at| now.duration_since(*at) > window) {
+            health.transitions.pop_front();
+        }
+        if transition.is_some() {
+            health.transitions.push_back(now);
+        }
+        // Changes in health are only alerted on once the target settles, after it started flapping.
+        if !health.flapping && health.transitions.len() >= transitions {
+            health.flapping = true;
+            transition = Some(Transition::Flapping(health.transitions.len(), window));
+        } else if health.flapping && health.transitions.is_empty() {
+            health.flapping = false;
+            transition = Some(Transition::Settled(health.unhealthy_since.is_none()));
+        } else if health.flapping {
+            transition = None;
+        }
+    }
+
+    let Some(transition) = transition.filter(|_| alerts.notify) else {
+        return;
+    };
+    match (&transition, alerts.traceroute) {
+        (Transition::Unhealthy(..), Some(_)) => {
+            alerts.trace(origin.clone(), transition, health.remote_addr)
+        }
+        _ => alerts.send(origin, transition, None),
+    }
+}
+
+/// Health of a target, once an attempt was made against it.
+pub(crate) fn health(
+    probe: &'static str,
+    name: &Option<String>,
+    target: &str,
+) -> Option<HealthSummary> {
+    let alerts = ALERTS.get()?;
+    let key = (probe, name.clone(), target.to_string());
+    let targets = alerts.targets.lock().unwrap();
+    let health = targets.get(&key)?;
+    let transitions = match alerts.flap {
+        Some((_, window)) => (health.transitions.iter())
+            .filter(|at| at.elapsed() <= window)
+            .count(),
+        None => 0,
+    };
+    Some(HealthSummary {
+        state: match health.unhealthy_since {
+            Some(_) => "unhealthy",
+            None => "healthy",
+        },
+        transitions,
+        flapping: health.flapping,
+    })
+}
+
+/// Waits for alerts still being delivered before exiting.
+pub(crate) async fn flush() {
+    let Some(alerts) = ALERTS.get() else {
+        return;
+    };
+    // Alerts whose path was being traced are only sent once it was, so wait for those too.
+    let all = async {
+        loop {
+            let mut deliveries = std::mem::take(&mut *alerts.deliveries.lock().unwrap());
+            if deliveries.is_empty() {
+                break;
+            }
+            while deliveries.join_next().await.is_some() {}
+        }
+    };
+    let tracing = alerts
+        .traceroute
+        .map_or(Duration::ZERO, |(hops, timeout)| timeout * hops.into());
+    if time::timeout(DELIVERY_TIMEOUT + tracing, all)
+        .await
+        .is_err()
+    {
+        warn!("alerts were not delivered before exiting");
+    }
+}
+
+enum Transition {
+    /// The target failed this many attempts in a row, the last with the error.
+    Unhealthy(u32, ProbeError),
+    /// The target recovered after being unhealthy for this long.
+    Recovered(Duration),
+    /// The target became unhealthy or recovered this many times within the window.
+    Flapping(usize, Duration),
+    /// The target stopped flapping, and is healthy or not.
+    Settled(bool),
+}
+
+impl Alerts {
+    /// Traces the path to the target, then sends the alert with it.
+    fn trace(&'static self, origin: Origin, transition: Transition, addr: Option<IpAddr>) {
+        let Some((max_hops, timeout)) = self.traceroute else {
+            return;
+        };
+        let mut deliveries = self.deliveries.lock().unwrap();
+        deliveries.spawn(async move {
+            let addr = match addr {
+                Some(addr) => Some(addr),
+                None => Resolver::default()
+                    .lookup(host(&origin.target), 0)
+                    .await
+                    .ok()
+                    .and_then(|addrs| addrs.first().map(|addr| addr.ip())),
+            };
+            let path = match addr {
+                Some(addr) => match traceroute::trace(addr, max_hops, timeout).await {
+                    Ok(path) => Some(path),
+                    Err(e) => {
+                        warn!("error tracing the path to {}: {}", addr, e);
+                        None
+                    }
+                },
+                None => {
+                    warn!(
+                        "not tracing the path to {}: unable to resolve it",
+                        origin.target
+                    );
+                    None
+                }
+            };
+            self.send(&origin, transition, path);
+        });
+    }
+
+    fn send(&self, origin: &Origin, transition: Transition, path: Option<Vec<Hop>>) {
+        let mut target = format!("{} ", origin.probe);
+        if let Some(name) = &origin.name {
+            target.push_str(name);
+            target.push(' ');
+        }
+        target.push_str(&origin.target);
+        if !origin.labels.is_empty() {
+            let labels: Vec<_> = origin
+                .labels
+                .iter()
+                .map(|(k, v)| format!("{}={}", k, v))
+                .collect();
+            write!(target, " ({})", labels.join(", ")).unwrap();
+        }
+
+        let timestamp = humantime::format_rfc3339_micros(SystemTime::now()).to_string();
+        let (text, payload) = match transition {
+            Transition::Unhealthy(failures, error) => {
+                let mut text = format!(
+                    "{} is unhealthy after {} consecutive failures, the last {}: {}",
+                    target, failures, error.kind, error.message
+                );
+                if let Some(path) = &path {
+                    let hops: Vec<_> = path.iter().map(Hop::to_string).collect();
+                    write!(text, ", path: {}", hops.join(", ")).unwrap();
+                }
+                warn!("{}", text);
+                let mut payload = json!({
+                    "event": "unhealthy",
+                    "timestamp": timestamp,
+                    "probe": origin.probe,
+                    "name": origin.name,
+                    "labels": *origin.labels,
+                    "target": origin.target,
+                    "consecutive_failures": failures,
+                    "error_kind": error.kind,
+                    "error_message": error.message,
+                });
+                if let Some(path) = path {
+                    payload["path"] = json!(path);
+                }
+                (format!(":red_circle: artemiss: {}", text), payload)
+            }
+            Transition::Recovered(down_for) => {
+                let down_for = Duration::from_secs(down_for.as_secs());
+                let text = format!(
+                    "{} recovered after being unhealthy for {}",
+                    target,
+                    humantime::format_duration(down_for)
+                );
+                info!("{}", text);
+                let payload = json!({
+                    "event": "recovered",
+                    "timestamp": timestamp,
+                    "probe": origin.probe,
+                    "name": origin.name,
+                    "labels": *origin.labels,
+                    "target": origin.target,
+                    "unhealthy_for_s": down_for.as_secs(),
+                });
+                (format!(":large_green_circle: artemiss: {}", text), payload)
+            }
+            Transition::Flapping(transitions, window) => {
+                let text = format!(
+                    "{} is flapping, having become unhealthy or recovered {} times in {}",
+                    target,
+                    transitions,
+                    humantime::format_duration(window)
+                );
+                warn!("{}", text);
+                let payload = json!({
+                    "event": "flapping",
+                    "timestamp": timestamp,
+                    "probe": origin.probe,
+                    "name": origin.name,
+                    "labels": *origin.labels,
+                    "target": origin.target,
+                    "transitions": transitions,
+                    "window_s": window.as_secs(),
+                });
+                (format!(":large_orange_circle: artemiss: {}", text), payload)
+            }
+            Transition::Settled(healthy) => {
+                let state = if healthy { "healthy" } else { "unhealthy" };
+                let text = format!("{} stopped flapping and is {}", target, state);
+                info!("{}", text);
+                let payload = json!({
+                    "event": "settled",
+                    "timestamp": timestamp,
+                    "probe": origin.probe,
+                    "name": origin.name,
+                    "labels": *origin.labels,
+                    "target": origin.target,
+                    "state": state,
+                });
+                let emoji = if healthy {
+                    "large_green_circle"
+                } else {
+                    "red_circle"
+                };
+                (format!(":{}: artemiss: {}", emoji, text), payload)
+            }
+        };
+        let body = match self.format {
+            AlertFormat::Json => payload,
+            AlertFormat::Slack => json!({ "text": text }),
+        }
+        .to_string();
+
+        let mut deliveries = self.deliveries.lock().unwrap();
+        while deliveries.try_join_next().is_some() {}
+        for (uri, client) in &self.webhooks {
+            let request = Request::post(uri.clone())
+                .header(CONTENT_TYPE, "application/json")
+                .body(Body::from(body.clone()))
+                .expect("invalid alert request");
+            let client = client.clone();
+            let webhook = report::redact(&uri.to_string());
+            deliveries.spawn(async move {
+                match time::timeout(DELIVERY_TIMEOUT, client.request(request)).await {
+                    Ok(Ok(res)) if res.status().is_success() => {}
+                    Ok(Ok(res)) => warn!("alert webhook {} returned {}", webhook, res.status()),
+                    Ok(Err(e)) => warn!("error sending alert to {}: {}", webhook, e),
+                    Err(_) => warn!("alert webhook {} timed out", webhook),
+                }
+            });
+        }
+    }
+}
+
+/// Host of a probe target, which is either a URL or `host:port`.
+pub(crate) fn host(target: &str) -> &str {
+    let host = match Url::parse(target) {
+        Ok(url) if url.has_host() => {
+            let start = target.find("://").map_or(0, |i| i + 3);
+            let authority = &target[start..];
+            let authority = authority.split(['/', '?', '#']).next().unwrap_or(authority);
+            let authority = authority.rsplit('@').next().unwrap_or(authority);
+            authority
+                .rsplit_once(':')
+                .filter(|(_, port)| port.chars().all(|c| c.is_ascii_digit()))
+                .map_or(authority, |(host, _)| host)
+        }
+        _ => target.rsplit_once(':').map_or(target, |(host, _)| host),
+    };
+    host.trim_start_matches('[').trim_end_matches(']')
+}
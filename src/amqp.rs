@@ -0,0 +1,255 @@
+use std::{
+    fs,
+    future::Future,
+    time::{Duration, Instant},
+};
+
+use async_compat::Compat;
+use clap::Parser;
+use futures_util::StreamExt;
+use lapin::{
+    options::{BasicConsumeOptions, BasicPublishOptions, QueueDeclareOptions},
+    runtime,
+    tcp::{AsyncTcpStream, OwnedIdentity, OwnedTLSConfig},
+    types::FieldTable,
+    uri::{AMQPScheme, AMQPUri},
+    BasicProperties, Connection, ConnectionProperties,
+};
+use serde::{Deserialize, Serialize};
+use tokio::time;
+
+use crate::{
+    probe::{self, CommonArgs, Probe},
+    report::{self, Attempt, ProbeError},
+    resolve,
+    tls::TlsArgs,
+};
+
+#[derive(Parser, Debug, Serialize, Deserialize)]
+pub struct AmqpArgs {
+    /// AMQP URL to connect to, as `amqp://[user:password@]host[:port][/vhost]` or `amqps://`.
+    #[arg(long)]
+    url: String,
+
+    /// Set a timeout for opening a connection, including the TLS and AMQP handshakes.
+    #[arg(long, default_value_t = 1000)]
+    connect_timeout_ms: u64,
+
+    /// Set a timeout for each step after connecting.
+    #[arg(long, default_value_t = 1000)]
+    timeout_ms: u64,
+
+    /// Declare a transient queue on every attempt, then publish a message to it and consume it
+    /// back.
+    #[arg(long)]
+    round_trip: bool,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    tls: TlsArgs,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    pub common: CommonArgs,
+}
+
+/// Opens a connection and a channel on every attempt, optionally making a publish and consume
+/// round trip through a transient queue.
+pub struct AmqpProbe {
+    uri: AMQPUri,
+    tls: OwnedTLSConfig,
+    round_trip: bool,
+    target: String,
+    connect_timeout: Duration,
+    timeout: Duration,
+}
+
+impl AmqpProbe {
+    pub fn new(args: &AmqpArgs) -> Self {
+        // The connection is made by lapin, which only takes extra roots and a client certificate.
+        assert!(
+            !args.tls.insecure && args.tls.sni.is_none(),
+            "--insecure and --sni are not supported by amqp"
+        );
+        let read = |path| fs::read(path).expect("unable to read tls file");
+        let tls = OwnedTLSConfig {
+            identity: args
+                .tls
+                .client_cert
+                .as_ref()
+                .zip(args.tls.client_key.as_ref())
+                .map(|(cert, key)| OwnedIdentity::PKCS8 {
+                    pem: read(cert),
+                    key: read(key),
+                }),
+            cert_chain: args
+                .tls
+                .ca_cert
+                .as_ref()
+                .map(|path| String::from_utf8(read(path)).expect("invalid ca cert")),
+        };
+
+        AmqpProbe {
+            uri: args.url.parse().expect("invalid amqp url"),
+            tls,
+            round_trip: args.round_trip,
+            target: report::redact(&args.url),
+            connect_timeout: Duration::from_millis(args.connect_timeout_ms),
+            timeout: Duration::from_millis(args.timeout_ms),
+        }
+    }
+
+    /// Runs a step of the attempt under the timeout, recording how long it took as a phase.
+    async fn step<T>(
+        &self,
+        phases: &mut Vec<(&'static str, Duration)>,
+        phase: &'static str,
+        timeout: Duration,
+        step: impl Future<Output = Result<T, ProbeError>>,
+    ) -> Result<T, ProbeError> {
+        let start = Instant::now();
+        match time::timeout(timeout, step).await {
+            Ok(Ok(value)) => {
+                phases.push((phase, start.elapsed()));
+                Ok(value)
+            }
+            Ok(Err(e)) => Err(e),
+            Err(_) => {
+                let kind = match phase {
+                    "connect" => "connect_timeout",
+                    _ => "timeout",
+                };
+                let message = format!("{} timed out after {}ms", phase, timeout.as_millis());
+                Err(ProbeError::new(kind, message))
+            }
+        }
+    }
+
+    async fn run(
+        &self,
+        conn: &mut Option<Connection>,
+        phases: &mut Vec<(&'static str, Duration)>,
+    ) -> Result<(), ProbeError> {
+        let connect = async {
+            let runtime = runtime::default_runtime().map_err(|e| ProbeError::new("connect", e))?;
+            let options = ConnectionProperties::default();
+            let tls = self.tls.clone();
+            // The socket is opened like those of other probes, restricted to the address family
+            // chosen, and handed over to lapin.
+            let connect = async move |uri: AMQPUri, _runtime| {
+                let host = &uri.authority.host;
+                let connection = resolve::connect(host, uri.authority.port).await?;
+                let stream = AsyncTcpStream::Plain(Compat::new(connection.stream));
+                Ok(match uri.scheme {
+                    AMQPScheme::AMQP => stream,
+                    AMQPScheme::AMQPS => stream.into_tls(host, tls.as_ref()).await?,
+                })
+            };
+            Connection::connector(self.uri.clone(), runtime, connect, options)
+                .await
+                .map_err(|e| ProbeError::from_cause("connect", &e))
+        };
+        let conn = &*conn.insert(
+            self.step(phases, "connect", self.connect_timeout, connect)
+                .await?,
+        );
+
+        let channel = async {
+            conn.create_channel()
+                .await
+                .map_err(|e| ProbeError::new("channel", e))
+        };
+        let channel = self.step(phases, "channel", self.timeout, channel).await?;
+
+        if !self.round_trip {
+            return Ok(());
+        }
+
+        let declare = async {
+            let options = QueueDeclareOptions {
+                exclusive: true,
+                auto_delete: true,
+                ..Default::default()
+            };
+            channel
+                .queue_declare("".into(), options, FieldTable::default())
+                .await
+                .map_err(|e| ProbeError::new("declare", e))
+        };
+        let queue = self.step(phases, "declare", self.timeout, declare).await?;
+
+        let payload = format!("artemiss canary {}", queue.name());
+        let publish = async {
+            channel
+                .basic_publish(
+                    "".into(),
+                    queue.name().clone(),
+                    BasicPublishOptions::default(),
+                    payload.as_bytes(),
+                    BasicProperties::default(),
+                )
+                .await
+                .map_err(|e| ProbeError::new("publish", e))?
+                .await
+                .map_err(|e| ProbeError::new("publish", e))
+        };
+        self.step(phases, "publish", self.timeout, publish).await?;
+
+        let consume = async {
+            let options = BasicConsumeOptions {
+                no_ack: true,
+                ..Default::default()
+            };
+            let mut consumer = channel
+                .basic_consume(
+                    queue.name().clone(),
+                    "".into(),
+                    options,
+                    FieldTable::default(),
+                )
+                .await
+                .map_err(|e| ProbeError::new("consume", e))?;
+            match consumer.next().await {
+                Some(Ok(delivery)) if delivery.data == payload.as_bytes() => Ok(()),
+                Some(Ok(_)) => Err(ProbeError::new("consume", "consumed an unexpected message")),
+                Some(Err(e)) => Err(ProbeError::new("consume", e)),
+                None => Err(ProbeError::new("consume", "consumer cancelled")),
+            }
+        };
+        self.step(phases, "consume", self.timeout, consume).await
+    }
+}
+
+impl Probe for AmqpProbe {
+    fn kind(&self) -> &'static str {
+        "amqp"
+    }
+
+    fn target(&self) -> String {
+        self.target.clone()
+    }
+
+    async fn attempt(&self, _worker: usize) -> Attempt {
+        let start = Instant::now();
+        let mut phases = vec![];
+        let mut conn = None;
+        let error = self.run(&mut conn, &mut phases).await.err();
+        let duration = start.elapsed();
+
+        // Closing is not part of the attempt, but leaves the server with a clean shutdown.
+        if let Some(conn) = conn {
+            let _ = time::timeout(self.timeout, conn.close(200, "OK".into())).await;
+        }
+
+        Attempt {
+            duration,
+            phases,
+            details: vec![],
+            error,
+        }
+    }
+}
+
+pub async fn amqp_main(args: AmqpArgs) {
+    probe::run(AmqpProbe::new(&args), &args.common).await
+}
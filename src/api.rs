@@ -0,0 +1,180 @@
+use std::{
+    collections::BTreeMap,
+    convert::Infallible,
+    net::SocketAddr,
+    sync::{LazyLock, Mutex},
+    time::{Instant, SystemTime},
+};
+
+use hyper::{
+    header::CONTENT_TYPE,
+    service::{make_service_fn, service_fn},
+    Body, Method, Request, Response, Server, StatusCode,
+};
+use log::error;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    control, metrics, probe,
+    report::{Attempt, Origin},
+    secret, stats,
+};
+
+static STARTED: LazyLock<(Instant, SystemTime)> =
+    LazyLock::new(|| (Instant::now(), SystemTime::now()));
+/// Latest state of every probe target, keyed by probe kind, name and target.
+static TARGETS: LazyLock<Mutex<BTreeMap<Key, Target>>> = LazyLock::new(Default::default);
+
+type Key = (&'static str, Option<String>, String);
+
+/// Latest state of a probe target.
+#[derive(Clone, Serialize)]
+struct Target {
+    probe: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+    target: String,
+    attempts: u64,
+    consecutive_failures: u64,
+    last_attempt_at: String,
+    last_outcome: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    last_error_kind: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    last_error_message: Option<String>,
+}
+
+/// Serves the control API, and metrics, on the address.
+pub fn serve(addr: SocketAddr) {
+    LazyLock::force(&STARTED);
+    let server = Server::try_bind(&addr)
+        .expect("error binding listen address")
+        .serve(make_service_fn(|_| async {
+            Ok::<_, Infallible>(service_fn(handle))
+        }));
+
+    tokio::spawn(async move {
+        if let Err(e) = server.await {
+            error!("api server error: {}", e);
+        }
+    });
+}
+
+/// Records the latest attempt against its target.
+pub fn observe(origin: &Origin, attempt: &Attempt) {
+    let key = (origin.probe, origin.name.clone(), origin.target.clone());
+    let mut targets = TARGETS.lock().unwrap();
+    let target = targets.entry(key).or_insert_with(|| Target {
+        probe: origin.probe,
+        name: origin.name.clone(),
+        target: origin.target.clone(),
+        attempts: 0,
+        consecutive_failures: 0,
+        last_attempt_at: String::new(),
+        last_outcome: "success",
+        last_error_kind: None,
+        last_error_message: None,
+    });
+
+    target.attempts += 1;
+    target.last_attempt_at = humantime::format_rfc3339_micros(SystemTime::now()).to_string();
+    match &attempt.error {
+        None => {
+            target.consecutive_failures = 0;
+            target.last_outcome = "success";
+        }
+        Some(e) => {
+            target.consecutive_failures += 1;
+            target.last_outcome = "failure";
+            target.last_error_kind = Some(e.kind);
+            target.last_error_message = Some(e.message.clone());
+        }
+    }
+}
+
+async fn handle(req: Request<Body>) -> Result<Response<Body>, Infallible> {
+    let res = match (req.method(), req.uri().path()) {
+        (&Method::POST, "/control") => control(req).await,
+        (&Method::GET, path) => get(path),
+        _ => status(StatusCode::METHOD_NOT_ALLOWED, ""),
+    };
+    Ok(res)
+}
+
+fn get(path: &str) -> Response<Body> {
+    match path {
+        // Unhealthy once shutting down, so that nothing waits on results that will not come.
+        "/healthz" if probe::stopping() => status(StatusCode::SERVICE_UNAVAILABLE, "stopping\n"),
+        "/healthz" => status(StatusCode::OK, "ok\n"),
+        "/status" => {
+            #[derive(Serialize)]
+            struct Status {
+                started_at: String,
+                uptime_s: u64,
+                stopping: bool,
+                summary: Vec<stats::Summary>,
+            }
+            let (started, started_at) = *STARTED;
+            json(&Status {
+                started_at: humantime::format_rfc3339_seconds(started_at).to_string(),
+                uptime_s: started.elapsed().as_secs(),
+                stopping: probe::stopping(),
+                summary: stats::summaries(),
+            })
+        }
+        "/targets" => {
+            let targets: Vec<_> = TARGETS.lock().unwrap().values().cloned().collect();
+            json(&targets)
+        }
+        "/metrics" => metrics::render(),
+        _ => status(StatusCode::NOT_FOUND, ""),
+    }
+}
+
+/// Sends the command in the body, like `{"command": "burst", "count": 20, "name": "api"}`, to the
+/// running probes it selects by `probe`, `name` and `target`, replying with the ones it was sent
+/// to.
+async fn control(req: Request<Body>) -> Response<Body> {
+    #[derive(Deserialize)]
+    struct Control {
+        #[serde(flatten)]
+        selector: control::Selector,
+        #[serde(flatten)]
+        command: control::Command,
+    }
+    #[derive(Serialize)]
+    struct Sent {
+        sent_to: Vec<control::Target>,
+    }
+
+    let body = match hyper::body::to_bytes(req.into_body()).await {
+        Ok(body) => body,
+        Err(e) => return status(StatusCode::BAD_REQUEST, format!("{}\n", e)),
+    };
+    let request: Control = match serde_json::from_slice(&body) {
+        Ok(request) => request,
+        Err(e) => return status(StatusCode::BAD_REQUEST, format!("{}\n", e)),
+    };
+    if let Err(e) = request.command.validate() {
+        return status(StatusCode::BAD_REQUEST, format!("{}\n", e));
+    }
+    let sent_to = control::send(&request.selector, request.command);
+    if sent_to.is_empty() {
+        return status(StatusCode::NOT_FOUND, "no running probe matched\n");
+    }
+    json(&Sent { sent_to })
+}
+
+fn status(code: StatusCode, body: impl Into<Body>) -> Response<Body> {
+    let mut res = Response::new(body.into());
+    *res.status_mut() = code;
+    res
+}
+
+fn json(value: &impl Serialize) -> Response<Body> {
+    let json = serde_json::to_string(value).unwrap();
+    let mut res = Response::new(Body::from(secret::redact(&json).into_owned()));
+    res.headers_mut()
+        .insert(CONTENT_TYPE, "application/json".parse().unwrap());
+    res
+}
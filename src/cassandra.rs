@@ -0,0 +1,446 @@
+use std::{
+    net::IpAddr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use clap::{Parser, ValueEnum};
+use futures_util::future;
+use scylla::{
+    client::{session::Session, session_builder::SessionBuilder, SelfIdentity},
+    cluster::{metadata::Peer, Node},
+    errors::{
+        ConnectionError, ConnectionPoolError, ConnectionSetupRequestErrorKind, DbError,
+        ExecutionError, MetadataError, NewSessionError, RequestAttemptError,
+    },
+    policies::{
+        host_filter::HostFilter,
+        load_balancing::{NodeIdentifier, SingleTargetLoadBalancingPolicy},
+    },
+    response::query_result::QueryResult,
+    statement::{unprepared::Statement, Consistency},
+};
+use serde::{Deserialize, Serialize};
+use tokio::{sync::Mutex, time};
+
+use crate::{
+    http::one_or_many,
+    probe::{self, CommonArgs, Probe},
+    report::{self, Attempt, ProbeError},
+    resolve::{self, Resolver},
+    secret, socket,
+    tls::{self, TlsArgs},
+};
+
+#[derive(Parser, Debug, Serialize, Deserialize)]
+pub struct CassandraArgs {
+    /// Nodes to discover the cluster from, as `host[:port]`, on port 9042 unless given. Can be
+    /// repeated or separated by commas.
+    #[arg(long, required = true, value_delimiter = ',')]
+    #[serde(deserialize_with = "one_or_many")]
+    contact_points: Vec<String>,
+
+    /// Run the query in this keyspace, e.g. a canary keyspace holding the table it reads.
+    #[arg(long)]
+    keyspace: Option<String>,
+
+    /// Query to run on every attempt. The default only needs the coordinator, while a read of a
+    /// table in a canary `--keyspace` also needs enough of its replicas for `--consistency`.
+    #[arg(long, default_value = "SELECT now() FROM system.local")]
+    query: String,
+
+    /// Consistency level to run the query at.
+    #[arg(long, value_enum, default_value_t)]
+    consistency: CqlConsistency,
+
+    /// Prefer coordinators in this datacenter.
+    #[arg(long)]
+    datacenter: Option<String>,
+
+    /// Run the query through every node of the cluster as its coordinator on every attempt,
+    /// rather than through the one the driver picks, reporting the latency of each as
+    /// `node_latency_ms`. The attempt fails if the query fails through any node.
+    #[arg(long)]
+    each_node: bool,
+
+    /// Authenticate as this user.
+    #[arg(long)]
+    username: Option<String>,
+
+    /// Password for `--username`.
+    #[arg(long, requires = "username")]
+    password: Option<String>,
+
+    /// Password for `--username`, read from a file or a `vault:` or `aws-sm:` source.
+    #[arg(long, requires = "username", conflicts_with = "password")]
+    password_file: Option<String>,
+
+    /// Set a timeout for only the connect phase of each connection the driver opens.
+    #[arg(long, default_value_t = 1000)]
+    connect_timeout_ms: u64,
+
+    /// Set a timeout for opening a session, including discovering the cluster, and for each
+    /// query.
+    #[arg(long, default_value_t = 2000)]
+    timeout_ms: u64,
+
+    /// Connect to the nodes over TLS. Certificates are verified against the address of each node,
+    /// as the driver connects to nodes by address.
+    #[arg(long)]
+    tls: bool,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    tls_args: TlsArgs,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    pub common: CommonArgs,
+}
+
+/// Consistency levels a query can be run at.
+#[derive(ValueEnum, Clone, Copy, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CqlConsistency {
+    One,
+    LocalOne,
+    Quorum,
+    #[default]
+    LocalQuorum,
+    EachQuorum,
+    All,
+}
+
+impl From<CqlConsistency> for Consistency {
+    fn from(consistency: CqlConsistency) -> Self {
+        match consistency {
+            CqlConsistency::One => Consistency::One,
+            CqlConsistency::LocalOne => Consistency::LocalOne,
+            CqlConsistency::Quorum => Consistency::Quorum,
+            CqlConsistency::LocalQuorum => Consistency::LocalQuorum,
+            CqlConsistency::EachQuorum => Consistency::EachQuorum,
+            CqlConsistency::All => Consistency::All,
+        }
+    }
+}
+
+/// Keeps a session with the cluster for every worker, opened on its first attempt and again after
+/// failing to, running the query through it on every attempt. The driver reconnects to nodes of
+/// the session that go down by itself.
+pub struct CassandraProbe {
+    builder: SessionBuilder,
+    /// Contact points resolved when opening each session, rather than by the driver, when
+    /// connections are restricted to an address family.
+    contact_points: Vec<String>,
+    target: String,
+    statement: Statement,
+    each_node: bool,
+    sessions: Vec<Mutex<Option<Session>>>,
+    timeout: Duration,
+}
+
+impl CassandraProbe {
+    pub fn new(args: &CassandraArgs) -> Self {
+        assert!(
+            args.tls_args.sni.is_none(),
+            "--sni is not supported by the cassandra probe, which verifies certificates against \
+             the address of each node"
+        );
+        let timeout = Duration::from_millis(args.timeout_ms);
+        let mut builder = SessionBuilder::new()
+            .connection_timeout(Duration::from_millis(args.connect_timeout_ms))
+            // Only the nodes of the cluster are needed, not its schema.
+            .fetch_schema_metadata(false)
+            .fetch_full_schema_metadata(false)
+            .custom_identity(SelfIdentity::new().with_application_name("artemiss"));
+        builder = builder.tcp_nodelay(socket::nodelay(true));
+        if let Some(time) = socket::keepalive_time() {
+            builder = builder.tcp_keepalive_interval(time);
+        }
+        builder = match resolve::is_restricted() {
+            true => builder.host_filter(Arc::new(FamilyFilter)),
+            false => builder.known_nodes(&args.contact_points),
+        };
+        if let Some(keyspace) = &args.keyspace {
+            builder = builder.use_keyspace(keyspace, false);
+        }
+        if let Some(datacenter) = &args.datacenter {
+            builder = builder.prefer_datacenter(datacenter.clone());
+        }
+        if let Some(username) = &args.username {
+            let password = match (&args.password, &args.password_file) {
+                (Some(password), _) => {
+                    secret::register(password);
+                    password.clone()
+                }
+                (_, Some(file)) => secret::read(file),
+                _ => panic!("--username requires --password or --password-file"),
+            };
+            builder = builder.user(username, password);
+        }
+        if args.tls {
+            builder = builder.tls_context(Some(Arc::new(tls::configure(&args.tls_args))));
+        }
+
+        let mut statement = Statement::new(args.query.clone());
+        statement.set_consistency(args.consistency.into());
+        statement.set_request_timeout(Some(timeout));
+
+        CassandraProbe {
+            builder,
+            contact_points: args.contact_points.clone(),
+            target: args.contact_points.join(","),
+            statement,
+            each_node: args.each_node,
+            sessions: (0..args.common.parallel)
+                .map(|_| Mutex::new(None))
+                .collect(),
+            timeout,
+        }
+    }
+
+    /// Opens a session, with the contact points resolved to the addresses of the family
+    /// connections are restricted to, if any.
+    async fn open(&self) -> Result<Session, ProbeError> {
+        let mut builder = self.builder.clone();
+        if resolve::is_restricted() {
+            let resolver = Resolver::default();
+            for point in &self.contact_points {
+                let (host, port) = contact_point(point);
+                let addrs = resolver
+                    .lookup(host, port)
+                    .await
+                    .map_err(|e| ProbeError::from_cause("dns", &e))?;
+                builder = builder.known_nodes_addr(addrs);
+            }
+        }
+        builder
+            .build()
+            .await
+            .map_err(|e| ProbeError::new(session_error_kind(&e), e))
+    }
+
+    /// Runs the query through every node as its coordinator at once, returning how long it took
+    /// through each and the first error, if any.
+    async fn each_node(
+        &self,
+        session: &Session,
+        details: &mut Vec<(&'static str, String)>,
+    ) -> Option<ProbeError> {
+        let cluster = session.get_cluster_state();
+        let queries = cluster.get_nodes_info().iter().map(|node| {
+            let mut statement = self.statement.clone();
+            statement.set_load_balancing_policy(Some(SingleTargetLoadBalancingPolicy::new(
+                NodeIdentifier::Node(node.clone()),
+                None,
+            )));
+            async move {
+                let start = Instant::now();
+                let result = session.query_unpaged(statement, ()).await;
+                (node, start.elapsed(), result)
+            }
+        });
+
+        let mut latencies = vec![];
+        let mut failed = 0;
+        let mut error = None;
+        for (node, latency, result) in future::join_all(queries).await {
+            match result {
+                Ok(_) => latencies.push(format!(
+                    "{}={:.3}",
+                    node.address,
+                    latency.as_micros() as f64 / 1000.0
+                )),
+                Err(e) => {
+                    failed += 1;
+                    let message = format!("through {}: {}", node.address, e);
+                    error.get_or_insert(ProbeError::new(error_kind(&e), message));
+                }
+            }
+        }
+        details.push(("node_latency_ms", latencies.join(",")));
+        details.push(("nodes_failed", failed.to_string()));
+        error
+    }
+}
+
+impl Probe for CassandraProbe {
+    fn kind(&self) -> &'static str {
+        "cassandra"
+    }
+
+    fn target(&self) -> String {
+        self.target.clone()
+    }
+
+    async fn attempt(&self, worker: usize) -> Attempt {
+        let mut session = self.sessions[worker % self.sessions.len()].lock().await;
+        let start = Instant::now();
+        let mut phases = vec![];
+        let mut details = vec![];
+
+        let opened = session.is_none();
+        if opened {
+            let error = match time::timeout(self.timeout, self.open()).await {
+                Ok(Ok(opened)) => {
+                    phases.push(("connect", start.elapsed()));
+                    *session = Some(opened);
+                    None
+                }
+                Ok(Err(e)) => Some(e),
+                Err(_) => Some(ProbeError::new(
+                    "connect_timeout",
+                    format!(
+                        "opening a session timed out after {}ms",
+                        self.timeout.as_millis()
+                    ),
+                )),
+            };
+            if let Some(error) = error {
+                return Attempt {
+                    duration: start.elapsed(),
+                    phases,
+                    details,
+                    error: Some(error),
+                };
+            }
+        }
+        let session = session.as_ref().expect("session opened above");
+        details.push(("session", if opened { "new" } else { "reused" }.to_string()));
+        details.extend(topology(session.get_cluster_state().get_nodes_info()));
+
+        let query = Instant::now();
+        let error = match self.each_node {
+            true => self.each_node(session, &mut details).await,
+            false => match session.query_unpaged(self.statement.clone(), ()).await {
+                Ok(result) => {
+                    details.extend(coordinator(&result));
+                    None
+                }
+                Err(e) => Some(ProbeError::new(error_kind(&e), e)),
+            },
+        };
+        phases.push(("query", query.elapsed()));
+
+        Attempt {
+            duration: start.elapsed(),
+            phases,
+            details,
+            error,
+        }
+    }
+}
+
+pub async fn cassandra_main(args: CassandraArgs) {
+    socket::ensure_supported("cassandra", &["--tcp-nodelay", "--tcp-keepalive-s"]);
+    probe::run(CassandraProbe::new(&args), &args.common).await
+}
+
+/// Accepts only the peers of the address family connections are restricted to.
+struct FamilyFilter;
+
+impl HostFilter for FamilyFilter {
+    fn accept(&self, peer: &Peer) -> bool {
+        resolve::allows(peer.address.ip())
+    }
+}
+
+/// Splits a contact point into its host and port, which is 9042 unless given.
+fn contact_point(point: &str) -> (&str, u16) {
+    if point.parse::<IpAddr>().is_ok() {
+        return (point, 9042);
+    }
+    let (host, port) = match point.rsplit_once(':') {
+        Some((host, port)) => match port.parse() {
+            Ok(port) => (host, port),
+            Err(_) => (point, 9042),
+        },
+        None => (point, 9042),
+    };
+    (host.trim_start_matches('[').trim_end_matches(']'), port)
+}
+
+/// Details of the node that coordinated a query.
+fn coordinator(result: &QueryResult) -> Vec<(&'static str, String)> {
+    let coordinator = result.request_coordinator();
+    let node = coordinator.node();
+    let mut details = vec![("coordinator", node.address.to_string())];
+    if let Some(datacenter) = &node.datacenter {
+        details.push(("coordinator_dc", datacenter.clone()));
+    }
+    if let Some(rack) = &node.rack {
+        details.push(("coordinator_rack", rack.clone()));
+    }
+    if let Some(shard) = coordinator.shard() {
+        details.push(("coordinator_shard", shard.to_string()));
+    }
+    details
+}
+
+/// How many nodes of the cluster the session knows of, and how many it has no connection to.
+fn topology(nodes: &[Arc<Node>]) -> Vec<(&'static str, String)> {
+    let down = nodes.iter().filter(|node| !node.is_connected()).count();
+    vec![
+        ("nodes", nodes.len().to_string()),
+        ("nodes_down", down.to_string()),
+    ]
+}
+
+fn session_error_kind(error: &NewSessionError) -> &'static str {
+    match error {
+        NewSessionError::FailedToResolveAnyHostname(_) => "dns",
+        NewSessionError::MetadataError(MetadataError::ConnectionPoolError(
+            ConnectionPoolError::Broken {
+                last_connection_error,
+            },
+        )) => connection_error_kind(last_connection_error),
+        NewSessionError::UseKeyspaceError(_) => "keyspace",
+        _ => "connect",
+    }
+}
+
+fn connection_error_kind(error: &ConnectionError) -> &'static str {
+    match error {
+        ConnectionError::ConnectTimeout => "connect_timeout",
+        ConnectionError::IoError(e) => report::classify("connect", &**e),
+        ConnectionError::ConnectionSetupRequestError(e) => match e.get_error() {
+            ConnectionSetupRequestErrorKind::DbError(DbError::AuthenticationError, _)
+            | ConnectionSetupRequestErrorKind::MissingAuthentication => "auth",
+            ConnectionSetupRequestErrorKind::DbError(e, _) => db_error_kind(e),
+            _ => "connect",
+        },
+        ConnectionError::UseKeyspaceError(_) => "keyspace",
+        _ => "connect",
+    }
+}
+
+/// Kind of error a query failed with, telling apart the cluster having no node to coordinate it,
+/// like the `NoHostAvailable` of other drivers, from too few replicas being available for its
+/// consistency level.
+fn error_kind(error: &ExecutionError) -> &'static str {
+    match error {
+        ExecutionError::EmptyPlan | ExecutionError::ConnectionPoolError(_) => "no_host_available",
+        ExecutionError::RequestTimeout(_) => "timeout",
+        ExecutionError::LastAttemptError(RequestAttemptError::DbError(e, _)) => db_error_kind(e),
+        ExecutionError::LastAttemptError(RequestAttemptError::BrokenConnectionError(_)) => {
+            "connection"
+        }
+        ExecutionError::UseKeyspaceError(_) => "keyspace",
+        ExecutionError::BadQuery(_) => "query",
+        _ => "cassandra",
+    }
+}
+
+fn db_error_kind(error: &DbError) -> &'static str {
+    match error {
+        DbError::Unavailable { .. } => "unavailable",
+        DbError::ReadTimeout { .. } => "read_timeout",
+        DbError::WriteTimeout { .. } => "write_timeout",
+        DbError::ReadFailure { .. } | DbError::WriteFailure { .. } => "replica_failure",
+        DbError::Overloaded | DbError::RateLimitReached { .. } => "overloaded",
+        DbError::IsBootstrapping => "bootstrapping",
+        DbError::AuthenticationError | DbError::Unauthorized => "auth",
+        DbError::SyntaxError | DbError::Invalid | DbError::ConfigError => "query",
+        _ => "cassandra",
+    }
+}
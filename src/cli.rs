@@ -0,0 +1,304 @@
+use std::time::Duration;
+
+use clap::{Args, CommandFactory, FromArgMatches, Parser, Subcommand};
+use figment::{
+    providers::{Env, Serialized},
+    Figment,
+};
+use log::{error, warn};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use tokio::time;
+
+use crate::{
+    agent, alert, amqp, cassandra, compare, config, db, dns, es, ftp, grpc, http, kafka, ldap,
+    limit, mongo, mqtt, ntp, pcap, ping, probe, redis, registry, report, resolve, s3, service,
+    sftp, smtp, socket, ssh, stats, store, tcp, tls, tui, udp, ws,
+};
+
+#[derive(Parser, Debug)]
+pub(crate) struct Cli {
+    #[command(subcommand)]
+    pub command: Commands,
+
+    #[command(flatten)]
+    report: report::ReportArgs,
+
+    #[command(flatten)]
+    global: GlobalArgs,
+
+    #[command(flatten)]
+    family: resolve::FamilyArgs,
+
+    #[command(flatten)]
+    socket: socket::SocketArgs,
+
+    #[command(flatten)]
+    limit: limit::LimitArgs,
+
+    #[command(flatten)]
+    alert: alert::AlertArgs,
+
+    #[command(flatten)]
+    pcap: pcap::PcapArgs,
+
+    #[command(flatten)]
+    agent: agent::AgentArgs,
+
+    #[command(flatten)]
+    service: service::ServiceArgs,
+}
+
+/// Options applying to the whole run.
+#[derive(Args, Debug, Serialize, Deserialize)]
+struct GlobalArgs {
+    /// Time to wait for in-flight attempts to finish after a shutdown signal before exiting.
+    #[arg(long, global = true, default_value_t = 5000)]
+    shutdown_grace_ms: u64,
+
+    /// Exit with a failure status if the percentage of failed attempts against any target
+    /// exceeds this, e.g. `1` for 1%. Runs bounded by `--count` or `--duration-s` default to
+    /// failing on any failed attempt, while unbounded ones are only checked once this is given.
+    #[arg(long, global = true)]
+    fail_threshold: Option<f64>,
+
+    /// Exit with a failure status unless every target had at least this many successful attempts.
+    #[arg(long, global = true, default_value_t = 0)]
+    min_success: u64,
+}
+
+// Parsed once per probe, so the size of the variants does not matter.
+#[allow(clippy::large_enum_variant)]
+#[derive(Subcommand, Debug)]
+pub(crate) enum Commands {
+    /// Start HTTP.
+    Http(http::HttpArgs),
+    /// Start DB.
+    Db(db::DbArgs),
+    /// Start TCP.
+    Tcp(tcp::TcpArgs),
+    /// Start DNS.
+    Dns(dns::DnsArgs),
+    /// Start Redis.
+    Redis(redis::RedisArgs),
+    /// Start gRPC.
+    Grpc(grpc::GrpcArgs),
+    /// Start WebSocket.
+    Ws(ws::WsArgs),
+    /// Start ICMP ping.
+    Ping(ping::PingArgs),
+    /// Start Kafka.
+    Kafka(kafka::KafkaArgs),
+    /// Start AMQP.
+    Amqp(amqp::AmqpArgs),
+    /// Start SMTP.
+    Smtp(smtp::SmtpArgs),
+    /// Start TLS handshakes.
+    Tls(tls::HandshakeArgs),
+    /// Start MongoDB.
+    Mongo(mongo::MongoArgs),
+    /// Start MQTT.
+    Mqtt(mqtt::MqttArgs),
+    /// Start UDP.
+    Udp(udp::UdpArgs),
+    /// Start SSH.
+    Ssh(ssh::SshArgs),
+    /// Start LDAP binds.
+    Ldap(ldap::LdapArgs),
+    /// Start NTP queries.
+    Ntp(ntp::NtpArgs),
+    /// Start Elasticsearch or OpenSearch cluster health checks.
+    Es(es::EsArgs),
+    /// Start S3 bucket requests.
+    S3(s3::S3Args),
+    /// Start multi-step HTTP transactions.
+    Scenario(http::ScenarioArgs),
+    /// Start HTTP/2 PINGs over connections kept open.
+    H2ping(http::H2PingArgs),
+    /// Re-issue a request recorded by an HTTP probe with `--record`.
+    Replay(http::ReplayArgs),
+    /// Collect the results agents push with `--collector`, and report them by location.
+    Collector(agent::CollectorArgs),
+    /// Summarise the attempts written to `--store` in past runs, with a timeline of their errors.
+    Report(store::QueryArgs),
+    /// Compare the latency and error rate of every target between two runs, exiting with a failure
+    /// status if any regressed.
+    Compare(compare::CompareArgs),
+    /// Start Cassandra or ScyllaDB.
+    Cassandra(cassandra::CassandraArgs),
+    /// Start FTP or FTPS transfers.
+    Ftp(ftp::FtpArgs),
+    /// Start SFTP transfers.
+    Sftp(sftp::SftpArgs),
+    /// Start the probes defined in a config file.
+    Run(config::RunArgs),
+    /// Start a probe added with `registry::register`, given its subcommand and options.
+    #[command(external_subcommand)]
+    Custom(Vec<String>),
+}
+
+/// Runs the command line interface, exiting the process when done.
+pub async fn main() {
+    let matches = registry::augment(Cli::command()).get_matches();
+    let args = Cli::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+
+    let mut report_args = extract_config(args.report);
+    // Reporting on a store reads it rather than writing this run to it.
+    let query_store = match args.command {
+        Commands::Report(_) => Some(report_args.take_store().expect("report needs --store")),
+        _ => None,
+    };
+    report::init(report_args);
+    let global = extract_config(args.global);
+    resolve::init(extract_config(args.family));
+    socket::init(extract_config(args.socket));
+    limit::init(extract_config(args.limit));
+    alert::init(extract_config(args.alert));
+    pcap::init(extract_config(args.pcap));
+    agent::init(extract_config(args.agent));
+    service::init(extract_config(args.service));
+
+    let run = async {
+        match args.command {
+            Commands::Http(args) => http::http_main(extract_config(args)).await,
+            Commands::Db(args) => db::db_main(extract_config(args)).await,
+            Commands::Tcp(args) => tcp::tcp_main(extract_config(args)).await,
+            Commands::Dns(args) => dns::dns_main(extract_config(args)).await,
+            Commands::Redis(args) => redis::redis_main(extract_config(args)).await,
+            Commands::Grpc(args) => grpc::grpc_main(extract_config(args)).await,
+            Commands::Ws(args) => ws::ws_main(extract_config(args)).await,
+            Commands::Ping(args) => ping::ping_main(extract_config(args)).await,
+            Commands::Kafka(args) => kafka::kafka_main(extract_config(args)).await,
+            Commands::Amqp(args) => amqp::amqp_main(extract_config(args)).await,
+            Commands::Smtp(args) => smtp::smtp_main(extract_config(args)).await,
+            Commands::Tls(args) => tls::tls_main(extract_config(args)).await,
+            Commands::Mongo(args) => mongo::mongo_main(extract_config(args)).await,
+            Commands::Mqtt(args) => mqtt::mqtt_main(extract_config(args)).await,
+            Commands::Udp(args) => udp::udp_main(extract_config(args)).await,
+            Commands::Ssh(args) => ssh::ssh_main(extract_config(args)).await,
+            Commands::Ldap(args) => ldap::ldap_main(extract_config(args)).await,
+            Commands::Ntp(args) => ntp::ntp_main(extract_config(args)).await,
+            Commands::Es(args) => es::es_main(extract_config(args)).await,
+            Commands::S3(args) => s3::s3_main(extract_config(args)).await,
+            Commands::Scenario(args) => http::scenario_main(extract_config(args)).await,
+            Commands::H2ping(args) => http::h2ping_main(extract_config(args)).await,
+            Commands::Replay(args) => http::replay_main(extract_config(args)).await,
+            Commands::Collector(args) => agent::collector_main(extract_config(args)).await,
+            Commands::Report(args) => {
+                store::report_main(query_store.as_deref().unwrap(), extract_config(args))
+            }
+            Commands::Compare(args) => compare::compare_main(extract_config(args)),
+            Commands::Cassandra(args) => cassandra::cassandra_main(extract_config(args)).await,
+            Commands::Ftp(args) => ftp::ftp_main(extract_config(args)).await,
+            Commands::Sftp(args) => sftp::sftp_main(extract_config(args)).await,
+            Commands::Run(args) => config::run_main(extract_config(args)).await,
+            Commands::Custom(argv) => {
+                registry::parse(&argv, true)
+                    .unwrap_or_else(|e| e.exit())
+                    .await
+            }
+        }
+    };
+    tokio::pin!(run);
+    service::ready();
+
+    tokio::select! {
+        _ = &mut run => {}
+        _ = shutdown_signal() => {
+            probe::shutdown();
+
+            let grace = Duration::from_millis(global.shutdown_grace_ms);
+            service::stopping(grace);
+            tokio::select! {
+                result = time::timeout(grace, &mut run) => if result.is_err() {
+                    warn!("in-flight attempts did not finish within {}ms", grace.as_millis());
+                },
+                _ = shutdown_signal() => warn!("shutting down without waiting for in-flight attempts"),
+            }
+        }
+    }
+
+    let summaries = stats::summaries();
+    report::summary(&summaries);
+    report::flush().await;
+
+    let breaches = breaches(&summaries, &global);
+    for breach in &breaches {
+        error!("{}", breach);
+    }
+
+    // Exit directly rather than waiting on blocking tasks that may still be stuck in a probe.
+    service::stopped();
+    std::process::exit(if breaches.is_empty() { 0 } else { 1 });
+}
+
+/// Describes every way the run fell short of `--fail-threshold` and `--min-success`.
+fn breaches(summaries: &[stats::Summary], global: &GlobalArgs) -> Vec<String> {
+    let mut breaches = vec![];
+    // A daemon stopped after running for months is not failing a check.
+    let fail_threshold = global
+        .fail_threshold
+        .or_else(|| probe::bounded().then_some(0.0));
+    if summaries.is_empty() && global.min_success > 0 {
+        breaches.push("no attempts were made".to_string());
+    }
+
+    for summary in summaries {
+        let target = match &summary.name {
+            Some(name) => format!("{} {} {}", summary.probe, name, summary.target),
+            None => format!("{} {}", summary.probe, summary.target),
+        };
+        let error_rate = 100.0 - summary.success_rate;
+        if let Some(threshold) = fail_threshold {
+            if error_rate > threshold {
+                breaches.push(format!(
+                    "{}: error rate {:.2}% exceeds --fail-threshold {}%",
+                    target, error_rate, threshold
+                ));
+            }
+        }
+        let successes = summary.attempts - summary.failures;
+        if successes < global.min_success {
+            breaches.push(format!(
+                "{}: {} successful attempts is below --min-success {}",
+                target, successes, global.min_success
+            ));
+        }
+    }
+    breaches
+}
+
+/// Completes when the process is asked to stop, by Ctrl-C, SIGTERM, quitting the dashboard or the
+/// service manager.
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut terminate =
+            signal(SignalKind::terminate()).expect("error installing signal handler");
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {}
+            _ = terminate.recv() => {}
+            _ = tui::quit() => {}
+            _ = service::stop_requested() => {}
+        }
+    }
+
+    #[cfg(not(unix))]
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {}
+        _ = tui::quit() => {}
+        _ = service::stop_requested() => {}
+    }
+}
+
+/// Merges `ARTEMISS_` environment variables over the parsed command line arguments.
+pub(crate) fn extract_config<T: Serialize + DeserializeOwned>(args: T) -> T {
+    dotenvy::dotenv().ok();
+
+    Figment::new()
+        .merge(Env::prefixed("ARTEMISS_")) // Environment variables take precedence.
+        .join(Serialized::defaults(args))
+        .extract()
+        .expect("error parsing environment for config")
+}
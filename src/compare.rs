@@ -0,0 +1,211 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Write,
+    fs::File,
+    io::{BufRead, BufReader, Read},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use clap::Parser;
+use log::error;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{
+    stats::Latencies,
+    store::{self, Key},
+};
+
+/// Header every SQLite database starts with.
+const SQLITE_HEADER: &[u8] = b"SQLite format 3\0";
+
+#[derive(Parser, Debug, Serialize, Deserialize)]
+pub struct CompareArgs {
+    /// Results of the run to compare against, either the JSON it printed with `--output json` or
+    /// a `--store` it wrote to.
+    baseline: PathBuf,
+
+    /// Results of the run to compare with the baseline, in either form.
+    candidate: PathBuf,
+
+    /// Count a target as regressed if its p50, p90 or p99 latency grew by more than this
+    /// percentage.
+    #[arg(long, default_value_t = 10.0)]
+    latency_threshold_pct: f64,
+
+    /// Count a target as regressed if its error rate grew by more than this many percentage
+    /// points.
+    #[arg(long, default_value_t = 1.0)]
+    error_rate_threshold: f64,
+
+    /// Only compare targets of this probe, e.g. `http`.
+    #[arg(long)]
+    probe: Option<String>,
+
+    /// Only compare targets containing this.
+    #[arg(long)]
+    target: Option<String>,
+}
+
+/// Attempts made against a target in one run.
+#[derive(Default)]
+struct Run {
+    attempts: u64,
+    failures: u64,
+    latencies: Latencies,
+}
+
+impl Run {
+    fn record(&mut self, latency_ms: f64, failed: bool) {
+        self.attempts += 1;
+        match failed {
+            true => self.failures += 1,
+            false => self
+                .latencies
+                .record(Duration::from_secs_f64(latency_ms.max(0.0) / 1000.0)),
+        }
+    }
+
+    fn error_rate(&self) -> f64 {
+        self.failures as f64 * 100.0 / self.attempts as f64
+    }
+}
+
+/// Prints how the latency and error rate of every target changed from the baseline to the
+/// candidate, exiting with a failure status if any regressed past the thresholds.
+pub fn compare_main(args: CompareArgs) {
+    let baseline = load(&args.baseline, &args);
+    let candidate = load(&args.candidate, &args);
+
+    println!(
+        "--- artemiss compare {} to {} ---",
+        args.baseline.display(),
+        args.candidate.display()
+    );
+    let keys: BTreeSet<_> = baseline.keys().chain(candidate.keys()).collect();
+    if keys.is_empty() {
+        println!("no attempts found");
+    }
+    let mut regressed = 0;
+    for key in keys {
+        let (probe, name, target) = key;
+        let mut line = format!("{} ", probe);
+        if let Some(name) = name {
+            let _ = write!(line, "{} ", name);
+        }
+        let _ = write!(line, "{}:", target);
+        let (before, after) = match (baseline.get(key), candidate.get(key)) {
+            (Some(before), Some(after)) => (before, after),
+            (Some(_), None) => {
+                println!("{} only in baseline", line);
+                continue;
+            }
+            _ => {
+                println!("{} only in candidate", line);
+                continue;
+            }
+        };
+
+        let mut regressions = vec![];
+        let _ = write!(line, " attempts={}->{}", before.attempts, after.attempts);
+        let change = after.error_rate() - before.error_rate();
+        let _ = write!(
+            line,
+            " errors={:.2}%->{:.2}% ({:+.2}pp)",
+            before.error_rate(),
+            after.error_rate(),
+            change
+        );
+        if change > args.error_rate_threshold {
+            regressions.push("error_rate");
+        }
+        if let (Some(before), Some(after)) = (
+            before.latencies.percentiles(),
+            after.latencies.percentiles(),
+        ) {
+            let quantiles = [
+                ("p50", before.p50, after.p50),
+                ("p90", before.p90, after.p90),
+                ("p99", before.p99, after.p99),
+            ];
+            for (quantile, before, after) in quantiles {
+                let change = (after - before) * 100.0 / before;
+                let _ = write!(
+                    line,
+                    " {}={:.3}ms->{:.3}ms ({:+.1}%)",
+                    quantile, before, after, change
+                );
+                if change > args.latency_threshold_pct {
+                    regressions.push(quantile);
+                }
+            }
+        }
+        if !regressions.is_empty() {
+            regressed += 1;
+            let _ = write!(line, " REGRESSED {}", regressions.join(","));
+        }
+        println!("{}", line);
+    }
+
+    if regressed > 0 {
+        error!(
+            "{} targets regressed past --latency-threshold-pct {}% or --error-rate-threshold {}pp",
+            regressed, args.latency_threshold_pct, args.error_rate_threshold
+        );
+        std::process::exit(1);
+    }
+}
+
+/// Reads the attempts of a run, from a store or the JSON it printed, by target.
+fn load(path: &Path, args: &CompareArgs) -> BTreeMap<Key, Run> {
+    let mut header = [0; SQLITE_HEADER.len()];
+    let mut file =
+        File::open(path).unwrap_or_else(|e| panic!("unable to open {}: {}", path.display(), e));
+    let is_store = file.read_exact(&mut header).is_ok() && header == SQLITE_HEADER;
+
+    let mut runs: BTreeMap<Key, Run> = BTreeMap::new();
+    let mut record = |key: Key, latency_ms: f64, failed: bool| {
+        let (probe, _, target) = &key;
+        let probe_matches = args.probe.as_ref().is_none_or(|p| p == probe);
+        let target_matches = args.target.as_ref().is_none_or(|t| target.contains(t));
+        if probe_matches && target_matches {
+            runs.entry(key).or_default().record(latency_ms, failed);
+        }
+    };
+    if is_store {
+        store::attempts(path, |key, latency_ms, error_kind| {
+            record(key, latency_ms, error_kind.is_some())
+        });
+        return runs;
+    }
+
+    let file =
+        File::open(path).unwrap_or_else(|e| panic!("unable to open {}: {}", path.display(), e));
+    for line in BufReader::new(file).lines() {
+        let line = line.unwrap_or_else(|e| panic!("unable to read {}: {}", path.display(), e));
+        // Lines besides attempts, like periodic summaries and log lines, are skipped.
+        let Ok(Value::Object(attempt)) = serde_json::from_str(&line) else {
+            continue;
+        };
+        let (Some(probe), Some(target), Some(outcome), Some(latency_ms)) = (
+            attempt.get("probe").and_then(Value::as_str),
+            attempt.get("target").and_then(Value::as_str),
+            attempt.get("outcome").and_then(Value::as_str),
+            attempt.get("latency_ms").and_then(Value::as_f64),
+        ) else {
+            continue;
+        };
+        if attempt.get("warmup").and_then(Value::as_bool) == Some(true) {
+            continue;
+        }
+        let name = attempt.get("name").and_then(Value::as_str);
+        let key = (
+            probe.to_string(),
+            name.map(str::to_string),
+            target.to_string(),
+        );
+        record(key, latency_ms, outcome != "success");
+    }
+    runs
+}
@@ -0,0 +1,270 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use clap::Parser;
+use figment::{
+    providers::{Format, Toml, Yaml},
+    Figment,
+};
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use tokio::{task::JoinSet, time};
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    amqp, cassandra,
+    cli::{Cli, Commands},
+    db, dns, es, ftp, grpc, http, kafka, ldap, mongo, mqtt, ntp, ping, probe, redis, registry, s3,
+    service, sftp, smtp, ssh, tcp, tls, udp, ws,
+};
+
+/// How often the config file is checked for changes with `--watch`.
+const WATCH_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Parser, Debug, Serialize, Deserialize)]
+pub struct RunArgs {
+    /// File defining the probes to run, in TOML or YAML.
+    /// Each probe has a `type` naming its subcommand and keys matching the subcommand's options.
+    /// The file is reloaded on `SIGHUP`.
+    #[arg(long)]
+    config: PathBuf,
+
+    /// Reload the config file whenever it changes. Probes that were added or changed are started,
+    /// and probes that were removed or changed are stopped, while the rest keep running.
+    #[arg(long)]
+    watch: bool,
+}
+
+#[derive(Deserialize)]
+struct Config {
+    probes: Vec<Map<String, Value>>,
+}
+
+pub async fn run_main(args: RunArgs) {
+    let config = load(&args.config).expect("error parsing probe config");
+    for (index, probe) in config.probes.iter().enumerate() {
+        if let Err(e) = parse(probe.clone()) {
+            panic!("invalid probe {}: {}", index, e);
+        }
+    }
+
+    let mut probes = JoinSet::new();
+    let mut running = vec![];
+    for probe in config.probes {
+        running.push(start(&mut probes, probe));
+    }
+
+    let mut reloads = Reloads::new(&args);
+    loop {
+        tokio::select! {
+            joined = probes.join_next() => if joined.is_none() {
+                break;
+            },
+            _ = reloads.next() => {
+                service::reloading();
+                match load(&args.config) {
+                    Ok(config) => running = reload(&mut probes, running, config),
+                    Err(e) => error!("not reloading {}: {}", args.config.display(), e),
+                }
+                service::ready();
+            }
+        }
+    }
+}
+
+/// Reads the probe definitions in the config file.
+fn load(path: &Path) -> Result<Config, Box<figment::Error>> {
+    let figment = match path.extension().and_then(|ext| ext.to_str()) {
+        Some("yaml" | "yml") => Figment::from(Yaml::file(path)),
+        _ => Figment::from(Toml::file(path)),
+    };
+    figment.extract().map_err(Box::new)
+}
+
+/// A probe started from its definition, and the token that stops it.
+type Running = (Map<String, Value>, CancellationToken);
+
+/// Starts the probe in its own scope, so that it can be stopped on its own.
+fn start(probes: &mut JoinSet<()>, probe: Map<String, Value>) -> Running {
+    let stop = probe::scope().child_token();
+    // Definitions are checked before they are started.
+    let command = parse(probe.clone()).expect("invalid probe");
+    probes.spawn(probe::scoped(stop.clone(), run(command)));
+    (probe, stop)
+}
+
+/// Stops the probes that are no longer defined and starts the ones newly defined, leaving the
+/// probes defined exactly as before running. Statistics are kept by target, so carry on for
+/// probes that were changed but still probe the same target.
+fn reload(probes: &mut JoinSet<()>, running: Vec<Running>, config: Config) -> Vec<Running> {
+    for (index, probe) in config.probes.iter().enumerate() {
+        if let Err(e) = parse(probe.clone()) {
+            error!("not reloading, invalid probe {}: {}", index, e);
+            return running;
+        }
+    }
+
+    let mut old = running;
+    let mut new = vec![];
+    let mut started = 0;
+    for probe in config.probes {
+        match old.iter().position(|(definition, _)| *definition == probe) {
+            Some(position) => new.push(old.swap_remove(position)),
+            None => {
+                new.push(start(probes, probe));
+                started += 1;
+            }
+        }
+    }
+    for (_, stop) in &old {
+        stop.cancel();
+    }
+
+    info!(
+        "reloaded probe config: {} started, {} stopped, {} unchanged",
+        started,
+        old.len(),
+        new.len() - started
+    );
+    new
+}
+
+/// Runs the probe main for a command.
+async fn run(command: Commands) {
+    match command {
+        Commands::Http(args) => http::http_main(args).await,
+        Commands::Db(args) => db::db_main(args).await,
+        Commands::Tcp(args) => tcp::tcp_main(args).await,
+        Commands::Dns(args) => dns::dns_main(args).await,
+        Commands::Redis(args) => redis::redis_main(args).await,
+        Commands::Grpc(args) => grpc::grpc_main(args).await,
+        Commands::Ws(args) => ws::ws_main(args).await,
+        Commands::Ping(args) => ping::ping_main(args).await,
+        Commands::Kafka(args) => kafka::kafka_main(args).await,
+        Commands::Amqp(args) => amqp::amqp_main(args).await,
+        Commands::Smtp(args) => smtp::smtp_main(args).await,
+        Commands::Tls(args) => tls::tls_main(args).await,
+        Commands::Mongo(args) => mongo::mongo_main(args).await,
+        Commands::Mqtt(args) => mqtt::mqtt_main(args).await,
+        Commands::Udp(args) => udp::udp_main(args).await,
+        Commands::Ssh(args) => ssh::ssh_main(args).await,
+        Commands::Ldap(args) => ldap::ldap_main(args).await,
+        Commands::Ntp(args) => ntp::ntp_main(args).await,
+        Commands::Es(args) => es::es_main(args).await,
+        Commands::S3(args) => s3::s3_main(args).await,
+        Commands::Scenario(args) => http::scenario_main(args).await,
+        Commands::H2ping(args) => http::h2ping_main(args).await,
+        Commands::Cassandra(args) => cassandra::cassandra_main(args).await,
+        Commands::Ftp(args) => ftp::ftp_main(args).await,
+        Commands::Sftp(args) => sftp::sftp_main(args).await,
+        Commands::Replay(_)
+        | Commands::Collector(_)
+        | Commands::Report(_)
+        | Commands::Compare(_)
+        | Commands::Run(_) => unreachable!("only probes are parsed from a config file"),
+        Commands::Custom(argv) => registry::parse(&argv, false).expect("invalid probe").await,
+    }
+}
+
+/// Signals that the config file should be reloaded, on `SIGHUP` and, with `--watch`, when the file
+/// is modified.
+struct Reloads {
+    path: PathBuf,
+    watch: bool,
+    modified: Option<SystemTime>,
+    #[cfg(unix)]
+    hangup: tokio::signal::unix::Signal,
+}
+
+impl Reloads {
+    fn new(args: &RunArgs) -> Self {
+        Reloads {
+            path: args.config.clone(),
+            watch: args.watch,
+            modified: modified(&args.config),
+            #[cfg(unix)]
+            hangup: tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())
+                .expect("error installing signal handler"),
+        }
+    }
+
+    async fn next(&mut self) {
+        let changed = async {
+            if !self.watch {
+                return std::future::pending().await;
+            }
+            loop {
+                time::sleep(WATCH_INTERVAL).await;
+                let modified = modified(&self.path);
+                if modified != self.modified {
+                    self.modified = modified;
+                    return;
+                }
+            }
+        };
+
+        #[cfg(unix)]
+        tokio::select! {
+            _ = self.hangup.recv() => {}
+            _ = changed => {}
+        }
+        #[cfg(not(unix))]
+        changed.await
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// Turns a probe definition into its equivalent command line, so that it is validated and
+/// defaulted exactly like the subcommand would be.
+fn parse(mut probe: Map<String, Value>) -> Result<Commands, String> {
+    let kind = match probe.remove("type") {
+        Some(Value::String(kind)) => kind,
+        _ => return Err("missing type".to_string()),
+    };
+    match kind.as_str() {
+        "run" => return Err("run cannot be nested".to_string()),
+        "replay" | "collector" | "report" | "compare" => {
+            return Err(format!("{} cannot be run from a config file", kind))
+        }
+        _ => {}
+    }
+
+    let mut argv = vec!["artemiss".to_string(), kind];
+    for (key, value) in probe {
+        let flag = format!("--{}", key.replace('_', "-"));
+        let values = match value {
+            Value::Array(values) => values,
+            value => vec![value],
+        };
+
+        for value in values {
+            match value {
+                Value::Bool(true) => argv.push(flag.clone()),
+                Value::Bool(false) | Value::Null => {}
+                Value::String(value) => argv.extend([flag.clone(), value]),
+                Value::Number(value) => argv.extend([flag.clone(), value.to_string()]),
+                // Structured values, like the steps of a scenario, are passed on as JSON.
+                Value::Object(value) => {
+                    argv.extend([flag.clone(), Value::Object(value).to_string()])
+                }
+                _ => return Err(format!("unsupported value for {}", key)),
+            }
+        }
+    }
+
+    let command = Cli::try_parse_from(argv)
+        .map(|cli| cli.command)
+        .map_err(|e| e.to_string())?;
+    // Options of registered probes are only parsed once the subcommand is found to be one.
+    if let Commands::Custom(argv) = &command {
+        drop(registry::parse(argv, false).map_err(|e| e.to_string())?);
+    }
+    Ok(command)
+}
@@ -0,0 +1,162 @@
+use std::{
+    sync::{LazyLock, Mutex},
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+
+use crate::probe::{MAX_PERIOD, MIN_PERIOD};
+
+/// Most workers a probe can be told to run.
+const MAX_PARALLEL: usize = 10_000;
+
+/// Most attempts a probe can be told to make at once.
+const MAX_BURST: u64 = 10_000;
+
+/// Probes running in the process, to send commands to.
+static RUNNING: LazyLock<Mutex<Vec<Running>>> = LazyLock::new(Default::default);
+
+/// A probe running in the process, identified the way its output names it.
+struct Running {
+    target: Target,
+    commands: mpsc::UnboundedSender<Command>,
+}
+
+/// Probe a command was sent to.
+#[derive(Clone, Debug, Serialize)]
+pub(crate) struct Target {
+    probe: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+    target: String,
+}
+
+/// Which probes to send a command to. Every probe running matches when nothing is given.
+#[derive(Debug, Default, Deserialize)]
+pub(crate) struct Selector {
+    probe: Option<String>,
+    name: Option<String>,
+    target: Option<String>,
+}
+
+/// Changes to how a running probe makes its attempts.
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub(crate) enum Command {
+    /// Stop making attempts on schedule until resumed.
+    Pause,
+    /// Make attempts on schedule again.
+    Resume,
+    /// Change the time between ticks, given as a period or as a rate, from 1ms to a day. With
+    /// `--rps`, this is the time between the ticks shared by every worker.
+    Interval {
+        interval_ms: Option<u64>,
+        rps: Option<f64>,
+    },
+    /// Run this many workers, up to 10000, starting or stopping workers to get there.
+    Parallel { parallel: usize },
+    /// Make this many attempts at once right away, up to 10000, besides the attempts on schedule.
+    Burst { count: u64 },
+}
+
+impl Command {
+    /// Checks the command can be carried out, returning why not otherwise.
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        match *self {
+            Command::Interval {
+                interval_ms: None,
+                rps: None,
+            }
+            | Command::Interval {
+                interval_ms: Some(_),
+                rps: Some(_),
+            } => Err("interval takes one of interval_ms or rps".to_string()),
+            Command::Interval {
+                interval_ms: Some(ms),
+                ..
+            } if !(MIN_PERIOD.as_millis()..=MAX_PERIOD.as_millis()).contains(&ms.into()) => {
+                Err(format!(
+                    "interval_ms must be between {} and {}",
+                    MIN_PERIOD.as_millis(),
+                    MAX_PERIOD.as_millis()
+                ))
+            }
+            Command::Interval { rps: Some(rps), .. }
+                if !(1.0 / MAX_PERIOD.as_secs_f64()..=1.0 / MIN_PERIOD.as_secs_f64())
+                    .contains(&rps) =>
+            {
+                Err(format!(
+                    "rps must be between 1/{} and {}",
+                    MAX_PERIOD.as_secs(),
+                    1.0 / MIN_PERIOD.as_secs_f64()
+                ))
+            }
+            Command::Parallel { parallel } if !(1..=MAX_PARALLEL).contains(&parallel) => {
+                Err(format!("parallel must be between 1 and {}", MAX_PARALLEL))
+            }
+            Command::Burst { count } if !(1..=MAX_BURST).contains(&count) => {
+                Err(format!("count must be between 1 and {}", MAX_BURST))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// New time between ticks, for an interval command that is valid.
+    pub(crate) fn period(&self) -> Option<Duration> {
+        match *self {
+            Command::Interval {
+                interval_ms: Some(ms),
+                ..
+            } => Some(Duration::from_millis(ms)),
+            Command::Interval { rps: Some(rps), .. } => {
+                Some(Duration::from_secs_f64(1.0 / rps).clamp(MIN_PERIOD, MAX_PERIOD))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Registers a probe that is starting, returning the commands sent to it. It is forgotten once
+/// the receiver is dropped.
+pub(crate) fn register(
+    probe: &'static str,
+    name: Option<String>,
+    target: String,
+) -> mpsc::UnboundedReceiver<Command> {
+    let (commands, receiver) = mpsc::unbounded_channel();
+    let target = Target {
+        probe,
+        name,
+        target,
+    };
+    let mut running = RUNNING.lock().unwrap();
+    running.retain(|running| !running.commands.is_closed());
+    running.push(Running { target, commands });
+    receiver
+}
+
+/// Sends a command to every running probe the selector matches, returning the ones it was sent
+/// to.
+pub(crate) fn send(selector: &Selector, command: Command) -> Vec<Target> {
+    let mut running = RUNNING.lock().unwrap();
+    running.retain(|running| !running.commands.is_closed());
+    running
+        .iter()
+        .filter(|running| selector.matches(&running.target))
+        .filter(|running| running.commands.send(command).is_ok())
+        .map(|running| running.target.clone())
+        .collect()
+}
+
+impl Selector {
+    fn matches(&self, target: &Target) -> bool {
+        let probe = self
+            .probe
+            .as_ref()
+            .is_none_or(|probe| probe == target.probe);
+        let name = (self.name.as_ref()).is_none_or(|name| target.name.as_ref() == Some(name));
+        let addr = (self.target.as_ref()).is_none_or(|addr| *addr == target.target);
+        probe && name && addr
+    }
+}
@@ -0,0 +1,455 @@
+mod mysql;
+mod pool;
+mod postgres;
+
+use std::{
+    error::Error,
+    fmt,
+    future::Future,
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::{atomic::AtomicU64, Arc},
+    time::{Duration, Instant},
+};
+
+use clap::{Parser, ValueEnum};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use tokio::{sync, time};
+
+use crate::{
+    ab,
+    probe::{self, CommonArgs, Probe},
+    report::{self, Attempt, ProbeError},
+    secret,
+    template::{Template, Vars},
+};
+use pool::{Checkout, Pool};
+
+pub type BoxError = Box<dyn Error + Send + Sync>;
+
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// A database the probe can open connections to.
+pub trait Database: Send + Sync {
+    /// Opens a new connection to the database.
+    fn connect(&self) -> BoxFuture<'_, Result<Box<dyn DbConnection>, BoxError>>;
+}
+
+/// An open connection to a database.
+pub trait DbConnection: Send {
+    /// Runs a query, returning the number of rows it returned or affected.
+    fn query<'a>(&'a mut self, sql: &'a str) -> BoxFuture<'a, Result<u64, BoxError>>;
+}
+
+/// Error returned when a query does not complete within the query timeout.
+#[derive(Debug)]
+pub struct QueryTimeout(Duration);
+
+impl fmt::Display for QueryTimeout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "query timed out after {}ms", self.0.as_millis())
+    }
+}
+
+impl Error for QueryTimeout {}
+
+/// Error returned when the database itself rejects a new connection, rather than the network.
+#[derive(Debug)]
+pub struct Rejected {
+    /// Either `db_auth` for bad credentials or `db_handshake` for any other reason.
+    pub kind: &'static str,
+    pub message: String,
+}
+
+impl fmt::Display for Rejected {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl Error for Rejected {}
+
+/// Error returned when the session a query was sent over ended, rather than the query failing
+/// within it.
+#[derive(Debug)]
+pub struct Ended {
+    /// `session_closed` for a connection that was lost, `session_timeout` for a session the server
+    /// ended for being idle, `session_terminated` for one it killed or shut down, or `read_only`
+    /// for a server that became a replica, as after a failover.
+    pub kind: &'static str,
+    pub message: String,
+}
+
+impl fmt::Display for Ended {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl Error for Ended {}
+
+#[derive(ValueEnum, Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Driver {
+    Mysql,
+    Postgres,
+}
+
+impl Driver {
+    /// Picks the driver matching the scheme of a connection string.
+    fn from_url(url: &str) -> Option<Self> {
+        let (scheme, _) = url.split_once("://")?;
+        match scheme {
+            "mysql" => Some(Driver::Mysql),
+            "postgres" | "postgresql" => Some(Driver::Postgres),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Driver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Driver::Mysql => write!(f, "mysql"),
+            Driver::Postgres => write!(f, "postgres"),
+        }
+    }
+}
+
+#[derive(Parser, Debug, Serialize, Deserialize)]
+pub struct DbArgs {
+    /// Set a timeout for only the connect phase of a connection.
+    #[arg(long, default_value_t = 15)]
+    connect_timeout_ms: u64,
+
+    /// Database connection string to connect to.
+    /// DATABASE_URL environment variable used by default.
+    #[arg(long)]
+    database_url: Option<String>,
+
+    /// Connect with the password in this file rather than one in the connection string, to keep
+    /// it off the command line. With the `vault` or `aws-secrets` features, the password can
+    /// instead be read from `vault:PATH#FIELD` or `aws-sm:ID[#FIELD]`.
+    #[arg(long)]
+    password_file: Option<String>,
+
+    /// Connect to the database over this Unix domain socket rather than the host and port of the
+    /// connection string, e.g. `/var/run/mysqld/mysqld.sock`, or for Postgres the directory
+    /// holding the socket, e.g. `/var/run/postgresql`, or the socket itself. Connections over it
+    /// are not encrypted.
+    #[arg(long)]
+    unix_socket: Option<PathBuf>,
+
+    /// Compare the database with this one, probing both from the same workers on the same
+    /// schedule and summarizing the paired differences in their latency and error rate.
+    #[arg(long)]
+    compare_database_url: Option<String>,
+
+    /// Database driver to connect with.
+    /// Inferred from the scheme of the connection string by default.
+    #[arg(long, value_enum)]
+    driver: Option<Driver>,
+
+    /// Insecure connection
+    #[arg(long)]
+    insecure: bool,
+
+    /// Query to run on every attempt. Placeholders like `{{seq}}`, `{{uuid}}`, `{{timestamp}}`
+    /// and `{{rand_int(min,max)}}` are expanded on every attempt, e.g. for unique primary keys.
+    #[arg(long, default_value = "SELECT 1")]
+    query: String,
+
+    /// Set a timeout for the query.
+    #[arg(long, default_value_t = 20)]
+    query_timeout_ms: u64,
+
+    /// Check connections out of a pool on every attempt, instead of opening a new one each time.
+    #[arg(long)]
+    pooled: bool,
+
+    /// Number of connections opened when the pool starts and kept open while idle.
+    #[arg(long, default_value_t = 1)]
+    pool_min_size: usize,
+
+    /// Maximum number of connections in the pool. Attempts wait for a connection once reached.
+    #[arg(long, default_value_t = 10)]
+    pool_max_size: usize,
+
+    /// Close connections left idle in the pool for longer than this.
+    /// By default idle connections are kept open indefinitely.
+    #[arg(long)]
+    pool_idle_timeout_ms: Option<u64>,
+
+    /// Hold a connection open for every worker and run the query over the same session on every
+    /// tick, opening it again on the tick after it ends. Sessions that end are failed apart from
+    /// connects, by how they ended: `session_closed`, `session_timeout` for a server idle timeout
+    /// like `wait_timeout`, `session_terminated` for a session the server killed or shut down, and
+    /// `read_only` for a server that fails writes after a failover.
+    #[arg(long, conflicts_with = "pooled")]
+    session: bool,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    pub common: CommonArgs,
+}
+
+/// A connection held across attempts, and when it was last used.
+type Session = (Box<dyn DbConnection>, Instant);
+
+pub struct DbProbe {
+    database: Arc<dyn Database>,
+    pool: Option<Arc<Pool>>,
+    /// Connection of every worker, with `--session`.
+    sessions: Option<Vec<sync::Mutex<Option<Session>>>>,
+    driver: Driver,
+    query: Template,
+    sequence: AtomicU64,
+    connect_timeout: Duration,
+    query_timeout: Duration,
+    target: String,
+}
+
+impl DbProbe {
+    pub fn new(args: &DbArgs) -> Self {
+        let url = args
+            .database_url
+            .as_deref()
+            .expect("DATABASE_URL not found");
+        Self::for_url(args, url, args.unix_socket.as_deref())
+    }
+
+    /// Creates a probe of the database at the URL, rather than `--database-url`, connecting over
+    /// the Unix domain socket if there is one.
+    fn for_url(args: &DbArgs, url: &str, unix_socket: Option<&Path>) -> Self {
+        let target = report::redact(url);
+        let url = &with_password(url, args.password_file.as_deref());
+        let driver = args
+            .driver
+            .or_else(|| Driver::from_url(url))
+            .expect("unknown database driver, set --driver");
+
+        let connect_timeout = Duration::from_millis(args.connect_timeout_ms);
+        let query_timeout = Duration::from_millis(args.query_timeout_ms);
+        let database: Arc<dyn Database> = match driver {
+            Driver::Mysql => Arc::new(mysql::MysqlDatabase::new(url, args.insecure, unix_socket)),
+            Driver::Postgres => Arc::new(postgres::PostgresDatabase::new(
+                url,
+                query_timeout,
+                args.insecure,
+                unix_socket,
+            )),
+        };
+
+        let pool = args.pooled.then(|| {
+            Arc::new(Pool::new(
+                database.clone(),
+                args.pool_min_size,
+                args.pool_max_size,
+                args.pool_idle_timeout_ms.map(Duration::from_millis),
+            ))
+        });
+
+        let sessions = args.session.then(|| {
+            (0..args.common.parallel)
+                .map(|_| Default::default())
+                .collect()
+        });
+
+        DbProbe {
+            database,
+            pool,
+            sessions,
+            driver,
+            query: Template::parse(args.query.as_str()).expect("invalid query template"),
+            sequence: AtomicU64::new(0),
+            connect_timeout,
+            query_timeout,
+            target,
+        }
+    }
+
+    /// Opens the connections the pool keeps open, if the probe has one.
+    async fn fill(&self) {
+        if let Some(pool) = &self.pool {
+            if let Err(e) = pool.fill().await {
+                warn!("error opening pool connections: {}", e);
+            }
+        }
+    }
+}
+
+impl Probe for DbProbe {
+    fn kind(&self) -> &'static str {
+        "db"
+    }
+
+    fn target(&self) -> String {
+        self.target.clone()
+    }
+
+    async fn attempt(&self, worker: usize) -> Attempt {
+        let start = Instant::now();
+        let mut phases = vec![];
+        let mut details = vec![("driver", self.driver.to_string())];
+
+        let mut session = match &self.sessions {
+            Some(sessions) => Some(sessions[worker % sessions.len()].lock().await),
+            None => None,
+        };
+        let connect_start = Instant::now();
+        let checkout = match (
+            session.as_mut().and_then(|session| session.take()),
+            &self.pool,
+        ) {
+            (Some((conn, since)), _) => {
+                let mut checkout = Checkout::from(conn);
+                checkout.idle = Some(since.elapsed());
+                Ok(Ok(checkout))
+            }
+            (None, Some(pool)) => {
+                // Wait until the pool has a connection to spare, timing how long that takes.
+                let permit = pool.acquire().await;
+                phases.push(("checkout", start.elapsed()));
+                time::timeout(self.connect_timeout, pool.get(permit)).await
+            }
+            (None, None) => {
+                time::timeout(self.connect_timeout, async {
+                    self.database.connect().await.map(Checkout::from)
+                })
+                .await
+            }
+        };
+        let mut checkout = match checkout {
+            Ok(Ok(checkout)) => checkout,
+            Ok(Err(e)) => {
+                let error = match e.downcast_ref::<Rejected>() {
+                    Some(rejected) => ProbeError::new(rejected.kind, rejected),
+                    None => ProbeError::from_cause("connect", &*e),
+                };
+                return failed(start, phases, details, error);
+            }
+            Err(_) => {
+                let message = format!("timed out after {}ms", self.connect_timeout.as_millis());
+                let error = ProbeError::new("connect_timeout", message);
+                return failed(start, phases, details, error);
+            }
+        };
+        match checkout.idle {
+            Some(idle) => {
+                details.push(("connection", "reused".to_string()));
+                details.push(("idle_ms", idle.as_millis().to_string()));
+            }
+            None => {
+                phases.push(("connect", connect_start.elapsed()));
+                if self.pool.is_some() || self.sessions.is_some() {
+                    details.push(("connection", "new".to_string()));
+                }
+            }
+        }
+
+        let query = self.query.render_string(&Vars::next(&self.sequence));
+        let query_start = Instant::now();
+        let result = time::timeout(self.query_timeout, checkout.conn.query(&query)).await;
+        phases.push(("query", query_start.elapsed()));
+        let error = match result {
+            Ok(Ok(rows)) => {
+                details.push(("rows", rows.to_string()));
+                None
+            }
+            Ok(Err(e)) if e.is::<QueryTimeout>() => Some(ProbeError::new("query_timeout", e)),
+            Ok(Err(e)) => match e.downcast_ref::<Ended>() {
+                Some(ended) => Some(ProbeError::new(ended.kind, ended)),
+                None => Some(ProbeError::from_cause("query", &*e)),
+            },
+            Err(_) => Some(ProbeError::new(
+                "query_timeout",
+                QueryTimeout(self.query_timeout),
+            )),
+        };
+        match (&mut session, &self.pool) {
+            // Sessions and pooled connections are kept through queries that failed within them,
+            // and only connections that failed are dropped.
+            (Some(session), _) if error.as_ref().is_none_or(|e| e.kind == "query") => {
+                **session = Some((checkout.conn, Instant::now()))
+            }
+            (None, Some(pool)) if error.as_ref().is_none_or(|e| e.kind == "query") => {
+                pool.put(checkout)
+            }
+            _ => {}
+        }
+
+        Attempt {
+            duration: start.elapsed(),
+            phases,
+            details,
+            error,
+        }
+    }
+}
+
+/// Sets the password of a connection string to the one read from `--password-file`, if given,
+/// and redacts its password from then on.
+fn with_password(url: &str, password_file: Option<&str>) -> String {
+    let password = password_file.map(secret::read);
+    if let Ok(mut parsed) = url::Url::parse(url) {
+        if let Some(password) = &password {
+            parsed
+                .set_password(Some(password))
+                .expect("cannot set a password in --database-url");
+            return parsed.to_string();
+        }
+        if let Some(password) = parsed.password() {
+            secret::register(password);
+            let decoded = percent_encoding::percent_decode_str(password).decode_utf8_lossy();
+            secret::register(&decoded);
+        }
+        return url.to_string();
+    }
+
+    // Key-value connection strings, e.g. `host=localhost password=secret`.
+    match password {
+        Some(password) => {
+            let quoted = password.replace('\\', "\\\\").replace('\'', "\\'");
+            format!("{} password='{}'", url, quoted)
+        }
+        None => {
+            for pair in url.split_whitespace() {
+                match pair.split_once('=') {
+                    Some((key, value)) if key.eq_ignore_ascii_case("password") => {
+                        secret::register(value)
+                    }
+                    _ => {}
+                }
+            }
+            url.to_string()
+        }
+    }
+}
+
+pub async fn db_main(args: DbArgs) {
+    let probe = DbProbe::new(&args);
+    probe.fill().await;
+    match &args.compare_database_url {
+        Some(url) => {
+            let other = DbProbe::for_url(&args, url, None);
+            other.fill().await;
+            ab::run(probe, other, &args.common).await
+        }
+        None => probe::run(probe, &args.common).await,
+    }
+}
+
+fn failed(
+    start: Instant,
+    phases: Vec<(&'static str, Duration)>,
+    details: Vec<(&'static str, String)>,
+    error: ProbeError,
+) -> Attempt {
+    Attempt {
+        duration: start.elapsed(),
+        phases,
+        details,
+        error: Some(error),
+    }
+}
@@ -0,0 +1,140 @@
+use std::path::Path;
+
+use mysql_async::prelude::Queryable;
+
+use super::{BoxError, BoxFuture, Database, DbConnection, Ended, Rejected};
+use crate::{resolve, socket};
+
+/// Server errors rejecting the credentials of a new connection.
+const ACCESS_DENIED: [u16; 3] = [
+    1044, // ER_DBACCESS_DENIED_ERROR
+    1045, // ER_ACCESS_DENIED_ERROR
+    1698, // ER_ACCESS_DENIED_NO_PASSWORD_ERROR
+];
+
+/// Server errors ending a session for having been idle for longer than `wait_timeout`.
+const IDLE_TIMEOUT: [u16; 1] = [
+    4031, // ER_CLIENT_INTERACTION_TIMEOUT
+];
+
+/// Server errors ending a session that was killed or whose server is shutting down.
+const TERMINATED: [u16; 3] = [
+    1053, // ER_SERVER_SHUTDOWN
+    1927, // ER_CONNECTION_KILLED
+    3169, // ER_SESSION_WAS_KILLED
+];
+
+/// Server errors rejecting writes to a server that is read-only, as a replica is.
+const READ_ONLY: [u16; 2] = [
+    1290, // ER_OPTION_PREVENTS_STATEMENT
+    1836, // ER_READ_ONLY_MODE
+];
+
+pub struct MysqlDatabase {
+    builder: mysql_async::OptsBuilder,
+}
+
+impl MysqlDatabase {
+    pub fn new(url: &str, insecure: bool, unix_socket: Option<&Path>) -> Self {
+        socket::ensure_supported("mysql", &["--tcp-nodelay", "--tcp-keepalive-s"]);
+        let opts = mysql_async::Opts::from_url(url).unwrap();
+        let nodelay = socket::nodelay(opts.tcp_nodelay());
+        let keepalive = socket::keepalive_time().or(opts.tcp_keepalive());
+        let socket = unix_socket.map(|path| path.to_str().expect("invalid unix socket path"));
+        let builder = mysql_async::OptsBuilder::from_opts(opts)
+            .tcp_nodelay(nodelay)
+            .tcp_keepalive(keepalive)
+            .socket(socket)
+            .ssl_opts(if insecure || socket.is_some() {
+                None
+            } else {
+                Some(mysql_async::SslOpts::default())
+            });
+
+        MysqlDatabase { builder }
+    }
+
+    /// Options to connect with, with the host pinned to an address of the family connections are
+    /// restricted to, if any. Certificates are still verified against the host.
+    async fn pinned(&self) -> Result<mysql_async::OptsBuilder, BoxError> {
+        let builder = self.builder.clone();
+        let opts = mysql_async::Opts::from(builder.clone());
+        if !resolve::is_restricted() || opts.socket().is_some() {
+            return Ok(builder);
+        }
+        let host = opts.ip_or_hostname();
+        let addr = resolve::first_addr(host, opts.tcp_port()).await?;
+        let ssl_opts = opts.ssl_opts().map(|ssl| {
+            ssl.clone()
+                .with_danger_tls_hostname_override(Some(host.to_string()))
+        });
+        Ok(builder
+            .ip_or_hostname(addr.ip().to_string())
+            .ssl_opts(ssl_opts))
+    }
+}
+
+impl Database for MysqlDatabase {
+    fn connect(&self) -> BoxFuture<'_, Result<Box<dyn DbConnection>, BoxError>> {
+        Box::pin(async {
+            let conn = mysql_async::Conn::new(self.pinned().await?)
+                .await
+                .map_err(connect_error)?;
+            Ok(Box::new(conn) as Box<dyn DbConnection>)
+        })
+    }
+}
+
+impl DbConnection for mysql_async::Conn {
+    fn query<'a>(&'a mut self, sql: &'a str) -> BoxFuture<'a, Result<u64, BoxError>> {
+        Box::pin(async move {
+            let mut result = self.query_iter(sql).await.map_err(query_error)?;
+            let mut rows = 0;
+            // Count the rows of every result set, or the rows affected by statements without any.
+            loop {
+                let set: Vec<mysql_async::Row> = result.collect().await.map_err(query_error)?;
+                rows += (set.len() as u64).max(result.affected_rows());
+                if result.is_empty() {
+                    break;
+                }
+            }
+            Ok(rows)
+        })
+    }
+}
+
+/// Tells errors returned by the server or the protocol while starting a session apart from failed
+/// connections.
+fn connect_error(e: mysql_async::Error) -> BoxError {
+    let kind = match &e {
+        mysql_async::Error::Server(server) if ACCESS_DENIED.contains(&server.code) => "db_auth",
+        mysql_async::Error::Server(_) | mysql_async::Error::Driver(_) => "db_handshake",
+        _ => return e.into(),
+    };
+    Box::new(Rejected {
+        kind,
+        message: e.to_string(),
+    })
+}
+
+/// Tells errors ending the session a query was sent over apart from the query failing.
+fn query_error(e: mysql_async::Error) -> BoxError {
+    let kind = match &e {
+        mysql_async::Error::Server(server) if IDLE_TIMEOUT.contains(&server.code) => {
+            "session_timeout"
+        }
+        mysql_async::Error::Server(server) if TERMINATED.contains(&server.code) => {
+            "session_terminated"
+        }
+        mysql_async::Error::Server(server) if READ_ONLY.contains(&server.code) => "read_only",
+        mysql_async::Error::Io(_)
+        | mysql_async::Error::Driver(mysql_async::DriverError::ConnectionClosed) => {
+            "session_closed"
+        }
+        _ => return e.into(),
+    };
+    Box::new(Ended {
+        kind,
+        message: e.to_string(),
+    })
+}
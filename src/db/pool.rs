@@ -0,0 +1,115 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use super::{BoxError, Database, DbConnection};
+
+/// Connections kept open between attempts and checked out for each one.
+pub struct Pool {
+    database: Arc<dyn Database>,
+    idle: Mutex<Vec<Idle>>,
+    permits: Arc<Semaphore>,
+    min_size: usize,
+    idle_timeout: Option<Duration>,
+}
+
+struct Idle {
+    conn: Box<dyn DbConnection>,
+    since: Instant,
+}
+
+/// A connection checked out of the pool, returned to it with [`Pool::put`].
+pub struct Checkout {
+    pub conn: Box<dyn DbConnection>,
+    /// How long the connection sat idle in the pool, or `None` if it was just opened.
+    pub idle: Option<Duration>,
+    _permit: Option<OwnedSemaphorePermit>,
+}
+
+impl From<Box<dyn DbConnection>> for Checkout {
+    /// Wraps a connection opened outside of any pool.
+    fn from(conn: Box<dyn DbConnection>) -> Self {
+        Checkout {
+            conn,
+            idle: None,
+            _permit: None,
+        }
+    }
+}
+
+impl Pool {
+    pub fn new(
+        database: Arc<dyn Database>,
+        min_size: usize,
+        max_size: usize,
+        idle_timeout: Option<Duration>,
+    ) -> Self {
+        Pool {
+            database,
+            idle: Mutex::new(vec![]),
+            permits: Arc::new(Semaphore::new(max_size)),
+            min_size: min_size.min(max_size),
+            idle_timeout,
+        }
+    }
+
+    /// Opens the minimum number of connections.
+    pub async fn fill(&self) -> Result<(), BoxError> {
+        for _ in 0..self.min_size {
+            let conn = self.database.connect().await?;
+            self.idle.lock().unwrap().push(Idle {
+                conn,
+                since: Instant::now(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Waits until fewer than the maximum number of connections are checked out.
+    pub async fn acquire(&self) -> OwnedSemaphorePermit {
+        self.permits
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("pool semaphore closed")
+    }
+
+    /// Checks out the most recently used idle connection, opening a new one if there is none.
+    /// Connections idle for longer than the idle timeout are closed, down to the minimum size.
+    pub async fn get(&self, permit: OwnedSemaphorePermit) -> Result<Checkout, BoxError> {
+        let idle = {
+            let mut idle = self.idle.lock().unwrap();
+            if let Some(idle_timeout) = self.idle_timeout {
+                idle.sort_by_key(|conn| conn.since);
+                while idle.len() > self.min_size && idle[0].since.elapsed() > idle_timeout {
+                    idle.remove(0);
+                }
+            }
+            idle.pop()
+        };
+
+        match idle {
+            Some(Idle { conn, since }) => Ok(Checkout {
+                conn,
+                idle: Some(since.elapsed()),
+                _permit: Some(permit),
+            }),
+            None => Ok(Checkout {
+                conn: self.database.connect().await?,
+                idle: None,
+                _permit: Some(permit),
+            }),
+        }
+    }
+
+    /// Returns a connection to the pool once it is no longer in use.
+    pub fn put(&self, checkout: Checkout) {
+        self.idle.lock().unwrap().push(Idle {
+            conn: checkout.conn,
+            since: Instant::now(),
+        });
+    }
+}
@@ -0,0 +1,353 @@
+use std::{
+    borrow::Cow,
+    error::Error,
+    fmt, io,
+    net::IpAddr,
+    path::Path,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use log::debug;
+use tokio::net::TcpStream;
+use tokio_postgres::{
+    config::{Host, SslMode, TargetSessionAttrs},
+    error::SqlState,
+    tls::MakeTlsConnect,
+    NoTls, SimpleQueryMessage,
+};
+use tokio_postgres_rustls::MakeRustlsConnect;
+
+use super::{BoxError, BoxFuture, Database, DbConnection, Ended, QueryTimeout, Rejected};
+use crate::{resolve, socket, tls};
+
+pub struct PostgresDatabase {
+    config: tokio_postgres::Config,
+    tls: Option<MakeRustlsConnect>,
+    query_timeout: Duration,
+}
+
+impl PostgresDatabase {
+    pub fn new(
+        url: &str,
+        query_timeout: Duration,
+        insecure: bool,
+        unix_socket: Option<&Path>,
+    ) -> Self {
+        let mut config = match unix_socket {
+            Some(socket) => over_socket(url, socket),
+            None => tokio_postgres::Config::from_str(url).unwrap(),
+        };
+        // The server also cancels statements running past the timeout, rather than leaving them
+        // running after the probe gives up on them.
+        let options = config.get_options().unwrap_or_default();
+        let options = format!(
+            "{} -c statement_timeout={}",
+            options,
+            query_timeout.as_millis()
+        );
+        config.options(options.trim_start());
+        // With a socket option set, connections start over a socket opened to the first host
+        // that accepts one, rather than to one of the kind of session wanted.
+        assert!(
+            !socket::is_set() || config.get_target_session_attrs() == TargetSessionAttrs::Any,
+            "target_session_attrs cannot be used with the socket options"
+        );
+
+        // The server never encrypts connections over a Unix domain socket.
+        let tls = if insecure || unix_socket.is_some() {
+            config.ssl_mode(SslMode::Disable);
+            None
+        } else {
+            config.ssl_mode(SslMode::Require);
+            Some(MakeRustlsConnect::new(tls::client_config()))
+        };
+
+        PostgresDatabase {
+            config,
+            tls,
+            query_timeout,
+        }
+    }
+
+    /// The configuration to connect with, with every host pinned to an address of the family
+    /// connections are restricted to, if any. Certificates are still verified against the hosts.
+    async fn pinned(&self) -> Result<Cow<'_, tokio_postgres::Config>, BoxError> {
+        if !resolve::is_restricted() {
+            return Ok(Cow::Borrowed(&self.config));
+        }
+        let Some(hosts) = tcp_hosts(&self.config) else {
+            return Ok(Cow::Borrowed(&self.config));
+        };
+        // Addresses given with the hosts are connected to instead of resolving them.
+        let hostaddrs = self.config.get_hostaddrs();
+        if let Some(addr) = hostaddrs.iter().find(|addr| !resolve::allows(**addr)) {
+            let message = format!("hostaddr {} is not of the address family chosen", addr);
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, message).into());
+        }
+        if !hostaddrs.is_empty() {
+            return Ok(Cow::Borrowed(&self.config));
+        }
+
+        let mut config = self.config.clone();
+        for (i, host) in hosts.into_iter().enumerate() {
+            let addr = resolve::first_addr(host, port(&self.config, i)).await?;
+            config.hostaddr(addr.ip());
+        }
+        Ok(Cow::Owned(config))
+    }
+
+    /// Opens a socket with the socket options to the first of the hosts that accepts one, for
+    /// the connection to start over, along with the name of that host. Without any socket option
+    /// set, connections are left to the driver.
+    async fn socket(&self) -> Result<Option<(TcpStream, &str)>, BoxError> {
+        if !socket::is_set() {
+            return Ok(None);
+        }
+        let Some(hosts) = tcp_hosts(&self.config).filter(|hosts| !hosts.is_empty()) else {
+            return Ok(None);
+        };
+        let hostaddrs = self.config.get_hostaddrs();
+        let mut last_err = None;
+        for (i, host) in hosts.into_iter().enumerate() {
+            let addr = hostaddrs.get(i).map(IpAddr::to_string);
+            match resolve::connect(addr.as_deref().unwrap_or(host), port(&self.config, i)).await {
+                Ok(connection) => return Ok(Some((connection.stream, host))),
+                Err(e) => last_err = Some(e),
+            }
+        }
+        Err(last_err.expect("a host was tried").into())
+    }
+}
+
+impl Database for PostgresDatabase {
+    fn connect(&self) -> BoxFuture<'_, Result<Box<dyn DbConnection>, BoxError>> {
+        Box::pin(async {
+            let closed = Arc::new(Mutex::new(None));
+            // The connection performs the actual I/O, so it runs until the client is dropped.
+            let client = match (self.socket().await?, &self.tls) {
+                (Some((stream, host)), Some(tls)) => {
+                    let tls =
+                        MakeTlsConnect::<TcpStream>::make_tls_connect(&mut tls.clone(), host)?;
+                    let (client, connection) = self
+                        .config
+                        .connect_raw(stream, tls)
+                        .await
+                        .map_err(connect_error)?;
+                    tokio::spawn(watch(connection, closed.clone()));
+                    client
+                }
+                (Some((stream, _)), None) => {
+                    let (client, connection) = self
+                        .config
+                        .connect_raw(stream, NoTls)
+                        .await
+                        .map_err(connect_error)?;
+                    tokio::spawn(watch(connection, closed.clone()));
+                    client
+                }
+                (None, Some(tls)) => {
+                    let (client, connection) = self
+                        .pinned()
+                        .await?
+                        .connect(tls.clone())
+                        .await
+                        .map_err(connect_error)?;
+                    tokio::spawn(watch(connection, closed.clone()));
+                    client
+                }
+                (None, None) => {
+                    let (client, connection) = self
+                        .pinned()
+                        .await?
+                        .connect(NoTls)
+                        .await
+                        .map_err(connect_error)?;
+                    tokio::spawn(watch(connection, closed.clone()));
+                    client
+                }
+            };
+
+            Ok(Box::new(PostgresConnection {
+                client,
+                query_timeout: self.query_timeout,
+                closed,
+            }) as Box<dyn DbConnection>)
+        })
+    }
+}
+
+/// Names of the hosts, unless any is a Unix domain socket, over which connections have no address
+/// family or socket options.
+fn tcp_hosts(config: &tokio_postgres::Config) -> Option<Vec<&str>> {
+    config
+        .get_hosts()
+        .iter()
+        .map(|host| match host {
+            Host::Tcp(host) => Some(host.as_str()),
+            Host::Unix(_) => None,
+        })
+        .collect()
+}
+
+/// Port of the host at the index, where a single port applies to every host.
+fn port(config: &tokio_postgres::Config, i: usize) -> u16 {
+    let ports = config.get_ports();
+    ports.get(i).or(ports.first()).copied().unwrap_or(5432)
+}
+
+/// Runs a connection until it closes, keeping the error that closed it.
+async fn watch<S, T>(
+    connection: tokio_postgres::Connection<S, T>,
+    closed: Arc<Mutex<Option<tokio_postgres::Error>>>,
+) where
+    S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin,
+    T: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin,
+{
+    if let Err(e) = connection.await {
+        debug!("postgres connection error: {}", e);
+        *closed.lock().unwrap() = Some(e);
+    }
+}
+
+struct PostgresConnection {
+    client: tokio_postgres::Client,
+    query_timeout: Duration,
+    /// Error the server closed the connection with, once it has.
+    closed: Arc<Mutex<Option<tokio_postgres::Error>>>,
+}
+
+impl PostgresConnection {
+    /// Tells an error ending the session apart from the query failing. A session the server
+    /// ends between queries gives its reason to the connection rather than to the next query.
+    fn ended(&self, e: &tokio_postgres::Error) -> Option<Ended> {
+        let closed = self.closed.lock().unwrap();
+        let cause = closed.as_ref().filter(|_| e.is_closed()).unwrap_or(e);
+        let code = cause.code();
+        let any = |codes: &[SqlState]| codes.iter().any(|c| Some(c) == code);
+        let kind = if any(&[
+            SqlState::IDLE_SESSION_TIMEOUT,
+            SqlState::IDLE_IN_TRANSACTION_SESSION_TIMEOUT,
+        ]) {
+            "session_timeout"
+        } else if any(&[
+            SqlState::ADMIN_SHUTDOWN,
+            SqlState::CRASH_SHUTDOWN,
+            SqlState::CANNOT_CONNECT_NOW,
+        ]) {
+            "session_terminated"
+        } else if any(&[SqlState::READ_ONLY_SQL_TRANSACTION]) {
+            "read_only"
+        } else if e.is_closed() || cause.source().is_some_and(|e| e.is::<std::io::Error>()) {
+            "session_closed"
+        } else {
+            return None;
+        };
+        let message = match cause.source() {
+            Some(source) => format!("{}: {}", cause, source),
+            None => cause.to_string(),
+        };
+        Some(Ended { kind, message })
+    }
+}
+
+impl DbConnection for PostgresConnection {
+    fn query<'a>(&'a mut self, sql: &'a str) -> BoxFuture<'a, Result<u64, BoxError>> {
+        Box::pin(async move {
+            let messages = self.client.simple_query(sql).await.map_err(|e| {
+                if e.code() == Some(&SqlState::QUERY_CANCELED) {
+                    QueryTimeout(self.query_timeout).into()
+                } else if let Some(ended) = self.ended(&e) {
+                    Box::new(ended)
+                } else {
+                    with_cause(e)
+                }
+            })?;
+
+            let rows = messages
+                .iter()
+                .map(|message| match message {
+                    SimpleQueryMessage::CommandComplete(rows) => *rows,
+                    _ => 0,
+                })
+                .sum();
+            Ok(rows)
+        })
+    }
+}
+
+/// Parses the connection string to connect over a Unix domain socket rather than to its hosts.
+/// The socket is given as the directory holding it, as libpq takes it, or as the socket itself,
+/// whose name gives the port.
+#[cfg(unix)]
+fn over_socket(url: &str, socket: &Path) -> tokio_postgres::Config {
+    let (scheme, rest) = url
+        .split_once("://")
+        .expect("--unix-socket needs a postgres:// connection string");
+    // Only the hosts and ports are left out, after any credentials.
+    let end = rest.find(['/', '?']).unwrap_or(rest.len());
+    let start = rest[..end].rfind('@').map_or(0, |at| at + 1);
+    let without_hosts = format!("{}://{}{}", scheme, &rest[..start], &rest[end..]);
+    let mut config = tokio_postgres::Config::from_str(&without_hosts).unwrap();
+
+    let name = socket.file_name().and_then(|name| name.to_str());
+    match name.and_then(|name| name.strip_prefix(".s.PGSQL.")) {
+        Some(port) => {
+            config.host_path(socket.parent().unwrap_or(Path::new("/")));
+            config.port(port.parse().expect("invalid port in postgres socket name"));
+        }
+        None => {
+            config.host_path(socket);
+            let ports = tokio_postgres::Config::from_str(url).unwrap();
+            if let Some(port) = ports.get_ports().first() {
+                config.port(*port);
+            }
+        }
+    }
+    config
+}
+
+#[cfg(not(unix))]
+fn over_socket(_url: &str, _socket: &Path) -> tokio_postgres::Config {
+    panic!("unix sockets are only supported on unix")
+}
+
+/// Tells errors returned by the server while starting a session apart from failed connections.
+fn connect_error(e: tokio_postgres::Error) -> BoxError {
+    let Some(db) = e.as_db_error() else {
+        return with_cause(e);
+    };
+    let kind = match *db.code() {
+        SqlState::INVALID_PASSWORD | SqlState::INVALID_AUTHORIZATION_SPECIFICATION => "db_auth",
+        _ => "db_handshake",
+    };
+    Box::new(Rejected {
+        kind,
+        message: format!("{}: {}", e, db),
+    })
+}
+
+/// `tokio_postgres::Error` does not include its cause when displayed, so fold it into the message.
+fn with_cause(e: tokio_postgres::Error) -> BoxError {
+    Box::new(WithCause(e))
+}
+
+/// Displays an error followed by its cause, keeping the cause as its source.
+#[derive(Debug)]
+struct WithCause(tokio_postgres::Error);
+
+impl fmt::Display for WithCause {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0.source() {
+            Some(cause) => write!(f, "{}: {}", self.0, cause),
+            None => write!(f, "{}", self.0),
+        }
+    }
+}
+
+impl Error for WithCause {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        self.0.source()
+    }
+}
@@ -0,0 +1,142 @@
+use std::{net::SocketAddr, time::Duration};
+
+use clap::Args;
+use hyper::Uri;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::time;
+
+use crate::{
+    http,
+    report::{self, Labels, Origin},
+    resolve::Resolver,
+};
+
+/// Time to wait for the Consul API to answer.
+const CONSUL_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Options for finding the instances of a service to probe one by one, which only the `http`
+/// probe takes, as it is the one that can pin each target to an address of its own.
+#[derive(Args, Debug, Default, Serialize, Deserialize)]
+pub struct DiscoverArgs {
+    /// Probe every instance found this way rather than whichever the target resolves to, each as
+    /// a target of its own labelled with `instance`: `srv:NAME` for the targets of the DNS SRV
+    /// records of a name, `dns:HOST:PORT` for every address of a host, like the pods of a
+    /// Kubernetes headless service, or `consul:SERVICE` for the passing instances of a Consul
+    /// service. Only HTTP targets can be discovered; other probes connect to what their target
+    /// resolves to.
+    #[arg(long)]
+    pub discover: Option<String>,
+
+    /// Look for instances again this often, starting probes of new instances and stopping probes
+    /// of instances that are gone.
+    #[arg(long, default_value_t = 30)]
+    pub discover_interval_s: u64,
+
+    /// Address of the Consul HTTP API, for `--discover consul:SERVICE`.
+    #[arg(long, default_value = "http://127.0.0.1:8500")]
+    pub consul_addr: String,
+}
+
+/// Where instances are found.
+enum Source {
+    Srv(String),
+    Dns(String, u16),
+    Consul(String),
+}
+
+/// Finds the instances of a service, refreshing them on an interval.
+pub struct Discovery {
+    source: Source,
+    consul_addr: String,
+    pub interval: Duration,
+}
+
+impl Discovery {
+    /// Finds instances the way the arguments ask to, if they do.
+    pub fn new(args: &DiscoverArgs) -> Option<Self> {
+        let discover = args.discover.as_deref()?;
+        let source = match discover.split_once(':') {
+            Some(("srv", name)) => Source::Srv(name.to_string()),
+            Some(("dns", host)) => {
+                let (host, port) = host.rsplit_once(':').expect("--discover dns: needs a port");
+                let port = port.parse().expect("invalid port in --discover");
+                Source::Dns(host.to_string(), port)
+            }
+            Some(("consul", service)) => Source::Consul(service.to_string()),
+            _ => panic!(
+                "--discover must be srv:, dns: or consul:, not {:?}",
+                discover
+            ),
+        };
+        assert!(
+            args.discover_interval_s > 0,
+            "--discover-interval-s must be positive"
+        );
+        Some(Discovery {
+            source,
+            consul_addr: args.consul_addr.trim_end_matches('/').to_string(),
+            interval: Duration::from_secs(args.discover_interval_s),
+        })
+    }
+
+    /// Looks up the instances, sorted and without duplicates.
+    pub async fn instances(&self, resolver: &Resolver) -> Result<Vec<SocketAddr>, String> {
+        let hosts = match &self.source {
+            Source::Srv(name) => resolver.srv(name).await.map_err(|e| e.to_string())?,
+            Source::Dns(host, port) => vec![(host.clone(), *port)],
+            Source::Consul(service) => self.consul(service).await?,
+        };
+        let mut instances = vec![];
+        for (host, port) in hosts {
+            let addrs = resolver.lookup(&host, port).await;
+            instances.extend(addrs.map_err(|e| format!("{}: {}", host, e))?);
+        }
+        instances.sort_unstable();
+        instances.dedup();
+        Ok(instances)
+    }
+
+    /// Asks Consul for the host and port of every instance of the service passing its checks.
+    async fn consul(&self, service: &str) -> Result<Vec<(String, u16)>, String> {
+        let url = format!(
+            "{}/v1/health/service/{}?passing=true",
+            self.consul_addr, service
+        );
+        let uri: Uri = url.parse().map_err(|e| format!("{}: {}", url, e))?;
+        let origin = Origin {
+            probe: "consul",
+            name: None,
+            labels: Labels::default(),
+            target: report::redact(&url),
+            worker: 0,
+        };
+        let request = async {
+            let res = http::client(&uri, origin).get(uri.clone()).await?;
+            let status = res.status();
+            let body = hyper::body::to_bytes(res.into_body()).await?;
+            Ok::<_, hyper::Error>((status, body))
+        };
+        let (status, body) = match time::timeout(CONSUL_TIMEOUT, request).await {
+            Ok(result) => result.map_err(|e| e.to_string())?,
+            Err(_) => return Err("consul did not answer in time".to_string()),
+        };
+        if !status.is_success() {
+            return Err(format!("consul answered {}", status));
+        }
+
+        let entries: Vec<Value> = serde_json::from_slice(&body).map_err(|e| e.to_string())?;
+        Ok(entries
+            .iter()
+            .filter_map(|entry| {
+                // Services registered without an address of their own are at their node's.
+                let address = entry["Service"]["Address"]
+                    .as_str()
+                    .filter(|address| !address.is_empty())
+                    .or(entry["Node"]["Address"].as_str())?;
+                let port = entry["Service"]["Port"].as_u64()?;
+                Some((address.to_string(), u16::try_from(port).ok()?))
+            })
+            .collect())
+    }
+}
@@ -0,0 +1,177 @@
+use std::{
+    future::Future,
+    io,
+    net::SocketAddr,
+    pin::Pin,
+    str::FromStr,
+    time::{Duration, Instant},
+};
+
+use clap::Parser;
+use hickory_resolver::{
+    config::{NameServerConfigGroup, ResolverConfig},
+    error::ResolveErrorKind,
+    name_server::{GenericConnector, RuntimeProvider, TokioHandle, TokioRuntimeProvider},
+    proto::{iocompat::AsyncIoTokioAsStd, op::ResponseCode, rr::RecordType, TokioTime},
+    AsyncResolver,
+};
+use serde::{Deserialize, Serialize};
+use tokio::net::{TcpStream, UdpSocket};
+
+use crate::{
+    pcap,
+    probe::{self, CommonArgs, Probe},
+    report::{Attempt, ProbeError},
+    resolve, socket,
+};
+
+#[derive(Parser, Debug, Serialize, Deserialize)]
+pub struct DnsArgs {
+    /// Hostname to resolve.
+    #[arg(long)]
+    hostname: String,
+
+    /// Record type to query for.
+    #[arg(long, default_value = "A")]
+    record_type: String,
+
+    /// Nameserver to send queries to, as `ip` or `ip:port`.
+    /// The system resolver configuration is used by default.
+    #[arg(long)]
+    nameserver: Option<String>,
+
+    /// Set a timeout for a single query.
+    #[arg(long, default_value_t = 1000)]
+    timeout_ms: u64,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    pub common: CommonArgs,
+}
+
+pub struct DnsProbe {
+    resolver: AsyncResolver<GenericConnector<SocketProvider>>,
+    hostname: String,
+    record_type: RecordType,
+    timeout: Duration,
+}
+
+impl DnsProbe {
+    pub fn new(args: &DnsArgs) -> Self {
+        let record_type = RecordType::from_str(&args.record_type).expect("invalid record type");
+
+        let (config, mut opts) = match &args.nameserver {
+            Some(nameserver) => {
+                let addr = resolve::parse_nameserver(nameserver);
+                let group = NameServerConfigGroup::from_ips_clear(&[addr.ip()], addr.port(), true);
+                (
+                    ResolverConfig::from_parts(None, vec![], group),
+                    Default::default(),
+                )
+            }
+            None => hickory_resolver::system_conf::read_system_conf()
+                .expect("error reading system resolver config"),
+        };
+        for nameserver in config.name_servers() {
+            pcap::watch(nameserver.socket_addr.ip());
+        }
+        // Every query should reach the nameserver, so disable caching and retries.
+        opts.cache_size = 0;
+        opts.attempts = 1;
+        opts.use_hosts_file = false;
+        opts.timeout = Duration::from_millis(args.timeout_ms);
+
+        DnsProbe {
+            timeout: opts.timeout,
+            resolver: AsyncResolver::new(config, opts, GenericConnector::new(Default::default())),
+            hostname: args.hostname.clone(),
+            record_type,
+        }
+    }
+}
+
+/// Opens the sockets queries are sent over with the socket options, like other probes do.
+#[derive(Clone, Default)]
+struct SocketProvider(TokioRuntimeProvider);
+
+impl RuntimeProvider for SocketProvider {
+    type Handle = TokioHandle;
+    type Timer = TokioTime;
+    type Udp = UdpSocket;
+    type Tcp = AsyncIoTokioAsStd<TcpStream>;
+
+    fn create_handle(&self) -> Self::Handle {
+        self.0.create_handle()
+    }
+
+    fn connect_tcp(
+        &self,
+        server_addr: SocketAddr,
+    ) -> Pin<Box<dyn Send + Future<Output = io::Result<Self::Tcp>>>> {
+        Box::pin(async move { socket::connect(server_addr).await.map(AsyncIoTokioAsStd) })
+    }
+
+    fn bind_udp(
+        &self,
+        _local_addr: SocketAddr,
+        server_addr: SocketAddr,
+    ) -> Pin<Box<dyn Send + Future<Output = io::Result<Self::Udp>>>> {
+        // The system picks a random port rather than the resolver.
+        Box::pin(socket::bind_udp(server_addr))
+    }
+}
+
+impl Probe for DnsProbe {
+    fn kind(&self) -> &'static str {
+        "dns"
+    }
+
+    fn target(&self) -> String {
+        self.hostname.clone()
+    }
+
+    async fn attempt(&self, _worker: usize) -> Attempt {
+        let start = Instant::now();
+        let result = self
+            .resolver
+            .lookup(self.hostname.as_str(), self.record_type)
+            .await;
+
+        let (details, error) = match result {
+            Ok(lookup) => {
+                let records: Vec<_> = lookup.iter().map(|r| r.to_string()).collect();
+                (vec![("records", format!("[{}]", records.join(", ")))], None)
+            }
+            Err(e) => {
+                let kind = match e.kind() {
+                    ResolveErrorKind::NoRecordsFound {
+                        response_code: ResponseCode::NXDomain,
+                        ..
+                    } => "nxdomain",
+                    ResolveErrorKind::NoRecordsFound { .. } => "no_records",
+                    ResolveErrorKind::Timeout => "timeout",
+                    ResolveErrorKind::Io(_)
+                    | ResolveErrorKind::Proto(_)
+                    | ResolveErrorKind::NoConnections => "transport",
+                    _ => "resolve",
+                };
+                let message = match kind {
+                    "timeout" => format!("timed out after {}ms", self.timeout.as_millis()),
+                    _ => e.to_string(),
+                };
+                (vec![], Some(ProbeError::new(kind, message)))
+            }
+        };
+
+        Attempt {
+            duration: start.elapsed(),
+            phases: vec![],
+            details,
+            error,
+        }
+    }
+}
+
+pub async fn dns_main(args: DnsArgs) {
+    probe::run(DnsProbe::new(&args), &args.common).await
+}
@@ -0,0 +1,345 @@
+use std::{
+    error::Error,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use clap::{Parser, ValueEnum};
+use hyper::{
+    header::{HeaderValue, AUTHORIZATION},
+    Body, Client, Request, Uri,
+};
+use log::{info, warn};
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::time;
+
+use crate::{
+    http::{ConnectTimeout, ConnectionInfo, HttpVersion, Proxy, TimingConnector},
+    probe::{self, CommonArgs, Probe},
+    report::{self, Attempt, Origin, ProbeError},
+    resolve::{ResolveArgs, Resolver},
+    secret,
+    tls::TlsArgs,
+};
+
+/// Characters left as they are in index names and patterns, like `logs-*,metrics`.
+const INDEX: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'*')
+    .remove(b',');
+
+/// Health of a cluster, from best to worst.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ClusterStatus {
+    /// Every shard is allocated.
+    Green,
+    /// Every primary shard is allocated, but some replicas are not.
+    Yellow,
+    /// Some primary shards are not allocated.
+    Red,
+}
+
+#[derive(Parser, Debug, Serialize, Deserialize)]
+pub struct EsArgs {
+    /// URL of the Elasticsearch or OpenSearch cluster, e.g. `https://es.internal:9200`.
+    #[arg(long)]
+    url: String,
+
+    /// Authenticate with HTTP basic auth as this user.
+    #[arg(long, requires = "password", conflicts_with = "api_key")]
+    username: Option<String>,
+
+    /// Password for `--username`.
+    #[arg(long, requires = "username")]
+    password: Option<String>,
+
+    /// Authenticate with this API key, encoded as Elasticsearch gives it out.
+    #[arg(long)]
+    api_key: Option<String>,
+
+    /// Count the attempt as failed if the cluster is worse than this.
+    #[arg(long, value_enum, default_value_t = ClusterStatus::Yellow)]
+    min_status: ClusterStatus,
+
+    /// Also search this index or pattern for nothing but the number of hits, checking that
+    /// searches are served.
+    #[arg(long)]
+    search_index: Option<String>,
+
+    /// Set a timeout for only the connect phase of a `Client`.
+    #[arg(long, default_value_t = 1000)]
+    connect_timeout_ms: u64,
+
+    /// Set a timeout for all the requests of an attempt.
+    #[arg(long, default_value_t = 5000)]
+    timeout_ms: u64,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    tls: TlsArgs,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    resolve: ResolveArgs,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    pub common: CommonArgs,
+}
+
+/// Gets the health of the cluster on every attempt, and searches an index if asked to, over a new
+/// connection for every attempt.
+pub struct EsProbe {
+    connectors: Vec<TimingConnector>,
+    /// URL of the cluster, without a trailing slash.
+    url: String,
+    authorization: Option<HeaderValue>,
+    min_status: ClusterStatus,
+    search_index: Option<String>,
+    /// Status of the cluster from the latest attempt of any worker, to report when it changes.
+    status: Mutex<Option<ClusterStatus>>,
+    target: String,
+    timeout: Duration,
+}
+
+impl EsProbe {
+    pub fn new(args: &EsArgs) -> Self {
+        let url = args.url.trim_end_matches('/').to_string();
+        let uri: Uri = url.parse().expect("invalid es url");
+        let authorization = match (&args.username, &args.password, &args.api_key) {
+            (Some(username), Some(password), _) => Some(format!(
+                "Basic {}",
+                STANDARD.encode(format!("{}:{}", username, password))
+            )),
+            (_, _, Some(key)) => Some(format!("ApiKey {}", key)),
+            _ => None,
+        }
+        .map(|value| HeaderValue::try_from(value).expect("invalid es credentials"));
+        for secret in [&args.password, &args.api_key].into_iter().flatten() {
+            secret::register(secret);
+        }
+
+        let proxy = Proxy::for_uri(&uri, None, None, &[]);
+        let resolver = Arc::new(Resolver::new(&args.resolve));
+        let target = report::redact(&url);
+        // Create a connector for every worker so that their connections are reported apart.
+        let connectors = (0..args.common.parallel)
+            .map(|worker| {
+                let origin = Origin {
+                    probe: "es",
+                    name: args.common.name.clone(),
+                    labels: args.common.labels(),
+                    target: target.clone(),
+                    worker,
+                };
+                TimingConnector::new(
+                    Duration::from_millis(args.connect_timeout_ms),
+                    &args.tls,
+                    HttpVersion::Http1,
+                    proxy.clone(),
+                    resolver.clone(),
+                    origin,
+                )
+            })
+            .collect();
+
+        EsProbe {
+            connectors,
+            url,
+            authorization,
+            min_status: args.min_status,
+            search_index: args.search_index.clone(),
+            status: Mutex::new(None),
+            target,
+            timeout: Duration::from_millis(args.timeout_ms),
+        }
+    }
+
+    fn request(&self, path: &str) -> Request<Body> {
+        let mut req = Request::new(Body::empty());
+        *req.uri_mut() = format!("{}{}", self.url, path)
+            .parse()
+            .expect("invalid es url");
+        if let Some(authorization) = &self.authorization {
+            req.headers_mut()
+                .insert(AUTHORIZATION, authorization.clone());
+        }
+        req
+    }
+
+    /// Records the status of the cluster, returning how it changed since the latest attempt, if
+    /// it did.
+    fn transition(&self, status: ClusterStatus) -> Option<String> {
+        let last = self.status.lock().unwrap().replace(status);
+        let last = last.filter(|&last| last != status)?;
+        let message = format!(
+            "es {}: cluster status changed from {} to {}",
+            self.target,
+            name(last),
+            name(status)
+        );
+        match status > last {
+            true => warn!("{}", message),
+            false => info!("{}", message),
+        }
+        Some(format!("{} -> {}", name(last), name(status)))
+    }
+}
+
+impl Probe for EsProbe {
+    fn kind(&self) -> &'static str {
+        "es"
+    }
+
+    fn target(&self) -> String {
+        self.target.clone()
+    }
+
+    async fn attempt(&self, worker: usize) -> Attempt {
+        // Requests of an attempt share a connection, which is closed along with the client.
+        let connector = self.connectors[worker % self.connectors.len()].clone();
+        let client = Client::builder().build::<_, Body>(connector);
+        let mut phases = vec![];
+        let mut details = vec![];
+
+        let start = Instant::now();
+        let requests = async {
+            let search = self.search_index.as_ref().map(|index| {
+                let index = utf8_percent_encode(index, INDEX);
+                ("search", format!("/{}/_search?size=0", index))
+            });
+            let health = ("health", "/_cluster/health".to_string());
+            let mut error = None;
+            for (phase, path) in [Some(health), search].into_iter().flatten() {
+                let sent = Instant::now();
+                let res = client.request(self.request(&path)).await?;
+                let status = res.status();
+                let info = res.extensions().get::<ConnectionInfo>().cloned();
+                let body = hyper::body::to_bytes(res.into_body()).await?;
+
+                let mut ready = sent;
+                if let Some(info) = info.filter(ConnectionInfo::first_use) {
+                    phases.extend(info.phases());
+                    if let Some(addr) = info.lifecycle.remote_addr {
+                        details.push(("remote_addr", addr.to_string()));
+                    }
+                    details.push(("family", info.family.to_string()));
+                    ready = ready.max(info.established_at);
+                }
+                phases.push((phase, ready.elapsed()));
+
+                let body: Value = serde_json::from_slice(&body).unwrap_or_default();
+                if !status.is_success() {
+                    let reason = body["error"]["reason"]
+                        .as_str()
+                        .or(body["error"].as_str())
+                        .unwrap_or_default();
+                    if let Some(kind) = body["error"]["type"].as_str() {
+                        details.push(("error_type", kind.to_string()));
+                    }
+                    details.push(("http_status", status.as_u16().to_string()));
+                    return Ok(Some(ProbeError::new(
+                        "http_status",
+                        format!("{} failed with {}: {}", phase, status, reason),
+                    )));
+                }
+                if phase == "search" {
+                    let hits = &body["hits"]["total"];
+                    let hits = hits["value"].as_u64().or(hits.as_u64());
+                    if let Some(hits) = hits {
+                        details.push(("hits", hits.to_string()));
+                    }
+                    if let Some(took) = body["took"].as_u64() {
+                        details.push(("took_ms", took.to_string()));
+                    }
+                    continue;
+                }
+
+                let status = match body["status"].as_str() {
+                    Some("green") => ClusterStatus::Green,
+                    Some("yellow") => ClusterStatus::Yellow,
+                    Some("red") => ClusterStatus::Red,
+                    _ => {
+                        return Ok(Some(ProbeError::new(
+                            "unexpected_reply",
+                            "cluster health has no status",
+                        )))
+                    }
+                };
+                details.push(("status", name(status).to_string()));
+                if let Some(change) = self.transition(status) {
+                    details.push(("status_changed", change));
+                }
+                for field in [
+                    "cluster_name",
+                    "number_of_nodes",
+                    "active_shards_percent_as_number",
+                    "unassigned_shards",
+                    "relocating_shards",
+                ] {
+                    let value = match &body[field] {
+                        Value::String(value) => value.clone(),
+                        Value::Number(value) => value.to_string(),
+                        _ => continue,
+                    };
+                    let name = match field {
+                        "active_shards_percent_as_number" => "active_shards_percent",
+                        field => field,
+                    };
+                    details.push((name, value));
+                }
+                // A search is still tried against a failing cluster, to tell if it serves them.
+                if status > self.min_status {
+                    error = Some(ProbeError::new(
+                        "cluster_health",
+                        format!("cluster status is {}", name(status)),
+                    ));
+                }
+            }
+            Ok::<_, hyper::Error>(error)
+        };
+
+        let error = match time::timeout(self.timeout, requests).await {
+            Ok(Ok(error)) => error,
+            Ok(Err(e)) => {
+                let kind = if !e.is_connect() {
+                    "request"
+                } else if e.source().is_some_and(|e| e.is::<ConnectTimeout>()) {
+                    "connect_timeout"
+                } else {
+                    "connect"
+                };
+                Some(ProbeError::from_cause(kind, &e))
+            }
+            Err(_) => Some(ProbeError::new(
+                "request_timeout",
+                format!("timed out after {}ms", self.timeout.as_millis()),
+            )),
+        };
+
+        Attempt {
+            duration: start.elapsed(),
+            phases,
+            details,
+            error,
+        }
+    }
+}
+
+pub async fn es_main(args: EsArgs) {
+    probe::run(EsProbe::new(&args), &args.common).await
+}
+
+fn name(status: ClusterStatus) -> &'static str {
+    match status {
+        ClusterStatus::Green => "green",
+        ClusterStatus::Yellow => "yellow",
+        ClusterStatus::Red => "red",
+    }
+}
@@ -0,0 +1,425 @@
+use std::{
+    collections::BTreeMap,
+    fs::{File, OpenOptions},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use log::error;
+use parquet::{
+    basic::Compression,
+    data_type::{ByteArray, ByteArrayType, DoubleType, Int64Type},
+    errors::ParquetError,
+    file::{properties::WriterProperties, writer::SerializedFileWriter},
+    schema::{parser::parse_message_type, types::Type},
+};
+
+use crate::{
+    report::{Attempt, Origin},
+    stats::{Percentiles, Summary},
+};
+
+/// Number of rows buffered before they are written to a Parquet file as a row group.
+const ROW_GROUP: usize = 10_000;
+
+static EXPORT: Mutex<Option<Export>> = Mutex::new(None);
+
+/// Tables that attempts and periodic summaries are written to.
+struct Export {
+    path: PathBuf,
+    format: Format,
+    attempts: Table,
+    /// Opened with the first periodic summary.
+    intervals: Option<Table>,
+}
+
+#[derive(Clone, Copy)]
+enum Format {
+    Csv,
+    Parquet,
+}
+
+/// Type of the values in a column.
+#[derive(Clone, Copy)]
+enum Kind {
+    Timestamp,
+    Int,
+    Float,
+    Text,
+    /// Text holding a JSON object.
+    Json,
+}
+
+struct Column {
+    name: &'static str,
+    kind: Kind,
+    optional: bool,
+}
+
+const fn column(name: &'static str, kind: Kind, optional: bool) -> Column {
+    Column {
+        name,
+        kind,
+        optional,
+    }
+}
+
+const ATTEMPTS: &[Column] = &[
+    column("timestamp", Kind::Timestamp, false),
+    column("probe", Kind::Text, false),
+    column("name", Kind::Text, true),
+    column("target", Kind::Text, false),
+    column("worker", Kind::Int, false),
+    column("outcome", Kind::Text, false),
+    column("latency_ms", Kind::Float, false),
+    column("error_kind", Kind::Text, true),
+    column("error_message", Kind::Text, true),
+    column("phases_ms", Kind::Json, false),
+    column("details", Kind::Json, false),
+];
+
+const INTERVALS: &[Column] = &[
+    column("timestamp", Kind::Timestamp, false),
+    column("interval_s", Kind::Int, false),
+    column("probe", Kind::Text, false),
+    column("name", Kind::Text, true),
+    column("target", Kind::Text, false),
+    column("attempts", Kind::Int, false),
+    column("failures", Kind::Int, false),
+    column("recovered", Kind::Int, false),
+    column("success_rate", Kind::Float, false),
+    column("p50_ms", Kind::Float, true),
+    column("p90_ms", Kind::Float, true),
+    column("p95_ms", Kind::Float, true),
+    column("p99_ms", Kind::Float, true),
+    column("p999_ms", Kind::Float, true),
+    column("max_ms", Kind::Float, true),
+];
+
+enum Value {
+    Timestamp(SystemTime),
+    Int(i64),
+    Float(f64),
+    Text(String),
+    Null,
+}
+
+impl From<Option<f64>> for Value {
+    fn from(value: Option<f64>) -> Self {
+        value.map_or(Value::Null, Value::Float)
+    }
+}
+
+impl From<Option<&str>> for Value {
+    fn from(value: Option<&str>) -> Self {
+        value.map_or(Value::Null, |value| Value::Text(value.to_string()))
+    }
+}
+
+/// A file rows with the same columns are written to.
+struct Table {
+    columns: &'static [Column],
+    sink: Sink,
+}
+
+enum Sink {
+    Csv(csv::Writer<File>),
+    Parquet {
+        writer: SerializedFileWriter<File>,
+        rows: Vec<Vec<Value>>,
+    },
+}
+
+/// Starts writing every attempt to a CSV or Parquet file, chosen by its extension, and the
+/// periodic summaries to a file next to it, e.g. `results.intervals.csv` for `results.csv`.
+pub fn init(path: &Path) {
+    let format = match path.extension().and_then(|extension| extension.to_str()) {
+        Some("csv") => Format::Csv,
+        Some("parquet") => Format::Parquet,
+        _ => panic!("--out must end in .csv or .parquet, not {}", path.display()),
+    };
+
+    let attempts = Table::open(path, format, "attempt", ATTEMPTS);
+    *EXPORT.lock().unwrap() = Some(Export {
+        path: path.to_path_buf(),
+        format,
+        attempts,
+        intervals: None,
+    });
+}
+
+/// Writes a probe attempt, if exporting.
+pub fn observe(origin: &Origin, attempt: &Attempt) {
+    let mut export = EXPORT.lock().unwrap();
+    let Some(export) = &mut *export else {
+        return;
+    };
+
+    let phases: BTreeMap<_, _> = attempt
+        .phases
+        .iter()
+        .map(|(name, duration)| (*name, duration.as_micros() as f64 / 1000.0))
+        .collect();
+    let details: BTreeMap<_, _> = attempt
+        .details
+        .iter()
+        .map(|(name, value)| (*name, value.as_str()))
+        .collect();
+    let outcome = match attempt.error {
+        None => "success",
+        Some(_) => "failure",
+    };
+    export.attempts.push(vec![
+        Value::Timestamp(SystemTime::now()),
+        Value::Text(origin.probe.to_string()),
+        origin.name.as_deref().into(),
+        Value::Text(origin.target.clone()),
+        Value::Int(origin.worker as i64),
+        Value::Text(outcome.to_string()),
+        Value::Float(attempt.duration.as_micros() as f64 / 1000.0),
+        attempt.error.as_ref().map(|e| e.kind).into(),
+        attempt.error.as_ref().map(|e| e.message.as_str()).into(),
+        Value::Text(serde_json::to_string(&phases).unwrap()),
+        Value::Text(serde_json::to_string(&details).unwrap()),
+    ]);
+}
+
+/// Writes the summaries of a periodic report, if exporting, and flushes what was written so far to
+/// CSV files.
+pub fn interval(summaries: &[Summary], interval_s: u64) {
+    let mut export = EXPORT.lock().unwrap();
+    let Some(export) = &mut *export else {
+        return;
+    };
+
+    let now = SystemTime::now();
+    let intervals = export.intervals.get_or_insert_with(|| {
+        let stem = export
+            .path
+            .file_stem()
+            .unwrap_or_default()
+            .to_string_lossy();
+        let extension = export
+            .path
+            .extension()
+            .unwrap_or_default()
+            .to_string_lossy();
+        let path = export
+            .path
+            .with_file_name(format!("{}.intervals.{}", stem, extension));
+        Table::open(&path, export.format, "interval", INTERVALS)
+    });
+    for summary in summaries {
+        let latency = |p: fn(&Percentiles) -> f64| summary.latency_ms.as_ref().map(p);
+        intervals.push(vec![
+            Value::Timestamp(now),
+            Value::Int(interval_s as i64),
+            Value::Text(summary.probe.to_string()),
+            summary.name.as_deref().into(),
+            Value::Text(summary.target.clone()),
+            Value::Int(summary.attempts as i64),
+            Value::Int(summary.failures as i64),
+            Value::Int(summary.recovered as i64),
+            Value::Float(summary.success_rate),
+            latency(|l| l.p50).into(),
+            latency(|l| l.p90).into(),
+            latency(|l| l.p95).into(),
+            latency(|l| l.p99).into(),
+            latency(|l| l.p999).into(),
+            latency(|l| l.max).into(),
+        ]);
+    }
+
+    for table in [Some(&mut export.attempts), export.intervals.as_mut()]
+        .into_iter()
+        .flatten()
+    {
+        if let Sink::Csv(writer) = &mut table.sink {
+            if let Err(e) = writer.flush() {
+                error!("error writing results: {}", e);
+            }
+        }
+    }
+}
+
+/// Writes out everything buffered and completes the files.
+pub fn shutdown() {
+    let Some(export) = EXPORT.lock().unwrap().take() else {
+        return;
+    };
+    for table in [Some(export.attempts), export.intervals]
+        .into_iter()
+        .flatten()
+    {
+        if let Err(e) = table.close() {
+            error!("error writing results: {}", e);
+        }
+    }
+}
+
+impl Table {
+    /// Opens the file, appending to CSV files so that results from several runs accumulate, and
+    /// replacing Parquet files, which cannot be appended to.
+    fn open(path: &Path, format: Format, message: &str, columns: &'static [Column]) -> Self {
+        let sink = match format {
+            Format::Csv => {
+                let file = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .expect("unable to open results file");
+                let empty = file.metadata().map(|m| m.len() == 0).unwrap_or(true);
+                let mut writer = csv::Writer::from_writer(file);
+                if empty {
+                    writer
+                        .write_record(columns.iter().map(|column| column.name))
+                        .expect("unable to write results file");
+                }
+                Sink::Csv(writer)
+            }
+            Format::Parquet => {
+                let file = File::create(path).expect("unable to create results file");
+                let properties = WriterProperties::builder()
+                    .set_compression(Compression::SNAPPY)
+                    .build();
+                let writer =
+                    SerializedFileWriter::new(file, schema(message, columns), Arc::new(properties))
+                        .expect("unable to write results file");
+                Sink::Parquet {
+                    writer,
+                    rows: vec![],
+                }
+            }
+        };
+        Table { columns, sink }
+    }
+
+    fn push(&mut self, row: Vec<Value>) {
+        let result = match &mut self.sink {
+            Sink::Csv(writer) => writer
+                .write_record(row.iter().map(to_field))
+                .map_err(|e| e.to_string()),
+            Sink::Parquet { writer, rows } => {
+                rows.push(row);
+                if rows.len() < ROW_GROUP {
+                    return;
+                }
+                write_row_group(writer, self.columns, &std::mem::take(rows))
+                    .map_err(|e| e.to_string())
+            }
+        };
+        if let Err(e) = result {
+            error!("error writing results: {}", e);
+        }
+    }
+
+    fn close(self) -> Result<(), String> {
+        match self.sink {
+            Sink::Csv(mut writer) => writer.flush().map_err(|e| e.to_string()),
+            Sink::Parquet { mut writer, rows } => {
+                if !rows.is_empty() {
+                    write_row_group(&mut writer, self.columns, &rows).map_err(|e| e.to_string())?;
+                }
+                writer.close().map(|_| ()).map_err(|e| e.to_string())
+            }
+        }
+    }
+}
+
+/// Formats a value as a CSV field, with timestamps in RFC 3339 and nulls empty.
+fn to_field(value: &Value) -> String {
+    match value {
+        Value::Timestamp(at) => humantime::format_rfc3339_micros(*at).to_string(),
+        Value::Int(value) => value.to_string(),
+        Value::Float(value) => value.to_string(),
+        Value::Text(value) => value.clone(),
+        Value::Null => String::new(),
+    }
+}
+
+fn schema(message: &str, columns: &[Column]) -> Arc<Type> {
+    let fields: String = columns
+        .iter()
+        .map(|column| {
+            let repetition = if column.optional {
+                "optional"
+            } else {
+                "required"
+            };
+            let (physical, logical) = match column.kind {
+                Kind::Timestamp => ("int64", " (TIMESTAMP(MICROS,true))"),
+                Kind::Int => ("int64", ""),
+                Kind::Float => ("double", ""),
+                Kind::Text => ("binary", " (STRING)"),
+                Kind::Json => ("binary", " (JSON)"),
+            };
+            format!("{} {} {}{};\n", repetition, physical, column.name, logical)
+        })
+        .collect();
+    let message = format!("message {} {{\n{}}}", message, fields);
+    Arc::new(parse_message_type(&message).expect("invalid results schema"))
+}
+
+/// Writes the rows as a row group, one column at a time.
+fn write_row_group(
+    writer: &mut SerializedFileWriter<File>,
+    columns: &[Column],
+    rows: &[Vec<Value>],
+) -> Result<(), ParquetError> {
+    let mut group = writer.next_row_group()?;
+    for (i, column) in columns.iter().enumerate() {
+        let values = rows.iter().map(|row| &row[i]);
+        // Nulls are left out of the values, and marked by a definition level of 0 instead.
+        let levels: Vec<i16> = values
+            .clone()
+            .map(|value| i16::from(!matches!(value, Value::Null)))
+            .collect();
+        let levels = column.optional.then_some(&levels[..]);
+
+        let mut writer = group.next_column()?.expect("fewer columns than in schema");
+        match column.kind {
+            Kind::Timestamp | Kind::Int => {
+                let values: Vec<i64> = values
+                    .filter_map(|value| match value {
+                        Value::Timestamp(at) => Some(
+                            at.duration_since(UNIX_EPOCH)
+                                .unwrap_or_default()
+                                .as_micros() as i64,
+                        ),
+                        Value::Int(value) => Some(*value),
+                        _ => None,
+                    })
+                    .collect();
+                writer
+                    .typed::<Int64Type>()
+                    .write_batch(&values, levels, None)?;
+            }
+            Kind::Float => {
+                let values: Vec<f64> = values
+                    .filter_map(|value| match value {
+                        Value::Float(value) => Some(*value),
+                        _ => None,
+                    })
+                    .collect();
+                writer
+                    .typed::<DoubleType>()
+                    .write_batch(&values, levels, None)?;
+            }
+            Kind::Text | Kind::Json => {
+                let values: Vec<ByteArray> = values
+                    .filter_map(|value| match value {
+                        Value::Text(value) => Some(ByteArray::from(value.as_str())),
+                        _ => None,
+                    })
+                    .collect();
+                writer
+                    .typed::<ByteArrayType>()
+                    .write_batch(&values, levels, None)?;
+            }
+        }
+        writer.close()?;
+    }
+    group.close()?;
+    Ok(())
+}
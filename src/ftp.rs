@@ -0,0 +1,531 @@
+use std::{
+    io,
+    net::SocketAddr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use clap::{Parser, ValueEnum};
+use rand::RngCore;
+use rustls::pki_types::ServerName;
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio_rustls::TlsConnector;
+
+use crate::{
+    limit,
+    probe::{self, CommonArgs, Probe},
+    report::{Attempt, ProbeError},
+    resolve, secret, socket,
+    tls::{self, TlsArgs},
+};
+
+/// How TLS is negotiated with the server.
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FtpTls {
+    /// Upgrade the connection with `AUTH TLS` after the greeting, as FTPES does.
+    #[default]
+    Explicit,
+    /// Negotiate TLS as soon as the connection is open, as on port 990.
+    Implicit,
+    /// Stay in cleartext.
+    None,
+}
+
+/// What is transferred once logged in.
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Transfer {
+    /// List the directory at `--path`, or the one logged into.
+    #[default]
+    List,
+    /// Download the file at `--path`.
+    Download,
+    /// Upload `--upload-bytes` of random data to `--path`, replacing the file if it exists.
+    Upload,
+}
+
+#[derive(Parser, Debug, Serialize, Deserialize)]
+pub struct FtpArgs {
+    /// Host of the FTP server.
+    #[arg(long)]
+    host: String,
+
+    /// Port of the FTP server.
+    #[arg(long, default_value_t = 21)]
+    port: u16,
+
+    /// How to negotiate TLS. The data connections are protected too unless this is `none`.
+    #[arg(long, value_enum, default_value_t)]
+    ftp_tls: FtpTls,
+
+    /// User to log in as.
+    #[arg(long, default_value = "anonymous")]
+    username: String,
+
+    /// Password for `--username`.
+    #[arg(long)]
+    password: Option<String>,
+
+    /// Password for `--username`, read from a file or a `vault:` or `aws-sm:` source.
+    #[arg(long, conflicts_with = "password")]
+    password_file: Option<String>,
+
+    /// What to transfer on every attempt once logged in.
+    #[arg(long, value_enum, default_value_t)]
+    transfer: Transfer,
+
+    /// Path of the directory to list or of the file to download or upload.
+    #[arg(long, required_if_eq_any = [("transfer", "download"), ("transfer", "upload")])]
+    path: Option<String>,
+
+    /// Size of the file written by `--transfer upload`.
+    #[arg(long, default_value_t = 1024)]
+    upload_bytes: usize,
+
+    /// Set a timeout for only the connect phase of a connection, including implicit TLS.
+    #[arg(long, default_value_t = 1000)]
+    connect_timeout_ms: u64,
+
+    /// Set a timeout for the rest of the session after connecting, including the transfer.
+    #[arg(long, default_value_t = 5000)]
+    timeout_ms: u64,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    tls: TlsArgs,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    pub common: CommonArgs,
+}
+
+trait Stream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Stream for T {}
+
+/// Logs in on every attempt and lists a directory, downloads a file or uploads one over a passive
+/// data connection, then says `QUIT`.
+pub struct FtpProbe {
+    host: String,
+    port: u16,
+    mode: FtpTls,
+    tls: TlsConnector,
+    sni: Option<String>,
+    username: String,
+    password: String,
+    transfer: Transfer,
+    path: Option<String>,
+    upload_bytes: usize,
+    connect_timeout: Duration,
+    timeout: Duration,
+}
+
+impl FtpProbe {
+    pub fn new(args: &FtpArgs) -> Self {
+        let password = match (&args.password, &args.password_file) {
+            (Some(password), _) => {
+                secret::register(password);
+                password.clone()
+            }
+            (_, Some(file)) => secret::read(file),
+            // Anonymous servers conventionally ask for an email address.
+            _ => "anonymous@".to_string(),
+        };
+
+        FtpProbe {
+            host: args.host.clone(),
+            port: args.port,
+            mode: args.ftp_tls,
+            tls: TlsConnector::from(Arc::new(tls::configure(&args.tls))),
+            sni: args.tls.sni.clone(),
+            username: args.username.clone(),
+            password,
+            transfer: args.transfer,
+            path: args.path.clone(),
+            upload_bytes: args.upload_bytes,
+            connect_timeout: Duration::from_millis(args.connect_timeout_ms),
+            timeout: Duration::from_millis(args.timeout_ms),
+        }
+    }
+
+    /// Negotiates TLS over the control or a data connection. Both verify the same name, so the
+    /// data connections resume the session of the control connection as servers may require.
+    async fn handshake(&self, stream: Box<dyn Stream>) -> Result<Box<dyn Stream>, ProbeError> {
+        let name = ServerName::try_from(self.sni.as_ref().unwrap_or(&self.host).clone())
+            .map_err(|e| ProbeError::new("tls", e))?;
+        let stream = self
+            .tls
+            .connect(name, stream)
+            .await
+            .map_err(|e| ProbeError::from_cause("tls", &e))?;
+        Ok(Box::new(stream))
+    }
+
+    /// Opens the control connection, negotiating TLS straight away in implicit mode, and returns
+    /// it with the address of the server.
+    async fn connect(
+        &self,
+        phases: &mut Vec<(&'static str, Duration)>,
+        details: &mut Vec<(&'static str, String)>,
+    ) -> Result<(Box<dyn Stream>, SocketAddr), ProbeError> {
+        let start = Instant::now();
+        let conn = resolve::connect(&self.host, self.port)
+            .await
+            .map_err(|e| ProbeError::from_cause("connect", &e))?;
+        details.extend(conn.details());
+        let stream = conn.stream;
+        let peer = stream
+            .peer_addr()
+            .map_err(|e| ProbeError::from_cause("connect", &e))?;
+        stream
+            .set_nodelay(socket::nodelay(true))
+            .map_err(|e| ProbeError::from_cause("connect", &e))?;
+        phases.push(("connect", start.elapsed()));
+
+        if self.mode != FtpTls::Implicit {
+            return Ok((Box::new(stream), peer));
+        }
+        phases.extend(limit::handshake().await);
+        let start = Instant::now();
+        let stream = self.handshake(Box::new(stream)).await?;
+        phases.push(("tls", start.elapsed()));
+        Ok((stream, peer))
+    }
+
+    /// Runs the session after connecting, up to and including `QUIT`.
+    async fn session(
+        &self,
+        stream: Box<dyn Stream>,
+        peer: SocketAddr,
+        phases: &mut Vec<(&'static str, Duration)>,
+        details: &mut Vec<(&'static str, String)>,
+    ) -> Result<(), ProbeError> {
+        let mut stream = BufReader::new(stream);
+
+        let start = Instant::now();
+        let (_, banner) = expect(&mut stream, None, &[220], "greeting").await?;
+        phases.push(("greeting", start.elapsed()));
+        details.push(("banner", banner.first().cloned().unwrap_or_default()));
+
+        if self.mode == FtpTls::Explicit {
+            phases.extend(limit::handshake().await);
+            let start = Instant::now();
+            expect(&mut stream, Some("AUTH TLS\r\n"), &[234], "auth_tls").await?;
+            // Anything sent before the handshake could have been injected in cleartext.
+            if !stream.buffer().is_empty() {
+                return Err(ProbeError::new(
+                    "auth_tls",
+                    "server sent data before the tls handshake",
+                ));
+            }
+            stream = BufReader::new(self.handshake(stream.into_inner()).await?);
+            phases.push(("tls", start.elapsed()));
+        }
+
+        let start = Instant::now();
+        let user = format!("USER {}\r\n", self.username);
+        let (code, _) = expect(&mut stream, Some(&user), &[230, 331], "auth").await?;
+        if code == 331 {
+            let pass = format!("PASS {}\r\n", self.password);
+            expect(&mut stream, Some(&pass), &[230], "auth").await?;
+        }
+        phases.push(("auth", start.elapsed()));
+
+        if self.mode != FtpTls::None {
+            expect(&mut stream, Some("PBSZ 0\r\n"), &[200], "auth_tls").await?;
+            expect(&mut stream, Some("PROT P\r\n"), &[200], "auth_tls").await?;
+        }
+        expect(&mut stream, Some("TYPE I\r\n"), &[200], "transfer").await?;
+
+        // The data connection counts against the connection limits like any other.
+        let throttle = limit::acquire().await;
+        phases.extend(
+            throttle
+                .phase()
+                .map(|(_, waited)| ("data_throttle", waited)),
+        );
+        let start = Instant::now();
+        let data = self.data_connection(&mut stream, peer).await?;
+        phases.push(("data_connect", start.elapsed()));
+        drop(throttle);
+
+        let start = Instant::now();
+        let (bytes, waited) = self.transfer(&mut stream, data, details).await?;
+        // Waiting to start the handshake over the data connection is not part of the transfer.
+        let elapsed = start
+            .elapsed()
+            .saturating_sub(waited.map_or(Duration::ZERO, |(_, waited)| waited));
+        phases.extend(waited.map(|(_, waited)| ("data_tls_throttle", waited)));
+        phases.push(("transfer", elapsed));
+        details.extend(transfer_details(self.transfer, bytes, elapsed));
+
+        expect(&mut stream, Some("QUIT\r\n"), &[221], "quit").await?;
+        Ok(())
+    }
+
+    /// Enters passive mode and opens the data connection. The address in the reply to `PASV` is
+    /// ignored in favour of the one of the control connection, as servers behind NAT often
+    /// advertise their private address.
+    async fn data_connection<S: AsyncRead + AsyncWrite + Unpin>(
+        &self,
+        stream: &mut BufReader<S>,
+        peer: SocketAddr,
+    ) -> Result<Box<dyn Stream>, ProbeError> {
+        let (code, reply) = send(stream, Some("EPSV\r\n")).await?;
+        let port = match code {
+            229 => epsv_port(&reply[0]),
+            // Servers without IPv6 support may not know `EPSV`.
+            500..=502 if peer.is_ipv4() => {
+                let (code, reply) = send(stream, Some("PASV\r\n")).await?;
+                if code != 227 {
+                    return Err(unexpected("data_connect", code, &reply));
+                }
+                pasv_port(&reply[0])
+            }
+            _ => return Err(unexpected("data_connect", code, &reply)),
+        };
+        let port = port.ok_or_else(|| {
+            ProbeError::new(
+                "data_connect",
+                format!("malformed passive reply: {}", reply.join(" ")),
+            )
+        })?;
+
+        let data = socket::connect(SocketAddr::new(peer.ip(), port))
+            .await
+            .map_err(|e| ProbeError::from_cause("data_connect", &e))?;
+        Ok(Box::new(data))
+    }
+
+    /// Runs the transfer over the data connection, returning how many bytes went through it and
+    /// any time spent waiting to start the handshake over it.
+    async fn transfer<S: AsyncRead + AsyncWrite + Unpin>(
+        &self,
+        stream: &mut BufReader<S>,
+        data: Box<dyn Stream>,
+        details: &mut Vec<(&'static str, String)>,
+    ) -> Result<(u64, Option<(&'static str, Duration)>), ProbeError> {
+        let path = self.path.as_deref();
+        let command = match (self.transfer, path) {
+            (Transfer::List, None) => "NLST\r\n".to_string(),
+            (Transfer::List, Some(path)) => format!("NLST {}\r\n", path),
+            (Transfer::Download, Some(path)) => format!("RETR {}\r\n", path),
+            (Transfer::Upload, Some(path)) => format!("STOR {}\r\n", path),
+            _ => unreachable!("--path is required to download or upload"),
+        };
+        // The server opens the data channel only once it has accepted the command, and only then
+        // takes part in the handshake over it.
+        expect(stream, Some(&command), &[125, 150], "transfer").await?;
+        let (mut data, waited) = match self.mode {
+            FtpTls::None => (data, None),
+            _ => {
+                let waited = limit::handshake().await;
+                (self.handshake(data).await?, waited)
+            }
+        };
+
+        let bytes = match self.transfer {
+            Transfer::Upload => {
+                let payload = payload(self.upload_bytes);
+                data.write_all(&payload)
+                    .await
+                    .map_err(|e| ProbeError::from_cause("transfer", &e))?;
+                // Closes TLS too, which servers check to tell a complete upload from a cut one.
+                data.shutdown()
+                    .await
+                    .map_err(|e| ProbeError::from_cause("transfer", &e))?;
+                payload.len() as u64
+            }
+            Transfer::List | Transfer::Download => {
+                let mut received = vec![];
+                match data.read_to_end(&mut received).await {
+                    // Servers commonly close the data connection without closing TLS, and the
+                    // reply that follows tells whether the transfer was complete anyway.
+                    Err(e) if e.kind() != io::ErrorKind::UnexpectedEof => {
+                        return Err(ProbeError::from_cause("transfer", &e));
+                    }
+                    _ => {}
+                }
+                if self.transfer == Transfer::List {
+                    let entries = received.split(|&b| b == b'\n');
+                    let entries = entries.filter(|line| !line.trim_ascii().is_empty()).count();
+                    details.push(("entries", entries.to_string()));
+                }
+                received.len() as u64
+            }
+        };
+        drop(data);
+
+        expect(stream, None, &[226, 250], "transfer").await?;
+        Ok((bytes, waited))
+    }
+}
+
+impl Probe for FtpProbe {
+    fn kind(&self) -> &'static str {
+        "ftp"
+    }
+
+    fn target(&self) -> String {
+        format!("{}:{}", self.host, self.port)
+    }
+
+    async fn attempt(&self, _worker: usize) -> Attempt {
+        let start = Instant::now();
+        let mut phases = vec![];
+        let mut details = vec![("tls_mode", format!("{:?}", self.mode).to_lowercase())];
+
+        let throttle = limit::acquire().await;
+        phases.extend(throttle.phase());
+        let connect = self.connect(&mut phases, &mut details);
+        let connected = limit::timeout(self.connect_timeout, connect).await;
+        drop(throttle);
+        let result = match connected {
+            Ok(Ok((stream, peer))) => {
+                let session = self.session(stream, peer, &mut phases, &mut details);
+                match limit::timeout(self.timeout, session).await {
+                    Ok(result) => result,
+                    Err(_) => Err(ProbeError::new(
+                        "timeout",
+                        format!("timed out after {}ms", self.timeout.as_millis()),
+                    )),
+                }
+            }
+            Ok(Err(e)) => Err(e),
+            Err(_) => Err(ProbeError::new(
+                "connect_timeout",
+                format!("timed out after {}ms", self.connect_timeout.as_millis()),
+            )),
+        };
+
+        Attempt {
+            duration: start.elapsed(),
+            phases,
+            details,
+            error: result.err(),
+        }
+    }
+}
+
+pub async fn ftp_main(args: FtpArgs) {
+    probe::run(FtpProbe::new(&args), &args.common).await
+}
+
+/// Random data of the given size to upload, so that nothing along the way can compress it.
+pub(crate) fn payload(size: usize) -> Vec<u8> {
+    let mut payload = vec![0; size];
+    rand::thread_rng().fill_bytes(&mut payload);
+    payload
+}
+
+/// Details telling what was transferred, how much and how fast.
+pub(crate) fn transfer_details(
+    transfer: Transfer,
+    bytes: u64,
+    elapsed: Duration,
+) -> Vec<(&'static str, String)> {
+    let mbps = if elapsed.is_zero() {
+        0.0
+    } else {
+        bytes as f64 * 8.0 / elapsed.as_secs_f64() / 1_000_000.0
+    };
+    vec![
+        ("transfer", format!("{:?}", transfer).to_lowercase()),
+        ("transfer_bytes", bytes.to_string()),
+        ("goodput_mbps", format!("{:.3}", mbps)),
+    ]
+}
+
+/// Sends a command, if any, and reads the reply.
+async fn send<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut BufReader<S>,
+    command: Option<&str>,
+) -> Result<(u16, Vec<String>), ProbeError> {
+    if let Some(command) = command {
+        stream
+            .write_all(command.as_bytes())
+            .await
+            .map_err(|e| ProbeError::from_cause("send", &e))?;
+    }
+    read_reply(stream)
+        .await
+        .map_err(|e| ProbeError::from_cause("receive", &e))
+}
+
+/// Sends a command, if any, and fails with the given kind unless the reply has one of the
+/// expected codes.
+async fn expect<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut BufReader<S>,
+    command: Option<&str>,
+    codes: &[u16],
+    kind: &'static str,
+) -> Result<(u16, Vec<String>), ProbeError> {
+    let (actual, lines) = send(stream, command).await?;
+    if !codes.contains(&actual) {
+        return Err(unexpected(kind, actual, &lines));
+    }
+    Ok((actual, lines))
+}
+
+fn unexpected(kind: &'static str, code: u16, lines: &[String]) -> ProbeError {
+    ProbeError::new(
+        kind,
+        format!("unexpected reply: {} {}", code, lines.join(" ")),
+    )
+}
+
+/// Reads a possibly multiline reply, returning its code and the text of every line. Unlike in
+/// SMTP, only the first and last lines of a multiline reply need to start with the code.
+async fn read_reply<S: AsyncRead + Unpin>(
+    stream: &mut BufReader<S>,
+) -> io::Result<(u16, Vec<String>)> {
+    let mut lines = vec![];
+    let mut code = None;
+    loop {
+        let mut line = String::new();
+        if stream.read_line(&mut line).await? == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        let line = line.trim_end();
+
+        let prefix: Option<u16> = line.get(..3).and_then(|code| code.parse().ok());
+        if code.is_none() {
+            code =
+                Some(prefix.ok_or_else(|| io::Error::other(format!("malformed reply: {}", line)))?);
+        }
+        if prefix != code {
+            lines.push(line.to_string());
+            continue;
+        }
+        lines.push(line.get(4..).unwrap_or_default().to_string());
+        if line.as_bytes().get(3) != Some(&b'-') {
+            return Ok((code.unwrap(), lines));
+        }
+    }
+}
+
+/// Port of the reply to `EPSV`, e.g. `Entering Extended Passive Mode (|||6446|)`.
+fn epsv_port(reply: &str) -> Option<u16> {
+    let start = reply.find('(')?;
+    let end = reply[start..].find(')')? + start;
+    reply[start + 1..end]
+        .trim_matches(|c: char| !c.is_ascii_digit())
+        .parse()
+        .ok()
+}
+
+/// Port of the reply to `PASV`, e.g. `Entering Passive Mode (192,168,1,2,19,46)`.
+fn pasv_port(reply: &str) -> Option<u16> {
+    let start = reply.find('(')?;
+    let end = reply[start..].find(')')? + start;
+    let numbers: Vec<u16> = reply[start + 1..end]
+        .split(',')
+        .map(|n| n.trim().parse().ok())
+        .collect::<Option<_>>()?;
+    match numbers[..] {
+        [_, _, _, _, high, low] if high < 256 && low < 256 => Some(high << 8 | low),
+        _ => None,
+    }
+}
@@ -0,0 +1,270 @@
+use std::{
+    error::Error,
+    io,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use clap::Parser;
+use hyper_util::rt::TokioIo;
+use rustls::pki_types::ServerName;
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    time,
+};
+use tokio_rustls::TlsConnector;
+use tonic::{
+    transport::{Endpoint, Uri},
+    Code, Request,
+};
+use tonic_health::pb::{
+    health_check_response::ServingStatus, health_client::HealthClient, HealthCheckRequest,
+};
+
+use crate::{
+    limit,
+    probe::{self, CommonArgs, Probe},
+    report::{self, Attempt, ProbeError},
+    resolve, socket,
+    tls::{self, TlsArgs},
+};
+
+#[derive(Parser, Debug, Serialize, Deserialize)]
+pub struct GrpcArgs {
+    /// URL of the server, as `http://host:port` or `https://host:port`.
+    #[arg(long)]
+    url: String,
+
+    /// Service to check the health of. The default checks the server as a whole.
+    #[arg(long, default_value = "")]
+    service: String,
+
+    /// Set a timeout for only the connect phase of a connection, including the TLS handshake.
+    #[arg(long, default_value_t = 15)]
+    connect_timeout_ms: u64,
+
+    /// Deadline of the `Check` RPC.
+    #[arg(long, default_value_t = 20)]
+    timeout_ms: u64,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    tls: TlsArgs,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    pub common: CommonArgs,
+}
+
+trait Stream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Stream for T {}
+
+pub struct GrpcProbe {
+    endpoint: Endpoint,
+    host: String,
+    port: u16,
+    tls: Option<TlsConnector>,
+    sni: Option<String>,
+    service: String,
+    target: String,
+    connect_timeout: Duration,
+    timeout: Duration,
+}
+
+impl GrpcProbe {
+    pub fn new(args: &GrpcArgs) -> Self {
+        let uri: Uri = args.url.parse().expect("invalid url");
+        let https = match uri.scheme_str() {
+            Some("https") => true,
+            Some("http") => false,
+            _ => panic!("unsupported scheme in {}", uri),
+        };
+        let host = uri
+            .host()
+            .expect("url has no host")
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .to_string();
+        let port = uri.port_u16().unwrap_or(if https { 443 } else { 80 });
+
+        let tls = https.then(|| {
+            let mut config = tls::configure(&args.tls);
+            config.alpn_protocols = vec![b"h2".to_vec()];
+            TlsConnector::from(Arc::new(config))
+        });
+
+        GrpcProbe {
+            endpoint: Endpoint::from(uri),
+            host,
+            port,
+            tls,
+            sni: args.tls.sni.clone(),
+            service: args.service.clone(),
+            target: report::redact(&args.url),
+            connect_timeout: Duration::from_millis(args.connect_timeout_ms),
+            timeout: Duration::from_millis(args.timeout_ms),
+        }
+    }
+
+    /// Opens a connection, negotiating TLS when configured, returning it with the details of its
+    /// address family and any time spent waiting to start the handshake.
+    async fn connect(&self) -> io::Result<(Box<dyn Stream>, Vec<(&'static str, String)>, Waited)> {
+        let conn = resolve::connect(&self.host, self.port).await?;
+        let family = conn.details();
+        let stream = conn.stream;
+        stream.set_nodelay(socket::nodelay(true))?;
+
+        match &self.tls {
+            Some(connector) => {
+                let name = ServerName::try_from(self.sni.as_ref().unwrap_or(&self.host).clone())
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+                let waited = limit::handshake().await;
+                Ok((
+                    Box::new(connector.connect(name, stream).await?),
+                    family,
+                    waited,
+                ))
+            }
+            None => Ok((Box::new(stream), family, None)),
+        }
+    }
+}
+
+/// Time spent waiting for the TLS handshake limit, as a phase.
+type Waited = Option<(&'static str, Duration)>;
+
+impl Probe for GrpcProbe {
+    fn kind(&self) -> &'static str {
+        "grpc"
+    }
+
+    fn target(&self) -> String {
+        self.target.clone()
+    }
+
+    async fn attempt(&self, _worker: usize) -> Attempt {
+        let start = Instant::now();
+        let throttle = limit::acquire().await;
+        let connect_start = Instant::now();
+        let result = limit::timeout(self.connect_timeout, self.connect()).await;
+        let connected = connect_start.elapsed();
+        let throttled = throttle.release();
+
+        let (stream, family, waited) = match result {
+            Ok(Ok(connected)) => connected,
+            Ok(Err(e)) => return failed(start, vec![], ProbeError::from_cause("connect", &e)),
+            Err(_) => {
+                return failed(
+                    start,
+                    vec![],
+                    ProbeError::new(
+                        "connect_timeout",
+                        format!("timed out after {}ms", self.connect_timeout.as_millis()),
+                    ),
+                )
+            }
+        };
+
+        // The connector hands over the connection already made, so it is only ever called once.
+        let mut stream = Some(stream);
+        let connector = tower::service_fn(move |_: Uri| {
+            let stream = stream
+                .take()
+                .ok_or_else(|| io::Error::other("connection closed"));
+            async move { stream.map(TokioIo::new) }
+        });
+
+        let mut phases: Vec<_> = throttled.into_iter().chain(waited).collect();
+        let waited = waited.map_or(Duration::ZERO, |(_, waited)| waited);
+        phases.push(("connect", connected.saturating_sub(waited)));
+        let check = async {
+            let channel = self.endpoint.connect_with_connector(connector).await?;
+            let mut request = Request::new(HealthCheckRequest {
+                service: self.service.clone(),
+            });
+            request.set_timeout(self.timeout);
+
+            let start = Instant::now();
+            let response = HealthClient::new(channel).check(request).await;
+            Ok::<_, tonic::transport::Error>((response, start.elapsed()))
+        };
+
+        let (response, rpc) = match time::timeout(self.timeout, check).await {
+            Ok(Ok(result)) => result,
+            Ok(Err(e)) => {
+                let kind = report::classify("connect", &e);
+                return failed(start, phases, ProbeError::new(kind, with_causes(&e)));
+            }
+            Err(_) => {
+                return failed(
+                    start,
+                    phases,
+                    ProbeError::new(
+                        "timeout",
+                        format!("timed out after {}ms", self.timeout.as_millis()),
+                    ),
+                )
+            }
+        };
+
+        phases.push(("rpc", rpc));
+        let (mut details, error) = match response {
+            Ok(response) => {
+                let status = response.into_inner().status();
+                let details = vec![
+                    ("code", format!("{:?}", Code::Ok)),
+                    ("status", status.as_str_name().to_string()),
+                ];
+                let error = (status != ServingStatus::Serving).then(|| {
+                    ProbeError::new("not_serving", format!("status {}", status.as_str_name()))
+                });
+                (details, error)
+            }
+            Err(status) => {
+                let kind = match status.code() {
+                    Code::DeadlineExceeded => "timeout",
+                    _ => "status",
+                };
+                let details = vec![("code", format!("{:?}", status.code()))];
+                let error =
+                    ProbeError::new(kind, format!("{:?}: {}", status.code(), status.message()));
+                (details, Some(error))
+            }
+        };
+        details.extend(family);
+
+        Attempt {
+            duration: start.elapsed(),
+            phases,
+            details,
+            error,
+        }
+    }
+}
+
+pub async fn grpc_main(args: GrpcArgs) {
+    probe::run(GrpcProbe::new(&args), &args.common).await
+}
+
+fn failed(start: Instant, phases: Vec<(&'static str, Duration)>, error: ProbeError) -> Attempt {
+    Attempt {
+        duration: start.elapsed(),
+        phases,
+        details: vec![],
+        error: Some(error),
+    }
+}
+
+/// Folds the chain of sources into the message, since transport errors only describe themselves
+/// as "transport error".
+fn with_causes(e: &dyn Error) -> String {
+    let mut message = e.to_string();
+    let mut source = e.source();
+    while let Some(cause) = source {
+        message = format!("{}: {}", message, cause);
+        source = cause.source();
+    }
+    message
+}
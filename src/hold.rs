@@ -0,0 +1,72 @@
+use std::{
+    io,
+    time::{Duration, Instant, SystemTime},
+};
+
+use clap::Args;
+use serde::{Deserialize, Serialize};
+
+use crate::report::ProbeError;
+
+/// Options for holding connections open to see how long they live.
+#[derive(Args, Clone, Debug, Default, Serialize, Deserialize)]
+pub struct HoldArgs {
+    /// Hold a connection open for every worker instead of opening one per attempt, to see how
+    /// long idle connections live. Each attempt checks that the connection is still alive, and
+    /// the first after it died fails with when and how it did: `closed` when the peer closed it,
+    /// `reset_by_peer` when it was reset or `keepalive_timeout` when TCP keepalives enabled with
+    /// `--tcp-keepalive-s` went unanswered. The next attempt opens a new connection.
+    #[arg(long)]
+    #[serde(default)]
+    pub hold: bool,
+}
+
+/// How and when a held connection died.
+#[derive(Clone, Debug)]
+pub struct Death {
+    pub at: Instant,
+    /// How long the connection had been open.
+    pub age: Duration,
+    pub error: ProbeError,
+}
+
+impl Death {
+    /// A connection opened at `opened` that ended just now, with the error reading from or
+    /// writing to it, or `None` if the peer closed it.
+    pub fn new(opened: Instant, error: Option<&io::Error>) -> Self {
+        let error = match error {
+            None => ProbeError::new("closed", "closed by peer"),
+            // Reads only time out once keepalives go unanswered.
+            Some(e) if e.kind() == io::ErrorKind::TimedOut => {
+                ProbeError::new("keepalive_timeout", e)
+            }
+            Some(e) => ProbeError::from_cause("connection", e),
+        };
+        Death::with_error(opened, error)
+    }
+
+    pub fn with_error(opened: Instant, error: ProbeError) -> Self {
+        Death {
+            at: Instant::now(),
+            age: opened.elapsed(),
+            error,
+        }
+    }
+
+    /// Details telling when the connection died and how long it lived.
+    pub fn details(&self) -> Vec<(&'static str, String)> {
+        let died_at = SystemTime::now() - self.at.elapsed();
+        vec![
+            (
+                "died_at",
+                humantime::format_rfc3339_micros(died_at).to_string(),
+            ),
+            age(self.age),
+        ]
+    }
+}
+
+/// Detail telling how long a connection has been open.
+pub fn age(age: Duration) -> (&'static str, String) {
+    ("age_ms", format!("{:.3}", age.as_secs_f64() * 1000.0))
+}
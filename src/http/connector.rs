@@ -0,0 +1,458 @@
+use std::{
+    fmt,
+    future::Future,
+    io,
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+use hyper::{
+    client::connect::{Connected, Connection},
+    service::Service,
+    Uri,
+};
+use log::trace;
+use rustls::pki_types::ServerName;
+#[cfg(unix)]
+use tokio::net::UnixStream;
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    net::TcpStream,
+};
+use tokio_rustls::{client::TlsStream, TlsConnector};
+
+use super::{proxy::Proxy, HttpVersion};
+use crate::{
+    hold::Death,
+    limit,
+    report::{Lifecycle, Origin, ProbeError},
+    resolve::{self, Resolver},
+    socket,
+    tls::{self, TlsArgs},
+};
+
+pub type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Timings of establishing a connection, attached to every response served over it.
+#[derive(Clone, Debug)]
+pub struct ConnectionInfo {
+    /// Time spent waiting for the connection limits before connecting, when there are any.
+    pub throttle: Option<Duration>,
+    /// Time taken to resolve the host.
+    pub dns: Duration,
+    /// Time taken to establish the TCP connection.
+    pub connect: Duration,
+    /// Address family the TCP connection was made over.
+    pub family: &'static str,
+    /// Family of the first address tried, if the connection fell back to the other family.
+    pub fallback_from: Option<&'static str>,
+    /// Time taken by the proxy to open a tunnel to the target, for `https` connections through an
+    /// HTTP proxy or any through a SOCKS5 proxy.
+    pub tunnel: Option<Duration>,
+    /// Time spent waiting for `--max-tls-handshakes-per-s` before the handshake, when set.
+    pub tls_throttle: Option<Duration>,
+    /// Time taken to complete the TLS handshake, for `https` connections.
+    pub tls: Option<Duration>,
+    /// When the connection became ready to send requests.
+    pub established_at: Instant,
+    /// Identity and socket addresses of the connection.
+    pub lifecycle: Arc<Lifecycle>,
+    uses: Arc<AtomicUsize>,
+    death: Arc<Mutex<Option<Death>>>,
+}
+
+impl ConnectionInfo {
+    /// Records a response served over this connection, returning whether it is the first one.
+    pub fn first_use(&self) -> bool {
+        self.uses.fetch_add(1, Ordering::Relaxed) == 0
+    }
+
+    /// Time taken by each phase of establishing the connection.
+    pub fn phases(&self) -> Vec<(&'static str, Duration)> {
+        let mut phases: Vec<_> = self
+            .throttle
+            .map(|throttle| ("throttle", throttle))
+            .into_iter()
+            .collect();
+        phases.push(("dns", self.dns));
+        phases.push(("connect", self.connect));
+        if let Some(tunnel) = self.tunnel {
+            phases.push(("tunnel", tunnel));
+        }
+        if let Some(tls_throttle) = self.tls_throttle {
+            phases.push(("tls_throttle", tls_throttle));
+        }
+        if let Some(tls) = self.tls {
+            phases.push(("tls", tls));
+        }
+        phases
+    }
+
+    /// How and when the connection died, once it has.
+    pub fn death(&self) -> Option<Death> {
+        self.death.lock().unwrap().clone()
+    }
+
+    /// Records the connection dying, unless it already has.
+    fn died(&self, death: impl FnOnce() -> Death) {
+        self.death.lock().unwrap().get_or_insert_with(death);
+    }
+}
+
+/// Error returned when a connection is not established within the connect timeout.
+#[derive(Debug)]
+pub struct ConnectTimeout(Duration);
+
+impl fmt::Display for ConnectTimeout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "connect timed out after {}ms", self.0.as_millis())
+    }
+}
+
+impl std::error::Error for ConnectTimeout {}
+
+/// Connector that resolves, connects and negotiates TLS itself so that each phase can be timed.
+#[derive(Clone)]
+pub struct TimingConnector {
+    tls: TlsConnector,
+    sni: Option<String>,
+    version: HttpVersion,
+    proxy: Option<Proxy>,
+    resolver: Arc<Resolver>,
+    /// Worker the connections are made for, to report their lifecycle against.
+    origin: Origin,
+    connect_timeout: Duration,
+    /// Unix domain socket to connect to rather than the host and port of the URL.
+    unix_socket: Option<PathBuf>,
+    /// Whether connections wait for the connection limits, which only those of probes do.
+    limited: bool,
+}
+
+impl TimingConnector {
+    pub fn new(
+        connect_timeout: Duration,
+        tls_args: &TlsArgs,
+        version: HttpVersion,
+        proxy: Option<Proxy>,
+        resolver: Arc<Resolver>,
+        origin: Origin,
+    ) -> Self {
+        let mut config = tls::configure(tls_args);
+        config.alpn_protocols = match version {
+            HttpVersion::Auto => vec![b"h2".to_vec(), b"http/1.1".to_vec()],
+            HttpVersion::Http1 => vec![b"http/1.1".to_vec()],
+            HttpVersion::H2 => vec![b"h2".to_vec()],
+        };
+
+        TimingConnector {
+            tls: TlsConnector::from(Arc::new(config)),
+            sni: tls_args.sni.clone(),
+            version,
+            proxy,
+            resolver,
+            origin,
+            connect_timeout,
+            unix_socket: None,
+            limited: true,
+        }
+    }
+
+    /// Connects to the Unix domain socket, if any, rather than to the host and port of the URL,
+    /// which still name the server in requests.
+    pub fn unix_socket(mut self, path: Option<PathBuf>) -> Self {
+        self.unix_socket = path;
+        self
+    }
+
+    /// Opens connections without waiting for the connection limits, for clients of the tool itself
+    /// rather than of the probe.
+    pub fn unlimited(mut self) -> Self {
+        self.limited = false;
+        self
+    }
+
+    async fn connect(self, uri: Uri) -> Result<Conn, BoxError> {
+        let https = match uri.scheme_str() {
+            Some("https") => true,
+            Some("http") => false,
+            _ => return Err(format!("unsupported scheme in {}", uri).into()),
+        };
+        let host = uri
+            .host()
+            .ok_or("missing host")?
+            .trim_start_matches('[')
+            .trim_end_matches(']');
+        let port = uri.port_u16().unwrap_or(if https { 443 } else { 80 });
+        if let Some(path) = &self.unix_socket {
+            if https {
+                return Err("https is not supported over a unix socket".into());
+            }
+            return self.connect_unix(path).await;
+        }
+
+        // Through a proxy, only the proxy is resolved and connected to.
+        let (peer_host, peer_port) = match &self.proxy {
+            Some(proxy) => (proxy.host.as_str(), proxy.port),
+            None => (host, port),
+        };
+        let start = Instant::now();
+        let addrs = self.resolver.lookup(peer_host, peer_port).await?;
+        let dns = start.elapsed();
+
+        let start = Instant::now();
+        let resolve::Connection {
+            stream: mut tcp,
+            family,
+            fallback_from,
+        } = resolve::connect_any(&addrs).await?;
+        let connect = start.elapsed();
+        tcp.set_nodelay(socket::nodelay(true))?;
+        let (local_addr, remote_addr) = (tcp.local_addr().ok(), tcp.peer_addr().ok());
+
+        let tunnel = match &self.proxy {
+            Some(proxy) if https || proxy.is_socks() => {
+                let start = Instant::now();
+                proxy.tunnel(&mut tcp, host, port, &self.resolver).await?;
+                Some(start.elapsed())
+            }
+            _ => None,
+        };
+
+        let tls_throttle = match (https, self.limited) {
+            (true, true) => limit::handshake().await.map(|(_, waited)| waited),
+            _ => None,
+        };
+        let (stream, tls) = if https {
+            let start = Instant::now();
+            let name = ServerName::try_from(self.sni.as_deref().unwrap_or(host).to_string())?;
+            let stream = self.tls.connect(name, tcp).await?;
+            if self.version == HttpVersion::H2 && stream.get_ref().1.alpn_protocol() != Some(b"h2")
+            {
+                return Err("server did not negotiate h2 with ALPN".into());
+            }
+            (Stream::Tls(Box::new(stream)), Some(start.elapsed()))
+        } else {
+            (Stream::Plain(tcp), None)
+        };
+
+        Ok(Conn {
+            stream,
+            // Requests over a cleartext connection to the proxy name the target in full.
+            proxied: self.proxy.as_ref().is_some_and(|proxy| !proxy.is_socks()) && !https,
+            info: ConnectionInfo {
+                throttle: None,
+                dns,
+                connect,
+                family,
+                fallback_from,
+                tunnel,
+                tls_throttle,
+                tls,
+                established_at: Instant::now(),
+                lifecycle: Arc::new(Lifecycle::opened(&self.origin, local_addr, remote_addr)),
+                uses: Arc::new(AtomicUsize::new(0)),
+                death: Arc::new(Mutex::new(None)),
+            },
+            error: None,
+        })
+    }
+
+    #[cfg(unix)]
+    async fn connect_unix(&self, path: &Path) -> Result<Conn, BoxError> {
+        let start = Instant::now();
+        let stream = UnixStream::connect(path)
+            .await
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+        let connect = start.elapsed();
+
+        Ok(Conn {
+            stream: Stream::Unix(stream),
+            proxied: false,
+            info: ConnectionInfo {
+                throttle: None,
+                dns: Duration::ZERO,
+                connect,
+                family: "unix",
+                fallback_from: None,
+                tunnel: None,
+                tls_throttle: None,
+                tls: None,
+                established_at: Instant::now(),
+                lifecycle: Arc::new(Lifecycle::opened(&self.origin, None, None)),
+                uses: Arc::new(AtomicUsize::new(0)),
+                death: Arc::new(Mutex::new(None)),
+            },
+            error: None,
+        })
+    }
+
+    #[cfg(not(unix))]
+    async fn connect_unix(&self, _path: &Path) -> Result<Conn, BoxError> {
+        Err("unix sockets are only supported on unix".into())
+    }
+}
+
+impl Service<Uri> for TimingConnector {
+    type Response = Conn;
+    type Error = BoxError;
+    type Future = Pin<Box<dyn Future<Output = Result<Conn, BoxError>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, uri: Uri) -> Self::Future {
+        let connector = self.clone();
+        let timeout = self.connect_timeout;
+        Box::pin(async move {
+            let throttle = match connector.limited {
+                true => Some(limit::acquire().await),
+                false => None,
+            };
+            match limit::timeout(timeout, connector.connect(uri)).await {
+                Ok(Ok(mut conn)) => {
+                    conn.info.throttle = throttle
+                        .and_then(|throttle| throttle.phase())
+                        .map(|(_, waited)| waited);
+                    Ok(conn)
+                }
+                Ok(Err(e)) => Err(e),
+                Err(_) => Err(ConnectTimeout(timeout).into()),
+            }
+        })
+    }
+}
+
+enum Stream {
+    Plain(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+    Tls(Box<TlsStream<TcpStream>>),
+}
+
+/// A connection established by [`TimingConnector`].
+pub struct Conn {
+    stream: Stream,
+    proxied: bool,
+    info: ConnectionInfo,
+    /// First error reading or writing, which closes the connection.
+    error: Option<ProbeError>,
+}
+
+impl Conn {
+    pub fn info(&self) -> &ConnectionInfo {
+        &self.info
+    }
+
+    /// Whether requests over the connection go to an HTTP proxy, and so name the target in full.
+    pub fn proxied(&self) -> bool {
+        self.proxied
+    }
+
+    fn record<T>(&mut self, poll: &Poll<io::Result<T>>) {
+        if let Poll::Ready(Err(e)) = poll {
+            self.error
+                .get_or_insert_with(|| ProbeError::from_cause("connection", e));
+            let opened = self.info.established_at;
+            self.info.died(|| Death::new(opened, Some(e)));
+        }
+    }
+}
+
+impl Drop for Conn {
+    fn drop(&mut self) {
+        // Connections hyper drops without an error or the peer closing them were closed by us.
+        let opened = self.info.established_at;
+        self.info
+            .died(|| Death::with_error(opened, ProbeError::new("dropped", "closed by the client")));
+        // Responses are only counted once the probe has read them, which may be after the
+        // connection is closed.
+        self.info.lifecycle.closed(None, self.error.as_ref());
+    }
+}
+
+impl Connection for Conn {
+    fn connected(&self) -> Connected {
+        let connected = Connected::new()
+            .proxy(self.proxied)
+            .extra(self.info.clone());
+        match &self.stream {
+            Stream::Tls(stream) if stream.get_ref().1.alpn_protocol() == Some(b"h2") => {
+                connected.negotiated_h2()
+            }
+            _ => connected,
+        }
+    }
+}
+
+impl AsyncRead for Conn {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let filled = buf.filled().len();
+        let poll = match &mut self.stream {
+            Stream::Plain(stream) => Pin::new(stream).poll_read(cx, buf),
+            #[cfg(unix)]
+            Stream::Unix(stream) => Pin::new(stream).poll_read(cx, buf),
+            Stream::Tls(stream) => Pin::new(stream).poll_read(cx, buf),
+        };
+        if let Poll::Ready(Ok(())) = poll {
+            trace!(
+                "read: {:?}",
+                String::from_utf8_lossy(&buf.filled()[filled..])
+            );
+            if buf.filled().len() == filled && buf.remaining() > 0 {
+                let opened = self.info.established_at;
+                self.info.died(|| Death::new(opened, None));
+            }
+        }
+        self.record(&poll);
+        poll
+    }
+}
+
+impl AsyncWrite for Conn {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let poll = match &mut self.stream {
+            Stream::Plain(stream) => Pin::new(stream).poll_write(cx, buf),
+            #[cfg(unix)]
+            Stream::Unix(stream) => Pin::new(stream).poll_write(cx, buf),
+            Stream::Tls(stream) => Pin::new(stream).poll_write(cx, buf),
+        };
+        if let Poll::Ready(Ok(n)) = poll {
+            trace!("write: {:?}", String::from_utf8_lossy(&buf[..n]));
+        }
+        self.record(&poll);
+        poll
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match &mut self.stream {
+            Stream::Plain(stream) => Pin::new(stream).poll_flush(cx),
+            #[cfg(unix)]
+            Stream::Unix(stream) => Pin::new(stream).poll_flush(cx),
+            Stream::Tls(stream) => Pin::new(stream).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match &mut self.stream {
+            Stream::Plain(stream) => Pin::new(stream).poll_shutdown(cx),
+            #[cfg(unix)]
+            Stream::Unix(stream) => Pin::new(stream).poll_shutdown(cx),
+            Stream::Tls(stream) => Pin::new(stream).poll_shutdown(cx),
+        }
+    }
+}
@@ -0,0 +1,93 @@
+use std::time::{Duration, Instant};
+
+use hyper::{body::HttpBody, Body};
+use tokio::time;
+
+use crate::report::ProbeError;
+
+/// A body read as it downloaded, without keeping it.
+pub struct Download {
+    pub bytes: u64,
+    /// Time from the first to the last byte of the body.
+    pub elapsed: Duration,
+    /// Goodput of every window of the download, in Mbit/s.
+    pub windows: Vec<f64>,
+    /// Why the download stopped before the end of the body, if it did.
+    pub stalled: Option<ProbeError>,
+}
+
+/// Reads a body to its end, measuring the goodput over windows of `window`, and gives up once no
+/// data arrives for `stall`.
+pub async fn download(
+    mut body: Body,
+    window: Duration,
+    stall: Option<Duration>,
+) -> Result<Download, hyper::Error> {
+    let start = Instant::now();
+    let mut download = Download {
+        bytes: 0,
+        elapsed: Duration::ZERO,
+        windows: vec![],
+        stalled: None,
+    };
+    let (mut window_start, mut window_bytes) = (start, 0);
+    loop {
+        let chunk = match stall {
+            Some(stall) => match time::timeout(stall, body.data()).await {
+                Ok(chunk) => chunk,
+                Err(_) => {
+                    download.stalled = Some(ProbeError::new(
+                        "stall",
+                        format!(
+                            "no data for {}ms after {} bytes",
+                            stall.as_millis(),
+                            download.bytes
+                        ),
+                    ));
+                    break;
+                }
+            },
+            None => body.data().await,
+        };
+        let Some(chunk) = chunk else {
+            break;
+        };
+        let len = chunk?.len() as u64;
+        download.bytes += len;
+        window_bytes += len;
+        // Windows end with the first chunk after them, so that a stall slows the one it is in.
+        let elapsed = window_start.elapsed();
+        if elapsed >= window {
+            download.windows.push(mbps(window_bytes, elapsed));
+            (window_start, window_bytes) = (Instant::now(), 0);
+        }
+    }
+    download.elapsed = start.elapsed();
+    Ok(download)
+}
+
+impl Download {
+    /// Goodput of the whole download, in Mbit/s.
+    pub fn goodput(&self) -> f64 {
+        mbps(self.bytes, self.elapsed)
+    }
+
+    /// Details telling how much was downloaded and how fast.
+    pub fn details(&self) -> Vec<(&'static str, String)> {
+        let mut details = vec![("goodput_mbps", format!("{:.3}", self.goodput()))];
+        if !self.windows.is_empty() {
+            let min = self.windows.iter().copied().fold(f64::INFINITY, f64::min);
+            let windows: Vec<_> = self.windows.iter().map(|w| format!("{:.3}", w)).collect();
+            details.push(("goodput_min_mbps", format!("{:.3}", min)));
+            details.push(("goodput_windows_mbps", windows.join(",")));
+        }
+        details
+    }
+}
+
+fn mbps(bytes: u64, elapsed: Duration) -> f64 {
+    if elapsed.is_zero() {
+        return 0.0;
+    }
+    bytes as f64 * 8.0 / elapsed.as_secs_f64() / 1_000_000.0
+}
@@ -0,0 +1,354 @@
+use std::{
+    io,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+use clap::Parser;
+use h2::{client::SendRequest, Ping, PingPong, Reason};
+use hyper::{body::Bytes, service::Service, Uri};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    sync::{self, oneshot},
+    time,
+};
+
+use super::{connector::Conn, ConnectTimeout, HttpVersion, Proxy, TimingConnector};
+use crate::{
+    probe::{self, CommonArgs, Probe},
+    report::{self, Attempt, Origin, ProbeError},
+    resolve::{ResolveArgs, Resolver},
+    tls::TlsArgs,
+};
+
+/// Length of the header of an HTTP/2 frame.
+const FRAME_HEADER: usize = 9;
+/// Type of a GOAWAY frame.
+const GOAWAY: u8 = 0x7;
+
+#[derive(Parser, Debug, Serialize, Deserialize)]
+pub struct H2PingArgs {
+    /// URL of the server, e.g. `https://example.com`. HTTP/2 is negotiated with ALPN over TLS,
+    /// and used with prior knowledge over cleartext.
+    #[arg(long)]
+    url: String,
+
+    /// Set a timeout for only the connect phase.
+    #[arg(long, default_value_t = 1000)]
+    connect_timeout_ms: u64,
+
+    /// Set a timeout for the server to answer each PING.
+    #[arg(long, default_value_t = 1000)]
+    timeout_ms: u64,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    tls: TlsArgs,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    resolve: ResolveArgs,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    pub common: CommonArgs,
+}
+
+/// Keeps an HTTP/2 connection open for every worker and sends a PING frame over it on every
+/// attempt, without making any requests. A connection is opened again once the server sends a
+/// GOAWAY or closes it.
+pub struct H2PingProbe {
+    uri: Uri,
+    connectors: Vec<TimingConnector>,
+    /// Connection of every worker, once it is open.
+    sessions: Vec<sync::Mutex<Option<Session>>>,
+    target: String,
+    timeout: Duration,
+}
+
+/// An HTTP/2 connection to send pings over.
+struct Session {
+    pings: PingPong,
+    /// Kept so the connection is not closed for having no handles left.
+    _requests: SendRequest<Bytes>,
+    /// Receives how the connection ended.
+    closed: oneshot::Receiver<Result<(), h2::Error>>,
+    /// Reason given by a GOAWAY the server sent, once it has.
+    goaway: Arc<Mutex<Option<Reason>>>,
+}
+
+impl H2PingProbe {
+    pub fn new(args: &H2PingArgs) -> Self {
+        let uri: Uri = args.url.parse().expect("invalid h2ping url");
+        let proxy = Proxy::for_uri(&uri, None, None, &[]);
+        let resolver = Arc::new(Resolver::new(&args.resolve));
+        let target = report::redact(&args.url);
+        // Create a connector for every worker so that their connections are reported apart.
+        let connectors = (0..args.common.parallel)
+            .map(|worker| {
+                let origin = Origin {
+                    probe: "h2ping",
+                    name: args.common.name.clone(),
+                    labels: args.common.labels(),
+                    target: target.clone(),
+                    worker,
+                };
+                TimingConnector::new(
+                    Duration::from_millis(args.connect_timeout_ms),
+                    &args.tls,
+                    HttpVersion::H2,
+                    proxy.clone(),
+                    resolver.clone(),
+                    origin,
+                )
+            })
+            .collect();
+
+        H2PingProbe {
+            uri,
+            connectors,
+            sessions: (0..args.common.parallel)
+                .map(|_| Default::default())
+                .collect(),
+            target,
+            timeout: Duration::from_millis(args.timeout_ms),
+        }
+    }
+
+    /// Opens a connection and completes the HTTP/2 handshake over it.
+    async fn open(
+        &self,
+        worker: usize,
+        phases: &mut Vec<(&'static str, Duration)>,
+        details: &mut Vec<(&'static str, String)>,
+    ) -> Result<Session, ProbeError> {
+        let mut connector = self.connectors[worker % self.connectors.len()].clone();
+        let conn = connector.call(self.uri.clone()).await.map_err(|e| {
+            let kind = match e.is::<ConnectTimeout>() {
+                true => "connect_timeout",
+                false => "connect",
+            };
+            ProbeError::from_cause(kind, &*e)
+        })?;
+        let info = conn.info().clone();
+        phases.extend(info.phases());
+        details.push(info.lifecycle.detail());
+        if let Some(addr) = info.lifecycle.remote_addr {
+            details.push(("remote_addr", addr.to_string()));
+        }
+        details.push(("family", info.family.to_string()));
+
+        let goaway = Arc::new(Mutex::new(None));
+        let start = Instant::now();
+        let (requests, mut connection) = h2::client::handshake(Watched::new(conn, goaway.clone()))
+            .await
+            .map_err(|e| ProbeError::from_cause("handshake", &e))?;
+        phases.push(("handshake", start.elapsed()));
+        let pings = connection
+            .ping_pong()
+            .expect("pings are only taken from a connection once");
+        let (sender, closed) = oneshot::channel();
+        tokio::spawn(async move {
+            let _ = sender.send(connection.await);
+        });
+
+        Ok(Session {
+            pings,
+            _requests: requests,
+            closed,
+            goaway,
+        })
+    }
+}
+
+impl Session {
+    /// Reason given by a GOAWAY the server sent, once it has.
+    fn goaway(&self) -> Option<Reason> {
+        *self.goaway.lock().unwrap()
+    }
+
+    /// How the connection ended, once the server sent a GOAWAY or it closed.
+    fn ended(&mut self) -> Option<ProbeError> {
+        if let Some(reason) = self.goaway() {
+            return Some(goaway(reason));
+        }
+        match self.closed.try_recv() {
+            Err(oneshot::error::TryRecvError::Empty) => None,
+            Ok(Err(e)) => Some(ProbeError::from_cause("connection", &e)),
+            _ => Some(ProbeError::new("connection", "closed by the server")),
+        }
+    }
+}
+
+impl Probe for H2PingProbe {
+    fn kind(&self) -> &'static str {
+        "h2ping"
+    }
+
+    fn target(&self) -> String {
+        self.target.clone()
+    }
+
+    async fn attempt(&self, worker: usize) -> Attempt {
+        // Pings of a worker take turns, as only one can be waiting for its answer at a time.
+        let mut session = self.sessions[worker % self.sessions.len()].lock().await;
+        let mut phases = vec![];
+        let mut details = vec![];
+
+        let start = Instant::now();
+        // A connection that ended since the last attempt is opened again, failing the attempt if
+        // the server gave an error for closing it.
+        let mut closed = None;
+        if let Some(ended) = session.as_mut().and_then(Session::ended) {
+            let reason = session.as_ref().and_then(Session::goaway);
+            match reason {
+                Some(reason) => details.push(("goaway", format!("{:?}", reason))),
+                None => details.push(("ended", ended.kind.to_string())),
+            }
+            closed = Some(ended).filter(|_| reason.is_some_and(|r| r != Reason::NO_ERROR));
+            *session = None;
+        }
+        let result = async {
+            let session = match &mut *session {
+                Some(session) => {
+                    details.push(("connection", "reused".to_string()));
+                    session
+                }
+                None => {
+                    details.push(("connection", "new".to_string()));
+                    let opened = self.open(worker, &mut phases, &mut details).await?;
+                    session.insert(opened)
+                }
+            };
+
+            let sent = Instant::now();
+            match time::timeout(self.timeout, session.pings.ping(Ping::opaque())).await {
+                Ok(Ok(_)) => {
+                    phases.push(("rtt", sent.elapsed()));
+                    Ok(())
+                }
+                Ok(Err(e)) => Err(session.ended().unwrap_or_else(|| {
+                    let kind = if e.is_io() { "connection" } else { "ping" };
+                    ProbeError::from_cause(kind, &e)
+                })),
+                Err(_) => Err(ProbeError::new(
+                    "ping_timeout",
+                    format!("no PING answer within {}ms", self.timeout.as_millis()),
+                )),
+            }
+        }
+        .await;
+        // A connection a ping failed over is not trusted with the next one.
+        if result.is_err() {
+            *session = None;
+        }
+
+        Attempt {
+            duration: start.elapsed(),
+            phases,
+            details,
+            error: result.err().or(closed),
+        }
+    }
+}
+
+pub async fn h2ping_main(args: H2PingArgs) {
+    probe::run(H2PingProbe::new(&args), &args.common).await
+}
+
+fn goaway(reason: Reason) -> ProbeError {
+    ProbeError::new("goaway", format!("server sent GOAWAY with {:?}", reason))
+}
+
+/// Connection that reads the frames the server sends for a GOAWAY, as h2 reports a connection
+/// the server closed with one carrying no error the same as one it just closed.
+struct Watched {
+    conn: Conn,
+    /// Header of the frame being read, and how much of it has been read.
+    header: [u8; FRAME_HEADER],
+    read: usize,
+    /// Length of the payload of the frame left to read.
+    left: usize,
+    /// Start of the payload of a GOAWAY being read: the last stream id, then the error code.
+    payload: Vec<u8>,
+    goaway: Arc<Mutex<Option<Reason>>>,
+}
+
+impl Watched {
+    fn new(conn: Conn, goaway: Arc<Mutex<Option<Reason>>>) -> Self {
+        Watched {
+            conn,
+            header: [0; FRAME_HEADER],
+            read: 0,
+            left: 0,
+            payload: Vec::with_capacity(8),
+            goaway,
+        }
+    }
+
+    fn scan(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            if self.read < FRAME_HEADER {
+                self.header[self.read] = byte;
+                self.read += 1;
+                if self.read == FRAME_HEADER {
+                    let [a, b, c, ..] = self.header;
+                    self.left = u32::from_be_bytes([0, a, b, c]) as usize;
+                    self.payload.clear();
+                }
+            } else {
+                self.left -= 1;
+                if self.header[3] == GOAWAY && self.payload.len() < 8 {
+                    self.payload.push(byte);
+                }
+            }
+
+            if self.read == FRAME_HEADER && self.left == 0 {
+                if self.header[3] == GOAWAY && self.payload.len() == 8 {
+                    let code = u32::from_be_bytes(self.payload[4..8].try_into().unwrap());
+                    self.goaway
+                        .lock()
+                        .unwrap()
+                        .get_or_insert(Reason::from(code));
+                }
+                self.read = 0;
+            }
+        }
+    }
+}
+
+impl AsyncRead for Watched {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let filled = buf.filled().len();
+        let poll = Pin::new(&mut self.conn).poll_read(cx, buf);
+        if let Poll::Ready(Ok(())) = poll {
+            self.scan(&buf.filled()[filled..]);
+        }
+        poll
+    }
+}
+
+impl AsyncWrite for Watched {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.conn).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.conn).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.conn).poll_shutdown(cx)
+    }
+}
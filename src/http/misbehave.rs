@@ -0,0 +1,94 @@
+use std::{fmt::Write, time::Instant};
+
+use hyper::{
+    body::Bytes,
+    header::{CONTENT_LENGTH, HOST},
+    service::Service,
+    Body, Request,
+};
+
+use super::{ConnectTimeout, HttpProbe, TimingConnector, TunnelRefused};
+use crate::{
+    misbehave::Misbehave,
+    report::{Attempt, ProbeError},
+};
+
+impl HttpProbe {
+    /// Opens a connection for the request and misbehaves on it, sending the request over it the
+    /// way `--misbehave` asks to rather than waiting for a response.
+    pub(super) async fn misbehave(
+        &self,
+        misbehave: &Misbehave,
+        connector: &TimingConnector,
+        req: Request<Body>,
+        body: &Bytes,
+    ) -> Attempt {
+        let start = Instant::now();
+        let conn = match connector.clone().call(req.uri().clone()).await {
+            Ok(conn) => conn,
+            Err(e) => {
+                let kind = if e.is::<ConnectTimeout>() {
+                    "connect_timeout"
+                } else if let Some(tunnel) = e.downcast_ref::<TunnelRefused>() {
+                    tunnel.kind
+                } else {
+                    "connect"
+                };
+                return Attempt {
+                    duration: start.elapsed(),
+                    phases: vec![],
+                    details: self.proxy_detail().into_iter().collect(),
+                    error: Some(ProbeError::from_cause(kind, &*e)),
+                };
+            }
+        };
+
+        let info = conn.info().clone();
+        let head = head(&req, conn.proxied(), body);
+        let mut payload = head.into_bytes();
+        payload.extend_from_slice(body);
+        let misbehaved = misbehave.run(conn, &payload).await;
+
+        let mut phases = info.phases();
+        phases.push(("held", misbehaved.held));
+        let mut details = misbehaved.details(misbehave);
+        details.push(info.lifecycle.detail());
+        details.push(("family", info.family.to_string()));
+        details.extend(self.proxy_detail());
+        Attempt {
+            duration: start.elapsed(),
+            phases,
+            details,
+            error: misbehaved.error(),
+        }
+    }
+}
+
+/// Writes out the head of an HTTP/1.1 request, naming the target in full if it is `proxied`.
+fn head(req: &Request<Body>, proxied: bool, body: &Bytes) -> String {
+    let uri = req.uri();
+    let target = match (proxied, uri.path_and_query()) {
+        (true, _) => uri.to_string(),
+        (false, Some(path)) => path.to_string(),
+        (false, None) => "/".to_string(),
+    };
+    let mut head = format!("{} {} HTTP/1.1\r\n", req.method(), target);
+    if !req.headers().contains_key(HOST) {
+        if let Some(authority) = uri.authority() {
+            let _ = write!(head, "host: {}\r\n", authority);
+        }
+    }
+    for (name, value) in req.headers() {
+        let _ = write!(
+            head,
+            "{}: {}\r\n",
+            name,
+            String::from_utf8_lossy(value.as_bytes())
+        );
+    }
+    if !body.is_empty() && !req.headers().contains_key(CONTENT_LENGTH) {
+        let _ = write!(head, "content-length: {}\r\n", body.len());
+    }
+    head.push_str("\r\n");
+    head
+}
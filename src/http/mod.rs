@@ -0,0 +1,1252 @@
+mod connector;
+mod download;
+mod h2ping;
+mod misbehave;
+mod oauth;
+mod proxy;
+mod record;
+mod redirect;
+mod scenario;
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    error::Error,
+    fs,
+    net::SocketAddr,
+    ops::RangeInclusive,
+    path::PathBuf,
+    sync::{atomic::AtomicU64, Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use clap::{Parser, ValueEnum};
+use hyper::{
+    body::Bytes,
+    client::connect::{capture_connection, CaptureConnection},
+    header::{HeaderName, HeaderValue, AUTHORIZATION, CONTENT_TYPE, PROXY_AUTHORIZATION},
+    http::Extensions,
+    Body, Client, HeaderMap, Method, Request, StatusCode, Uri, Version,
+};
+use log::{info, warn};
+use regex::bytes::Regex;
+use serde::{Deserialize, Deserializer, Serialize};
+use tokio::{task::JoinSet, time};
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    ab,
+    discover::{DiscoverArgs, Discovery},
+    hold::{self, HoldArgs},
+    misbehave::{Misbehave, MisbehaveArgs},
+    ports,
+    probe::{self, CommonArgs, Probe},
+    report::{self, Attempt, Origin, ProbeError},
+    resolve::{ResolveArgs, Resolver},
+    secret, stats,
+    template::{Template, Vars},
+    tls::TlsArgs,
+};
+pub(crate) use connector::{ConnectTimeout, ConnectionInfo, TimingConnector};
+use download::Download;
+pub use h2ping::{h2ping_main, H2PingArgs, H2PingProbe};
+use oauth::OAuth2;
+pub(crate) use proxy::Proxy;
+pub(crate) use proxy::TunnelRefused;
+pub use record::{replay_main, ReplayArgs};
+use record::{Received, Recorder, Sent};
+use redirect::Hop;
+pub use scenario::{scenario_main, ScenarioArgs, ScenarioProbe};
+
+/// Shortest connect timeout for fetching OAuth2 tokens, as `--connect-timeout-ms` is usually
+/// tuned for the target alone.
+const TOKEN_CONNECT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// HTTP version used for requests.
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum, Serialize, Deserialize)]
+pub enum HttpVersion {
+    /// Negotiate HTTP/2 or HTTP/1.1 with ALPN over TLS, and use HTTP/1.1 over cleartext.
+    #[default]
+    #[serde(rename = "auto")]
+    Auto,
+    /// Only use HTTP/1.1.
+    #[value(name = "http1.1")]
+    #[serde(rename = "http1.1")]
+    Http1,
+    /// Only use HTTP/2, negotiated with ALPN over TLS or with prior knowledge over cleartext.
+    #[serde(rename = "h2")]
+    H2,
+}
+
+/// How workers are spread over several targets.
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Distribute {
+    /// Run `--parallel` workers against every target.
+    #[default]
+    PerTarget,
+    /// Deal `--parallel` workers out to the targets in turn, so they are shared between them.
+    RoundRobin,
+}
+
+#[derive(Parser, Debug, Serialize, Deserialize)]
+pub struct HttpArgs {
+    /// Set a timeout for only the connect phase of a `Client`.
+    #[arg(long, default_value_t = 15)]
+    connect_timeout_ms: u64,
+
+    /// Enables a request timeout.
+    #[arg(long, default_value_t = 20)]
+    timeout_ms: u64,
+
+    /// Keep a connection alive for every worker and reuse it for each attempt, instead of opening
+    /// a fresh connection every time. Requests failing on a reused connection are not retried.
+    #[arg(long)]
+    reuse_connections: bool,
+
+    /// Probe through a rolling deploy, telling failures on connections reused from earlier
+    /// attempts apart from failures on new ones with `connection`, and marking requests that were
+    /// in flight on a connection when it failed with `dropped`. Whether any were dropped is
+    /// reported at the end. Implies `--reuse-connections`.
+    #[arg(long)]
+    drain: bool,
+
+    /// Set a timeout for idle sockets being kept-alive.
+    /// The default is set to effectively have no idle connections in the pool,
+    /// or to never time out with `--reuse-connections`.
+    #[arg(long)]
+    pool_idle_timeout_us: Option<u64>,
+
+    /// Sets the maximum idle connection per host allowed in the pool.
+    #[arg(long, default_value_t = 1)]
+    pool_max_idle_per_host: usize,
+
+    /// URL to send requests to. Can be repeated to probe several targets at once.
+    /// Placeholders like `{{seq}}`, `{{uuid}}`, `{{timestamp}}` and `{{rand_int(min,max)}}` are
+    /// expanded on every attempt, as they are in the body.
+    #[arg(long, required_unless_present = "url_file")]
+    #[serde(default, deserialize_with = "one_or_many")]
+    url: Vec<String>,
+
+    /// Also send requests to every URL in this file, one per line. Blank lines and lines starting
+    /// with `#` are ignored.
+    #[arg(long)]
+    url_file: Option<PathBuf>,
+
+    /// How workers are spread over the URLs when there are several.
+    #[arg(long, value_enum, default_value_t)]
+    distribute: Distribute,
+
+    /// Compare the two URLs given, probing both from the same workers on the same schedule and
+    /// summarizing the paired differences in their latency and error rate, e.g. before moving
+    /// traffic from the first to the second.
+    #[arg(long, conflicts_with = "distribute")]
+    compare: bool,
+
+    /// HTTP version to use.
+    #[arg(long, value_enum, default_value_t)]
+    http_version: HttpVersion,
+
+    /// HTTP method of the request.
+    #[arg(long, default_value = "GET")]
+    method: String,
+
+    /// Add a header to the request, as `K: V`. Can be repeated.
+    /// From the environment, `ARTEMISS_HEADER` takes a single header or a list like `[K: V, K: V]`.
+    #[arg(long)]
+    #[serde(default, deserialize_with = "one_or_many")]
+    header: Vec<String>,
+
+    /// Send this as the request body, expanding any placeholders on every attempt.
+    #[arg(long, conflicts_with = "body_file")]
+    body: Option<String>,
+
+    /// Send the contents of this file as the request body.
+    #[arg(long)]
+    body_file: Option<PathBuf>,
+
+    /// Set the `Content-Type` header of the request.
+    #[arg(long)]
+    content_type: Option<String>,
+
+    /// Count a response as failed unless its status is in one of these codes or ranges,
+    /// e.g. `200-299,304`. Can be repeated. Without it, only `200-399` count as succeeded.
+    #[arg(long)]
+    #[serde(default, deserialize_with = "one_or_many")]
+    expect_status: Vec<String>,
+
+    /// Count a response as failed unless it has this header, as `K: V` for the header with that
+    /// value, or `K` for the header with any value. Can be repeated.
+    #[arg(long)]
+    #[serde(default, deserialize_with = "one_or_many")]
+    expect_header: Vec<String>,
+
+    /// Report the value of this response header in a `header.<name>` detail, e.g. to tell which
+    /// backend or POP served the response. Can be repeated.
+    #[arg(long)]
+    #[serde(default, deserialize_with = "one_or_many")]
+    capture_header: Vec<String>,
+
+    /// Count a response as failed unless its body contains this.
+    #[arg(long)]
+    expect_body: Option<String>,
+
+    /// Count a response as failed unless its body matches this regex.
+    #[arg(long)]
+    expect_body_regex: Option<String>,
+
+    /// Follow up to this many redirects, reporting the status, location and latency of each in
+    /// the `redirect_chain` detail and their total time as the `redirects` phase. Another redirect
+    /// after that many fails the attempt with `redirect_limit`, and one back to a URL already
+    /// visited with `redirect_loop`.
+    #[arg(long, default_value_t = 10)]
+    max_redirects: usize,
+
+    /// Report redirects as the response instead of following them.
+    #[arg(long, conflicts_with = "max_redirects")]
+    no_follow_redirects: bool,
+
+    /// Measure throughput by downloading the body as it arrives without keeping it, reporting
+    /// its goodput overall and over every `--download-window-ms` in the `goodput_*` details, and
+    /// the time from its first to its last byte as the `download` phase. Raise `--timeout-ms` to
+    /// allow for the whole download.
+    #[arg(long, conflicts_with_all = ["expect_body", "expect_body_regex"])]
+    download: bool,
+
+    /// Length of the windows download goodput is measured over.
+    #[arg(long, default_value_t = 1000, requires = "download")]
+    download_window_ms: u64,
+
+    /// Fail a download with `stall` once no data arrives for this long, to tell throughput
+    /// collapsing apart from the request failing.
+    #[arg(long, requires = "download")]
+    stall_timeout_ms: Option<u64>,
+
+    /// Authenticate with HTTP basic authentication, as `user:password`.
+    #[arg(long, conflicts_with_all = ["bearer_token", "oauth2_token_url"])]
+    basic_auth: Option<String>,
+
+    /// Send this token in an `Authorization: Bearer` header.
+    #[arg(long, conflicts_with = "oauth2_token_url")]
+    bearer_token: Option<String>,
+
+    /// Send the token in this file in an `Authorization: Bearer` header, to keep it off the
+    /// command line. With the `vault` or `aws-secrets` features, the token can instead be read
+    /// from `vault:PATH#FIELD` or `aws-sm:ID[#FIELD]`.
+    #[arg(long, conflicts_with_all = ["bearer_token", "basic_auth", "oauth2_token_url"])]
+    token_file: Option<String>,
+
+    /// Fetch access tokens from this token endpoint with the OAuth2 client credentials grant,
+    /// and send them in an `Authorization: Bearer` header. Tokens are refreshed shortly before
+    /// they expire, or after a request with one gets a 401, and fetching them is timed as the
+    /// `token` phase rather than counted in the latency of the attempt.
+    #[arg(long, requires_all = ["client_id", "client_secret"])]
+    oauth2_token_url: Option<String>,
+
+    /// Client ID to fetch tokens with, for `--oauth2-token-url`.
+    #[arg(long, requires = "oauth2_token_url")]
+    client_id: Option<String>,
+
+    /// Client secret to fetch tokens with, for `--oauth2-token-url`.
+    #[arg(long, requires = "oauth2_token_url")]
+    client_secret: Option<String>,
+
+    /// Scope to request tokens for, for `--oauth2-token-url`.
+    #[arg(long, requires = "oauth2_token_url")]
+    oauth2_scope: Option<String>,
+
+    /// Send requests through this proxy, as `http://[user:password@]host:port`, or `socks5://` or
+    /// `socks5h://` for a SOCKS5 proxy resolving the host itself. Defaults to
+    /// `HTTPS_PROXY` or `HTTP_PROXY` from the environment, matching the scheme of the URL, or else
+    /// `ALL_PROXY`.
+    #[arg(long)]
+    proxy: Option<String>,
+
+    /// Connect to the server over this Unix domain socket rather than the host and port of the
+    /// URL, which still name it in requests, e.g. `/var/run/docker.sock` with
+    /// `--url http://localhost/_ping`. Only `http://` URLs are supported over it.
+    #[arg(long, conflicts_with_all = ["proxy", "discover"])]
+    unix_socket: Option<PathBuf>,
+
+    /// Credentials for the proxy, as `user:password`.
+    #[arg(long)]
+    proxy_auth: Option<String>,
+
+    /// Send requests to these hosts directly rather than through the proxy, e.g.
+    /// `localhost,.internal` or `*` for every host. Can be repeated or separated by commas.
+    /// Defaults to `NO_PROXY` from the environment.
+    #[arg(long, value_delimiter = ',')]
+    #[serde(default, deserialize_with = "one_or_many")]
+    no_proxy: Vec<String>,
+
+    /// Write the request and response of every failed attempt to a JSON file in this directory,
+    /// with credentials redacted, to re-issue later with `artemiss replay`.
+    #[arg(long)]
+    record: Option<PathBuf>,
+
+    /// Keep up to this many bytes of the request and response bodies in recordings.
+    #[arg(long, default_value_t = 0, requires = "record")]
+    record_body_bytes: usize,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    hold: HoldArgs,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    discover: DiscoverArgs,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    misbehave: MisbehaveArgs,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    tls: TlsArgs,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    resolve: ResolveArgs,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    pub common: CommonArgs,
+}
+
+pub struct HttpProbe {
+    clients: Vec<Client<TimingConnector>>,
+    method: Method,
+    uri: Uri,
+    /// URL to render on every attempt instead of `uri`, if it has placeholders.
+    url_template: Option<Template>,
+    headers: HeaderMap,
+    body: Bytes,
+    /// Body to render on every attempt instead of `body`, if it has placeholders.
+    body_template: Option<Template>,
+    sequence: AtomicU64,
+    expect_status: Vec<RangeInclusive<u16>>,
+    /// Headers a response must have, with the value they must have if given.
+    expect_headers: Vec<(HeaderName, Option<String>)>,
+    /// Headers reported from every response, with the detail they are reported as.
+    capture_headers: Vec<(HeaderName, &'static str)>,
+    expect_body: Option<String>,
+    expect_body_regex: Option<Regex>,
+    /// Most redirects followed, or `None` not to follow them.
+    max_redirects: Option<usize>,
+    /// Window to measure goodput over and time to give up after without data, with `--download`.
+    download: Option<(Duration, Option<Duration>)>,
+    oauth2: Option<OAuth2>,
+    proxy: Option<Proxy>,
+    recorder: Option<Recorder>,
+    /// Connection each worker last made a request over, with `--hold`.
+    held: Option<Vec<std::sync::Mutex<Option<ConnectionInfo>>>>,
+    /// How to misbehave on connections, and the connector opening them for every worker, with
+    /// `--misbehave`.
+    misbehave: Option<(Misbehave, Vec<TimingConnector>)>,
+    /// Whether to tell failed requests sent over new connections from those sent over reused
+    /// ones, with `--drain`.
+    drain: bool,
+    target: String,
+    timeout: Duration,
+}
+
+/// Final response of an attempt, after following any redirects.
+struct Response {
+    status: StatusCode,
+    version: Version,
+    headers: HeaderMap,
+    info: Option<ConnectionInfo>,
+    /// When the request the response is to was sent.
+    sent_at: Instant,
+    first_byte: Instant,
+    /// Body of the response, unless it was downloaded.
+    body: Bytes,
+    download: Option<Download>,
+    hops: Vec<Hop>,
+    /// Why redirects stopped being followed before getting a response that is not one, or the
+    /// download stalled.
+    stopped: Option<ProbeError>,
+}
+
+impl HttpArgs {
+    /// Every URL to send requests to, from `--url` and then `--url-file`.
+    pub fn urls(&self) -> Vec<String> {
+        let mut urls = self.url.clone();
+        if let Some(path) = &self.url_file {
+            let file = fs::read_to_string(path).expect("unable to read url file");
+            urls.extend(
+                file.lines()
+                    .map(str::trim)
+                    .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                    .map(str::to_string),
+            );
+        }
+        urls
+    }
+}
+
+impl HttpProbe {
+    /// Creates a probe sending requests to one of the URLs of the arguments.
+    pub fn new(args: &HttpArgs, url: &str) -> Self {
+        Self::build(args, url, None)
+    }
+
+    /// Creates a probe sending requests to one of the URLs of the arguments, connecting to this
+    /// instance of its host if given.
+    fn build(args: &HttpArgs, url: &str, instance: Option<SocketAddr>) -> Self {
+        let url_template = Template::parse(url).expect("invalid url template");
+        // A URL with placeholders is checked, and its proxy picked, by how it first renders.
+        let uri: Uri = url_template
+            .render_string(&Vars::next(&AtomicU64::new(0)))
+            .parse()
+            .expect("invalid url");
+        let proxy = Proxy::for_uri(
+            &uri,
+            args.proxy.as_deref(),
+            args.proxy_auth.as_deref(),
+            &args.no_proxy,
+        );
+        // Holding a connection is reusing it for as long as it lives.
+        let reuse_connections = args.reuse_connections || args.hold.hold || args.drain;
+        let pool_idle_timeout = match (args.pool_idle_timeout_us, reuse_connections) {
+            (Some(timeout), _) => Some(Duration::from_micros(timeout)),
+            (None, true) => None,
+            (None, false) => Some(Duration::from_micros(1)),
+        };
+
+        let mut resolver = Resolver::new(&args.resolve);
+        let mut target = report::redact(url);
+        let mut labels = args.common.labels();
+        if let Some(addr) = instance {
+            let host = uri.host().unwrap_or_default();
+            resolver = resolver.pin(host.trim_start_matches('[').trim_end_matches(']'), addr);
+            target = format!("{}#{}", target, addr);
+            Arc::make_mut(&mut labels).insert("instance".to_string(), addr.to_string());
+        }
+        let resolver = Arc::new(resolver);
+        // Create a client for every worker so that they do not share connections
+        let origin = |worker| Origin {
+            probe: "http",
+            name: args.common.name.clone(),
+            labels: labels.clone(),
+            target: target.clone(),
+            worker,
+        };
+        let connectors: Vec<_> = (0..args.common.parallel)
+            .map(|worker| {
+                TimingConnector::new(
+                    Duration::from_millis(args.connect_timeout_ms),
+                    &args.tls,
+                    args.http_version,
+                    proxy.clone(),
+                    resolver.clone(),
+                    origin(worker),
+                )
+                .unix_socket(args.unix_socket.clone())
+            })
+            .collect();
+        let clients = connectors
+            .into_iter()
+            .map(|connector| {
+                Client::builder()
+                    .pool_idle_timeout(pool_idle_timeout)
+                    .pool_max_idle_per_host(args.pool_max_idle_per_host)
+                    .retry_canceled_requests(!reuse_connections)
+                    .http2_only(args.http_version == HttpVersion::H2)
+                    .build::<_, Body>(connector)
+            })
+            .collect();
+        let misbehave = Misbehave::new(&args.misbehave).map(|misbehave| {
+            assert!(
+                !args.hold.hold && args.http_version != HttpVersion::H2,
+                "--misbehave cannot be used with --hold or --http-version h2"
+            );
+            // Requests are written out by hand, so connections only ever speak HTTP/1.1.
+            let connectors = (0..args.common.parallel)
+                .map(|worker| {
+                    TimingConnector::new(
+                        Duration::from_millis(args.connect_timeout_ms),
+                        &args.tls,
+                        HttpVersion::Http1,
+                        proxy.clone(),
+                        resolver.clone(),
+                        origin(worker),
+                    )
+                    .unix_socket(args.unix_socket.clone())
+                })
+                .collect();
+            (misbehave, connectors)
+        });
+
+        let mut headers = HeaderMap::new();
+        for header in &args.header {
+            let (name, value) = header.split_once(':').expect("header must be `K: V`");
+            headers.append(
+                HeaderName::try_from(name.trim()).expect("invalid header name"),
+                HeaderValue::try_from(value.trim()).expect("invalid header value"),
+            );
+        }
+        let bearer_token =
+            (args.bearer_token.clone()).or_else(|| args.token_file.as_deref().map(secret::read));
+        if let Some((_, password)) = args.basic_auth.as_deref().and_then(|c| c.split_once(':')) {
+            secret::register(password);
+        }
+        for secret in [&bearer_token, &args.client_secret].into_iter().flatten() {
+            secret::register(secret);
+        }
+        let authorization = match (&args.basic_auth, &bearer_token) {
+            (Some(credentials), _) => Some(format!("Basic {}", STANDARD.encode(credentials))),
+            (_, Some(token)) => Some(format!("Bearer {}", token)),
+            _ => None,
+        };
+        if let Some(authorization) = authorization {
+            headers.insert(
+                AUTHORIZATION,
+                HeaderValue::try_from(authorization).expect("invalid credentials"),
+            );
+        }
+        if let Some(content_type) = &args.content_type {
+            headers.insert(
+                CONTENT_TYPE,
+                HeaderValue::try_from(content_type).expect("invalid content type"),
+            );
+        }
+        // Over a tunnel, the proxy only sees the credentials in the `CONNECT` request.
+        if let Some(authorization) = proxy.as_ref().and_then(|proxy| proxy.authorization.clone()) {
+            if uri.scheme_str() != Some("https") {
+                headers.insert(PROXY_AUTHORIZATION, authorization);
+            }
+        }
+
+        let body = match (&args.body, &args.body_file) {
+            (Some(body), _) => Bytes::from(body.clone()),
+            (None, Some(path)) => Bytes::from(fs::read(path).expect("unable to read body file")),
+            (None, None) => Bytes::new(),
+        };
+        let body_template = Template::parse(body.to_vec()).expect("invalid body template");
+
+        let oauth2 = args.oauth2_token_url.as_deref().map(|url| {
+            let token_uri: Uri = url.parse().expect("invalid oauth2 token url");
+            let origin = Origin {
+                probe: "oauth2",
+                name: args.common.name.clone(),
+                labels: labels.clone(),
+                target: report::redact(url),
+                worker: 0,
+            };
+            let connector = TimingConnector::new(
+                Duration::from_millis(args.connect_timeout_ms).max(TOKEN_CONNECT_TIMEOUT),
+                &args.tls,
+                HttpVersion::Auto,
+                Proxy::for_uri(&token_uri, args.proxy.as_deref(), None, &args.no_proxy),
+                resolver.clone(),
+                origin,
+            );
+            OAuth2::new(
+                Client::builder().build(connector),
+                url,
+                args.client_id.as_deref().unwrap_or_default(),
+                args.client_secret.as_deref().unwrap_or_default(),
+                args.oauth2_scope.clone(),
+            )
+        });
+
+        HttpProbe {
+            clients,
+            method: args.method.parse().expect("invalid method"),
+            uri,
+            url_template: (!url_template.is_static()).then_some(url_template),
+            headers,
+            body,
+            body_template: (!body_template.is_static()).then_some(body_template),
+            sequence: AtomicU64::new(0),
+            expect_status: match args.expect_status.is_empty() {
+                true => vec![200..=399],
+                false => args
+                    .expect_status
+                    .iter()
+                    .flat_map(|statuses| statuses.split(','))
+                    .map(parse_status_range)
+                    .collect(),
+            },
+            expect_headers: args
+                .expect_header
+                .iter()
+                .map(|header| {
+                    let (name, value) = match header.split_once(':') {
+                        Some((name, value)) => (name, Some(value.trim().to_string())),
+                        None => (header.as_str(), None),
+                    };
+                    let name = HeaderName::try_from(name.trim()).expect("invalid header name");
+                    (name, value)
+                })
+                .collect(),
+            capture_headers: args
+                .capture_header
+                .iter()
+                .map(|name| {
+                    let name = HeaderName::try_from(name.trim()).expect("invalid header name");
+                    let detail = capture_detail(&name);
+                    (name, detail)
+                })
+                .collect(),
+            expect_body: args.expect_body.clone(),
+            expect_body_regex: args
+                .expect_body_regex
+                .as_deref()
+                .map(|regex| Regex::new(regex).expect("invalid body regex")),
+            max_redirects: (!args.no_follow_redirects).then_some(args.max_redirects),
+            download: args.download.then(|| {
+                (
+                    Duration::from_millis(args.download_window_ms),
+                    args.stall_timeout_ms.map(Duration::from_millis),
+                )
+            }),
+            oauth2,
+            proxy,
+            recorder: args
+                .record
+                .clone()
+                .map(|dir| Recorder::new(dir, args.record_body_bytes)),
+            held: args.hold.hold.then(|| {
+                (0..args.common.parallel)
+                    .map(|_| Default::default())
+                    .collect()
+            }),
+            misbehave,
+            drain: args.drain,
+            target,
+            timeout: Duration::from_millis(args.timeout_ms),
+        }
+    }
+}
+
+impl Probe for HttpProbe {
+    fn kind(&self) -> &'static str {
+        "http"
+    }
+
+    fn target(&self) -> String {
+        self.target.clone()
+    }
+
+    async fn attempt(&self, worker: usize) -> Attempt {
+        let client = &self.clients[worker % self.clients.len()];
+        if let Some(held) = &self.held {
+            let mut held = held[worker % held.len()].lock().unwrap();
+            if let Some(info) = held.take_if(|info| info.death().is_some()) {
+                // The attempt after a held connection died reports it, and the next opens another.
+                let death = info.death().unwrap();
+                let mut details = vec![info.lifecycle.detail()];
+                details.extend(death.details());
+                return Attempt {
+                    details,
+                    ..failed(death.error)
+                };
+            }
+        }
+        let vars = Vars::next(&self.sequence);
+        let uri = match &self.url_template {
+            Some(template) => {
+                let url = template.render_string(&vars);
+                match url.parse() {
+                    Ok(uri) => uri,
+                    Err(e) => {
+                        let message = format!("invalid url {}: {}", url, e);
+                        return failed(ProbeError::new("template", message));
+                    }
+                }
+            }
+            None => self.uri.clone(),
+        };
+        let body = match &self.body_template {
+            Some(template) => Bytes::from(template.render(&vars)),
+            None => self.body.clone(),
+        };
+        let mut req = Request::new(Body::from(body.clone()));
+        *req.method_mut() = self.method.clone();
+        *req.uri_mut() = uri;
+        *req.headers_mut() = self.headers.clone();
+
+        let mut token = None;
+        let mut authorization = None;
+        if let Some(oauth2) = &self.oauth2 {
+            match oauth2.authorization().await {
+                Ok((value, fetched)) => {
+                    req.headers_mut().insert(AUTHORIZATION, value.clone());
+                    authorization = Some(value);
+                    token = fetched;
+                }
+                Err(error) => return failed(error),
+            }
+        }
+        if let Some((misbehave, connectors)) = &self.misbehave {
+            let connector = &connectors[worker % connectors.len()];
+            return self.misbehave(misbehave, connector, req, &body).await;
+        }
+
+        let sent = self.recorder.as_ref().map(|_| Sent {
+            method: req.method().clone(),
+            uri: req.uri().clone(),
+            headers: req.headers().clone(),
+            body: body.clone(),
+        });
+        let mut received = None;
+
+        // Connection the last request was sent over, for failures to be told apart by with `--drain`.
+        let mut captured = None;
+        let start = Instant::now();
+        let first = req.uri().clone();
+        let request = async {
+            let mut req = req;
+            let mut hops = vec![];
+            let mut visited = vec![first.clone()];
+            loop {
+                let (method, uri, headers) = (
+                    req.method().clone(),
+                    req.uri().clone(),
+                    req.headers().clone(),
+                );
+                if self.drain {
+                    captured = Some(capture_connection(&mut req));
+                }
+                let sent_at = Instant::now();
+                let res = client.request(req).await?;
+                let first_byte = Instant::now();
+                let status = res.status();
+                let version = res.version();
+                let info = res.extensions().get::<ConnectionInfo>().cloned();
+                let (parts, response_body) = res.into_parts();
+                let location = self
+                    .max_redirects
+                    .and_then(|_| redirect::location(status, &parts.headers));
+                let mut response = Response {
+                    status,
+                    version,
+                    headers: parts.headers,
+                    info,
+                    sent_at,
+                    first_byte,
+                    body: Bytes::new(),
+                    download: None,
+                    hops: vec![],
+                    stopped: None,
+                };
+                let (Some(max_redirects), Some(location)) = (self.max_redirects, location) else {
+                    match self.download {
+                        Some((window, stall)) => {
+                            let download = download::download(response_body, window, stall).await?;
+                            response.stopped = download.stalled.clone();
+                            response.download = Some(download);
+                        }
+                        None => response.body = hyper::body::to_bytes(response_body).await?,
+                    }
+                    response.hops = hops;
+                    return Ok::<_, hyper::Error>(response);
+                };
+                response.body = hyper::body::to_bytes(response_body).await?;
+                let next =
+                    redirect::follow(status, &location, &method, &uri, &headers, &body, &first);
+                let stopped = match &next {
+                    Err(error) => Some(error.clone()),
+                    Ok(_) if hops.len() >= max_redirects => Some(ProbeError::new(
+                        "redirect_limit",
+                        format!(
+                            "redirected to {} after {} redirects",
+                            location, max_redirects
+                        ),
+                    )),
+                    Ok(next) if visited.contains(next.uri()) => Some(ProbeError::new(
+                        "redirect_loop",
+                        format!("redirected back to {}", next.uri()),
+                    )),
+                    Ok(_) => None,
+                };
+                if stopped.is_some() {
+                    response.hops = hops;
+                    response.stopped = stopped;
+                    return Ok(response);
+                }
+                // Connections opened for redirects are timed as part of them, not the final request.
+                if let Some(info) = &response.info {
+                    info.first_use();
+                }
+                hops.push(Hop {
+                    status,
+                    location,
+                    latency: first_byte - sent_at,
+                });
+                req = next.unwrap();
+                visited.push(req.uri().clone());
+            }
+        };
+        let result = time::timeout(self.timeout, request).await;
+
+        let (phases, details, error) = match result {
+            Ok(Ok(response)) => {
+                let Response {
+                    status,
+                    version,
+                    headers,
+                    info,
+                    sent_at,
+                    first_byte,
+                    body,
+                    download,
+                    hops,
+                    stopped,
+                } = response;
+                if let (Some(oauth2), Some(authorization)) = (&self.oauth2, &authorization) {
+                    if status == StatusCode::UNAUTHORIZED {
+                        oauth2.reject(authorization).await;
+                    }
+                }
+                let mut phases: Vec<_> = token.map(|token| ("token", token)).into_iter().collect();
+                if !hops.is_empty() {
+                    phases.push(("redirects", sent_at - start));
+                }
+                let mut ready = sent_at;
+                let first_use = info.as_ref().is_some_and(|info| info.first_use());
+                // Reused connections were still made over one family or the other.
+                let family = info.as_ref().map(|info| (info.family, info.fallback_from));
+                let lifecycle = info.as_ref().map(|info| info.lifecycle.clone());
+                let age = self.held.as_ref().zip(info.as_ref()).map(|(held, info)| {
+                    *held[worker % held.len()].lock().unwrap() = Some(info.clone());
+                    hold::age(info.established_at.elapsed())
+                });
+                // Connection phases only apply to the request that opened the connection.
+                if let Some(info) = info.filter(|_| first_use) {
+                    phases.extend(info.phases());
+                    ready = ready.max(info.established_at);
+                }
+                phases.push(("ttfb", first_byte.saturating_duration_since(ready)));
+                if let Some(download) = &download {
+                    phases.push(("download", download.elapsed));
+                }
+                let error = stopped.or_else(|| self.validate(status, &headers, &body));
+                let mut details = vec![
+                    ("status", status.as_u16().to_string()),
+                    ("version", format!("{:?}", version)),
+                    (
+                        "bytes",
+                        download
+                            .as_ref()
+                            .map_or(body.len() as u64, |download| download.bytes)
+                            .to_string(),
+                    ),
+                    (
+                        "connection",
+                        if first_use { "new" } else { "reused" }.to_string(),
+                    ),
+                ];
+                if let Some(download) = &download {
+                    details.extend(download.details());
+                }
+                if !hops.is_empty() {
+                    details.push(("redirects", hops.len().to_string()));
+                    details.push(redirect::chain(&hops));
+                }
+                if let Some(age) = age {
+                    details.push(age);
+                }
+                if let Some(lifecycle) = lifecycle {
+                    details.push(lifecycle.detail());
+                    if let Some(addr) = lifecycle.local_addr {
+                        details.push(("local_addr", addr.to_string()));
+                        details.push(("local_port", addr.port().to_string()));
+                    }
+                    if let Some(addr) = lifecycle.remote_addr {
+                        details.push(("remote_addr", addr.to_string()));
+                    }
+                }
+                if let Some((family, fallback_from)) = family {
+                    details.push(("family", family.to_string()));
+                    if let Some(fallback_from) = fallback_from {
+                        details.push(("fallback_from", fallback_from.to_string()));
+                    }
+                }
+                details.extend(self.proxy_detail());
+                for (name, detail) in &self.capture_headers {
+                    let values: Vec<_> = headers
+                        .get_all(name)
+                        .iter()
+                        .map(|value| String::from_utf8_lossy(value.as_bytes()))
+                        .collect();
+                    if !values.is_empty() {
+                        details.push((detail, values.join(",")));
+                    }
+                }
+                if self.recorder.is_some() {
+                    received = Some(Received {
+                        status,
+                        version,
+                        headers,
+                        body,
+                    });
+                }
+                (phases, details, error)
+            }
+            Ok(Err(e)) => {
+                let tunnel = e.source().and_then(|e| e.downcast_ref::<TunnelRefused>());
+                let kind = if !e.is_connect() {
+                    "request"
+                } else if e.source().is_some_and(|e| e.is::<ConnectTimeout>()) {
+                    "connect_timeout"
+                } else if let Some(tunnel) = tunnel {
+                    tunnel.kind
+                } else {
+                    "connect"
+                };
+                let mut details: Vec<_> = self.proxy_detail().into_iter().collect();
+                if self.drain {
+                    // Requests that failed to connect never left, and canceled ones were never
+                    // written to the connection.
+                    let reused = !e.is_connect() && reused(captured.as_ref());
+                    let dropped = !e.is_connect() && !e.is_canceled();
+                    details.extend(drain(reused, dropped));
+                }
+                let error = ProbeError::from_cause(kind, &e);
+                if error.kind == ports::EXHAUSTED {
+                    details.extend(ports::details(None));
+                }
+                (vec![], details, Some(error))
+            }
+            Err(_) => {
+                let mut details: Vec<_> = self.proxy_detail().into_iter().collect();
+                if self.drain {
+                    // A new connection may have timed out before the request was sent over it.
+                    let reused = reused(captured.as_ref());
+                    details.extend(drain(reused, reused));
+                }
+                let error = ProbeError::new(
+                    "request_timeout",
+                    format!("timed out after {}ms", self.timeout.as_millis()),
+                );
+                (vec![], details, Some(error))
+            }
+        };
+
+        let attempt = Attempt {
+            duration: start.elapsed(),
+            phases,
+            details,
+            error,
+        };
+        if let (Some(recorder), Some(sent)) = (&self.recorder, &sent) {
+            recorder
+                .record(&self.target, worker, &attempt, sent, received.as_ref())
+                .await;
+        }
+        attempt
+    }
+}
+
+impl HttpProbe {
+    /// Names the proxy requests are sent through, if any.
+    fn proxy_detail(&self) -> Option<(&'static str, String)> {
+        self.proxy.as_ref().map(|proxy| ("proxy", proxy.addr()))
+    }
+
+    /// Checks a response against the expected status, headers and body.
+    fn validate(&self, status: StatusCode, headers: &HeaderMap, body: &[u8]) -> Option<ProbeError> {
+        let code = status.as_u16();
+        let http_proxy = self.proxy.as_ref().is_some_and(|proxy| !proxy.is_socks());
+        if http_proxy && status == StatusCode::PROXY_AUTHENTICATION_REQUIRED {
+            return Some(ProbeError::new(
+                "proxy_auth",
+                format!("proxy rejected the credentials: {}", status),
+            ));
+        }
+        if !self.expect_status.iter().any(|range| range.contains(&code)) {
+            return Some(ProbeError::new(
+                "http_status",
+                format!("unexpected status {}", status),
+            ));
+        }
+
+        for (name, expected) in &self.expect_headers {
+            let mut values = headers.get_all(name).iter();
+            let found = match expected {
+                Some(expected) => values.any(|value| value.as_bytes() == expected.as_bytes()),
+                None => values.next().is_some(),
+            };
+            if !found {
+                let message = match expected {
+                    Some(expected) => format!("no {} header with {:?}", name, expected),
+                    None => format!("no {} header", name),
+                };
+                return Some(ProbeError::new("header_mismatch", message));
+            }
+        }
+
+        if let Some(expected) = &self.expect_body {
+            let found = expected.is_empty()
+                || body
+                    .windows(expected.len())
+                    .any(|window| window == expected.as_bytes());
+            if !found {
+                return Some(ProbeError::new(
+                    "body_mismatch",
+                    format!("body does not contain {:?}", expected),
+                ));
+            }
+        }
+
+        if let Some(regex) = &self.expect_body_regex {
+            if !regex.is_match(body) {
+                return Some(ProbeError::new(
+                    "body_mismatch",
+                    format!("body does not match {:?}", regex.as_str()),
+                ));
+            }
+        }
+
+        None
+    }
+}
+
+pub async fn http_main(args: HttpArgs) {
+    let urls = args.urls();
+    assert!(!urls.is_empty(), "no urls to send requests to");
+    if args.distribute == Distribute::RoundRobin {
+        assert!(
+            args.common.parallel >= urls.len(),
+            "--parallel must be at least the number of urls with --distribute round-robin"
+        );
+    }
+
+    if args.compare {
+        assert!(
+            args.discover.discover.is_none(),
+            "--compare cannot discover instances"
+        );
+        assert!(urls.len() == 2, "--compare takes exactly two urls");
+        let a = HttpProbe::new(&args, &urls[0]);
+        let b = HttpProbe::new(&args, &urls[1]);
+        return ab::run(a, b, &args.common).await;
+    }
+
+    if let Some(discovery) = Discovery::new(&args.discover) {
+        let targets = discovered(&args, &urls, discovery).await;
+        if args.drain {
+            drained(&targets, &args.common.name);
+        }
+        return;
+    }
+
+    let mut probes = JoinSet::new();
+    let mut targets = vec![];
+    for (index, url) in urls.iter().enumerate() {
+        let mut common = args.common.clone();
+        if args.distribute == Distribute::RoundRobin {
+            // Worker `n` goes to target `n % urls`, so the first targets get any remainder.
+            common.parallel = (args.common.parallel - index).div_ceil(urls.len());
+        }
+        let probe = HttpProbe::new(&args, url);
+        targets.push(probe.target.clone());
+        let run = async move { probe::run(probe, &common).await };
+        probes.spawn(probe::scoped(probe::scope(), run));
+    }
+
+    while probes.join_next().await.is_some() {}
+    if args.drain {
+        drained(&targets, &args.common.name);
+    }
+}
+
+/// Probes every instance found by `--discover` with each URL, starting probes of instances as they
+/// appear and stopping them as they go, until the probes stop on their own. Returns the targets
+/// probed.
+async fn discovered(args: &HttpArgs, urls: &[String], discovery: Discovery) -> Vec<String> {
+    let resolver = Resolver::new(&args.resolve);
+    let scope = probe::scope();
+    let mut probes = JoinSet::new();
+    let mut running: BTreeMap<SocketAddr, CancellationToken> = BTreeMap::new();
+    let mut targets = vec![];
+    let mut refresh = time::interval(discovery.interval);
+    let mut first = true;
+    loop {
+        tokio::select! {
+            _ = refresh.tick() => {
+                let instances = match discovery.instances(&resolver).await {
+                    Ok(instances) => instances,
+                    Err(e) if first => panic!("error discovering instances: {}", e),
+                    Err(e) => {
+                        warn!("not refreshing instances: {}", e);
+                        continue;
+                    }
+                };
+                first = false;
+
+                let (mut started, mut stopped) = (0, 0);
+                running.retain(|addr, stop| {
+                    let found = instances.contains(addr);
+                    if !found {
+                        stop.cancel();
+                        stopped += 1;
+                    }
+                    found
+                });
+                for addr in instances {
+                    if running.contains_key(&addr) {
+                        continue;
+                    }
+                    let stop = scope.child_token();
+                    let mut common = args.common.clone();
+                    common.label.push(format!("instance={}", addr));
+                    for url in urls {
+                        let probe = HttpProbe::build(args, url, Some(addr));
+                        targets.push(probe.target.clone());
+                        let common = common.clone();
+                        let own = stop.clone();
+                        let run = async move {
+                            probe::run(probe, &common).await;
+                            own
+                        };
+                        probes.spawn(probe::scoped(stop.clone(), run));
+                    }
+                    running.insert(addr, stop);
+                    started += 1;
+                }
+                if started > 0 || stopped > 0 {
+                    info!(
+                        "discovered instances: {} started, {} stopped, {} running",
+                        started,
+                        stopped,
+                        running.len()
+                    );
+                }
+            }
+            Some(Ok(stop)) = probes.join_next(), if !probes.is_empty() => {
+                // Probes stop on their own once they reach their count or duration, or shut down.
+                if !stop.is_cancelled() {
+                    break;
+                }
+            }
+            _ = scope.cancelled() => break,
+        }
+    }
+
+    while probes.join_next().await.is_some() {}
+    targets
+}
+
+/// Reports whether any request in flight was dropped while probing with `--drain`.
+fn drained(targets: &[String], name: &Option<String>) {
+    for summary in stats::summaries() {
+        if summary.probe != "http" || summary.name != *name || !targets.contains(&summary.target) {
+            continue;
+        }
+        let count = |kind: &str| summary.connections.get(kind).cloned().unwrap_or_default();
+        let (new, reused) = (count("new"), count("reused"));
+        let dropped = new.dropped + reused.dropped;
+        let message = format!(
+            "{} failed on new connections and {} on reused ones",
+            new.failures, reused.failures
+        );
+        match dropped {
+            0 => info!(
+                "http {}: no request in flight was dropped, {}",
+                summary.target, message
+            ),
+            _ => warn!(
+                "http {}: {} requests in flight were dropped, {}",
+                summary.target, dropped, message
+            ),
+        }
+    }
+}
+
+/// Creates a client for requests made besides probe attempts, e.g. to send alerts, going through
+/// any proxy set in the environment and not waiting for the connection limits of the probes.
+pub(crate) fn client(uri: &Uri, origin: Origin) -> Client<TimingConnector> {
+    let connector = TimingConnector::new(
+        Duration::from_secs(5),
+        &TlsArgs::default(),
+        HttpVersion::Auto,
+        Proxy::for_uri(uri, None, None, &[]),
+        Arc::new(Resolver::default()),
+        origin,
+    )
+    .unlimited();
+    Client::builder().build(connector)
+}
+
+/// An attempt that failed before sending its request.
+fn failed(error: ProbeError) -> Attempt {
+    Attempt {
+        duration: Duration::ZERO,
+        phases: vec![],
+        details: vec![],
+        error: Some(error),
+    }
+}
+
+/// Whether a request that got no response was sent over a connection already used by an
+/// earlier one, rather than one opened for it or none at all.
+fn reused(captured: Option<&CaptureConnection>) -> bool {
+    let Some(captured) = captured else {
+        return false;
+    };
+    let mut extensions = Extensions::new();
+    match &*captured.connection_metadata() {
+        Some(connected) => connected.get_extras(&mut extensions),
+        None => return false,
+    }
+    extensions
+        .get::<ConnectionInfo>()
+        .is_some_and(|info| !info.first_use())
+}
+
+/// Details of a failed attempt with `--drain`, telling the connection it failed on and whether its
+/// request was dropped in flight.
+fn drain(reused: bool, dropped: bool) -> Vec<(&'static str, String)> {
+    let connection = if reused { "reused" } else { "new" };
+    let mut details = vec![("connection", connection.to_string())];
+    if dropped {
+        details.push(("dropped", "true".to_string()));
+    }
+    details
+}
+
+/// Names the detail a captured header is reported as. Details are named statically, so each name
+/// is kept for as long as the process, once however often probes are rebuilt.
+fn capture_detail(name: &HeaderName) -> &'static str {
+    static DETAILS: Mutex<BTreeSet<&'static str>> = Mutex::new(BTreeSet::new());
+    let detail = format!("header.{}", name);
+    let mut details = DETAILS.lock().unwrap();
+    match details.get(detail.as_str()) {
+        Some(detail) => detail,
+        None => {
+            let detail = Box::leak(detail.into_boxed_str());
+            details.insert(detail);
+            detail
+        }
+    }
+}
+
+/// Parses a status code like `200` or an inclusive range like `200-299`.
+fn parse_status_range(status: &str) -> RangeInclusive<u16> {
+    let status = status.trim();
+    let (start, end) = status.split_once('-').unwrap_or((status, status));
+    let parse = |code: &str| code.trim().parse().expect("invalid expected status");
+    parse(start)..=parse(end)
+}
+
+/// Accepts either a single string or a list, so that one value can be set from the environment
+/// without list syntax.
+pub(crate) fn one_or_many<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<String>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
+    }
+
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(one) => vec![one],
+        OneOrMany::Many(many) => many,
+    })
+}
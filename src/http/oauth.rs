@@ -0,0 +1,154 @@
+use std::time::{Duration, Instant};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use hyper::{
+    header::{HeaderValue, ACCEPT, AUTHORIZATION, CONTENT_TYPE},
+    Body, Client, Method, Request, Uri,
+};
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use serde::Deserialize;
+use tokio::{sync::Mutex, time};
+
+use super::connector::TimingConnector;
+use crate::report::ProbeError;
+
+/// Longest a token is refreshed before it expires.
+const REFRESH_MARGIN: Duration = Duration::from_secs(30);
+
+/// Set a timeout for fetching a token.
+const TOKEN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Fetches access tokens with the OAuth2 client credentials grant, keeping the last one until
+/// shortly before it expires or it is rejected.
+pub struct OAuth2 {
+    client: Client<TimingConnector>,
+    uri: Uri,
+    /// Credentials of the client, sent as `client_secret_basic`.
+    credentials: HeaderValue,
+    scope: Option<String>,
+    token: Mutex<Option<Token>>,
+}
+
+struct Token {
+    /// Value of the `Authorization` header to send the token in.
+    authorization: HeaderValue,
+    /// When the token should be refreshed, if it expires.
+    refresh_at: Option<Instant>,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    expires_in: Option<u64>,
+}
+
+impl OAuth2 {
+    pub fn new(
+        client: Client<TimingConnector>,
+        url: &str,
+        client_id: &str,
+        client_secret: &str,
+        scope: Option<String>,
+    ) -> Self {
+        // Both parts are form encoded before being joined, as RFC 6749 requires.
+        let encode = |part| utf8_percent_encode(part, NON_ALPHANUMERIC).to_string();
+        let credentials = format!("{}:{}", encode(client_id), encode(client_secret));
+        let credentials = format!("Basic {}", STANDARD.encode(credentials));
+
+        OAuth2 {
+            client,
+            uri: url.parse().expect("invalid oauth2 token url"),
+            credentials: HeaderValue::try_from(credentials).expect("invalid oauth2 client"),
+            scope,
+            token: Mutex::new(None),
+        }
+    }
+
+    /// Returns the `Authorization` header to send, along with how long fetching a new token
+    /// took if the last one had to be refreshed.
+    pub async fn authorization(&self) -> Result<(HeaderValue, Option<Duration>), ProbeError> {
+        // Held while fetching, so that workers wait for one token rather than all fetching one.
+        let mut token = self.token.lock().await;
+        if let Some(token) = &*token {
+            if token.refresh_at.is_none_or(|at| Instant::now() < at) {
+                return Ok((token.authorization.clone(), None));
+            }
+        }
+
+        let start = Instant::now();
+        let fetched = match time::timeout(TOKEN_TIMEOUT, self.fetch()).await {
+            Ok(fetched) => fetched?,
+            Err(_) => {
+                let message = format!("timed out after {}ms", TOKEN_TIMEOUT.as_millis());
+                return Err(ProbeError::new("oauth2", message));
+            }
+        };
+        let authorization = fetched.authorization.clone();
+        *token = Some(fetched);
+        Ok((authorization, Some(start.elapsed())))
+    }
+
+    /// Forgets the token sent in a request that was rejected, so that the next one fetches a new
+    /// token, unless another worker already has.
+    pub async fn reject(&self, authorization: &HeaderValue) {
+        let mut token = self.token.lock().await;
+        if token
+            .as_ref()
+            .is_some_and(|token| token.authorization == *authorization)
+        {
+            *token = None;
+        }
+    }
+
+    async fn fetch(&self) -> Result<Token, ProbeError> {
+        let mut body = "grant_type=client_credentials".to_string();
+        if let Some(scope) = &self.scope {
+            body.push_str("&scope=");
+            body.extend(utf8_percent_encode(scope, NON_ALPHANUMERIC));
+        }
+        let mut req = Request::new(Body::from(body));
+        *req.method_mut() = Method::POST;
+        *req.uri_mut() = self.uri.clone();
+        let headers = req.headers_mut();
+        headers.insert(AUTHORIZATION, self.credentials.clone());
+        headers.insert(
+            CONTENT_TYPE,
+            HeaderValue::from_static("application/x-www-form-urlencoded"),
+        );
+        headers.insert(ACCEPT, HeaderValue::from_static("application/json"));
+
+        let fetched = Instant::now();
+        let res = self
+            .client
+            .request(req)
+            .await
+            .map_err(|e| ProbeError::from_cause("oauth2", &e))?;
+        let status = res.status();
+        let body = hyper::body::to_bytes(res.into_body())
+            .await
+            .map_err(|e| ProbeError::from_cause("oauth2", &e))?;
+        if !status.is_success() {
+            return Err(ProbeError::new(
+                "oauth2",
+                format!(
+                    "token request failed with {}: {}",
+                    status,
+                    String::from_utf8_lossy(&body)
+                ),
+            ));
+        }
+
+        let response: TokenResponse = serde_json::from_slice(&body)
+            .map_err(|e| ProbeError::new("oauth2", format!("invalid token response: {}", e)))?;
+        let authorization = HeaderValue::try_from(format!("Bearer {}", response.access_token))
+            .map_err(|e| ProbeError::new("oauth2", format!("invalid access token: {}", e)))?;
+        let refresh_at = response.expires_in.map(|expires_in| {
+            let lifetime = Duration::from_secs(expires_in);
+            fetched + lifetime - REFRESH_MARGIN.min(lifetime / 2)
+        });
+        Ok(Token {
+            authorization,
+            refresh_at,
+        })
+    }
+}
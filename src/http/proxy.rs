@@ -0,0 +1,353 @@
+use std::{env, fmt, net::IpAddr};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use hyper::{header::HeaderValue, Uri};
+use percent_encoding::percent_decode_str;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use url::Url;
+
+use super::connector::BoxError;
+use crate::resolve::Resolver;
+
+/// Longest response to a `CONNECT` request that is read before giving up on it.
+const MAX_RESPONSE: usize = 16 * 1024;
+
+/// Proxy that connections are made through. HTTP proxies tunnel `https` with `CONNECT` and get
+/// `http` requests in absolute form, while SOCKS5 proxies tunnel every connection.
+#[derive(Clone, Debug)]
+pub struct Proxy {
+    pub host: String,
+    pub port: u16,
+    /// Value of the `Proxy-Authorization` header, if an HTTP proxy needs credentials.
+    pub authorization: Option<HeaderValue>,
+    socks: Option<Socks>,
+}
+
+/// How to open tunnels through a SOCKS5 proxy.
+#[derive(Clone, Debug)]
+struct Socks {
+    /// Whether the proxy resolves the target, for `socks5h://`, rather than the client.
+    remote_dns: bool,
+    /// User name and password, if the proxy needs credentials.
+    credentials: Option<(String, String)>,
+}
+
+/// Error returned when the proxy refuses to open a tunnel.
+#[derive(Debug)]
+pub struct TunnelRefused {
+    /// Kind of error, `proxy_auth` if the proxy rejected the credentials or `proxy` otherwise.
+    pub kind: &'static str,
+    line: String,
+}
+
+impl fmt::Display for TunnelRefused {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "proxy refused to open a tunnel: {}", self.line)
+    }
+}
+
+impl std::error::Error for TunnelRefused {}
+
+impl Proxy {
+    /// Parses a proxy URL like `http://[user:password@]host:port`, `socks5://` or `socks5h://`,
+    /// taking credentials from `auth`, as `user:password`, over any in the URL.
+    pub fn new(url: &str, auth: Option<&str>) -> Self {
+        let url = Url::parse(url).expect("invalid proxy url");
+        let remote_dns = match url.scheme() {
+            "http" => None,
+            "socks5" => Some(false),
+            "socks5h" => Some(true),
+            scheme => panic!(
+                "only http://, socks5:// and socks5h:// proxies are supported, not {}",
+                scheme
+            ),
+        };
+
+        let decode = |part: &str| percent_decode_str(part).decode_utf8_lossy().into_owned();
+        let credentials = match auth {
+            Some(auth) => Some(auth.to_string()),
+            None if !url.username().is_empty() => Some(format!(
+                "{}:{}",
+                decode(url.username()),
+                decode(url.password().unwrap_or_default())
+            )),
+            None => None,
+        };
+        let host = url
+            .host_str()
+            .expect("proxy url has no host")
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .to_string();
+
+        if let Some(remote_dns) = remote_dns {
+            let credentials = credentials.map(|credentials| {
+                let (user, password) = credentials.split_once(':').unwrap_or((&credentials, ""));
+                assert!(
+                    user.len() <= 255 && password.len() <= 255,
+                    "socks5 user and password must be at most 255 bytes"
+                );
+                (user.to_string(), password.to_string())
+            });
+            return Proxy {
+                host,
+                port: url.port().unwrap_or(1080),
+                authorization: None,
+                socks: Some(Socks {
+                    remote_dns,
+                    credentials,
+                }),
+            };
+        }
+
+        let authorization = credentials.map(|credentials| {
+            let value = format!("Basic {}", STANDARD.encode(credentials));
+            HeaderValue::try_from(value).expect("invalid proxy credentials")
+        });
+        Proxy {
+            host,
+            port: url.port_or_known_default().unwrap_or(80),
+            authorization,
+            socks: None,
+        }
+    }
+
+    /// Whether every connection is tunnelled through the proxy, rather than only `https` ones.
+    pub fn is_socks(&self) -> bool {
+        self.socks.is_some()
+    }
+
+    /// Picks the proxy for a URL from `--proxy`, or else from `HTTPS_PROXY`, `HTTP_PROXY` or
+    /// `ALL_PROXY` in the environment, unless the host of the URL is excluded by `no_proxy`, or
+    /// else by `NO_PROXY` in the environment.
+    pub fn for_uri(
+        uri: &Uri,
+        proxy: Option<&str>,
+        auth: Option<&str>,
+        no_proxy: &[String],
+    ) -> Option<Self> {
+        let var = |name: &str| {
+            env::var(name)
+                .or_else(|_| env::var(name.to_lowercase()))
+                .ok()
+                .filter(|value| !value.is_empty())
+        };
+
+        let url = match proxy {
+            Some(proxy) => proxy.to_string(),
+            None => {
+                let scheme_var = match uri.scheme_str() {
+                    Some("https") => "HTTPS_PROXY",
+                    _ => "HTTP_PROXY",
+                };
+                var(scheme_var).or_else(|| var("ALL_PROXY"))?
+            }
+        };
+
+        let no_proxy = match no_proxy {
+            [] => var("NO_PROXY")
+                .map(|hosts| hosts.split(',').map(str::to_string).collect())
+                .unwrap_or_default(),
+            hosts => hosts.to_vec(),
+        };
+        let host = uri.host()?.trim_start_matches('[').trim_end_matches(']');
+        if bypass(&no_proxy, host) {
+            return None;
+        }
+
+        Some(Proxy::new(&url, auth))
+    }
+
+    /// Asks the proxy to open a tunnel to the target over a connection to it, resolving the
+    /// target with the resolver first for a `socks5://` proxy.
+    pub async fn tunnel<S>(
+        &self,
+        stream: &mut S,
+        host: &str,
+        port: u16,
+        resolver: &Resolver,
+    ) -> Result<(), BoxError>
+    where
+        S: AsyncReadExt + AsyncWriteExt + Unpin,
+    {
+        match &self.socks {
+            Some(socks) => socks.tunnel(stream, host, port, resolver).await,
+            None => self.connect(stream, host, port).await,
+        }
+    }
+
+    /// Opens a tunnel through an HTTP proxy with a `CONNECT` request.
+    async fn connect<S>(&self, stream: &mut S, host: &str, port: u16) -> Result<(), BoxError>
+    where
+        S: AsyncReadExt + AsyncWriteExt + Unpin,
+    {
+        let authority = match host.contains(':') {
+            true => format!("[{}]:{}", host, port),
+            false => format!("{}:{}", host, port),
+        };
+        let mut request = format!("CONNECT {0} HTTP/1.1\r\nHost: {0}\r\n", authority);
+        if let Some(authorization) = &self.authorization {
+            request.push_str("Proxy-Authorization: ");
+            request.push_str(authorization.to_str()?);
+            request.push_str("\r\n");
+        }
+        request.push_str("\r\n");
+        stream.write_all(request.as_bytes()).await?;
+
+        // Nothing else is sent until the client starts on the tunnel, so this reads exactly the
+        // response.
+        let mut response = vec![];
+        let mut buf = [0; 1024];
+        while !response.ends_with(b"\r\n\r\n") {
+            if response.len() > MAX_RESPONSE {
+                return Err("proxy response to CONNECT is too long".into());
+            }
+            let n = stream.read(&mut buf).await?;
+            if n == 0 {
+                return Err("proxy closed the connection in response to CONNECT".into());
+            }
+            response.extend_from_slice(&buf[..n]);
+        }
+
+        let response = String::from_utf8_lossy(&response);
+        let line = response.lines().next().unwrap_or_default();
+        let status = line
+            .split_whitespace()
+            .nth(1)
+            .and_then(|status| status.parse::<u16>().ok())
+            .ok_or_else(|| format!("malformed proxy response to CONNECT: {}", line))?;
+        if !(200..300).contains(&status) {
+            return Err(TunnelRefused {
+                kind: if status == 407 { "proxy_auth" } else { "proxy" },
+                line: line.to_string(),
+            }
+            .into());
+        }
+        Ok(())
+    }
+
+    pub fn addr(&self) -> String {
+        format!("{}:{}", self.host, self.port)
+    }
+}
+
+impl Socks {
+    /// Opens a tunnel through a SOCKS5 proxy, as RFC 1928 and RFC 1929 describe.
+    async fn tunnel<S>(
+        &self,
+        stream: &mut S,
+        host: &str,
+        port: u16,
+        resolver: &Resolver,
+    ) -> Result<(), BoxError>
+    where
+        S: AsyncReadExt + AsyncWriteExt + Unpin,
+    {
+        let method = match self.credentials {
+            Some(_) => 0x02,
+            None => 0x00,
+        };
+        stream.write_all(&[0x05, 0x01, method]).await?;
+        let mut reply = [0; 2];
+        stream.read_exact(&mut reply).await?;
+        if reply[0] != 0x05 {
+            return Err(format!("not a socks5 proxy, it answered version {}", reply[0]).into());
+        }
+        if reply[1] != method {
+            return Err(refused(
+                "proxy_auth",
+                "proxy accepts none of the methods offered",
+            ));
+        }
+
+        if let Some((user, password)) = &self.credentials {
+            let mut request = vec![0x01, user.len() as u8];
+            request.extend_from_slice(user.as_bytes());
+            request.push(password.len() as u8);
+            request.extend_from_slice(password.as_bytes());
+            stream.write_all(&request).await?;
+            stream.read_exact(&mut reply).await?;
+            if reply[1] != 0x00 {
+                return Err(refused("proxy_auth", "proxy rejected the credentials"));
+            }
+        }
+
+        let mut request = vec![0x05, 0x01, 0x00];
+        let ip = match host.parse::<IpAddr>() {
+            Ok(ip) => Some(ip),
+            Err(_) if self.remote_dns => None,
+            Err(_) => Some(resolver.lookup(host, port).await?[0].ip()),
+        };
+        match ip {
+            Some(IpAddr::V4(ip)) => {
+                request.push(0x01);
+                request.extend_from_slice(&ip.octets());
+            }
+            Some(IpAddr::V6(ip)) => {
+                request.push(0x04);
+                request.extend_from_slice(&ip.octets());
+            }
+            None => {
+                let len = u8::try_from(host.len()).map_err(|_| "host name is too long")?;
+                request.push(0x03);
+                request.push(len);
+                request.extend_from_slice(host.as_bytes());
+            }
+        }
+        request.extend_from_slice(&port.to_be_bytes());
+        stream.write_all(&request).await?;
+
+        let mut reply = [0; 4];
+        stream.read_exact(&mut reply).await?;
+        if reply[1] != 0x00 {
+            let reason = match reply[1] {
+                0x01 => "general failure",
+                0x02 => "connection not allowed by ruleset",
+                0x03 => "network unreachable",
+                0x04 => "host unreachable",
+                0x05 => "connection refused",
+                0x06 => "ttl expired",
+                0x07 => "command not supported",
+                0x08 => "address type not supported",
+                _ => "unknown error",
+            };
+            return Err(refused("proxy", &format!("{} ({})", reason, reply[1])));
+        }
+        // The address the proxy bound is of no use, but has to be read past.
+        let len = match reply[3] {
+            0x01 => 4,
+            0x04 => 16,
+            0x03 => {
+                let mut len = [0];
+                stream.read_exact(&mut len).await?;
+                len[0] as usize
+            }
+            atyp => return Err(format!("malformed socks5 reply with address type {}", atyp).into()),
+        };
+        let mut bound = vec![0; len + 2];
+        stream.read_exact(&mut bound).await?;
+        Ok(())
+    }
+}
+
+fn refused(kind: &'static str, line: &str) -> BoxError {
+    TunnelRefused {
+        kind,
+        line: line.to_string(),
+    }
+    .into()
+}
+
+/// Whether a host is excluded from the proxy by a list like `localhost,.internal,10.0.0.1`, where
+/// domains also exclude their subdomains and `*` excludes every host.
+fn bypass(no_proxy: &[String], host: &str) -> bool {
+    no_proxy.iter().map(|entry| entry.trim()).any(|entry| {
+        let domain = entry.trim_start_matches('.');
+        entry == "*"
+            || (!domain.is_empty()
+                && (host.eq_ignore_ascii_case(domain)
+                    || host
+                        .to_ascii_lowercase()
+                        .ends_with(&format!(".{}", domain.to_ascii_lowercase()))))
+    })
+}
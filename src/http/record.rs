@@ -0,0 +1,372 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use clap::Parser;
+use hyper::{
+    body::Bytes,
+    header::{HeaderName, HeaderValue, AUTHORIZATION, COOKIE, PROXY_AUTHORIZATION, SET_COOKIE},
+    Body, Client, HeaderMap, Method, Request, StatusCode, Uri, Version,
+};
+use log::{debug, error, warn};
+use serde::{Deserialize, Serialize};
+use tokio::time;
+
+use super::{one_or_many, ConnectionInfo, HttpVersion, Proxy, TimingConnector};
+use crate::{
+    report::{self, Attempt, Labels, Origin},
+    resolve::{ResolveArgs, Resolver},
+    secret,
+    tls::TlsArgs,
+};
+
+/// Headers whose values are credentials, replaced with `***` in recordings.
+const SENSITIVE: &[HeaderName] = &[AUTHORIZATION, PROXY_AUTHORIZATION, COOKIE, SET_COOKIE];
+
+/// Number of recordings written, to tell apart those of the same millisecond.
+static RECORDED: AtomicU64 = AtomicU64::new(0);
+
+/// Writes the requests and responses of failed attempts to a directory, one JSON file each.
+pub struct Recorder {
+    dir: PathBuf,
+    body_bytes: usize,
+}
+
+/// Request as it was sent in an attempt.
+pub struct Sent {
+    pub method: Method,
+    pub uri: Uri,
+    pub headers: HeaderMap,
+    pub body: Bytes,
+}
+
+/// Response as it was received in an attempt.
+pub struct Received {
+    pub status: StatusCode,
+    pub version: Version,
+    pub headers: HeaderMap,
+    pub body: Bytes,
+}
+
+/// A failed attempt, as written by `--record`.
+#[derive(Serialize, Deserialize)]
+pub struct Recording {
+    pub timestamp: String,
+    pub target: String,
+    pub worker: usize,
+    pub duration_ms: f64,
+    #[serde(default)]
+    pub phases_ms: BTreeMap<String, f64>,
+    #[serde(default)]
+    pub details: BTreeMap<String, String>,
+    pub error_kind: String,
+    pub error: String,
+    pub request: RecordedRequest,
+    /// Response to the request, unless the attempt failed before getting one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub response: Option<RecordedResponse>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct RecordedRequest {
+    pub method: String,
+    pub url: String,
+    /// Headers as `K: V`, with credentials replaced with `***`.
+    #[serde(default)]
+    pub headers: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body: Option<RecordedBody>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct RecordedResponse {
+    pub status: u16,
+    pub version: String,
+    /// Headers as `K: V`, with cookies replaced with `***`.
+    #[serde(default)]
+    pub headers: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body: Option<RecordedBody>,
+}
+
+/// A body, kept up to `--record-body-bytes` as text if it is UTF-8 or as base64 otherwise.
+#[derive(Serialize, Deserialize)]
+pub struct RecordedBody {
+    /// Size of the whole body.
+    pub size: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub base64: Option<String>,
+}
+
+impl Recorder {
+    pub fn new(dir: PathBuf, body_bytes: usize) -> Self {
+        fs::create_dir_all(&dir).expect("unable to create record directory");
+        Recorder { dir, body_bytes }
+    }
+
+    /// Writes the request and any response of a failed attempt. Failing to write it is logged
+    /// rather than failing the attempt.
+    pub async fn record(
+        &self,
+        target: &str,
+        worker: usize,
+        attempt: &Attempt,
+        sent: &Sent,
+        received: Option<&Received>,
+    ) {
+        let Some(error) = &attempt.error else {
+            return;
+        };
+        let now = SystemTime::now();
+        let recording = Recording {
+            timestamp: humantime::format_rfc3339_micros(now).to_string(),
+            target: target.to_string(),
+            worker,
+            duration_ms: attempt.duration.as_secs_f64() * 1000.0,
+            phases_ms: attempt
+                .phases
+                .iter()
+                .map(|(name, duration)| (name.to_string(), duration.as_secs_f64() * 1000.0))
+                .collect(),
+            details: attempt
+                .details
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.clone()))
+                .collect(),
+            error_kind: error.kind.to_string(),
+            error: error.message.clone(),
+            request: RecordedRequest {
+                method: sent.method.to_string(),
+                url: report::redact(&sent.uri.to_string()),
+                headers: headers(&sent.headers),
+                body: self.body(&sent.body),
+            },
+            response: received.map(|received| RecordedResponse {
+                status: received.status.as_u16(),
+                version: format!("{:?}", received.version),
+                headers: headers(&received.headers),
+                body: self.body(&received.body),
+            }),
+        };
+
+        let millis = now
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        let n = RECORDED.fetch_add(1, Ordering::Relaxed);
+        let path = self.dir.join(format!("{}-{}-{}.json", millis, worker, n));
+        let json = serde_json::to_string_pretty(&recording).unwrap();
+        let json = secret::redact(&json).into_owned();
+        match tokio::fs::write(&path, json).await {
+            Ok(()) => debug!("recorded failed attempt in {}", path.display()),
+            Err(e) => error!(
+                "unable to record failed attempt in {}: {}",
+                path.display(),
+                e
+            ),
+        }
+    }
+
+    fn body(&self, body: &[u8]) -> Option<RecordedBody> {
+        if body.is_empty() {
+            return None;
+        }
+        let kept = &body[..body.len().min(self.body_bytes)];
+        let (text, base64) = match std::str::from_utf8(kept) {
+            _ if kept.is_empty() => (None, None),
+            Ok(text) => (Some(text.to_string()), None),
+            Err(_) => (None, Some(STANDARD.encode(kept))),
+        };
+        Some(RecordedBody {
+            size: body.len(),
+            text,
+            base64,
+        })
+    }
+}
+
+fn headers(headers: &HeaderMap) -> Vec<String> {
+    headers
+        .iter()
+        .map(|(name, value)| match SENSITIVE.contains(name) {
+            true => format!("{}: ***", name),
+            false => format!("{}: {}", name, String::from_utf8_lossy(value.as_bytes())),
+        })
+        .collect()
+}
+
+#[derive(Parser, Debug, Serialize, Deserialize)]
+pub struct ReplayArgs {
+    /// Recording of a failed attempt, as written to the `--record` directory of an HTTP probe.
+    file: PathBuf,
+
+    /// Add a header to the request, replacing any recorded with the same name, as `K: V`, e.g.
+    /// to supply credentials that were redacted from the recording. Can be repeated.
+    #[arg(long)]
+    #[serde(default, deserialize_with = "one_or_many")]
+    header: Vec<String>,
+
+    /// Set a timeout for only the connect phase.
+    #[arg(long, default_value_t = 1000)]
+    connect_timeout_ms: u64,
+
+    /// Set a timeout for the whole request.
+    #[arg(long, default_value_t = 5000)]
+    timeout_ms: u64,
+
+    /// HTTP version to use.
+    #[arg(long, value_enum, default_value_t)]
+    http_version: HttpVersion,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    tls: TlsArgs,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    resolve: ResolveArgs,
+}
+
+/// Re-issues a recorded request once, printing the response next to the recorded one.
+pub async fn replay_main(args: ReplayArgs) {
+    let file = fs::read(&args.file).expect("unable to read recording");
+    let recording: Recording = serde_json::from_slice(&file).expect("invalid recording");
+    let request = &recording.request;
+    let uri: Uri = request.url.parse().expect("invalid recorded url");
+
+    let mut headers = HeaderMap::new();
+    for header in &request.headers {
+        let (name, value) = header
+            .split_once(':')
+            .expect("recorded header must be `K: V`");
+        let (name, value) = (name.trim(), value.trim());
+        if value == "***" {
+            warn!(
+                "{} was redacted from the recording, add it with --header",
+                name
+            );
+            continue;
+        }
+        headers.append(
+            HeaderName::try_from(name).expect("invalid recorded header name"),
+            HeaderValue::try_from(value).expect("invalid recorded header value"),
+        );
+    }
+    for header in &args.header {
+        let (name, value) = header.split_once(':').expect("header must be `K: V`");
+        headers.insert(
+            HeaderName::try_from(name.trim()).expect("invalid header name"),
+            HeaderValue::try_from(value.trim()).expect("invalid header value"),
+        );
+    }
+    let body = match &request.body {
+        Some(body) => {
+            let kept = match (&body.text, &body.base64) {
+                (Some(text), _) => Bytes::from(text.clone()),
+                (None, Some(base64)) => Bytes::from(
+                    STANDARD
+                        .decode(base64)
+                        .expect("invalid recorded request body"),
+                ),
+                (None, None) => Bytes::new(),
+            };
+            if kept.len() < body.size {
+                warn!(
+                    "only {} of the {} bytes of the request body were recorded, sending those",
+                    kept.len(),
+                    body.size
+                );
+            }
+            kept
+        }
+        None => Bytes::new(),
+    };
+
+    let origin = Origin {
+        probe: "replay",
+        name: None,
+        labels: Labels::default(),
+        target: recording.target.clone(),
+        worker: recording.worker,
+    };
+    let connector = TimingConnector::new(
+        Duration::from_millis(args.connect_timeout_ms),
+        &args.tls,
+        args.http_version,
+        Proxy::for_uri(&uri, None, None, &[]),
+        Arc::new(Resolver::new(&args.resolve)),
+        origin,
+    );
+    let client = Client::builder()
+        .http2_only(args.http_version == HttpVersion::H2)
+        .build::<_, Body>(connector);
+
+    let mut req = Request::new(Body::from(body));
+    *req.method_mut() = request.method.parse().expect("invalid recorded method");
+    *req.uri_mut() = uri;
+    *req.headers_mut() = headers;
+
+    match &recording.response {
+        Some(response) => println!(
+            "recorded: {} {} {}: {}",
+            recording.timestamp, response.status, recording.error_kind, recording.error
+        ),
+        None => println!(
+            "recorded: {} {}: {}",
+            recording.timestamp, recording.error_kind, recording.error
+        ),
+    }
+
+    let start = Instant::now();
+    let timeout = Duration::from_millis(args.timeout_ms);
+    let exchange = async {
+        let res = client.request(req).await?;
+        let first_byte = start.elapsed();
+        let (parts, body) = res.into_parts();
+        let body = hyper::body::to_bytes(body).await?;
+        Ok::<_, hyper::Error>((parts, first_byte, body))
+    };
+    let (parts, first_byte, body) = match time::timeout(timeout, exchange).await {
+        Ok(Ok(exchange)) => exchange,
+        Ok(Err(e)) => {
+            println!("replayed: failed after {:?}: {}", start.elapsed(), e);
+            return;
+        }
+        Err(_) => {
+            println!("replayed: timed out after {}ms", timeout.as_millis());
+            return;
+        }
+    };
+
+    let mut line = format!(
+        "replayed: {} {:?} in {:?}, first byte after {:?}",
+        parts.status,
+        parts.version,
+        start.elapsed(),
+        first_byte
+    );
+    if let Some(info) = parts.extensions.get::<ConnectionInfo>() {
+        line.push_str(&format!(" dns={:?} connect={:?}", info.dns, info.connect));
+        if let Some(tls) = info.tls {
+            line.push_str(&format!(" tls={:?}", tls));
+        }
+    }
+    println!("{}", line);
+    for (name, value) in &parts.headers {
+        println!("< {}: {}", name, String::from_utf8_lossy(value.as_bytes()));
+    }
+    if !body.is_empty() {
+        println!();
+        println!("{}", String::from_utf8_lossy(&body));
+    }
+}
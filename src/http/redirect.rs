@@ -0,0 +1,94 @@
+use std::time::Duration;
+
+use hyper::{
+    body::Bytes,
+    header::{AUTHORIZATION, CONTENT_LENGTH, CONTENT_TYPE, COOKIE, LOCATION},
+    Body, HeaderMap, Method, Request, StatusCode, Uri,
+};
+use url::Url;
+
+use crate::report::ProbeError;
+
+/// A redirect followed on the way to the final response of an attempt.
+pub struct Hop {
+    pub status: StatusCode,
+    pub location: String,
+    /// Time from sending the request to the redirect arriving.
+    pub latency: Duration,
+}
+
+/// Where a response redirects to, if it is a redirect with a `Location`.
+pub fn location(status: StatusCode, headers: &HeaderMap) -> Option<String> {
+    if !matches!(status.as_u16(), 301 | 302 | 303 | 307 | 308) {
+        return None;
+    }
+    let location = headers.get(LOCATION)?.to_str().ok()?;
+    Some(location.to_string())
+}
+
+/// Builds the request that follows a redirect to the location from a request to `uri`, sending
+/// the credentials of the request only if the location is on the same origin as `first`, the
+/// URL of the attempt.
+pub fn follow(
+    status: StatusCode,
+    location: &str,
+    method: &Method,
+    uri: &Uri,
+    headers: &HeaderMap,
+    body: &Bytes,
+    first: &Uri,
+) -> Result<Request<Body>, ProbeError> {
+    let invalid = |e: &dyn std::fmt::Display| {
+        ProbeError::new(
+            "redirect",
+            format!("invalid redirect location {:?}: {}", location, e),
+        )
+    };
+    let base = Url::parse(&uri.to_string()).map_err(|e| invalid(&e))?;
+    let next = base.join(location).map_err(|e| invalid(&e))?;
+    let next: Uri = next.as_str().parse().map_err(|e| invalid(&e))?;
+
+    // Like browsers, POST becomes GET after 301 and 302 as well as after 303.
+    let get = (status == StatusCode::SEE_OTHER && method != Method::HEAD)
+        || (matches!(status, StatusCode::MOVED_PERMANENTLY | StatusCode::FOUND)
+            && method == Method::POST);
+    let mut headers = headers.clone();
+    let body = match get {
+        true => {
+            headers.remove(CONTENT_TYPE);
+            headers.remove(CONTENT_LENGTH);
+            Bytes::new()
+        }
+        false => body.clone(),
+    };
+    if origin(&next) != origin(first) {
+        headers.remove(AUTHORIZATION);
+        headers.remove(COOKIE);
+    }
+
+    let mut req = Request::new(Body::from(body));
+    *req.method_mut() = if get { Method::GET } else { method.clone() };
+    *req.uri_mut() = next;
+    *req.headers_mut() = headers;
+    Ok(req)
+}
+
+fn origin(uri: &Uri) -> (Option<&str>, Option<&str>, Option<u16>) {
+    (uri.scheme_str(), uri.host(), uri.port_u16())
+}
+
+/// Detail listing the status, location and latency of every redirect followed.
+pub fn chain(hops: &[Hop]) -> (&'static str, String) {
+    let hops: Vec<_> = hops
+        .iter()
+        .map(|hop| {
+            format!(
+                "{} {} {:.3}ms",
+                hop.status.as_u16(),
+                hop.location,
+                hop.latency.as_secs_f64() * 1000.0
+            )
+        })
+        .collect();
+    ("redirect_chain", hops.join(" -> "))
+}
@@ -0,0 +1,369 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    error::Error,
+    ops::RangeInclusive,
+    sync::{atomic::AtomicU64, Arc},
+    time::{Duration, Instant},
+};
+
+use clap::Parser;
+use hyper::{
+    header::{HeaderName, HeaderValue, COOKIE, SET_COOKIE},
+    Body, Client, Method, Request, Uri,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::time;
+
+use super::{one_or_many, parse_status_range, ConnectTimeout, HttpVersion, Proxy, TimingConnector};
+use crate::{
+    probe::{self, CommonArgs, Probe},
+    report::{self, Attempt, Origin, ProbeError},
+    resolve::{ResolveArgs, Resolver},
+    template::{Template, Vars},
+    tls::TlsArgs,
+};
+
+#[derive(Parser, Debug, Serialize, Deserialize)]
+pub struct ScenarioArgs {
+    /// Step of the transaction, as a JSON object, run in the order given. Can be repeated.
+    /// In a config file, steps are written as tables or maps rather than JSON. A step has a
+    /// `name`, a `url`, and optionally a `method`, `headers` like `["K: V"]`, a `body`, and
+    /// `expect_status` like `["200-299", "304"]`, which defaults to `200-399`. It extracts
+    /// variables for the steps after it with `extract_json`, mapping names to paths like
+    /// `$.data.token`, and `extract_header`, mapping names to response headers. Variables are
+    /// expanded like `{{token}}` in the url, headers and body, along with the placeholders
+    /// `--url` takes. Cookies set by a step are sent back on the steps after it to the same host.
+    #[arg(long, required = true)]
+    #[serde(default, deserialize_with = "one_or_many")]
+    step: Vec<String>,
+
+    /// Set a timeout for only the connect phase of a `Client`.
+    #[arg(long, default_value_t = 1000)]
+    connect_timeout_ms: u64,
+
+    /// Set a timeout for each step.
+    #[arg(long, default_value_t = 5000)]
+    timeout_ms: u64,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    tls: TlsArgs,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    resolve: ResolveArgs,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    pub common: CommonArgs,
+}
+
+/// A step as it is defined.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct StepArgs {
+    name: String,
+    url: String,
+    #[serde(default)]
+    method: Option<String>,
+    #[serde(default)]
+    headers: Vec<String>,
+    #[serde(default)]
+    body: Option<String>,
+    #[serde(default)]
+    expect_status: Vec<String>,
+    #[serde(default)]
+    extract_json: BTreeMap<String, String>,
+    #[serde(default)]
+    extract_header: BTreeMap<String, String>,
+}
+
+/// A request of the transaction, and what to take from its response.
+struct Step {
+    /// Name of the step, which is also the phase it is timed as.
+    name: &'static str,
+    method: Method,
+    url: Template,
+    headers: Vec<(HeaderName, Template)>,
+    body: Template,
+    expect_status: Vec<RangeInclusive<u16>>,
+    extract_json: Vec<(String, String)>,
+    extract_header: Vec<(String, HeaderName)>,
+}
+
+/// Runs a transaction of HTTP requests on every attempt, passing values from the response of one
+/// step into the requests of the next, over new connections for every attempt.
+pub struct ScenarioProbe {
+    connectors: Vec<TimingConnector>,
+    steps: Vec<Step>,
+    sequence: AtomicU64,
+    target: String,
+    timeout: Duration,
+}
+
+impl ScenarioProbe {
+    pub fn new(args: &ScenarioArgs) -> Self {
+        let defined: Vec<StepArgs> = args
+            .step
+            .iter()
+            .map(|step| serde_json::from_str(step).expect("invalid scenario step"))
+            .collect();
+        let target = report::redact(&defined[0].url);
+
+        let mut vars: Vec<String> = vec![];
+        let mut names = HashSet::new();
+        let steps: Vec<Step> = defined
+            .into_iter()
+            .map(|step| {
+                assert!(
+                    names.insert(step.name.clone()),
+                    "scenario step {} is defined twice",
+                    step.name
+                );
+                let parse = |text: &str| {
+                    Template::parse_with_vars(text, &vars).expect("invalid scenario step template")
+                };
+                let headers = step
+                    .headers
+                    .iter()
+                    .map(|header| {
+                        let (name, value) = header.split_once(':').expect("header must be `K: V`");
+                        let name = HeaderName::try_from(name.trim()).expect("invalid header name");
+                        (name, parse(value.trim()))
+                    })
+                    .collect();
+                let parsed = Step {
+                    // Phases are named statically, so names are kept for as long as the process.
+                    name: Box::leak(step.name.into_boxed_str()),
+                    method: step
+                        .method
+                        .as_deref()
+                        .unwrap_or("GET")
+                        .parse()
+                        .expect("invalid scenario step method"),
+                    url: parse(&step.url),
+                    headers,
+                    body: parse(step.body.as_deref().unwrap_or_default()),
+                    expect_status: match step.expect_status.is_empty() {
+                        true => vec![200..=399],
+                        false => step
+                            .expect_status
+                            .iter()
+                            .flat_map(|status| status.split(','))
+                            .map(parse_status_range)
+                            .collect(),
+                    },
+                    extract_json: step.extract_json.into_iter().collect(),
+                    extract_header: step
+                        .extract_header
+                        .into_iter()
+                        .map(|(var, header)| {
+                            let header = HeaderName::try_from(header).expect("invalid header name");
+                            (var, header)
+                        })
+                        .collect(),
+                };
+                vars.extend(parsed.extract_json.iter().map(|(var, _)| var.clone()));
+                vars.extend(parsed.extract_header.iter().map(|(var, _)| var.clone()));
+                parsed
+            })
+            .collect();
+
+        // Connections are proxied, and the url checked, by how the first step first renders.
+        let uri: Uri = steps[0]
+            .url
+            .render_string(&Vars::next(&AtomicU64::new(0)))
+            .parse()
+            .expect("invalid scenario step url");
+        let proxy = Proxy::for_uri(&uri, None, None, &[]);
+        let resolver = Arc::new(Resolver::new(&args.resolve));
+        // Create a connector for every worker so that their connections are reported apart.
+        let connectors = (0..args.common.parallel)
+            .map(|worker| {
+                let origin = Origin {
+                    probe: "scenario",
+                    name: args.common.name.clone(),
+                    labels: args.common.labels(),
+                    target: target.clone(),
+                    worker,
+                };
+                TimingConnector::new(
+                    Duration::from_millis(args.connect_timeout_ms),
+                    &args.tls,
+                    HttpVersion::Auto,
+                    proxy.clone(),
+                    resolver.clone(),
+                    origin,
+                )
+            })
+            .collect();
+
+        ScenarioProbe {
+            connectors,
+            steps,
+            sequence: AtomicU64::new(0),
+            target,
+            timeout: Duration::from_millis(args.timeout_ms),
+        }
+    }
+}
+
+impl Probe for ScenarioProbe {
+    fn kind(&self) -> &'static str {
+        "scenario"
+    }
+
+    fn target(&self) -> String {
+        self.target.clone()
+    }
+
+    async fn attempt(&self, worker: usize) -> Attempt {
+        // Steps of a transaction share connections, which are closed along with the client.
+        let connector = self.connectors[worker % self.connectors.len()].clone();
+        let client = Client::builder().build::<_, Body>(connector);
+        let mut vars = Vars::next(&self.sequence);
+        // Cookies set by each host, by name.
+        let mut cookies: BTreeMap<String, BTreeMap<String, String>> = BTreeMap::new();
+        let mut phases = vec![];
+        let mut statuses = vec![];
+        let mut completed = 0;
+        let mut error = None;
+
+        let start = Instant::now();
+        for step in &self.steps {
+            let sent = Instant::now();
+            let result = time::timeout(self.timeout, async {
+                let uri: Uri = step
+                    .url
+                    .render_string(&vars)
+                    .parse()
+                    .map_err(|e| ProbeError::from_cause("url", &e))?;
+                let host = uri.host().unwrap_or_default().to_string();
+                let mut req = Request::new(Body::from(step.body.render(&vars)));
+                *req.method_mut() = step.method.clone();
+                *req.uri_mut() = uri;
+                for (name, value) in &step.headers {
+                    let value = HeaderValue::try_from(value.render(&vars))
+                        .map_err(|e| ProbeError::from_cause("header", &e))?;
+                    req.headers_mut().append(name, value);
+                }
+                if let Some(jar) = cookies
+                    .get(&host)
+                    .filter(|_| !req.headers().contains_key(COOKIE))
+                {
+                    let cookie: Vec<_> = jar.iter().map(|(k, v)| format!("{}={}", k, v)).collect();
+                    if let Ok(cookie) = HeaderValue::try_from(cookie.join("; ")) {
+                        req.headers_mut().insert(COOKIE, cookie);
+                    }
+                }
+
+                let res = client.request(req).await.map_err(|e| {
+                    let kind = if !e.is_connect() {
+                        "request"
+                    } else if e.source().is_some_and(|e| e.is::<ConnectTimeout>()) {
+                        "connect_timeout"
+                    } else {
+                        "connect"
+                    };
+                    ProbeError::from_cause(kind, &e)
+                })?;
+                let status = res.status();
+                statuses.push(status.as_u16().to_string());
+                for set in res.headers().get_all(SET_COOKIE) {
+                    let Ok(set) = set.to_str() else { continue };
+                    let pair = set.split(';').next().unwrap_or_default();
+                    if let Some((name, value)) = pair.split_once('=') {
+                        let jar = cookies.entry(host.clone()).or_default();
+                        jar.insert(name.trim().to_string(), value.trim().to_string());
+                    }
+                }
+                for (var, header) in &step.extract_header {
+                    let value = res
+                        .headers()
+                        .get(header)
+                        .and_then(|value| value.to_str().ok());
+                    let value = value.ok_or_else(|| {
+                        ProbeError::new("extract", format!("no {} header for {}", header, var))
+                    })?;
+                    vars.set(var, value.to_string());
+                }
+                let body = hyper::body::to_bytes(res.into_body())
+                    .await
+                    .map_err(|e| ProbeError::from_cause("body", &e))?;
+
+                if !step
+                    .expect_status
+                    .iter()
+                    .any(|range| range.contains(&status.as_u16()))
+                {
+                    return Err(ProbeError::new(
+                        "http_status",
+                        format!("unexpected status {}", status),
+                    ));
+                }
+                if !step.extract_json.is_empty() {
+                    let body: Value = serde_json::from_slice(&body)
+                        .map_err(|e| ProbeError::from_cause("extract", &e))?;
+                    for (var, path) in &step.extract_json {
+                        let value = lookup(&body, path).ok_or_else(|| {
+                            ProbeError::new("extract", format!("no value at {} for {}", path, var))
+                        })?;
+                        let value = match value {
+                            Value::String(value) => value.clone(),
+                            value => value.to_string(),
+                        };
+                        vars.set(var, value);
+                    }
+                }
+                Ok(())
+            })
+            .await
+            .unwrap_or_else(|_| {
+                Err(ProbeError::new(
+                    "request_timeout",
+                    format!("timed out after {}ms", self.timeout.as_millis()),
+                ))
+            });
+            phases.push((step.name, sent.elapsed()));
+
+            if let Err(e) = result {
+                error = Some((step.name, e));
+                break;
+            }
+            completed += 1;
+        }
+
+        let mut details = vec![("steps", format!("{}/{}", completed, self.steps.len()))];
+        if let Some((step, _)) = &error {
+            details.push(("failed_step", step.to_string()));
+        }
+        if !statuses.is_empty() {
+            details.push(("statuses", statuses.join(",")));
+        }
+        Attempt {
+            duration: start.elapsed(),
+            phases,
+            details,
+            error: error
+                .map(|(step, e)| ProbeError::new(e.kind, format!("{}: {}", step, e.message))),
+        }
+    }
+}
+
+pub async fn scenario_main(args: ScenarioArgs) {
+    probe::run(ScenarioProbe::new(&args), &args.common).await
+}
+
+/// Finds the value at a path like `$.data.items[0].id` or `data.items.0.id`.
+fn lookup<'a>(value: &'a Value, path: &str) -> Option<&'a Value> {
+    let path = path.strip_prefix('$').unwrap_or(path);
+    let mut value = value;
+    for key in path.split(['.', '[', ']']).filter(|key| !key.is_empty()) {
+        value = match value {
+            Value::Array(items) => items.get(key.parse::<usize>().ok()?)?,
+            value => value.get(key)?,
+        };
+    }
+    Some(value)
+}
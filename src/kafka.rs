@@ -0,0 +1,225 @@
+use std::{
+    collections::BTreeMap,
+    future::Future,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use chrono::Utc;
+use clap::Parser;
+use rskafka::{
+    client::{
+        error::Error, partition::Compression, partition::UnknownTopicHandling, ClientBuilder,
+    },
+    record::Record,
+    BackoffConfig,
+};
+use serde::{Deserialize, Serialize};
+use tokio::time;
+
+use crate::{
+    http::one_or_many,
+    probe::{self, CommonArgs, Probe},
+    report::{Attempt, ProbeError},
+    resolve, socket,
+    tls::{self, TlsArgs},
+};
+
+#[derive(Parser, Debug, Serialize, Deserialize)]
+pub struct KafkaArgs {
+    /// Brokers to bootstrap from, as `host:port`. Can be repeated or separated by commas.
+    #[arg(long, required = true, value_delimiter = ',')]
+    #[serde(deserialize_with = "one_or_many")]
+    brokers: Vec<String>,
+
+    /// Produce a record to this canary topic on every attempt, after fetching metadata.
+    #[arg(long)]
+    topic: Option<String>,
+
+    /// Partition of the canary topic to produce to.
+    #[arg(long, default_value_t = 0)]
+    partition: i32,
+
+    /// Also consume the produced record back from the canary topic.
+    #[arg(long, requires = "topic")]
+    consume: bool,
+
+    /// Set a timeout for connecting to the brokers and fetching the initial metadata.
+    #[arg(long, default_value_t = 1000)]
+    connect_timeout_ms: u64,
+
+    /// Set a timeout for each request after connecting.
+    #[arg(long, default_value_t = 1000)]
+    timeout_ms: u64,
+
+    /// Connect to the brokers over TLS.
+    #[arg(long)]
+    tls: bool,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    tls_args: TlsArgs,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    pub common: CommonArgs,
+}
+
+/// Connects to the cluster and fetches metadata on every attempt, optionally producing a record to
+/// a canary topic and consuming it back.
+pub struct KafkaProbe {
+    brokers: Vec<String>,
+    topic: Option<String>,
+    partition: i32,
+    consume: bool,
+    tls: Option<Arc<rustls::ClientConfig>>,
+    connect_timeout: Duration,
+    timeout: Duration,
+}
+
+impl KafkaProbe {
+    pub fn new(args: &KafkaArgs) -> Self {
+        KafkaProbe {
+            brokers: args.brokers.clone(),
+            topic: args.topic.clone(),
+            partition: args.partition,
+            consume: args.consume,
+            tls: args.tls.then(|| Arc::new(tls::configure(&args.tls_args))),
+            connect_timeout: Duration::from_millis(args.connect_timeout_ms),
+            timeout: Duration::from_millis(args.timeout_ms),
+        }
+    }
+
+    /// Runs a step of the attempt under the timeout, recording how long it took as a phase.
+    async fn step<T>(
+        &self,
+        phases: &mut Vec<(&'static str, Duration)>,
+        phase: &'static str,
+        timeout: Duration,
+        step: impl Future<Output = Result<T, Error>>,
+    ) -> Result<T, ProbeError> {
+        let start = Instant::now();
+        match time::timeout(timeout, step).await {
+            Ok(Ok(value)) => {
+                phases.push((phase, start.elapsed()));
+                Ok(value)
+            }
+            Ok(Err(e)) => Err(ProbeError::from_cause(phase, &e)),
+            Err(_) => {
+                let kind = match phase {
+                    "connect" => "connect_timeout",
+                    _ => "timeout",
+                };
+                let message = format!("{} timed out after {}ms", phase, timeout.as_millis());
+                Err(ProbeError::new(kind, message))
+            }
+        }
+    }
+
+    async fn run(
+        &self,
+        phases: &mut Vec<(&'static str, Duration)>,
+        details: &mut Vec<(&'static str, String)>,
+    ) -> Result<(), ProbeError> {
+        let mut builder = ClientBuilder::new(self.brokers.clone())
+            .client_id("artemiss")
+            // Give up on retrying within the attempt rather than backing off indefinitely.
+            .backoff_config(BackoffConfig {
+                deadline: Some(self.timeout),
+                ..Default::default()
+            });
+        if let Some(tls) = &self.tls {
+            builder = builder.tls_config(tls.clone());
+        }
+        let client = self
+            .step(phases, "connect", self.connect_timeout, builder.build())
+            .await?;
+
+        let topics = self
+            .step(phases, "metadata", self.timeout, client.list_topics())
+            .await?;
+        details.push(("topics", topics.len().to_string()));
+
+        let Some(topic) = &self.topic else {
+            return Ok(());
+        };
+
+        let timestamp = Utc::now();
+        let value = format!("artemiss canary {}", timestamp.to_rfc3339());
+        let record = Record {
+            key: None,
+            value: Some(value.clone().into_bytes()),
+            headers: BTreeMap::new(),
+            timestamp,
+        };
+        let produce = async {
+            let partition = client
+                .partition_client(topic.as_str(), self.partition, UnknownTopicHandling::Error)
+                .await?;
+            let offsets = partition
+                .produce(vec![record], Compression::NoCompression)
+                .await?;
+            Ok((partition, offsets))
+        };
+        let (partition, offsets) = self.step(phases, "produce", self.timeout, produce).await?;
+        let Some(&offset) = offsets.first() else {
+            return Err(ProbeError::new("produce", "no offset returned for record"));
+        };
+        details.push(("offset", offset.to_string()));
+
+        if !self.consume {
+            return Ok(());
+        }
+
+        let max_wait_ms = self.timeout.as_millis().min(i32::MAX as u128) as i32;
+        let fetch = partition.fetch_records(offset, 1..1_000_000, max_wait_ms);
+        let (records, _) = self.step(phases, "consume", self.timeout, fetch).await?;
+        let found = records.iter().any(|record| {
+            record.offset == offset && record.record.value.as_deref() == Some(value.as_bytes())
+        });
+        if !found {
+            return Err(ProbeError::new(
+                "consume",
+                format!("record at offset {} was not consumed", offset),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+impl Probe for KafkaProbe {
+    fn kind(&self) -> &'static str {
+        "kafka"
+    }
+
+    fn target(&self) -> String {
+        match &self.topic {
+            Some(topic) => format!("{}/{}", self.brokers.join(","), topic),
+            None => self.brokers.join(","),
+        }
+    }
+
+    async fn attempt(&self, _worker: usize) -> Attempt {
+        let start = Instant::now();
+        let mut phases = vec![];
+        let mut details = vec![];
+        let error = self.run(&mut phases, &mut details).await.err();
+
+        Attempt {
+            duration: start.elapsed(),
+            phases,
+            details,
+            error,
+        }
+    }
+}
+
+pub async fn kafka_main(args: KafkaArgs) {
+    resolve::ensure_unrestricted(
+        "kafka",
+        "the client connects to brokers by the names they advertise, resolving them itself",
+    );
+    socket::ensure_supported("kafka", &[]);
+    probe::run(KafkaProbe::new(&args), &args.common).await
+}
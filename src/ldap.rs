@@ -0,0 +1,436 @@
+use std::{
+    io,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use clap::{Parser, ValueEnum};
+use rustls::pki_types::ServerName;
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio_rustls::TlsConnector;
+
+use crate::{
+    limit,
+    probe::{self, CommonArgs, Probe},
+    report::{Attempt, ProbeError},
+    resolve, secret, socket,
+    tls::{self, TlsArgs},
+};
+
+/// Name of the extended operation upgrading a connection to TLS.
+const STARTTLS_OID: &[u8] = b"1.3.6.1.4.1.1466.20037";
+
+/// Largest response accepted from the server.
+const MAX_MESSAGE: usize = 1 << 20;
+
+/// How TLS is negotiated with the directory server.
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LdapTls {
+    /// Upgrade the connection with the StartTLS extended operation before binding.
+    #[default]
+    Starttls,
+    /// Negotiate TLS as soon as the connection is open, as LDAPS on port 636.
+    Implicit,
+    /// Stay in cleartext.
+    None,
+}
+
+#[derive(Parser, Debug, Serialize, Deserialize)]
+pub struct LdapArgs {
+    /// Host of the directory server.
+    #[arg(long)]
+    host: String,
+
+    /// Port of the directory server.
+    #[arg(long, default_value_t = 389)]
+    port: u16,
+
+    /// How to negotiate TLS.
+    #[arg(long, value_enum, default_value_t)]
+    ldap_tls: LdapTls,
+
+    /// Bind as this DN with a simple bind, e.g. `cn=probe,ou=svc,dc=example,dc=com` or
+    /// `probe@example.com` for Active Directory. Binds anonymously without it.
+    #[arg(long, requires = "password")]
+    bind_dn: Option<String>,
+
+    /// Password for `--bind-dn`.
+    #[arg(long, requires = "bind_dn")]
+    password: Option<String>,
+
+    /// Count the attempt as successful only if the bind is rejected, to check that the server is
+    /// reachable and verifying credentials using dummy ones.
+    #[arg(long, requires = "bind_dn")]
+    expect_bind_rejected: bool,
+
+    /// Set a timeout for only the connect phase of a connection, including implicit TLS.
+    #[arg(long, default_value_t = 1000)]
+    connect_timeout_ms: u64,
+
+    /// Set a timeout for the rest of the session after connecting.
+    #[arg(long, default_value_t = 1000)]
+    timeout_ms: u64,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    tls: TlsArgs,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    pub common: CommonArgs,
+}
+
+trait Stream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Stream for T {}
+
+/// Goes through `StartTLS` and a simple bind on every attempt, then unbinds.
+pub struct LdapProbe {
+    host: String,
+    port: u16,
+    mode: LdapTls,
+    tls: TlsConnector,
+    sni: Option<String>,
+    credentials: Option<(String, String)>,
+    expect_bind_rejected: bool,
+    connect_timeout: Duration,
+    timeout: Duration,
+}
+
+/// Result of an operation, as the server sent it.
+struct LdapResult {
+    code: u32,
+    diagnostic: String,
+}
+
+impl LdapResult {
+    fn describe(&self) -> String {
+        let mut description = format!("{} ({})", result_name(self.code), self.code);
+        if !self.diagnostic.is_empty() {
+            description.push_str(": ");
+            description.push_str(&self.diagnostic);
+        }
+        description
+    }
+}
+
+impl LdapProbe {
+    pub fn new(args: &LdapArgs) -> Self {
+        LdapProbe {
+            host: args.host.clone(),
+            port: args.port,
+            mode: args.ldap_tls,
+            tls: TlsConnector::from(Arc::new(tls::configure(&args.tls))),
+            sni: args.tls.sni.clone(),
+            credentials: args
+                .bind_dn
+                .clone()
+                .zip(args.password.clone())
+                .inspect(|(_, password)| secret::register(password)),
+            expect_bind_rejected: args.expect_bind_rejected,
+            connect_timeout: Duration::from_millis(args.connect_timeout_ms),
+            timeout: Duration::from_millis(args.timeout_ms),
+        }
+    }
+
+    /// Negotiates TLS over the connection.
+    async fn handshake(&self, stream: Box<dyn Stream>) -> Result<Box<dyn Stream>, ProbeError> {
+        let name = ServerName::try_from(self.sni.as_ref().unwrap_or(&self.host).clone())
+            .map_err(|e| ProbeError::new("tls", e))?;
+        let stream = self
+            .tls
+            .connect(name, stream)
+            .await
+            .map_err(|e| ProbeError::from_cause("tls", &e))?;
+        Ok(Box::new(stream))
+    }
+
+    /// Opens a connection, negotiating TLS straight away in implicit mode.
+    async fn connect(
+        &self,
+        phases: &mut Vec<(&'static str, Duration)>,
+        details: &mut Vec<(&'static str, String)>,
+    ) -> Result<Box<dyn Stream>, ProbeError> {
+        let start = Instant::now();
+        let conn = resolve::connect(&self.host, self.port)
+            .await
+            .map_err(|e| ProbeError::from_cause("connect", &e))?;
+        details.extend(conn.details());
+        let stream = conn.stream;
+        stream
+            .set_nodelay(socket::nodelay(true))
+            .map_err(|e| ProbeError::from_cause("connect", &e))?;
+        phases.push(("connect", start.elapsed()));
+
+        if self.mode != LdapTls::Implicit {
+            return Ok(Box::new(stream));
+        }
+        phases.extend(limit::handshake().await);
+        let start = Instant::now();
+        let stream = self.handshake(Box::new(stream)).await?;
+        phases.push(("tls", start.elapsed()));
+        Ok(stream)
+    }
+
+    /// Runs the session after connecting, up to and including the unbind.
+    async fn session(
+        &self,
+        mut stream: Box<dyn Stream>,
+        phases: &mut Vec<(&'static str, Duration)>,
+        details: &mut Vec<(&'static str, String)>,
+    ) -> Result<(), ProbeError> {
+        let mut id = 0;
+        if self.mode == LdapTls::Starttls {
+            phases.extend(limit::handshake().await);
+            let start = Instant::now();
+            id += 1;
+            let request = message(id, tlv(0x77, &tlv(0x80, STARTTLS_OID)));
+            let result = exchange(&mut stream, &request, id, 0x78).await?;
+            if result.code != 0 {
+                return Err(ProbeError::new(
+                    "starttls",
+                    format!("server refused StartTLS: {}", result.describe()),
+                ));
+            }
+            stream = self.handshake(stream).await?;
+            phases.push(("starttls", start.elapsed()));
+        }
+
+        let (kind, dn, password) = match &self.credentials {
+            Some((dn, password)) => ("simple", dn.as_str(), password.as_str()),
+            None => ("anonymous", "", ""),
+        };
+        details.push(("bind_type", kind.to_string()));
+        let mut bind = tlv(0x02, &[3]);
+        bind.extend(tlv(0x04, dn.as_bytes()));
+        bind.extend(tlv(0x80, password.as_bytes()));
+        id += 1;
+        let start = Instant::now();
+        let result = exchange(&mut stream, &message(id, tlv(0x60, &bind)), id, 0x61).await?;
+        phases.push(("bind", start.elapsed()));
+        details.push(("result_code", result.code.to_string()));
+        details.push(("result", result_name(result.code).to_string()));
+
+        let accepted = result.code == 0;
+        if accepted == self.expect_bind_rejected {
+            let message = match accepted {
+                true => "expected the bind to be rejected".to_string(),
+                false => format!("bind failed: {}", result.describe()),
+            };
+            return Err(ProbeError::new("bind", message));
+        }
+
+        // The server closes the connection on an unbind without answering it.
+        id += 1;
+        stream
+            .write_all(&message(id, vec![0x42, 0x00]))
+            .await
+            .map_err(|e| ProbeError::from_cause("send", &e))?;
+        let _ = stream.shutdown().await;
+        Ok(())
+    }
+}
+
+impl Probe for LdapProbe {
+    fn kind(&self) -> &'static str {
+        "ldap"
+    }
+
+    fn target(&self) -> String {
+        format!("{}:{}", self.host, self.port)
+    }
+
+    async fn attempt(&self, _worker: usize) -> Attempt {
+        let start = Instant::now();
+        let mut phases = vec![];
+        let mut details = vec![("tls_mode", format!("{:?}", self.mode).to_lowercase())];
+
+        let throttle = limit::acquire().await;
+        phases.extend(throttle.phase());
+        let connect = self.connect(&mut phases, &mut details);
+        let connected = limit::timeout(self.connect_timeout, connect).await;
+        drop(throttle);
+        let result = match connected {
+            Ok(Ok(stream)) => {
+                let session = self.session(stream, &mut phases, &mut details);
+                match limit::timeout(self.timeout, session).await {
+                    Ok(result) => result,
+                    Err(_) => Err(ProbeError::new(
+                        "timeout",
+                        format!("timed out after {}ms", self.timeout.as_millis()),
+                    )),
+                }
+            }
+            Ok(Err(e)) => Err(e),
+            Err(_) => Err(ProbeError::new(
+                "connect_timeout",
+                format!("timed out after {}ms", self.connect_timeout.as_millis()),
+            )),
+        };
+
+        Attempt {
+            duration: start.elapsed(),
+            phases,
+            details,
+            error: result.err(),
+        }
+    }
+}
+
+pub async fn ldap_main(args: LdapArgs) {
+    probe::run(LdapProbe::new(&args), &args.common).await
+}
+
+/// Sends a request and reads the result of the response to it, which must have the given tag.
+async fn exchange<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+    request: &[u8],
+    id: u32,
+    tag: u8,
+) -> Result<LdapResult, ProbeError> {
+    stream
+        .write_all(request)
+        .await
+        .map_err(|e| ProbeError::from_cause("send", &e))?;
+    let response = read_message(stream)
+        .await
+        .map_err(|e| ProbeError::from_cause("receive", &e))?;
+    parse_result(&response, id, tag)
+        .ok_or_else(|| ProbeError::new("protocol", "malformed response from the directory server"))
+}
+
+/// Encodes a BER element with the tag and value.
+fn tlv(tag: u8, value: &[u8]) -> Vec<u8> {
+    let mut element = vec![tag];
+    match value.len() {
+        len @ 0..=0x7f => element.push(len as u8),
+        len => {
+            let bytes = len.to_be_bytes();
+            let skip = bytes.iter().take_while(|&&b| b == 0).count();
+            element.push(0x80 | (bytes.len() - skip) as u8);
+            element.extend_from_slice(&bytes[skip..]);
+        }
+    }
+    element.extend_from_slice(value);
+    element
+}
+
+/// Wraps an operation in an `LDAPMessage` with the message ID.
+fn message(id: u32, op: Vec<u8>) -> Vec<u8> {
+    let bytes = id.to_be_bytes();
+    let skip = bytes.iter().take_while(|&&b| b == 0).count().min(3);
+    let mut id = bytes[skip..].to_vec();
+    // Integers are signed, so a leading bit needs a zero in front.
+    if id[0] & 0x80 != 0 {
+        id.insert(0, 0);
+    }
+    let mut body = tlv(0x02, &id);
+    body.extend(op);
+    tlv(0x30, &body)
+}
+
+/// Reads a whole `LDAPMessage`, without reading past its end.
+async fn read_message<S: AsyncRead + Unpin>(stream: &mut S) -> io::Result<Vec<u8>> {
+    let mut head = [0; 2];
+    stream.read_exact(&mut head).await?;
+    if head[0] != 0x30 {
+        return Err(io::Error::other("response is not an LDAP message"));
+    }
+    let len = match head[1] {
+        len @ 0..=0x7f => len as usize,
+        long => {
+            let count = (long & 0x7f) as usize;
+            if count == 0 || count > 4 {
+                return Err(io::Error::other("invalid length in LDAP message"));
+            }
+            let mut bytes = [0; 4];
+            stream.read_exact(&mut bytes[4 - count..]).await?;
+            u32::from_be_bytes(bytes) as usize
+        }
+    };
+    if len > MAX_MESSAGE {
+        return Err(io::Error::other(format!("LDAP message of {} bytes", len)));
+    }
+    let mut message = vec![0; len];
+    stream.read_exact(&mut message).await?;
+    Ok(message)
+}
+
+/// Splits the next BER element off the front of `input`, returning its tag and value.
+fn element<'a>(input: &mut &'a [u8]) -> Option<(u8, &'a [u8])> {
+    let (&tag, rest) = input.split_first()?;
+    let (&first, mut rest) = rest.split_first()?;
+    let len = match first {
+        len @ 0..=0x7f => len as usize,
+        long => {
+            let count = (long & 0x7f) as usize;
+            if count == 0 || count > 4 || rest.len() < count {
+                return None;
+            }
+            let len = rest[..count]
+                .iter()
+                .fold(0, |len, &b| len << 8 | b as usize);
+            rest = &rest[count..];
+            len
+        }
+    };
+    if rest.len() < len {
+        return None;
+    }
+    let (value, rest) = rest.split_at(len);
+    *input = rest;
+    Some((tag, value))
+}
+
+/// Parses the `LDAPResult` of a response with the message ID and operation tag from the body of an
+/// `LDAPMessage`.
+fn parse_result(mut message: &[u8], id: u32, tag: u8) -> Option<LdapResult> {
+    let (0x02, message_id) = element(&mut message)? else {
+        return None;
+    };
+    let message_id = message_id.iter().fold(0u64, |id, &b| id << 8 | b as u64);
+    let (op_tag, mut op) = element(&mut message)?;
+    if message_id != id as u64 || op_tag != tag {
+        return None;
+    }
+    let (0x0a, code) = element(&mut op)? else {
+        return None;
+    };
+    let code = code.iter().fold(0u32, |code, &b| code << 8 | b as u32);
+    let (0x04, _matched) = element(&mut op)? else {
+        return None;
+    };
+    let (0x04, diagnostic) = element(&mut op)? else {
+        return None;
+    };
+    Some(LdapResult {
+        code,
+        diagnostic: String::from_utf8_lossy(diagnostic).into_owned(),
+    })
+}
+
+/// Name of an LDAP result code, as in RFC 4511.
+fn result_name(code: u32) -> &'static str {
+    match code {
+        0 => "success",
+        1 => "operationsError",
+        2 => "protocolError",
+        3 => "timeLimitExceeded",
+        7 => "authMethodNotSupported",
+        8 => "strongerAuthRequired",
+        10 => "referral",
+        13 => "confidentialityRequired",
+        32 => "noSuchObject",
+        34 => "invalidDNSyntax",
+        48 => "inappropriateAuthentication",
+        49 => "invalidCredentials",
+        50 => "insufficientAccessRights",
+        51 => "busy",
+        52 => "unavailable",
+        53 => "unwillingToPerform",
+        80 => "other",
+        _ => "unknown",
+    }
+}
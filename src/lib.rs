@@ -0,0 +1,91 @@
+//! Synthetic probes for HTTP, databases and other network services.
+//!
+//! Every probe implements [`Probe`], making a single attempt against its target when asked.
+//! A [`Runner`] schedules the attempts of a probe across workers, the same way the command line
+//! does, and yields a [`ProbeResult`] for each one. Probes are built from the same arguments as
+//! their subcommand, e.g. `HttpArgs::parse_from(["http", "--url", "https://example.com"])`.
+//!
+//! Probes of other kinds can be added to the command line with [`registry::register`], so that
+//! a program wrapping [`cli::main`] runs them like the built-in ones.
+
+mod ab;
+pub mod agent;
+mod alert;
+pub mod amqp;
+mod api;
+pub mod cassandra;
+pub mod cli;
+pub mod compare;
+mod config;
+mod control;
+pub mod db;
+pub mod discover;
+pub mod dns;
+pub mod es;
+mod export;
+pub mod ftp;
+pub mod grpc;
+pub mod hold;
+pub mod http;
+pub mod kafka;
+pub mod ldap;
+pub mod limit;
+mod logger;
+mod metrics;
+pub mod misbehave;
+pub mod mongo;
+pub mod mqtt;
+pub mod ntp;
+mod otlp;
+mod pcap;
+pub mod ping;
+mod ports;
+pub mod probe;
+mod profile;
+pub mod redis;
+pub mod registry;
+pub mod report;
+pub mod resolve;
+pub mod s3;
+mod secret;
+mod service;
+pub mod sftp;
+pub mod smtp;
+pub mod socket;
+pub mod ssh;
+mod stats;
+pub mod store;
+pub mod sweep;
+pub mod tcp;
+mod template;
+pub mod tls;
+mod traceroute;
+mod tui;
+pub mod udp;
+pub mod ws;
+
+pub use amqp::AmqpProbe;
+pub use cassandra::CassandraProbe;
+pub use db::DbProbe;
+pub use dns::DnsProbe;
+pub use es::EsProbe;
+pub use ftp::FtpProbe;
+pub use grpc::GrpcProbe;
+pub use http::{H2PingProbe, HttpProbe, ScenarioProbe};
+pub use kafka::KafkaProbe;
+pub use ldap::LdapProbe;
+pub use mongo::MongoProbe;
+pub use mqtt::MqttProbe;
+pub use ntp::NtpProbe;
+pub use ping::PingProbe;
+pub use probe::{CommonArgs, Probe, ProbeResult, Runner};
+pub use redis::RedisProbe;
+pub use report::{Attempt, Origin, ProbeError};
+pub use s3::S3Probe;
+pub use sftp::SftpProbe;
+pub use smtp::SmtpProbe;
+pub use ssh::SshProbe;
+pub use tcp::TcpProbe;
+pub use tls::HandshakeProbe;
+pub use udp::UdpProbe;
+pub use ws::WsProbe;
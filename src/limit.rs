@@ -0,0 +1,173 @@
+use std::{
+    cell::Cell,
+    future::Future,
+    sync::{Arc, Mutex, OnceLock},
+    time::{Duration, Instant},
+};
+
+use clap::Args;
+use serde::{Deserialize, Serialize};
+use tokio::{
+    sync::{OwnedSemaphorePermit, Semaphore},
+    time::{self, error::Elapsed},
+};
+
+tokio::task_local! {
+    /// Time spent waiting to start TLS handshakes within the current `timeout`.
+    static WAITED: Cell<Duration>;
+}
+
+/// Limits shared by every worker of every probe.
+static LIMITS: OnceLock<Limits> = OnceLock::new();
+
+/// Options limiting how fast connections are opened across all workers, to open them the way a
+/// fleet of production clients would rather than in bursts on every tick.
+#[derive(Args, Clone, Debug, Default, Serialize, Deserialize)]
+pub struct LimitArgs {
+    /// Open at most this many new connections per second across all workers, spacing them
+    /// evenly.
+    #[arg(long, global = true)]
+    max_connections_per_s: Option<f64>,
+
+    /// Start at most this many TLS handshakes per second across all workers, spacing them
+    /// evenly and reporting the wait as the `tls_throttle` phase.
+    #[arg(long, global = true)]
+    max_tls_handshakes_per_s: Option<f64>,
+
+    /// Have at most this many connections being opened at once across all workers.
+    #[arg(long, global = true)]
+    max_concurrent_connects: Option<usize>,
+}
+
+#[derive(Default)]
+struct Limits {
+    connections: Option<Pacer>,
+    handshakes: Option<Pacer>,
+    connecting: Option<Arc<Semaphore>>,
+}
+
+/// Spaces events out to a rate.
+struct Pacer {
+    interval: Duration,
+    /// Earliest time the next event may happen.
+    next: Mutex<Instant>,
+}
+
+impl Pacer {
+    fn new(per_s: f64) -> Self {
+        assert!(per_s > 0.0, "rate limits must be positive");
+        Pacer {
+            interval: Duration::from_secs_f64(1.0 / per_s),
+            next: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Waits for the next slot, which is taken even if the wait is cancelled.
+    async fn wait(&self) {
+        time::sleep_until(self.reserve().into()).await;
+    }
+
+    /// Takes the next slot, returning when it is.
+    fn reserve(&self) -> Instant {
+        let mut next = self.next.lock().unwrap();
+        let at = (*next).max(Instant::now());
+        *next = at + self.interval;
+        at
+    }
+}
+
+/// Permission to open a connection, to be dropped as soon as it is open, so that only opening
+/// connections is limited rather than the sessions over them.
+pub struct Throttle {
+    /// Time spent waiting for the limits.
+    pub waited: Duration,
+    _connecting: Option<OwnedSemaphorePermit>,
+}
+
+impl Throttle {
+    /// Phase of time spent waiting for the limits, when there are any.
+    pub fn phase(&self) -> Option<(&'static str, Duration)> {
+        is_set().then_some(("throttle", self.waited))
+    }
+
+    /// Lets the next connection be opened now that this one is, returning the phase of time
+    /// spent waiting.
+    pub fn release(self) -> Option<(&'static str, Duration)> {
+        self.phase()
+    }
+}
+
+/// Sets the limits shared by every worker.
+pub(crate) fn init(args: LimitArgs) {
+    let limits = Limits {
+        connections: args.max_connections_per_s.map(Pacer::new),
+        handshakes: args.max_tls_handshakes_per_s.map(Pacer::new),
+        connecting: args.max_concurrent_connects.map(|max| {
+            assert!(max > 0, "--max-concurrent-connects must be positive");
+            Arc::new(Semaphore::new(max))
+        }),
+    };
+    if LIMITS.set(limits).is_err() {
+        panic!("connection limits already set");
+    }
+}
+
+fn limits() -> &'static Limits {
+    LIMITS.get_or_init(Limits::default)
+}
+
+fn is_set() -> bool {
+    let limits = limits();
+    limits.connections.is_some() || limits.connecting.is_some()
+}
+
+/// Waits until a connection may be opened. Call it before timing the connection, so that waiting
+/// is reported as the `throttle` phase rather than counted against the connect timeout.
+pub async fn acquire() -> Throttle {
+    let limits = limits();
+    let start = Instant::now();
+    let connecting = match &limits.connecting {
+        Some(semaphore) => Some(semaphore.clone().acquire_owned().await.unwrap()),
+        None => None,
+    };
+    if let Some(pacer) = &limits.connections {
+        pacer.wait().await;
+    }
+    Throttle {
+        waited: start.elapsed(),
+        _connecting: connecting,
+    }
+}
+
+/// Waits until a TLS handshake may be started, returning the `tls_throttle` phase of time spent
+/// waiting when handshakes are limited. Call it just before starting the handshake, so that
+/// connections refused before one are not counted.
+pub async fn handshake() -> Option<(&'static str, Duration)> {
+    let pacer = limits().handshakes.as_ref()?;
+    let start = Instant::now();
+    let at = pacer.reserve();
+    // Any timeout this runs within is extended for the whole wait from the start.
+    let _ =
+        WAITED.try_with(|waited| waited.set(waited.get() + at.saturating_duration_since(start)));
+    time::sleep_until(at.into()).await;
+    Some(("tls_throttle", start.elapsed()))
+}
+
+/// Requires a future to complete within a duration, not counting time it spends waiting to start
+/// TLS handshakes, so that the limits are not reported as timeouts.
+pub async fn timeout<F: Future>(duration: Duration, future: F) -> Result<F::Output, Elapsed> {
+    WAITED
+        .scope(Cell::new(Duration::ZERO), async {
+            let deadline = Instant::now() + duration;
+            tokio::pin!(future);
+            loop {
+                let waited = WAITED.with(Cell::get);
+                match time::timeout_at((deadline + waited).into(), &mut future).await {
+                    Ok(output) => return Ok(output),
+                    Err(elapsed) if WAITED.with(Cell::get) == waited => return Err(elapsed),
+                    Err(_) => {}
+                }
+            }
+        })
+        .await
+}
@@ -0,0 +1,209 @@
+use std::borrow::Cow;
+#[cfg(unix)]
+use std::{ffi::CString, fmt::Write, io, os::unix::net::UnixDatagram};
+
+use log::Log;
+#[cfg(unix)]
+use log::{kv, Level, Metadata, Record};
+
+use crate::{report::LogSink, secret, tui};
+
+/// Socket journald reads entries sent with its native protocol from.
+#[cfg(unix)]
+const JOURNAL_SOCKET: &str = "/run/systemd/journal/socket";
+/// Name log lines are sent to syslog and journald under.
+#[cfg(unix)]
+const IDENTIFIER: &std::ffi::CStr = c"artemiss";
+
+/// Sets up logging to the sink, filtered by `RUST_LOG` whichever it is.
+pub(crate) fn init(sink: LogSink, tui: bool) {
+    let mut builder = env_logger::Builder::from_default_env();
+    if tui {
+        builder
+            .target(env_logger::Target::Pipe(Box::new(tui::LogWriter)))
+            .write_style(env_logger::WriteStyle::Never);
+    }
+    let filter = builder.build();
+    let max_level = filter.filter();
+    let logger: Box<dyn Log> = match sink {
+        LogSink::Stderr => Box::new(filter),
+        #[cfg(unix)]
+        LogSink::Syslog => Box::new(Syslog::open(filter)),
+        #[cfg(unix)]
+        LogSink::Journald => Box::new(
+            Journald::open(filter)
+                .unwrap_or_else(|e| panic!("cannot log to journald at {}: {}", JOURNAL_SOCKET, e)),
+        ),
+        #[cfg(not(unix))]
+        LogSink::Syslog | LogSink::Journald => {
+            panic!("--log-sink {:?} is only supported on unix", sink)
+        }
+    };
+    log::set_boxed_logger(Box::new(Redacted(logger))).expect("logging already initialised");
+    log::set_max_level(max_level);
+}
+
+/// Redacts secrets from the log lines of a sink.
+struct Redacted(Box<dyn Log>);
+
+impl Log for Redacted {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        self.0.enabled(metadata)
+    }
+
+    fn log(&self, record: &log::Record) {
+        if !secret::any() || !self.0.enabled(record.metadata()) {
+            return self.0.log(record);
+        }
+        let message = record.args().to_string();
+        match secret::redact(&message) {
+            Cow::Borrowed(_) => self.0.log(record),
+            Cow::Owned(message) => self.0.log(
+                &record
+                    .to_builder()
+                    .args(format_args!("{}", message))
+                    .build(),
+            ),
+        }
+    }
+
+    fn flush(&self) {
+        self.0.flush()
+    }
+}
+
+/// Syslog severity of a log level. Failed attempts are logged as errors, and failed warmups,
+/// alerts and closed connections as warnings.
+#[cfg(unix)]
+fn severity(level: Level) -> libc::c_int {
+    match level {
+        Level::Error => libc::LOG_ERR,
+        Level::Warn => libc::LOG_WARNING,
+        Level::Info => libc::LOG_INFO,
+        Level::Debug | Level::Trace => libc::LOG_DEBUG,
+    }
+}
+
+/// Logs to the local syslog daemon, with the fields of a line in its message.
+#[cfg(unix)]
+struct Syslog {
+    filter: env_logger::Logger,
+}
+
+#[cfg(unix)]
+impl Syslog {
+    fn open(filter: env_logger::Logger) -> Self {
+        // SAFETY: the identifier is static, so it outlives every later call to syslog.
+        unsafe { libc::openlog(IDENTIFIER.as_ptr(), libc::LOG_PID, libc::LOG_DAEMON) };
+        Syslog { filter }
+    }
+}
+
+#[cfg(unix)]
+impl Log for Syslog {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.filter.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.filter.matches(record) {
+            return;
+        }
+        let message = record.args().to_string().replace('\0', "");
+        let message = CString::new(message).unwrap();
+        // SAFETY: the format takes the one string given, which is NUL terminated.
+        unsafe { libc::syslog(severity(record.level()), c"%s".as_ptr(), message.as_ptr()) };
+    }
+
+    fn flush(&self) {}
+}
+
+/// Logs to journald with its native protocol, with the fields of a line as fields of its entry,
+/// like `PROBE`, `TARGET` and `ERROR_KIND`.
+#[cfg(unix)]
+struct Journald {
+    filter: env_logger::Logger,
+    socket: UnixDatagram,
+}
+
+#[cfg(unix)]
+impl Journald {
+    fn open(filter: env_logger::Logger) -> io::Result<Self> {
+        let socket = UnixDatagram::unbound()?;
+        socket.connect(JOURNAL_SOCKET)?;
+        Ok(Journald { filter, socket })
+    }
+}
+
+#[cfg(unix)]
+impl Log for Journald {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.filter.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.filter.matches(record) {
+            return;
+        }
+        let mut entry = Entry(vec![]);
+        entry.field("MESSAGE", &record.args().to_string());
+        entry.field("PRIORITY", &severity(record.level()).to_string());
+        entry.field("SYSLOG_IDENTIFIER", IDENTIFIER.to_str().unwrap());
+        entry.field("SYSLOG_PID", &std::process::id().to_string());
+        if let Some(file) = record.file() {
+            entry.field("CODE_FILE", file);
+        }
+        if let Some(line) = record.line() {
+            entry.field("CODE_LINE", &line.to_string());
+        }
+        let _ = record.key_values().visit(&mut entry);
+        // Nothing is left to report failing to log to, so entries journald drops are lost.
+        let _ = self.socket.send(&entry.0);
+    }
+
+    fn flush(&self) {}
+}
+
+/// Fields of a journal entry, in the native protocol.
+#[cfg(unix)]
+struct Entry(Vec<u8>);
+
+#[cfg(unix)]
+impl Entry {
+    fn field(&mut self, name: &str, value: &str) {
+        self.0.extend_from_slice(name.as_bytes());
+        // Values spanning lines are framed by their length rather than ended by a newline.
+        if value.contains('\n') {
+            self.0.push(b'\n');
+            self.0
+                .extend_from_slice(&(value.len() as u64).to_le_bytes());
+        } else {
+            self.0.push(b'=');
+        }
+        self.0.extend_from_slice(value.as_bytes());
+        self.0.push(b'\n');
+    }
+}
+
+#[cfg(unix)]
+impl<'kvs> kv::VisitSource<'kvs> for Entry {
+    fn visit_pair(&mut self, key: kv::Key<'kvs>, value: kv::Value<'kvs>) -> Result<(), kv::Error> {
+        let mut value_text = String::new();
+        let _ = write!(value_text, "{}", value);
+        // Fields that do not apply to a line are left empty.
+        if value_text.is_empty() {
+            return Ok(());
+        }
+        // Journal field names are upper case letters, digits and underscores.
+        let name: String = key
+            .as_str()
+            .chars()
+            .map(|c| match c.is_ascii_alphanumeric() {
+                true => c.to_ascii_uppercase(),
+                false => '_',
+            })
+            .collect();
+        self.field(&name, &value_text);
+        Ok(())
+    }
+}
@@ -0,0 +1,247 @@
+use std::{
+    collections::BTreeMap,
+    convert::Infallible,
+    net::SocketAddr,
+    sync::{LazyLock, Mutex},
+};
+
+use hyper::{
+    header::CONTENT_TYPE,
+    service::{make_service_fn, service_fn},
+    Body, Method, Request, Response, Server, StatusCode,
+};
+use log::error;
+use prometheus::{
+    proto::{LabelPair, MetricFamily},
+    register_gauge_vec, register_histogram_vec, register_int_counter_vec, Encoder, GaugeVec,
+    HistogramVec, IntCounterVec, TextEncoder,
+};
+
+use crate::{
+    report::{Attempt, Labels, Origin},
+    stats,
+};
+
+/// Latency buckets in seconds, fine-grained at the low end where probe timeouts usually sit.
+pub const BUCKETS: &[f64] = &[
+    0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// Labels given with `--label` to each probe target, keyed by probe kind, name and target, which
+/// are added to its series when they are rendered.
+static LABELS: LazyLock<Mutex<BTreeMap<Key, Labels>>> = LazyLock::new(Default::default);
+
+type Key = (String, String, String);
+
+static REQUESTS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "artemiss_requests_total",
+        "Number of probe attempts.",
+        &["probe", "name", "target", "worker"]
+    )
+    .unwrap()
+});
+
+static ERRORS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "artemiss_errors_total",
+        "Number of failed probe attempts.",
+        &["probe", "name", "target", "worker", "class"]
+    )
+    .unwrap()
+});
+
+static SKIPPED: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "artemiss_skipped_ticks_total",
+        "Number of ticks skipped with attempts in flight.",
+        &["probe", "name", "target", "worker"]
+    )
+    .unwrap()
+});
+
+static REQUEST_DURATION: LazyLock<HistogramVec> = LazyLock::new(|| {
+    register_histogram_vec!(
+        "artemiss_request_duration_seconds",
+        "Time taken by probe attempts.",
+        &["probe", "name", "target", "worker"],
+        BUCKETS.to_vec()
+    )
+    .unwrap()
+});
+
+static CONNECT_DURATION: LazyLock<HistogramVec> = LazyLock::new(|| {
+    register_histogram_vec!(
+        "artemiss_connect_duration_seconds",
+        "Time taken to establish connections.",
+        &["probe", "name", "target", "worker"],
+        BUCKETS.to_vec()
+    )
+    .unwrap()
+});
+
+static LATENCY_QUANTILES: LazyLock<GaugeVec> = LazyLock::new(|| {
+    register_gauge_vec!(
+        "artemiss_latency_quantile_seconds",
+        "Latency of successful probe attempts over the whole run at each quantile, \
+         taken from a histogram of every attempt against the target.",
+        &["probe", "name", "target", "quantile"]
+    )
+    .unwrap()
+});
+
+/// Records a probe attempt in the metrics.
+pub fn observe(origin: &Origin, attempt: &Attempt) {
+    let name = origin.name.as_deref().unwrap_or_default();
+    let worker = origin.worker.to_string();
+    let labels = [origin.probe, name, &origin.target, &worker];
+    remember(origin);
+
+    REQUESTS.with_label_values(&labels).inc();
+    REQUEST_DURATION
+        .with_label_values(&labels)
+        .observe(attempt.duration.as_secs_f64());
+    if let Some(connect) = attempt.phase("connect") {
+        CONNECT_DURATION
+            .with_label_values(&labels)
+            .observe(connect.as_secs_f64());
+    }
+    if let Some(e) = &attempt.error {
+        ERRORS
+            .with_label_values(&[origin.probe, name, &origin.target, &worker, e.kind])
+            .inc();
+    }
+}
+
+/// Records ticks a worker skipped in the metrics.
+pub fn skipped(origin: &Origin, ticks: u64) {
+    let name = origin.name.as_deref().unwrap_or_default();
+    let worker = origin.worker.to_string();
+    remember(origin);
+    SKIPPED
+        .with_label_values(&[origin.probe, name, &origin.target, &worker])
+        .inc_by(ticks);
+}
+
+/// Keeps the labels of the probe target, to add to its series.
+fn remember(origin: &Origin) {
+    if origin.labels.is_empty() {
+        return;
+    }
+    let key = (
+        origin.probe.to_string(),
+        origin.name.clone().unwrap_or_default(),
+        origin.target.clone(),
+    );
+    LABELS
+        .lock()
+        .unwrap()
+        .entry(key)
+        .or_insert_with(|| origin.labels.clone());
+}
+
+/// Serves the metrics on `/metrics` in the background.
+pub fn serve(addr: SocketAddr) {
+    let server = Server::try_bind(&addr)
+        .expect("error binding metrics address")
+        .serve(make_service_fn(|_| async {
+            Ok::<_, Infallible>(service_fn(handle))
+        }));
+
+    tokio::spawn(async move {
+        if let Err(e) = server.await {
+            error!("metrics server error: {}", e);
+        }
+    });
+}
+
+async fn handle(req: Request<Body>) -> Result<Response<Body>, Infallible> {
+    if req.method() != Method::GET || req.uri().path() != "/metrics" {
+        let mut res = Response::new(Body::empty());
+        *res.status_mut() = StatusCode::NOT_FOUND;
+        return Ok(res);
+    }
+    Ok(render())
+}
+
+/// Renders every metric in the Prometheus text format.
+pub fn render() -> Response<Body> {
+    for summary in stats::summaries() {
+        let Some(latency) = summary.latency_ms else {
+            continue;
+        };
+        let name = summary.name.as_deref().unwrap_or_default();
+        let quantiles = [
+            ("0.5", latency.p50),
+            ("0.9", latency.p90),
+            ("0.99", latency.p99),
+            ("0.999", latency.p999),
+            ("1", latency.max),
+        ];
+        for (quantile, ms) in quantiles {
+            LATENCY_QUANTILES
+                .with_label_values(&[summary.probe, name, &summary.target, quantile])
+                .set(ms / 1000.0);
+        }
+    }
+
+    let encoder = TextEncoder::new();
+    let mut buf = Vec::new();
+    let mut families = prometheus::gather();
+    label(&mut families);
+    if let Err(e) = encoder.encode(&families, &mut buf) {
+        error!("metrics encode error: {}", e);
+    }
+
+    let mut res = Response::new(Body::from(buf));
+    res.headers_mut()
+        .insert(CONTENT_TYPE, encoder.format_type().parse().unwrap());
+    res
+}
+
+/// Adds the labels given to each probe target to the series about it. Labels named like the ones
+/// the series already has are left out rather than override them.
+fn label(families: &mut [MetricFamily]) {
+    let labels = LABELS.lock().unwrap();
+    if labels.is_empty() {
+        return;
+    }
+    for family in families {
+        for metric in family.mut_metric() {
+            let value = |name| {
+                let pair = metric.get_label().iter().find(|pair| pair.name() == name);
+                pair.map(|pair| pair.value().to_string())
+                    .unwrap_or_default()
+            };
+            let key = (value("probe"), value("name"), value("target"));
+            let Some(extra) = labels.get(&key) else {
+                continue;
+            };
+
+            let mut pairs = metric.take_label();
+            for (key, value) in extra.iter() {
+                let key = label_name(key);
+                if pairs.iter().any(|pair| pair.name() == key) {
+                    continue;
+                }
+                let mut pair = LabelPair::default();
+                pair.set_name(key);
+                pair.set_value(value.clone());
+                pairs.push(pair);
+            }
+            metric.set_label(pairs);
+        }
+    }
+}
+
+/// Makes a label key into a valid Prometheus label name, replacing what it may not contain.
+fn label_name(key: &str) -> String {
+    let mut name: String = key
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    if name.starts_with(|c: char| c.is_ascii_digit()) {
+        name.insert(0, '_');
+    }
+    name
+}
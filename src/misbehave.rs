@@ -0,0 +1,198 @@
+use std::time::{Duration, Instant};
+
+use clap::{Args, ValueEnum};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    time,
+};
+
+use crate::report::ProbeError;
+
+/// How a misbehaving client treats the connections it opens.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Misbehaviour {
+    /// Open the connection and never send anything.
+    Idle,
+    /// Send the payload one byte at a time, slowloris-style.
+    SlowWrite,
+    /// Send the payload, then read what comes back one byte at a time.
+    SlowRead,
+    /// Send the payload, then close the sending half of the connection while still reading.
+    HalfClose,
+}
+
+/// Options for misbehaving on connections, to see how servers and load balancers deal with
+/// clients that hold them.
+#[derive(Args, Clone, Debug, Default, Serialize, Deserialize)]
+pub struct MisbehaveArgs {
+    /// Misbehave on a connection opened for every attempt instead of using it properly, until the
+    /// peer closes it or `--misbehave-for-ms` passes. Attempts report how long the connection was
+    /// held with `held_ms` and how it ended with `ended`: `closed` when the peer closed it,
+    /// `reset` when it reset it, and failing with `not_closed` when it never did.
+    #[arg(long, value_enum)]
+    pub misbehave: Option<Misbehaviour>,
+
+    /// Longest to misbehave on a connection before giving up on the peer closing it.
+    #[arg(long, default_value_t = 60_000, requires = "misbehave")]
+    pub misbehave_for_ms: u64,
+
+    /// Time between every byte written with `--misbehave slow-write` or read with
+    /// `--misbehave slow-read`.
+    #[arg(long, default_value_t = 1000, requires = "misbehave")]
+    pub misbehave_interval_ms: u64,
+}
+
+/// How to misbehave on connections, from [`MisbehaveArgs`].
+#[derive(Clone, Copy, Debug)]
+pub struct Misbehave {
+    how: Misbehaviour,
+    limit: Duration,
+    interval: Duration,
+}
+
+/// How a connection misbehaved on ended.
+pub struct Misbehaved {
+    /// Time from starting to misbehave to the connection ending.
+    pub held: Duration,
+    /// `closed`, `reset`, or `limit` when it was still open after `--misbehave-for-ms`.
+    pub ended: &'static str,
+    pub sent: u64,
+    pub received: u64,
+}
+
+impl Misbehave {
+    pub fn new(args: &MisbehaveArgs) -> Option<Self> {
+        let how = args.misbehave?;
+        assert!(
+            args.misbehave_interval_ms > 0,
+            "--misbehave-interval-ms must be positive"
+        );
+        Some(Misbehave {
+            how,
+            limit: Duration::from_millis(args.misbehave_for_ms),
+            interval: Duration::from_millis(args.misbehave_interval_ms),
+        })
+    }
+
+    /// Misbehaves on a connection, sending it the payload the way it was asked to, until the peer
+    /// closes it or the limit passes.
+    pub async fn run<S: AsyncRead + AsyncWrite + Unpin>(
+        &self,
+        mut stream: S,
+        payload: &[u8],
+    ) -> Misbehaved {
+        let start = Instant::now();
+        let mut misbehaved = Misbehaved {
+            held: Duration::ZERO,
+            ended: "limit",
+            sent: 0,
+            received: 0,
+        };
+        let ended = time::timeout(self.limit, self.hold(&mut stream, payload, &mut misbehaved));
+        if let Ok(ended) = ended.await {
+            misbehaved.ended = ended;
+        }
+        misbehaved.held = start.elapsed();
+        misbehaved
+    }
+
+    /// Holds the connection until the peer ends it, returning how it did.
+    async fn hold<S: AsyncRead + AsyncWrite + Unpin>(
+        &self,
+        stream: &mut S,
+        payload: &[u8],
+        misbehaved: &mut Misbehaved,
+    ) -> &'static str {
+        let sent = match self.how {
+            Misbehaviour::Idle => Ok(()),
+            Misbehaviour::SlowWrite => match self.trickle(stream, payload, misbehaved).await {
+                Some(ended) => return ended,
+                None => Ok(()),
+            },
+            Misbehaviour::SlowRead => stream.write_all(payload).await,
+            Misbehaviour::HalfClose => match stream.write_all(payload).await {
+                Ok(()) => stream.shutdown().await,
+                Err(e) => Err(e),
+            },
+        };
+        if sent.is_err() {
+            return "reset";
+        }
+        if self.how != Misbehaviour::SlowWrite {
+            misbehaved.sent = payload.len() as u64;
+        }
+
+        // Whatever comes back is read and discarded, so that the connection ending is noticed.
+        let mut buf = vec![
+            0;
+            if self.how == Misbehaviour::SlowRead {
+                1
+            } else {
+                4096
+            }
+        ];
+        loop {
+            match stream.read(&mut buf).await {
+                Ok(0) => return "closed",
+                Ok(n) => misbehaved.received += n as u64,
+                Err(_) => return "reset",
+            }
+            if self.how == Misbehaviour::SlowRead {
+                time::sleep(self.interval).await;
+            }
+        }
+    }
+
+    /// Writes the payload one byte at a time, returning how the connection ended if the peer
+    /// ended it meanwhile.
+    async fn trickle<S: AsyncRead + AsyncWrite + Unpin>(
+        &self,
+        stream: &mut S,
+        payload: &[u8],
+        misbehaved: &mut Misbehaved,
+    ) -> Option<&'static str> {
+        let mut buf = [0; 4096];
+        for byte in payload {
+            if stream.write_all(&[*byte]).await.is_err() || stream.flush().await.is_err() {
+                return Some("reset");
+            }
+            misbehaved.sent += 1;
+            match time::timeout(self.interval, stream.read(&mut buf)).await {
+                Err(_) => {}
+                Ok(Ok(0)) => return Some("closed"),
+                Ok(Ok(n)) => misbehaved.received += n as u64,
+                Ok(Err(_)) => return Some("reset"),
+            }
+        }
+        None
+    }
+}
+
+impl Misbehaved {
+    /// Details telling how the connection was held and how it ended.
+    pub fn details(&self, how: &Misbehave) -> Vec<(&'static str, String)> {
+        let how = how.how.to_possible_value().unwrap();
+        vec![
+            ("misbehave", how.get_name().to_string()),
+            ("ended", self.ended.to_string()),
+            (
+                "held_ms",
+                format!("{:.3}", self.held.as_secs_f64() * 1000.0),
+            ),
+            ("bytes_sent", self.sent.to_string()),
+            ("bytes_received", self.received.to_string()),
+        ]
+    }
+
+    /// Error of the attempt, when the peer never closed the connection.
+    pub fn error(&self) -> Option<ProbeError> {
+        (self.ended == "limit").then(|| {
+            ProbeError::new(
+                "not_closed",
+                format!("still open after {}ms", self.held.as_millis()),
+            )
+        })
+    }
+}
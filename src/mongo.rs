@@ -0,0 +1,329 @@
+use std::{
+    sync::{Arc, Mutex as SyncMutex},
+    time::{Duration, Instant},
+};
+
+use clap::{Parser, ValueEnum};
+use log::{info, warn};
+use mongodb::{
+    bson::{doc, Document},
+    error::{Error, ErrorKind},
+    event::{
+        cmap::CmapEvent,
+        command::CommandEvent,
+        sdam::{SdamEvent, TopologyDescription},
+        EventHandler,
+    },
+    options::{ClientOptions, ServerAddress, Tls},
+    Client, ServerType,
+};
+use serde::{Deserialize, Serialize};
+use tokio::{sync::Mutex, time};
+
+use crate::{
+    probe::{self, CommonArgs, Probe},
+    report::{self, Attempt, ProbeError},
+    resolve, socket,
+};
+
+#[derive(Parser, Debug, Serialize, Deserialize)]
+pub struct MongoArgs {
+    /// MongoDB connection string, as `mongodb://[user:password@]host[:port][,...][/?options]` or
+    /// `mongodb+srv://`. TLS is configured with its options, e.g. `tls=true`.
+    #[arg(long)]
+    url: String,
+
+    /// Command to run on every attempt.
+    #[arg(long, value_enum, default_value_t)]
+    command: MongoCommand,
+
+    /// Set a timeout for selecting a server to run the command on.
+    #[arg(long, default_value_t = 1000)]
+    server_selection_timeout_ms: u64,
+
+    /// Set a timeout for only the connect phase of a connection, including the handshake.
+    #[arg(long, default_value_t = 1000)]
+    connect_timeout_ms: u64,
+
+    /// Set a timeout for the whole attempt, including server selection.
+    #[arg(long, default_value_t = 2000)]
+    timeout_ms: u64,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    pub common: CommonArgs,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MongoCommand {
+    /// `ping`, which only checks the server responds.
+    #[default]
+    Ping,
+    /// `hello`, also reporting the replica set and whether the server is a writable primary.
+    Hello,
+}
+
+/// Keeps a client for every worker, running `ping` or `hello` against the primary on each
+/// attempt. Changes of primary seen by the driver's monitoring are reported with the next attempt
+/// of every worker, and logged once.
+pub struct MongoProbe {
+    target: String,
+    command: MongoCommand,
+    workers: Vec<Mutex<Worker>>,
+    timeout: Duration,
+}
+
+struct Worker {
+    client: Client,
+    events: Arc<SyncMutex<Events>>,
+}
+
+/// What the driver reported about the attempt in flight, and the topology it saw.
+#[derive(Default)]
+struct Events {
+    /// When checking out a connection for the command started, once a server was selected.
+    checkout_started: Option<Instant>,
+    /// How long opening a connection for the command took, if one was opened.
+    connected: Option<Duration>,
+    /// Server the command ran on, and the duration of its round trip.
+    command: Option<(String, Duration)>,
+    primary: Option<String>,
+    /// The last server that was primary, which may have stepped down since.
+    last_primary: Option<String>,
+    /// Changes of primary since the last attempt.
+    changes: Vec<String>,
+}
+
+impl MongoProbe {
+    pub async fn new(args: &MongoArgs) -> Self {
+        let target = report::redact(&args.url);
+        let mut workers = vec![];
+        for worker in 0..args.common.parallel {
+            let mut options = ClientOptions::parse(&args.url)
+                .await
+                .expect("invalid mongo url");
+            if resolve::is_restricted() {
+                pin(&mut options).await;
+            }
+            options.app_name = Some("artemiss".to_string());
+            options.server_selection_timeout =
+                Some(Duration::from_millis(args.server_selection_timeout_ms));
+            options.connect_timeout = Some(Duration::from_millis(args.connect_timeout_ms));
+
+            let events = Arc::new(SyncMutex::new(Events::default()));
+            options.cmap_event_handler = Some(EventHandler::callback({
+                let events = events.clone();
+                move |event| {
+                    let mut events = events.lock().unwrap();
+                    match event {
+                        CmapEvent::ConnectionCheckoutStarted(_) => {
+                            events.checkout_started = Some(Instant::now())
+                        }
+                        CmapEvent::ConnectionReady(ready) => {
+                            events.connected = Some(ready.duration)
+                        }
+                        _ => {}
+                    }
+                }
+            }));
+            options.command_event_handler = Some(EventHandler::callback({
+                let events = events.clone();
+                move |event| {
+                    let command = match event {
+                        CommandEvent::Succeeded(e) => (e.connection.address, e.duration),
+                        CommandEvent::Failed(e) => (e.connection.address, e.duration),
+                        _ => return,
+                    };
+                    events.lock().unwrap().command = Some((command.0.to_string(), command.1));
+                }
+            }));
+            options.sdam_event_handler = Some(EventHandler::callback({
+                let events = events.clone();
+                let target = target.clone();
+                move |event| {
+                    let SdamEvent::TopologyDescriptionChanged(changed) = event else {
+                        return;
+                    };
+                    let primary = primary(&changed.new_description);
+                    let mut events = events.lock().unwrap();
+                    if primary == events.primary {
+                        return;
+                    }
+                    events.primary = primary.clone();
+
+                    // Stepdowns usually pass through having no primary, so compare against the
+                    // last primary seen.
+                    let change = match (&events.last_primary, primary) {
+                        (None, Some(primary)) => {
+                            if worker == 0 {
+                                info!("mongo {}: discovered primary {}", target, primary);
+                            }
+                            events.last_primary = Some(primary);
+                            return;
+                        }
+                        (Some(last), None) => {
+                            if worker == 0 {
+                                warn!("mongo {}: lost primary {}", target, last);
+                            }
+                            format!("{} -> none", last)
+                        }
+                        (Some(last), Some(primary)) if *last != primary => {
+                            if worker == 0 {
+                                warn!(
+                                    "mongo {}: primary changed from {} to {}",
+                                    target, last, primary
+                                );
+                            }
+                            let change = format!("{} -> {}", last, primary);
+                            events.last_primary = Some(primary);
+                            change
+                        }
+                        _ => return,
+                    };
+                    events.changes.push(change);
+                }
+            }));
+
+            workers.push(Mutex::new(Worker {
+                client: Client::with_options(options).expect("invalid mongo options"),
+                events,
+            }));
+        }
+
+        MongoProbe {
+            target,
+            command: args.command,
+            workers,
+            timeout: Duration::from_millis(args.timeout_ms),
+        }
+    }
+}
+
+impl Probe for MongoProbe {
+    fn kind(&self) -> &'static str {
+        "mongo"
+    }
+
+    fn target(&self) -> String {
+        self.target.clone()
+    }
+
+    async fn attempt(&self, worker: usize) -> Attempt {
+        let worker = self.workers[worker % self.workers.len()].lock().await;
+        {
+            let mut events = worker.events.lock().unwrap();
+            events.checkout_started = None;
+            events.connected = None;
+            events.command = None;
+        }
+
+        let command = match self.command {
+            MongoCommand::Ping => doc! { "ping": 1 },
+            MongoCommand::Hello => doc! { "hello": 1 },
+        };
+        let start = Instant::now();
+        let admin = worker.client.database("admin");
+        let result = time::timeout(self.timeout, admin.run_command(command)).await;
+        let duration = start.elapsed();
+
+        let mut events = worker.events.lock().unwrap();
+        let mut phases = vec![];
+        if let Some(checkout_started) = events.checkout_started {
+            phases.push(("server_selection", checkout_started - start));
+        }
+        if let Some(connected) = events.connected {
+            phases.push(("connect", connected));
+        }
+        let mut details = vec![];
+        if let Some((server, rtt)) = events.command.take() {
+            phases.push(("rtt", rtt));
+            details.push(("server", server));
+        }
+        if let Some(primary) = &events.primary {
+            details.push(("primary", primary.clone()));
+        }
+        if !events.changes.is_empty() {
+            details.push(("primary_changed", events.changes.join(", ")));
+            events.changes.clear();
+        }
+
+        let error = match result {
+            Ok(Ok(reply)) => {
+                if let MongoCommand::Hello = self.command {
+                    details.extend(hello_details(&reply));
+                }
+                None
+            }
+            Ok(Err(e)) => Some(ProbeError::from_cause(error_kind(&e), &e)),
+            Err(_) => Some(ProbeError::new(
+                "timeout",
+                format!("timed out after {}ms", self.timeout.as_millis()),
+            )),
+        };
+
+        Attempt {
+            duration,
+            phases,
+            details,
+            error,
+        }
+    }
+}
+
+pub async fn mongo_main(args: MongoArgs) {
+    socket::ensure_supported("mongo", &[]);
+    probe::run(MongoProbe::new(&args).await, &args.common).await
+}
+
+/// Pins the server of a direct connection without TLS to an address of the family connections are
+/// restricted to. The driver discovers the other members of a replica set by name and verifies
+/// certificates against the name it connects to, so no other connection can be restricted.
+async fn pin(options: &mut ClientOptions) {
+    let tls = !matches!(options.tls, None | Some(Tls::Disabled));
+    if options.direct_connection != Some(true) || tls {
+        resolve::ensure_unrestricted(
+            "mongo",
+            "the driver resolves every member of a replica set itself; connect with \
+             directConnection=true and without TLS to restrict it",
+        );
+    }
+    if let [ServerAddress::Tcp { host, port }] = options.hosts.as_slice() {
+        let port = port.unwrap_or(27017);
+        let addr = resolve::first_addr(host, port)
+            .await
+            .unwrap_or_else(|e| panic!("unable to resolve {}: {}", host, e));
+        options.hosts = vec![addr.into()];
+    }
+}
+
+/// Address of the primary of the topology, if it has one.
+fn primary(topology: &TopologyDescription) -> Option<String> {
+    topology
+        .servers()
+        .into_iter()
+        .find(|(_, server)| server.server_type() == ServerType::RsPrimary)
+        .map(|(address, _)| address.to_string())
+}
+
+/// Details of the replica set reported by `hello`.
+fn hello_details(reply: &Document) -> Vec<(&'static str, String)> {
+    let mut details = vec![];
+    if let Ok(writable) = reply.get_bool("isWritablePrimary") {
+        details.push(("writable_primary", writable.to_string()));
+    }
+    if let Ok(set_name) = reply.get_str("setName") {
+        details.push(("set_name", set_name.to_string()));
+    }
+    details
+}
+
+fn error_kind(error: &Error) -> &'static str {
+    match *error.kind {
+        ErrorKind::ServerSelection { .. } => "server_selection",
+        ErrorKind::Authentication { .. } => "auth",
+        ErrorKind::Command(_) => "command",
+        ErrorKind::Io(_) => "io",
+        _ => "mongo",
+    }
+}
@@ -0,0 +1,375 @@
+use std::{
+    process,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use clap::Parser;
+use log::warn;
+use percent_encoding::percent_decode_str;
+use rumqttc::{
+    AsyncClient, ConnectionError, Event, MqttOptions, NetworkOptions, Packet, Publish, QoS,
+    SubscribeReasonCode, TlsConfiguration, Transport,
+};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    sync::{mpsc, Mutex},
+    task::JoinHandle,
+    time,
+};
+use url::Url;
+
+use crate::{
+    probe::{self, CommonArgs, Probe},
+    report::{self, Attempt, ProbeError},
+    resolve, socket,
+    tls::{self, TlsArgs},
+};
+
+#[derive(Parser, Debug, Serialize, Deserialize)]
+pub struct MqttArgs {
+    /// Broker URL to connect to, as `mqtt://[user:password@]host[:port]`, `mqtts://`, or
+    /// `ws://host[:port]/path` and `wss://` to connect over WebSocket.
+    #[arg(long)]
+    url: String,
+
+    /// Canary topic to subscribe to and publish to.
+    #[arg(long, default_value = "artemiss/canary")]
+    topic: String,
+
+    /// Quality of service to subscribe and publish with.
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u8).range(0..=2))]
+    qos: u8,
+
+    /// Keep alive interval of the connection.
+    #[arg(long, default_value_t = 30)]
+    keep_alive_s: u64,
+
+    /// Set a timeout for connecting, up to receiving the `CONNACK`.
+    #[arg(long, default_value_t = 1000)]
+    connect_timeout_ms: u64,
+
+    /// Set a timeout for subscribing, and for receiving a published message back.
+    #[arg(long, default_value_t = 1000)]
+    timeout_ms: u64,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    tls: TlsArgs,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    pub common: CommonArgs,
+}
+
+/// A connection kept open by a worker, subscribed to the canary topic.
+struct Session {
+    client: AsyncClient,
+    /// Messages received on the canary topic, ending with the error that closed the connection.
+    received: mpsc::UnboundedReceiver<Result<Publish, ConnectionError>>,
+    /// Drives the connection, which only makes progress while polled.
+    driver: JoinHandle<()>,
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        self.driver.abort();
+    }
+}
+
+/// Keeps a connection subscribed to the canary topic open for every worker, publishing a message
+/// on each attempt and measuring how long it takes to be received back. A connection is reopened
+/// on the next attempt after any failure.
+pub struct MqttProbe {
+    url: Url,
+    host: String,
+    port: u16,
+    transport: Transport,
+    topic: String,
+    qos: QoS,
+    keep_alive: Duration,
+    sessions: Vec<Mutex<Option<Session>>>,
+    sequence: AtomicU64,
+    connect_timeout: Duration,
+    timeout: Duration,
+}
+
+impl MqttProbe {
+    pub fn new(args: &MqttArgs) -> Self {
+        // The connection is made by rumqttc, which verifies the certificate against the host.
+        assert!(args.tls.sni.is_none(), "--sni is not supported by mqtt");
+        let url = Url::parse(&args.url).expect("invalid mqtt url");
+        let tls = || TlsConfiguration::Rustls(Arc::new(tls::configure(&args.tls)));
+        let (transport, default_port) = match url.scheme() {
+            "mqtt" | "tcp" => (Transport::Tcp, 1883),
+            "mqtts" | "ssl" => (Transport::Tls(tls()), 8883),
+            "ws" => (Transport::Ws, 80),
+            "wss" => (Transport::Wss(tls()), 443),
+            scheme => panic!("unsupported scheme {}", scheme),
+        };
+        if !matches!(transport, Transport::Tcp) {
+            resolve::ensure_unrestricted(
+                "mqtt over TLS or WebSocket",
+                "rumqttc resolves the host itself to verify certificates against and send requests \
+                 to",
+            );
+        }
+        let host = url
+            .host_str()
+            .expect("mqtt url has no host")
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .to_string();
+        let port = url.port().unwrap_or(default_port);
+        let qos = match args.qos {
+            0 => QoS::AtMostOnce,
+            1 => QoS::AtLeastOnce,
+            _ => QoS::ExactlyOnce,
+        };
+
+        MqttProbe {
+            url,
+            host,
+            port,
+            transport,
+            topic: args.topic.clone(),
+            qos,
+            keep_alive: Duration::from_secs(args.keep_alive_s),
+            sessions: (0..args.common.parallel)
+                .map(|_| Mutex::new(None))
+                .collect(),
+            sequence: AtomicU64::new(0),
+            connect_timeout: Duration::from_millis(args.connect_timeout_ms),
+            timeout: Duration::from_millis(args.timeout_ms),
+        }
+    }
+
+    /// Connects and subscribes to the canary topic for a worker, returning the time of each phase.
+    async fn connect(
+        &self,
+        worker: usize,
+    ) -> Result<(Session, Vec<(&'static str, Duration)>), ProbeError> {
+        // WebSocket transports take the whole URL as the broker address.
+        let broker = match self.transport {
+            Transport::Ws | Transport::Wss(_) => {
+                let mut url = self.url.clone();
+                let _ = url.set_username("");
+                let _ = url.set_password(None);
+                url.to_string()
+            }
+            // Connections restricted to an address family go to an address of it instead.
+            _ if resolve::is_restricted() => resolve::first_addr(&self.host, self.port)
+                .await
+                .map_err(|e| ProbeError::from_cause("resolve", &e))?
+                .ip()
+                .to_string(),
+            _ => self.host.clone(),
+        };
+        let client_id = format!("artemiss-{}-{}", process::id(), worker);
+        let mut options = MqttOptions::new(client_id, broker, self.port);
+        options.set_transport(self.transport.clone());
+        options.set_keep_alive(self.keep_alive);
+        if !self.url.username().is_empty() {
+            let decode = |s| percent_decode_str(s).decode_utf8_lossy().into_owned();
+            options.set_credentials(
+                decode(self.url.username()),
+                decode(self.url.password().unwrap_or_default()),
+            );
+        }
+
+        let (client, mut eventloop) = AsyncClient::new(options, 10);
+        let mut network = NetworkOptions::new();
+        network.set_tcp_nodelay(socket::nodelay(false));
+        #[cfg(target_os = "linux")]
+        if let Some(interface) = socket::interface() {
+            network.set_bind_device(interface);
+        }
+        eventloop.set_network_options(network);
+        let mut phases = vec![];
+
+        let start = Instant::now();
+        let connack = async {
+            loop {
+                match eventloop.poll().await {
+                    Ok(Event::Incoming(Packet::ConnAck(_))) => return Ok(()),
+                    Ok(_) => continue,
+                    Err(ConnectionError::ConnectionRefused(code)) => {
+                        return Err(ProbeError::new(
+                            "connack",
+                            format!("connection refused: {:?}", code),
+                        ))
+                    }
+                    Err(e) => return Err(ProbeError::from_cause("connect", &e)),
+                }
+            }
+        };
+        match time::timeout(self.connect_timeout, connack).await {
+            Ok(result) => result?,
+            Err(_) => {
+                let message = format!("timed out after {}ms", self.connect_timeout.as_millis());
+                return Err(ProbeError::new("connect_timeout", message));
+            }
+        }
+        phases.push(("connect", start.elapsed()));
+
+        let start = Instant::now();
+        let suback = async {
+            client
+                .subscribe(&self.topic, self.qos)
+                .await
+                .map_err(|e| ProbeError::from_cause("subscribe", &e))?;
+            loop {
+                match eventloop.poll().await {
+                    Ok(Event::Incoming(Packet::SubAck(suback))) => {
+                        if suback.return_codes.contains(&SubscribeReasonCode::Failure) {
+                            return Err(ProbeError::new(
+                                "suback",
+                                format!("subscription to {} refused", self.topic),
+                            ));
+                        }
+                        return Ok(());
+                    }
+                    Ok(_) => continue,
+                    Err(e) => return Err(ProbeError::from_cause("disconnected", &e)),
+                }
+            }
+        };
+        match time::timeout(self.timeout, suback).await {
+            Ok(result) => result?,
+            Err(_) => {
+                let message = format!("no suback after {}ms", self.timeout.as_millis());
+                return Err(ProbeError::new("subscribe_timeout", message));
+            }
+        }
+        phases.push(("subscribe", start.elapsed()));
+
+        let (sender, received) = mpsc::unbounded_channel();
+        let target = self.target();
+        let driver = tokio::spawn(async move {
+            loop {
+                match eventloop.poll().await {
+                    Ok(Event::Incoming(Packet::Publish(publish))) => {
+                        if sender.send(Ok(publish)).is_err() {
+                            return;
+                        }
+                    }
+                    Ok(_) => {}
+                    Err(e) => {
+                        warn!("mqtt {} worker {} disconnected: {}", target, worker, e);
+                        let _ = sender.send(Err(e));
+                        return;
+                    }
+                }
+            }
+        });
+
+        let session = Session {
+            client,
+            received,
+            driver,
+        };
+        Ok((session, phases))
+    }
+
+    /// Publishes a message to the canary topic and waits for it to be received back.
+    async fn round_trip(&self, session: &mut Session, worker: usize) -> Result<(), ProbeError> {
+        // Messages left over from other workers, or attempts that timed out.
+        loop {
+            match session.received.try_recv() {
+                Ok(Ok(_)) => continue,
+                Ok(Err(e)) => return Err(ProbeError::from_cause("disconnected", &e)),
+                Err(mpsc::error::TryRecvError::Empty) => break,
+                Err(mpsc::error::TryRecvError::Disconnected) => {
+                    return Err(ProbeError::new("disconnected", "connection closed"))
+                }
+            }
+        }
+
+        let sequence = self.sequence.fetch_add(1, Ordering::Relaxed);
+        let payload = format!("artemiss {} {} {}", process::id(), worker, sequence);
+        session
+            .client
+            .publish(&self.topic, self.qos, false, payload.as_bytes())
+            .await
+            .map_err(|e| ProbeError::from_cause("publish", &e))?;
+
+        loop {
+            match session.received.recv().await {
+                Some(Ok(publish)) if publish.payload == payload.as_bytes() => return Ok(()),
+                Some(Ok(_)) => continue,
+                Some(Err(e)) => return Err(ProbeError::from_cause("disconnected", &e)),
+                None => return Err(ProbeError::new("disconnected", "connection closed")),
+            }
+        }
+    }
+}
+
+impl Probe for MqttProbe {
+    fn kind(&self) -> &'static str {
+        "mqtt"
+    }
+
+    fn target(&self) -> String {
+        report::redact(self.url.as_str())
+    }
+
+    async fn attempt(&self, worker: usize) -> Attempt {
+        let mut session = self.sessions[worker % self.sessions.len()].lock().await;
+
+        let start = Instant::now();
+        let mut phases = vec![];
+        let open = match &mut *session {
+            Some(open) => open,
+            None => match self.connect(worker).await {
+                Ok((open, connected)) => {
+                    phases = connected;
+                    session.insert(open)
+                }
+                Err(error) => {
+                    return Attempt {
+                        duration: start.elapsed(),
+                        phases,
+                        details: vec![],
+                        error: Some(error),
+                    }
+                }
+            },
+        };
+
+        let published = Instant::now();
+        let error = match time::timeout(self.timeout, self.round_trip(open, worker)).await {
+            Ok(Ok(())) => {
+                phases.push(("rtt", published.elapsed()));
+                None
+            }
+            Ok(Err(error)) => Some(error),
+            Err(_) => {
+                let message = format!("no message received after {}ms", self.timeout.as_millis());
+                Some(ProbeError::new("timeout", message))
+            }
+        };
+        if error.is_some() {
+            *session = None;
+        }
+
+        Attempt {
+            duration: start.elapsed(),
+            phases,
+            details: vec![],
+            error,
+        }
+    }
+}
+
+pub async fn mqtt_main(args: MqttArgs) {
+    // rumqttc only binds to interfaces where they are supported.
+    let supported: &[&str] = match cfg!(target_os = "linux") {
+        true => &["--tcp-nodelay", "--interface"],
+        false => &["--tcp-nodelay"],
+    };
+    socket::ensure_supported("mqtt", supported);
+    probe::run(MqttProbe::new(&args), &args.common).await
+}
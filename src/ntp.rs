@@ -0,0 +1,258 @@
+use std::{
+    net::IpAddr,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use clap::Parser;
+use serde::{Deserialize, Serialize};
+use tokio::time;
+
+use crate::{
+    probe::{self, CommonArgs, Probe},
+    report::{Attempt, ProbeError},
+    resolve::{ResolveArgs, Resolver},
+    udp,
+};
+
+/// Seconds from the NTP epoch, 1900, to the Unix epoch.
+const NTP_UNIX_OFFSET: u64 = 2_208_988_800;
+
+/// Length of an NTP packet without extensions.
+const PACKET: usize = 48;
+
+#[derive(Parser, Debug, Serialize, Deserialize)]
+pub struct NtpArgs {
+    /// Host of the NTP server.
+    #[arg(long)]
+    host: String,
+
+    /// Port of the NTP server.
+    #[arg(long, default_value_t = 123)]
+    port: u16,
+
+    /// Count the attempt as failed if the clock is estimated to be further than this from the
+    /// server's, in either direction.
+    #[arg(long)]
+    max_offset_ms: Option<f64>,
+
+    /// Set a timeout for receiving a reply.
+    #[arg(long, default_value_t = 1000)]
+    timeout_ms: u64,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    resolve: ResolveArgs,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    pub common: CommonArgs,
+}
+
+/// Queries the time from an NTP server with a client request on every attempt, estimating the
+/// offset of the local clock from the server's.
+pub struct NtpProbe {
+    host: String,
+    port: u16,
+    resolver: Resolver,
+    max_offset: Option<f64>,
+    timeout: Duration,
+}
+
+/// What the server sent back, with the timestamps of the exchange.
+struct Reply {
+    leap: u8,
+    stratum: u8,
+    /// Reference of the server's clock: a kiss code when the stratum is 0, the name of the source
+    /// at stratum 1, or the address of the server it synchronises to above.
+    reference_id: [u8; 4],
+    root_delay: f64,
+    root_dispersion: f64,
+    /// Time the request reached the server, by its clock.
+    received: u64,
+    /// Time the reply left the server, by its clock.
+    transmitted: u64,
+}
+
+impl NtpProbe {
+    pub fn new(args: &NtpArgs) -> Self {
+        NtpProbe {
+            host: args.host.clone(),
+            port: args.port,
+            resolver: Resolver::new(&args.resolve),
+            max_offset: args.max_offset_ms,
+            timeout: Duration::from_millis(args.timeout_ms),
+        }
+    }
+}
+
+impl Probe for NtpProbe {
+    fn kind(&self) -> &'static str {
+        "ntp"
+    }
+
+    fn target(&self) -> String {
+        format!("{}:{}", self.host, self.port)
+    }
+
+    async fn attempt(&self, _worker: usize) -> Attempt {
+        let start = Instant::now();
+        let mut phases = vec![];
+        let mut details = vec![];
+
+        let result = async {
+            let addr = *self
+                .resolver
+                .lookup(&self.host, self.port)
+                .await
+                .map_err(|e| ProbeError::from_cause("resolve", &e))?
+                .first()
+                .ok_or_else(|| ProbeError::new("resolve", "no addresses resolved"))?;
+            let socket = udp::bind(addr)
+                .await
+                .map_err(|e| ProbeError::from_cause("bind", &e))?;
+            if let Ok(local) = socket.local_addr() {
+                details.push(("local_addr", local.to_string()));
+            }
+            details.push(("remote_addr", addr.to_string()));
+            let family = if addr.is_ipv4() { "ipv4" } else { "ipv6" };
+            details.push(("family", family.to_string()));
+
+            // The server echoes the transmit timestamp back as the origin of its reply, which
+            // tells replies to this request apart from late or spoofed ones.
+            let mut request = [0; PACKET];
+            // No leap warning, version 4, client mode.
+            request[0] = 4 << 3 | 3;
+            let sent = now();
+            request[40..48].copy_from_slice(&sent.to_be_bytes());
+            let sent_at = Instant::now();
+            socket
+                .send(&request)
+                .await
+                .map_err(|e| ProbeError::from_cause("send", &e))?;
+
+            let mut buf = [0; 1024];
+            let reply = loop {
+                let len = match time::timeout(self.timeout, socket.recv(&mut buf)).await {
+                    Ok(Ok(len)) => len,
+                    // An ICMP port unreachable is reported as the connection being refused.
+                    Ok(Err(e)) => return Err(ProbeError::from_cause("receive", &e)),
+                    Err(_) => {
+                        return Err(ProbeError::new(
+                            "timeout",
+                            format!("no reply within {}ms", self.timeout.as_millis()),
+                        ))
+                    }
+                };
+                if let Some(reply) = parse(&buf[..len], sent) {
+                    break reply;
+                }
+            };
+            let arrived = now();
+            phases.push(("rtt", sent_at.elapsed()));
+
+            details.push(("stratum", reply.stratum.to_string()));
+            details.push(("reference_id", reference(&reply)));
+            if reply.stratum == 0 {
+                return Err(ProbeError::new(
+                    "kiss_of_death",
+                    format!("server sent kiss code {}", reference(&reply)),
+                ));
+            }
+            if reply.leap == 3 {
+                return Err(ProbeError::new(
+                    "unsynchronized",
+                    "server clock is not synchronized",
+                ));
+            }
+
+            let offset =
+                (seconds(reply.received, sent) + seconds(reply.transmitted, arrived)) / 2.0;
+            let delay = seconds(arrived, sent) - seconds(reply.transmitted, reply.received);
+            phases.push(("delay", Duration::from_secs_f64(delay.max(0.0))));
+            details.push(("offset_ms", format!("{:.3}", offset * 1000.0)));
+            details.push(("root_delay_ms", format!("{:.3}", reply.root_delay * 1000.0)));
+            details.push((
+                "root_dispersion_ms",
+                format!("{:.3}", reply.root_dispersion * 1000.0),
+            ));
+            if reply.leap != 0 {
+                details.push(("leap", reply.leap.to_string()));
+            }
+
+            match self.max_offset {
+                Some(max) if (offset * 1000.0).abs() > max => Err(ProbeError::new(
+                    "offset",
+                    format!(
+                        "clock is {:.3}ms off the server's, more than {}ms",
+                        offset * 1000.0,
+                        max
+                    ),
+                )),
+                _ => Ok(()),
+            }
+        }
+        .await;
+
+        Attempt {
+            duration: start.elapsed(),
+            phases,
+            details,
+            error: result.err(),
+        }
+    }
+}
+
+pub async fn ntp_main(args: NtpArgs) {
+    probe::run(NtpProbe::new(&args), &args.common).await
+}
+
+/// Current time as an NTP timestamp, in seconds since 1900 with a 32 bit fraction.
+fn now() -> u64 {
+    let since_unix = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let seconds = since_unix.as_secs() + NTP_UNIX_OFFSET;
+    let fraction = ((since_unix.subsec_nanos() as u64) << 32) / 1_000_000_000;
+    seconds << 32 | fraction
+}
+
+/// Seconds from `b` to `a`, which are assumed to be within 68 years of each other so that the
+/// difference is right across NTP eras.
+fn seconds(a: u64, b: u64) -> f64 {
+    a.wrapping_sub(b) as i64 as f64 / (1u64 << 32) as f64
+}
+
+/// Parses a server reply to the request transmitted at `sent`, or `None` if it is not one.
+fn parse(packet: &[u8], sent: u64) -> Option<Reply> {
+    if packet.len() < PACKET {
+        return None;
+    }
+    let u32_at = |at: usize| u32::from_be_bytes(packet[at..at + 4].try_into().unwrap());
+    let u64_at = |at: usize| u64::from_be_bytes(packet[at..at + 8].try_into().unwrap());
+    // Servers answer clients in server mode.
+    let mode = packet[0] & 0x7;
+    if mode != 4 || u64_at(24) != sent {
+        return None;
+    }
+    Some(Reply {
+        leap: packet[0] >> 6,
+        stratum: packet[1],
+        reference_id: packet[12..16].try_into().unwrap(),
+        root_delay: u32_at(4) as f64 / 65536.0,
+        root_dispersion: u32_at(8) as f64 / 65536.0,
+        received: u64_at(32),
+        transmitted: u64_at(40),
+    })
+}
+
+/// Reference ID of the reply, as text.
+fn reference(reply: &Reply) -> String {
+    let id = reply.reference_id;
+    match reply.stratum {
+        0 | 1 => String::from_utf8_lossy(&id)
+            .trim_end_matches('\0')
+            .to_string(),
+        // Servers synchronised over IPv6 send the start of a hash of the address instead.
+        _ => IpAddr::from(id).to_string(),
+    }
+}
@@ -0,0 +1,187 @@
+use std::{
+    sync::OnceLock,
+    time::{Duration, SystemTime},
+};
+
+use log::error;
+use opentelemetry::{
+    metrics::{Counter, Histogram, MeterProvider as _},
+    trace::{Span, SpanKind, Status, TraceContextExt, Tracer as _, TracerProvider as _},
+    Context, KeyValue,
+};
+use opentelemetry_otlp::{MetricExporter, SpanExporter, WithExportConfig};
+use opentelemetry_sdk::{
+    metrics::{PeriodicReader, SdkMeterProvider},
+    runtime,
+    trace::{Tracer, TracerProvider},
+    Resource,
+};
+
+use crate::{
+    metrics::BUCKETS,
+    report::{Attempt, Origin},
+};
+
+static EXPORT: OnceLock<Export> = OnceLock::new();
+
+/// Providers batching spans and metrics to the collector, and the instruments recorded into them.
+struct Export {
+    tracer_provider: TracerProvider,
+    meter_provider: SdkMeterProvider,
+    tracer: Tracer,
+    requests: Counter<u64>,
+    errors: Counter<u64>,
+    request_duration: Histogram<f64>,
+    connect_duration: Histogram<f64>,
+}
+
+/// Starts exporting attempts to the OTLP gRPC endpoint.
+pub fn init(endpoint: &str) {
+    let resource = Resource::new([KeyValue::new("service.name", "artemiss")]);
+
+    let spans = SpanExporter::builder()
+        .with_tonic()
+        .with_endpoint(endpoint)
+        .build()
+        .expect("unable to configure otlp span exporter");
+    let tracer_provider = TracerProvider::builder()
+        .with_batch_exporter(spans, runtime::Tokio)
+        .with_resource(resource.clone())
+        .build();
+
+    let metrics = MetricExporter::builder()
+        .with_tonic()
+        .with_endpoint(endpoint)
+        .build()
+        .expect("unable to configure otlp metric exporter");
+    let meter_provider = SdkMeterProvider::builder()
+        .with_reader(PeriodicReader::builder(metrics, runtime::Tokio).build())
+        .with_resource(resource)
+        .build();
+
+    let meter = meter_provider.meter("artemiss");
+    let export = Export {
+        tracer: tracer_provider.tracer("artemiss"),
+        requests: meter
+            .u64_counter("artemiss.requests")
+            .with_description("Number of probe attempts.")
+            .build(),
+        errors: meter
+            .u64_counter("artemiss.errors")
+            .with_description("Number of failed probe attempts.")
+            .build(),
+        request_duration: meter
+            .f64_histogram("artemiss.request.duration")
+            .with_description("Time taken by probe attempts.")
+            .with_unit("s")
+            .with_boundaries(BUCKETS.to_vec())
+            .build(),
+        connect_duration: meter
+            .f64_histogram("artemiss.connect.duration")
+            .with_description("Time taken to establish connections.")
+            .with_unit("s")
+            .with_boundaries(BUCKETS.to_vec())
+            .build(),
+        tracer_provider,
+        meter_provider,
+    };
+    if EXPORT.set(export).is_err() {
+        panic!("otlp export already initialised");
+    }
+}
+
+/// Exports a probe attempt as a span with a child span for each phase, and records it in the
+/// metrics.
+pub fn observe(origin: &Origin, attempt: &Attempt) {
+    let Some(export) = EXPORT.get() else {
+        return;
+    };
+
+    let mut attributes = vec![
+        KeyValue::new("artemiss.probe", origin.probe),
+        KeyValue::new("artemiss.target", origin.target.clone()),
+        KeyValue::new("artemiss.worker", origin.worker as i64),
+    ];
+    if let Some(name) = &origin.name {
+        attributes.push(KeyValue::new("artemiss.name", name.clone()));
+    }
+    for (key, value) in origin.labels.iter() {
+        attributes.push(KeyValue::new(
+            format!("artemiss.label.{}", key),
+            value.clone(),
+        ));
+    }
+
+    export.requests.add(1, &attributes);
+    export
+        .request_duration
+        .record(attempt.duration.as_secs_f64(), &attributes);
+    if let Some(connect) = attempt.phase("connect") {
+        export
+            .connect_duration
+            .record(connect.as_secs_f64(), &attributes);
+    }
+    if let Some(e) = &attempt.error {
+        let mut attributes = attributes.clone();
+        attributes.push(KeyValue::new("class", e.kind));
+        export.errors.add(1, &attributes);
+    }
+
+    // Attempts are reported once they are over, so the span is backdated to when it started.
+    let end = SystemTime::now();
+    let start = end - attempt.duration;
+    for (name, value) in &attempt.details {
+        attributes.push(KeyValue::new(format!("artemiss.{}", name), value.clone()));
+    }
+    if let Some(e) = &attempt.error {
+        attributes.push(KeyValue::new("error.type", e.kind));
+    }
+    let mut span = export
+        .tracer
+        .span_builder(origin.probe)
+        .with_kind(SpanKind::Client)
+        .with_start_time(start)
+        .with_attributes(attributes)
+        .start(&export.tracer);
+    if let Some(e) = &attempt.error {
+        span.set_status(Status::error(e.message.clone()));
+    }
+    let cx = Context::current_with_span(span);
+
+    // Phases are reported in the order they happened, one after another.
+    let mut offset = Duration::ZERO;
+    for (name, duration) in &attempt.phases {
+        let mut phase = export
+            .tracer
+            .span_builder(*name)
+            .with_start_time(start + offset)
+            .start_with_context(&export.tracer, &cx);
+        offset += *duration;
+        phase.end_with_timestamp((start + offset).min(end));
+    }
+
+    cx.span().end_with_timestamp(end);
+}
+
+/// Flushes everything not exported yet and stops exporting.
+pub async fn shutdown() {
+    let Some(export) = EXPORT.get() else {
+        return;
+    };
+
+    // Shutting down blocks until the export finishes, which runs on the runtime.
+    let tracer_provider = export.tracer_provider.clone();
+    let meter_provider = export.meter_provider.clone();
+    let result = tokio::task::spawn_blocking(move || {
+        if let Err(e) = tracer_provider.shutdown() {
+            error!("failed to export spans: {}", e);
+        }
+        if let Err(e) = meter_provider.shutdown() {
+            error!("failed to export metrics: {}", e);
+        }
+    })
+    .await;
+    if let Err(e) = result {
+        error!("failed to shut down otlp export: {}", e);
+    }
+}
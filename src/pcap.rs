@@ -0,0 +1,365 @@
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    fs::File,
+    io::{self, BufWriter, Write},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    path::{Path, PathBuf},
+    sync::{Mutex, OnceLock, RwLock},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use clap::Args;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use tokio::{task::JoinSet, time};
+
+use crate::{
+    alert,
+    report::{Attempt, Origin},
+    resolve::Resolver,
+};
+
+/// Most bytes kept of every packet, which covers the headers of the handshakes that matter.
+const SNAPLEN: usize = 2048;
+/// Most bytes of packets kept at once, however many the window holds.
+const MAX_BUFFERED: usize = 64 << 20;
+/// Time to keep capturing after a failed attempt before writing its packets, so that the ones
+/// closing its connection are included.
+const LINGER: Duration = Duration::from_millis(500);
+/// Link type of packets that start at their IP header.
+const LINKTYPE_RAW: u32 = 101;
+/// Time to wait for captures still being written before exiting.
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(5);
+
+static CAPTURE: OnceLock<Capture> = OnceLock::new();
+
+/// Options for capturing the packets of failed attempts.
+#[derive(Args, Debug, Serialize, Deserialize)]
+pub struct PcapArgs {
+    /// Keep a rolling capture of the packets on every interface and, when an attempt fails, write
+    /// the ones exchanged with its target to a pcap file in this directory, named by the `seq` of
+    /// the attempt, e.g. `42-http.pcap`. Needs Linux and `CAP_NET_RAW`.
+    #[arg(long, global = true)]
+    pcap_on_failure: Option<PathBuf>,
+
+    /// Seconds of packets before a failed attempt to write along with it.
+    #[arg(long, global = true, default_value_t = 5)]
+    pcap_window_s: u64,
+}
+
+struct Capture {
+    dir: PathBuf,
+    window: Duration,
+    packets: Mutex<Packets>,
+    /// Address each target was last connected at, to pick its packets by.
+    addrs: Mutex<HashMap<Key, IpAddr>>,
+    /// Addresses probes have connected or sent to, the only ones packets are kept for.
+    watched: RwLock<HashSet<IpAddr>>,
+    /// Captures still being written.
+    writes: Mutex<JoinSet<()>>,
+}
+
+/// Keyed by probe kind, name and target, like the statistics.
+type Key = (&'static str, Option<String>, String);
+
+/// Packets captured within the window, oldest first.
+#[derive(Default)]
+struct Packets {
+    queue: VecDeque<Packet>,
+    bytes: usize,
+    /// Whether packets still within the window have had to be dropped to stay under the cap.
+    overflowed: bool,
+}
+
+struct Packet {
+    time: SystemTime,
+    /// Length of the packet on the wire, which may be more than was kept.
+    len: usize,
+    /// The packet from its IP header, up to the snap length.
+    data: Box<[u8]>,
+}
+
+/// Starts capturing packets, if asked to.
+pub(crate) fn init(args: PcapArgs) {
+    let Some(dir) = args.pcap_on_failure else {
+        return;
+    };
+    assert!(args.pcap_window_s > 0, "--pcap-window-s must be positive");
+    std::fs::create_dir_all(&dir).expect("cannot create --pcap-on-failure directory");
+    let socket = open().unwrap_or_else(|e| panic!("cannot capture packets: {}", e));
+
+    let capture = Capture {
+        dir,
+        window: Duration::from_secs(args.pcap_window_s),
+        packets: Default::default(),
+        addrs: Default::default(),
+        watched: Default::default(),
+        writes: Mutex::new(JoinSet::new()),
+    };
+    if CAPTURE.set(capture).is_err() {
+        panic!("packet capture already initialised");
+    }
+    std::thread::spawn(move || {
+        let capture = CAPTURE.get().unwrap();
+        if let Err(e) = capture.run(socket) {
+            warn!("packet capture stopped: {}", e);
+        }
+    });
+}
+
+/// Keeps the packets exchanged with an address from then on, called for every address probes
+/// connect or send to.
+pub(crate) fn watch(addr: IpAddr) {
+    let Some(capture) = CAPTURE.get() else {
+        return;
+    };
+    if !capture.watched.read().unwrap().contains(&addr) {
+        capture.watched.write().unwrap().insert(addr);
+    }
+}
+
+/// Writes the packets exchanged with the target of a failed attempt, identified by its `seq`.
+pub(crate) fn observe(origin: &Origin, attempt: &Attempt, seq: u64) {
+    let Some(capture) = CAPTURE.get() else {
+        return;
+    };
+
+    let key = (origin.probe, origin.name.clone(), origin.target.clone());
+    let remote_addr = attempt
+        .details
+        .iter()
+        .find(|(name, _)| *name == "remote_addr")
+        .and_then(|(_, addr)| addr.parse::<std::net::SocketAddr>().ok());
+    let known = {
+        let mut addrs = capture.addrs.lock().unwrap();
+        if let Some(addr) = remote_addr {
+            addrs.insert(key.clone(), addr.ip());
+            watch(addr.ip());
+        }
+        addrs.get(&key).copied()
+    };
+    if attempt.error.is_none() {
+        return;
+    }
+
+    let since = SystemTime::now() - capture.window;
+    let path = capture.dir.join(format!("{}-{}.pcap", seq, origin.probe));
+    let target = origin.target.clone();
+    let mut writes = capture.writes.lock().unwrap();
+    while writes.try_join_next().is_some() {}
+    writes.spawn(async move {
+        time::sleep(LINGER).await;
+        // Attempts that failed before connecting are matched by what their target resolves to,
+        // which is watched from then on for probes connecting through their client libraries.
+        let addrs = match known {
+            Some(addr) => vec![addr],
+            None => Resolver::default()
+                .lookup(alert::host(&target), 0)
+                .await
+                .map(|addrs| addrs.iter().map(|addr| addr.ip()).collect())
+                .unwrap_or_default(),
+        };
+        addrs.iter().copied().for_each(watch);
+        if addrs.is_empty() {
+            warn!(
+                "not writing capture of failed attempt seq={}: unable to resolve {}",
+                seq, target
+            );
+            return;
+        }
+        let written = tokio::task::spawn_blocking(move || {
+            capture.write(&path, since, &addrs).map(|n| (path, n))
+        })
+        .await
+        .map_err(io::Error::other)
+        .and_then(|written| written);
+        match written {
+            Ok((path, packets)) => info!(
+                "wrote {} packets of failed attempt seq={} to {}",
+                packets,
+                seq,
+                path.display()
+            ),
+            Err(e) => warn!("cannot write capture of failed attempt seq={}: {}", seq, e),
+        }
+    });
+}
+
+/// Waits for captures still being written before exiting.
+pub(crate) async fn flush() {
+    let Some(capture) = CAPTURE.get() else {
+        return;
+    };
+    let mut writes = std::mem::take(&mut *capture.writes.lock().unwrap());
+    let all = async { while writes.join_next().await.is_some() {} };
+    if time::timeout(FLUSH_TIMEOUT, all).await.is_err() {
+        warn!("packet captures were not written before exiting");
+    }
+}
+
+impl Capture {
+    /// Reads packets from the socket for as long as it is open, keeping the ones exchanged with
+    /// watched addresses within the window.
+    fn run(&self, socket: socket2::Socket) -> io::Result<()> {
+        let mut buf = vec![0; SNAPLEN];
+        loop {
+            let Some((len, kept)) = receive(&socket, &mut buf)? else {
+                continue;
+            };
+            let watched = ends(&buf[..kept]).is_some_and(|(src, dst)| {
+                let watched = self.watched.read().unwrap();
+                watched.contains(&src) || watched.contains(&dst)
+            });
+            if !watched {
+                continue;
+            }
+            let now = SystemTime::now();
+            let mut packets = self.packets.lock().unwrap();
+            packets.bytes += kept;
+            packets.queue.push_back(Packet {
+                time: now,
+                len,
+                data: buf[..kept].into(),
+            });
+            // Packets stay for as long as a failed attempt may still need them written.
+            let oldest = now - self.window - LINGER;
+            while (packets.queue.front())
+                .is_some_and(|packet| packet.time < oldest || packets.bytes > MAX_BUFFERED)
+            {
+                let packet = packets.queue.pop_front().unwrap();
+                packets.bytes -= packet.data.len();
+                if packet.time >= oldest && !packets.overflowed {
+                    packets.overflowed = true;
+                    warn!(
+                        "dropping captured packets within --pcap-window-s to keep under {} MiB, \
+                         so captures of failed attempts may miss their earliest packets",
+                        MAX_BUFFERED >> 20
+                    );
+                }
+            }
+        }
+    }
+
+    /// Writes the packets since a time to or from any of the addresses, returning how many there
+    /// were.
+    fn write(&self, path: &Path, since: SystemTime, addrs: &[IpAddr]) -> io::Result<usize> {
+        let mut out = BufWriter::new(File::create(path)?);
+        out.write_all(&0xa1b2_c3d4u32.to_le_bytes())?;
+        out.write_all(&2u16.to_le_bytes())?;
+        out.write_all(&4u16.to_le_bytes())?;
+        out.write_all(&[0; 8])?;
+        out.write_all(&(SNAPLEN as u32).to_le_bytes())?;
+        out.write_all(&LINKTYPE_RAW.to_le_bytes())?;
+
+        // Packets are copied out so that capturing carries on while they are written.
+        let packets: Vec<Packet> = (self.packets.lock().unwrap().queue.iter())
+            .filter(|packet| packet.time >= since)
+            .filter(|packet| {
+                ends(&packet.data)
+                    .is_some_and(|(src, dst)| addrs.contains(&src) || addrs.contains(&dst))
+            })
+            .map(|packet| Packet {
+                time: packet.time,
+                len: packet.len,
+                data: packet.data.clone(),
+            })
+            .collect();
+        for packet in &packets {
+            let time = packet.time.duration_since(UNIX_EPOCH).unwrap_or_default();
+            out.write_all(&(time.as_secs() as u32).to_le_bytes())?;
+            out.write_all(&time.subsec_micros().to_le_bytes())?;
+            out.write_all(&(packet.data.len() as u32).to_le_bytes())?;
+            out.write_all(&(packet.len as u32).to_le_bytes())?;
+            out.write_all(&packet.data)?;
+        }
+        out.flush()?;
+        Ok(packets.len())
+    }
+}
+
+/// Source and destination of an IP packet.
+fn ends(packet: &[u8]) -> Option<(IpAddr, IpAddr)> {
+    match packet.first()? >> 4 {
+        4 if packet.len() >= 20 => {
+            let src: [u8; 4] = packet[12..16].try_into().unwrap();
+            let dst: [u8; 4] = packet[16..20].try_into().unwrap();
+            Some((Ipv4Addr::from(src).into(), Ipv4Addr::from(dst).into()))
+        }
+        6 if packet.len() >= 40 => {
+            let src: [u8; 16] = packet[8..24].try_into().unwrap();
+            let dst: [u8; 16] = packet[24..40].try_into().unwrap();
+            Some((Ipv6Addr::from(src).into(), Ipv6Addr::from(dst).into()))
+        }
+        _ => None,
+    }
+}
+
+/// Opens a socket receiving every IP packet sent or received on any interface, without its link
+/// layer header.
+#[cfg(target_os = "linux")]
+fn open() -> io::Result<socket2::Socket> {
+    use socket2::{Domain, Protocol, Socket, Type};
+
+    let all = (libc::ETH_P_ALL as u16).to_be();
+    Socket::new(
+        Domain::PACKET,
+        Type::DGRAM,
+        Some(Protocol::from(i32::from(all))),
+    )
+}
+
+#[cfg(not(target_os = "linux"))]
+fn open() -> io::Result<socket2::Socket> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "packet capture is only supported on linux",
+    ))
+}
+
+/// Receives a packet into the buffer, returning its length and how much of it was kept, or
+/// nothing if it is not an IP packet or is the copy of one sent over loopback.
+#[cfg(target_os = "linux")]
+fn receive(socket: &socket2::Socket, buf: &mut [u8]) -> io::Result<Option<(usize, usize)>> {
+    use std::os::fd::AsRawFd;
+
+    // SAFETY: zeroed is a valid, empty link layer address.
+    let mut addr: libc::sockaddr_ll = unsafe { std::mem::zeroed() };
+    let mut addr_len = std::mem::size_of::<libc::sockaddr_ll>() as libc::socklen_t;
+    // SAFETY: the buffer and address outlive the call, with the lengths given.
+    let read = unsafe {
+        libc::recvfrom(
+            socket.as_raw_fd(),
+            buf.as_mut_ptr() as *mut libc::c_void,
+            buf.len(),
+            libc::MSG_TRUNC,
+            &mut addr as *mut _ as *mut libc::sockaddr,
+            &mut addr_len,
+        )
+    };
+    if read < 0 {
+        let e = io::Error::last_os_error();
+        return match e.kind() {
+            io::ErrorKind::Interrupted => Ok(None),
+            _ => Err(e),
+        };
+    }
+
+    // Packets sent over loopback are seen again as they are received.
+    if addr.sll_pkttype == libc::PACKET_OUTGOING && addr.sll_hatype == libc::ARPHRD_LOOPBACK {
+        return Ok(None);
+    }
+    let protocol = u16::from_be(addr.sll_protocol);
+    if protocol != libc::ETH_P_IP as u16 && protocol != libc::ETH_P_IPV6 as u16 {
+        return Ok(None);
+    }
+    let len = read as usize;
+    Ok(Some((len, len.min(buf.len()))))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn receive(_socket: &socket2::Socket, _buf: &mut [u8]) -> io::Result<Option<(usize, usize)>> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "packet capture is only supported on linux",
+    ))
+}
@@ -0,0 +1,199 @@
+use std::{
+    net::{IpAddr, SocketAddr},
+    sync::atomic::{AtomicU16, Ordering},
+    time::{Duration, Instant},
+};
+
+use clap::Parser;
+use log::info;
+use serde::{Deserialize, Serialize};
+use socket2::Type;
+use surge_ping::{Client, Config, PingIdentifier, PingSequence, Pinger, SurgeError, ICMP};
+use tokio::sync::Mutex;
+
+use crate::{
+    pcap,
+    probe::{self, CommonArgs, Probe},
+    report::{Attempt, ProbeError},
+    resolve::Resolver,
+    socket,
+};
+
+#[derive(Parser, Debug, Serialize, Deserialize)]
+pub struct PingArgs {
+    /// Host to ping, as a name or an IP address.
+    #[arg(long)]
+    host: String,
+
+    /// Set a timeout for the reply to each echo request.
+    #[arg(long, default_value_t = 1000)]
+    timeout_ms: u64,
+
+    /// Number of echo requests to send on every attempt, one after another.
+    #[arg(long, default_value_t = 1)]
+    packets: u16,
+
+    /// Number of bytes of payload in each echo request.
+    #[arg(long, default_value_t = 56)]
+    size: usize,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    pub common: CommonArgs,
+}
+
+/// Sends ICMP echo requests, over a raw socket when permitted and otherwise over an unprivileged
+/// ICMP datagram socket.
+pub struct PingProbe {
+    /// Receives the replies for every worker for as long as it is kept.
+    _client: Client,
+    host: String,
+    socket: &'static str,
+    workers: Vec<Mutex<Worker>>,
+    sequence: AtomicU16,
+    payload: Vec<u8>,
+    packets: u16,
+    timeout: Duration,
+}
+
+struct Worker {
+    pinger: Pinger,
+    /// Round trip of the last reply, to measure jitter against.
+    last_rtt: Option<Duration>,
+}
+
+impl PingProbe {
+    pub async fn new(args: &PingArgs) -> Self {
+        let addr = Resolver::default()
+            .lookup(&args.host, 0)
+            .await
+            .expect("unable to resolve host")
+            .first()
+            .expect("host has no addresses")
+            .ip();
+        pcap::watch(addr);
+
+        let kind = match addr {
+            IpAddr::V4(_) => ICMP::V4,
+            IpAddr::V6(_) => ICMP::V6,
+        };
+        let mut config = Config::builder().kind(kind).sock_type_hint(Type::RAW);
+        if let Some(ip) = socket::bind_addr() {
+            config = config.bind(SocketAddr::new(ip, 0));
+        }
+        if let Some(interface) = socket::interface() {
+            config = config.interface(interface);
+        }
+        let config = config.build();
+        let client = Client::new(&config).expect("unable to open icmp socket");
+        let socket = if client.get_socket().get_type() == Type::RAW {
+            "raw"
+        } else {
+            "dgram"
+        };
+        info!("pinging {} over a {} icmp socket", addr, socket);
+
+        let timeout = Duration::from_millis(args.timeout_ms);
+        let mut workers = vec![];
+        for worker in 0..args.common.parallel {
+            let mut pinger = client.pinger(addr, PingIdentifier(worker as u16)).await;
+            pinger.timeout(timeout);
+            workers.push(Mutex::new(Worker {
+                pinger,
+                last_rtt: None,
+            }));
+        }
+
+        PingProbe {
+            _client: client,
+            host: args.host.clone(),
+            socket,
+            workers,
+            sequence: AtomicU16::new(0),
+            payload: vec![0; args.size],
+            packets: args.packets.max(1),
+            timeout,
+        }
+    }
+}
+
+impl Probe for PingProbe {
+    fn kind(&self) -> &'static str {
+        "ping"
+    }
+
+    fn target(&self) -> String {
+        self.host.clone()
+    }
+
+    async fn attempt(&self, worker: usize) -> Attempt {
+        let mut worker = self.workers[worker % self.workers.len()].lock().await;
+
+        let start = Instant::now();
+        let mut rtts = vec![];
+        let mut error = None;
+        for _ in 0..self.packets {
+            // Sequence numbers are shared, since datagram sockets ignore the identifier.
+            let sequence = PingSequence(self.sequence.fetch_add(1, Ordering::Relaxed));
+            match worker.pinger.ping(sequence, &self.payload).await {
+                Ok((_, rtt)) => rtts.push(rtt),
+                Err(SurgeError::Timeout { .. }) => {}
+                Err(e) => error = Some(ProbeError::new("send", e)),
+            }
+        }
+        let duration = start.elapsed();
+
+        let sent = self.packets as usize;
+        let lost = sent - rtts.len();
+        let mut details = vec![
+            ("socket", self.socket.to_string()),
+            ("sent", sent.to_string()),
+            ("received", rtts.len().to_string()),
+            ("loss", format!("{:.2}%", lost as f64 * 100.0 / sent as f64)),
+        ];
+
+        // Jitter is the mean difference between consecutive round trips, carrying over the last
+        // one from the previous attempt.
+        let mut previous = worker.last_rtt;
+        let mut deltas = vec![];
+        for rtt in &rtts {
+            if let Some(previous) = previous {
+                deltas.push(rtt.abs_diff(previous));
+            }
+            previous = Some(*rtt);
+        }
+        worker.last_rtt = previous;
+        if !deltas.is_empty() {
+            let jitter = deltas.iter().sum::<Duration>() / deltas.len() as u32;
+            details.push(("jitter", format!("{:.3}ms", jitter.as_secs_f64() * 1000.0)));
+        }
+
+        let mut phases = vec![];
+        if !rtts.is_empty() {
+            phases.push(("rtt", rtts.iter().sum::<Duration>() / rtts.len() as u32));
+        }
+
+        let error = error.or_else(|| match lost {
+            0 => None,
+            lost if lost == sent => Some(ProbeError::new(
+                "timeout",
+                format!("no reply within {}ms", self.timeout.as_millis()),
+            )),
+            lost => Some(ProbeError::new(
+                "loss",
+                format!("{} of {} echo requests lost", lost, sent),
+            )),
+        });
+
+        Attempt {
+            duration,
+            phases,
+            details,
+            error,
+        }
+    }
+}
+
+pub async fn ping_main(args: PingArgs) {
+    probe::run(PingProbe::new(&args).await, &args.common).await
+}
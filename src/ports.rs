@@ -0,0 +1,133 @@
+use std::net::SocketAddr;
+
+/// Error kind of connections that failed for want of a free local port, e.g. with
+/// `EADDRNOTAVAIL` once every ephemeral port to a target is taken.
+pub(crate) const EXHAUSTED: &str = "ports_exhausted";
+
+/// How much of the ephemeral port range of the host is taken by TCP sockets.
+struct Usage {
+    first: u16,
+    last: u16,
+    in_use: usize,
+    time_wait: usize,
+    /// Ports taken by sockets connected to the target, which cannot be used to connect to it
+    /// again until they are freed.
+    to_target: Option<usize>,
+}
+
+impl Usage {
+    /// Details describing the usage, like `ports_used_pct` of the range.
+    fn details(&self) -> Vec<(&'static str, String)> {
+        let size = (self.last - self.first) as usize + 1;
+        let mut details = vec![
+            ("ephemeral_ports", format!("{}-{}", self.first, self.last)),
+            ("ports_in_use", self.in_use.to_string()),
+            ("ports_time_wait", self.time_wait.to_string()),
+            (
+                "ports_used_pct",
+                format!("{:.1}", self.in_use as f64 * 100.0 / size as f64),
+            ),
+        ];
+        if let Some(to_target) = self.to_target {
+            details.push(("ports_to_target", to_target.to_string()));
+        }
+        details
+    }
+}
+
+/// Details of how much of the ephemeral port range is in use, counting the sockets connected to
+/// the target separately if it is known, or none where that cannot be told.
+pub(crate) fn details(target: Option<SocketAddr>) -> Vec<(&'static str, String)> {
+    usage(target)
+        .map(|usage| usage.details())
+        .unwrap_or_default()
+}
+
+/// Reads the ephemeral port range and the TCP sockets of every process on the host from
+/// `/proc`.
+#[cfg(target_os = "linux")]
+fn usage(target: Option<SocketAddr>) -> Option<Usage> {
+    use std::fs;
+
+    let range = fs::read_to_string("/proc/sys/net/ipv4/ip_local_port_range").ok()?;
+    let mut range = range.split_whitespace().map(str::parse::<u16>);
+    let (first, last) = (range.next()?.ok()?, range.next()?.ok()?);
+
+    let mut usage = Usage {
+        first,
+        last,
+        in_use: 0,
+        time_wait: 0,
+        to_target: target.map(|_| 0),
+    };
+    for table in ["/proc/net/tcp", "/proc/net/tcp6"] {
+        let Ok(sockets) = fs::read_to_string(table) else {
+            continue;
+        };
+        for socket in sockets.lines().skip(1) {
+            let mut fields = socket.split_whitespace().skip(1);
+            let (Some(local), Some(remote), Some(state)) =
+                (fields.next(), fields.next(), fields.next())
+            else {
+                continue;
+            };
+            let Some(port) = local.rsplit_once(':').and_then(|(_, port)| hex_port(port)) else {
+                continue;
+            };
+            // Listening sockets hold ports of their own, not ephemeral ones.
+            if state == LISTEN || !(first..=last).contains(&port) {
+                continue;
+            }
+            usage.in_use += 1;
+            if state == TIME_WAIT {
+                usage.time_wait += 1;
+            }
+            if let (Some(count), Some(target)) = (&mut usage.to_target, target) {
+                let target = SocketAddr::new(target.ip().to_canonical(), target.port());
+                if parse(remote) == Some(target) {
+                    *count += 1;
+                }
+            }
+        }
+    }
+    Some(usage)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn usage(_target: Option<SocketAddr>) -> Option<Usage> {
+    None
+}
+
+/// States of sockets as `/proc/net/tcp` lists them.
+#[cfg(target_os = "linux")]
+const TIME_WAIT: &str = "06";
+#[cfg(target_os = "linux")]
+const LISTEN: &str = "0A";
+
+#[cfg(target_os = "linux")]
+fn hex_port(port: &str) -> Option<u16> {
+    u16::from_str_radix(port, 16).ok()
+}
+
+/// Parses an address of `/proc/net/tcp`, which lists addresses as words of hexadecimal in the
+/// byte order of the host, e.g. `0100007F:1F90` for `127.0.0.1:8080` on x86.
+#[cfg(target_os = "linux")]
+fn parse(addr: &str) -> Option<SocketAddr> {
+    use std::net::{IpAddr, Ipv6Addr};
+
+    let (ip, port) = addr.rsplit_once(':')?;
+    let mut octets = vec![];
+    for word in 0..ip.len() / 8 {
+        let word = u32::from_str_radix(ip.get(word * 8..word * 8 + 8)?, 16).ok()?;
+        octets.extend(word.to_ne_bytes());
+    }
+    let ip = match octets.len() {
+        4 => IpAddr::from(<[u8; 4]>::try_from(octets).ok()?),
+        16 => {
+            // Sockets of either family are listed in the table of IPv6 when dual-stack.
+            IpAddr::from(Ipv6Addr::from(<[u8; 16]>::try_from(octets).ok()?)).to_canonical()
+        }
+        _ => return None,
+    };
+    Some(SocketAddr::new(ip, hex_port(port)?))
+}
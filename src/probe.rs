@@ -0,0 +1,976 @@
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, LazyLock,
+    },
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use clap::{Args, ValueEnum};
+use futures_util::{stream::FuturesUnordered, Stream, StreamExt};
+use log::{error, info};
+use rand::Rng;
+use serde::{Deserialize, Deserializer, Serialize};
+use tokio::{
+    sync::{mpsc, watch, Mutex},
+    task::JoinSet,
+    time::{self, Instant, Interval, MissedTickBehavior},
+};
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    control,
+    http::one_or_many,
+    profile::Profile,
+    report::{self, Attempt, Labels, Origin, ProbeError},
+};
+
+/// Cancelled when probes should stop making new attempts.
+static SHUTDOWN: LazyLock<CancellationToken> = LazyLock::new(CancellationToken::new);
+
+/// Shortest time between ticks that can be asked for, the resolution of the timer.
+pub(crate) const MIN_PERIOD: Duration = Duration::from_millis(1);
+
+/// Longest time between ticks that can be asked for.
+pub(crate) const MAX_PERIOD: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Set once a probe is started without `--count` or `--duration-s`, to run until stopped.
+static UNBOUNDED: AtomicBool = AtomicBool::new(false);
+
+tokio::task_local! {
+    /// Cancelled when the probes started within its scope should stop, e.g. because they were
+    /// removed from a reloaded config.
+    static SCOPE: CancellationToken;
+}
+
+/// Stops every worker from making new attempts. Attempts already in flight are not interrupted.
+pub fn shutdown() {
+    SHUTDOWN.cancel();
+}
+
+/// Runs a future, stopping the probes it starts when the token is cancelled as well as on
+/// shutdown.
+pub async fn scoped<F: Future>(stop: CancellationToken, future: F) -> F::Output {
+    SCOPE.scope(stop, future).await
+}
+
+/// Token stopping the probes of the current scope, to carry it over into spawned tasks with
+/// [`scoped`].
+pub fn scope() -> CancellationToken {
+    SCOPE
+        .try_with(CancellationToken::clone)
+        .unwrap_or_else(|_| SHUTDOWN.clone())
+}
+
+/// Whether every probe started so far stops by itself, after `--count` attempts or
+/// `--duration-s`.
+pub(crate) fn bounded() -> bool {
+    !UNBOUNDED.load(Ordering::Relaxed)
+}
+
+/// Whether probes have been told to stop making new attempts.
+pub fn stopping() -> bool {
+    SHUTDOWN.is_cancelled()
+}
+
+/// What to do when attempts fall behind their schedule, e.g. because they took longer than the
+/// interval.
+#[derive(ValueEnum, Clone, Copy, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MissedTicks {
+    /// Make the missed attempts immediately to catch up.
+    #[default]
+    Burst,
+    /// Make the next attempt immediately and schedule the rest from then on.
+    Delay,
+    /// Drop the missed attempts and wait for the next one on the original schedule.
+    Skip,
+}
+
+impl From<MissedTicks> for MissedTickBehavior {
+    fn from(missed: MissedTicks) -> Self {
+        match missed {
+            MissedTicks::Burst => MissedTickBehavior::Burst,
+            MissedTicks::Delay => MissedTickBehavior::Delay,
+            MissedTicks::Skip => MissedTickBehavior::Skip,
+        }
+    }
+}
+
+/// What a worker does on a tick while it already has `--max-in-flight` attempts.
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum WhenBusy {
+    /// Wait for an attempt to finish, then make the attempt of the tick.
+    #[default]
+    Queue,
+    /// Skip the tick, counting it as skipped.
+    Skip,
+}
+
+/// Options shared by every probe.
+#[derive(Args, Clone, Debug, Serialize, Deserialize)]
+pub struct CommonArgs {
+    /// Name identifying the probe in its output.
+    #[arg(long)]
+    pub name: Option<String>,
+
+    /// Attach a label to everything reported about the probe, as `key=value`: log lines, JSON
+    /// records, summaries, metrics and alerts. Can be repeated.
+    #[arg(long)]
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub label: Vec<String>,
+
+    /// Interval of making attempts.
+    #[arg(long, default_value_t = 100)]
+    pub interval_ms: u64,
+
+    /// Number of workers to run in parallel.
+    #[arg(long, default_value_t = 1)]
+    pub parallel: usize,
+
+    /// Make this many attempts per second in total, shared between workers, instead of each
+    /// worker making one every interval.
+    #[arg(long, conflicts_with = "interval_ms", value_parser = parse_rps)]
+    pub rps: Option<f64>,
+
+    /// Make attempts at a rate that changes over the run, shared between workers like `--rps`:
+    /// `step:START,STEP,EVERY_S[,MAX]` starts at START and adds STEP attempts per second every
+    /// EVERY_S seconds up to MAX, `spike:BASE,PEAK,EVERY_S,LENGTH_S` holds BASE but rises to PEAK
+    /// for the last LENGTH_S seconds of every EVERY_S, and `sine:MIN,MAX,PERIOD_S` swings from MIN
+    /// to MAX and back every PERIOD_S seconds. The rate in effect is reported with every attempt
+    /// as `target_rps`, e.g. to find the rate at which errors start.
+    #[arg(long, conflicts_with_all = ["interval_ms", "rps", "min_interval_ms"])]
+    pub rps_profile: Option<String>,
+
+    /// Tighten the interval while the target is degraded, halving it on every attempt that fails
+    /// or is slower than `--degraded-latency-ms` down to this, and doubling it back towards
+    /// `--interval-ms` on every healthy attempt. The interval in effect is reported with every
+    /// attempt as `interval_ms`.
+    #[arg(long, conflicts_with = "rps")]
+    pub min_interval_ms: Option<u64>,
+
+    /// Count attempts slower than this as degraded, with `--min-interval-ms`, without counting
+    /// them as failed.
+    #[arg(long, requires = "min_interval_ms")]
+    pub degraded_latency_ms: Option<u64>,
+
+    /// What to do when attempts fall behind their schedule.
+    #[arg(long, value_enum, default_value_t)]
+    pub missed_tick_behavior: MissedTicks,
+
+    /// Number of attempts each worker may have in flight at once. With more than one, a slow
+    /// attempt does not hold up the attempts of the ticks after it.
+    #[arg(long, default_value_t = 1)]
+    pub max_in_flight: usize,
+
+    /// What a worker does on a tick while it already has `--max-in-flight` attempts.
+    #[arg(long, value_enum, default_value_t)]
+    pub when_busy: WhenBusy,
+
+    /// Make this many attempts at once on every tick of a worker rather than one, e.g. to see how
+    /// a target copes with a thundering herd. Attempts are counted in bursts by
+    /// `--max-in-flight`, and every attempt counts towards `--count`.
+    #[arg(long, default_value_t = 1)]
+    pub burst: usize,
+
+    /// Count an attempt as failed once it has taken this long, abandoning it, however long the
+    /// interval is. Probes still apply their own timeouts within it.
+    #[arg(long)]
+    pub attempt_deadline_ms: Option<u64>,
+
+    /// Delay each attempt by a random amount up to this, so that workers do not tick in lockstep.
+    #[arg(long, default_value_t = 0)]
+    pub jitter_ms: u64,
+
+    /// Stagger the start of workers evenly over this period.
+    #[arg(long, default_value_t = 0)]
+    pub ramp_up_ms: u64,
+
+    /// Stop each worker after making this many attempts, besides any warmup attempts.
+    #[arg(long)]
+    pub count: Option<u64>,
+
+    /// Stop making attempts after this many seconds.
+    #[arg(long)]
+    pub duration_s: Option<u64>,
+
+    /// Treat the first this many attempts of every worker as warmup, e.g. while caches fill and
+    /// connections are first made. Warmup attempts are made and printed, but left out of
+    /// summaries, thresholds, metrics, exports and alerts.
+    #[arg(long, default_value_t = 0)]
+    pub warmup_count: u64,
+
+    /// Treat attempts started in this many seconds after the probe starts as warmup, like
+    /// `--warmup-count`.
+    #[arg(long)]
+    pub warmup_s: Option<u64>,
+
+    /// Count an otherwise successful attempt as failed if it takes longer than this.
+    #[arg(long)]
+    pub max_latency_ms: Option<u64>,
+
+    /// Try a failed attempt again up to this many times before counting it as failed.
+    #[arg(long, default_value_t = 0)]
+    pub retries: u32,
+
+    /// Time to wait before the first retry of an attempt, doubling for every retry after it.
+    #[arg(long, default_value_t = 100)]
+    pub retry_backoff_ms: u64,
+
+    /// Only retry attempts failing with these kinds of error, e.g. `connect_timeout`. Can be
+    /// repeated or separated by commas. Every kind is retried by default.
+    #[arg(long, value_delimiter = ',')]
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub retry_on: Vec<String>,
+
+    /// Count an attempt as successful only if it fails, and as failed with `unexpected_success`
+    /// if it succeeds, e.g. to check that a firewall rule or ACL blocks the target. Given kinds of
+    /// error, like `connect_refused` or `tls`, separated by commas, failing with any other kind is
+    /// counted as failed with `unexpected_error`.
+    #[arg(long, num_args = 0.., value_delimiter = ',')]
+    #[serde(default, deserialize_with = "some_one_or_many")]
+    pub expect_failure: Option<Vec<String>>,
+}
+
+impl CommonArgs {
+    /// Labels given with `--label`, the last of any given twice winning.
+    pub fn labels(&self) -> Labels {
+        let labels = self.label.iter().map(|label| {
+            let (key, value) = label
+                .split_once('=')
+                .filter(|(key, _)| !key.is_empty())
+                .expect("invalid label, expected key=value");
+            (key.to_string(), value.to_string())
+        });
+        Arc::new(labels.collect())
+    }
+}
+
+/// Makes attempts against a target, e.g. opening a connection or sending a request.
+pub trait Probe: Send + Sync + 'static {
+    /// Kind of probe, e.g. `http`.
+    fn kind(&self) -> &'static str;
+
+    /// What attempts are made against, with any credentials redacted.
+    fn target(&self) -> String;
+
+    /// Makes a single attempt from the given worker.
+    fn attempt(&self, worker: usize) -> impl Future<Output = Attempt> + Send;
+}
+
+/// How failed attempts are tried again.
+#[derive(Clone)]
+struct Retry {
+    retries: u32,
+    backoff: Duration,
+    on: Arc<[String]>,
+}
+
+impl Retry {
+    fn applies(&self, error: &ProbeError) -> bool {
+        self.on.is_empty() || self.on.iter().any(|kind| kind == error.kind)
+    }
+}
+
+/// Tightens the interval of the workers while their attempts are degraded, with
+/// `--min-interval-ms`.
+struct Adaptive {
+    min: Duration,
+    degraded_latency: Option<Duration>,
+    /// Interval to relax back to, as configured or last changed through the control API.
+    base: std::sync::Mutex<Duration>,
+    period: watch::Sender<Duration>,
+}
+
+impl Adaptive {
+    /// Tightens or relaxes the interval by how an attempt went, returning the interval in effect
+    /// and how it changed, if it did.
+    fn observe(&self, attempt: &Attempt) -> (Duration, Option<&'static str>) {
+        let degraded = attempt.error.is_some()
+            || (self.degraded_latency).is_some_and(|latency| attempt.duration > latency);
+        let base = *self.base.lock().unwrap();
+        let mut interval = Duration::ZERO;
+        let changed = self.period.send_if_modified(|period| {
+            interval = match degraded {
+                true => (*period / 2).max(self.min),
+                false => (*period * 2).min(base),
+            };
+            std::mem::replace(period, interval) != interval
+        });
+        let change = changed.then_some(if degraded { "tightened" } else { "relaxed" });
+        (interval, change)
+    }
+}
+
+/// Follows the rate of `--rps-profile` over the run.
+struct Paced {
+    profile: Profile,
+    start: Instant,
+    period: watch::Sender<Duration>,
+}
+
+impl Paced {
+    /// Sets the period of the shared interval to the rate the profile asks for at a tick,
+    /// returning the rate.
+    fn follow(&self, tick: Instant) -> f64 {
+        let rps = self.profile.rps(tick.saturating_duration_since(self.start));
+        let interval = paced_period(rps);
+        self.period
+            .send_if_modified(|period| std::mem::replace(period, interval) != interval);
+        rps
+    }
+}
+
+/// Time between ticks for a rate, within what the timer can keep to, as `--rps` can ask for any
+/// rate and rates stepping up without a maximum grow without bound.
+fn paced_period(rps: f64) -> Duration {
+    Duration::try_from_secs_f64(1.0 / rps)
+        .unwrap_or(MAX_PERIOD)
+        .clamp(MIN_PERIOD, MAX_PERIOD)
+}
+
+/// Parses `--rps`, which must be a positive and finite number of attempts per second.
+fn parse_rps(s: &str) -> Result<f64, String> {
+    let rps: f64 = s.parse().map_err(|e| format!("{}", e))?;
+    match rps.is_finite() && rps > 0.0 {
+        true => Ok(rps),
+        false => Err("must be a positive and finite number of attempts per second".to_string()),
+    }
+}
+
+/// Schedules the attempts of a worker.
+enum Schedule {
+    /// The worker makes an attempt every interval.
+    Worker(Interval, watch::Receiver<Duration>),
+    /// Workers take turns at making an attempt from an interval shared between them, starting no
+    /// earlier than the given instant.
+    Shared(Arc<Mutex<Interval>>, watch::Receiver<Duration>, Instant),
+}
+
+impl Schedule {
+    /// Waits for the next tick, returning when it was scheduled for.
+    async fn tick(&mut self) -> Instant {
+        match self {
+            Schedule::Worker(interval, period) => next(interval, period).await,
+            Schedule::Shared(interval, period, not_before) => {
+                time::sleep_until(*not_before).await;
+                let mut interval = interval.lock().await;
+                next(&mut interval, period).await.max(*not_before)
+            }
+        }
+    }
+}
+
+/// Waits for the next tick of the interval, starting it over whenever its period is changed.
+async fn next(interval: &mut Interval, period: &mut watch::Receiver<Duration>) -> Instant {
+    loop {
+        tokio::select! {
+            scheduled = interval.tick() => return scheduled,
+            Ok(()) = period.changed() => {
+                let period = *period.borrow_and_update();
+                // Workers sharing an interval each see the change, but it only starts over once.
+                if period != interval.period() {
+                    let behavior = interval.missed_tick_behavior();
+                    *interval = time::interval_at(Instant::now() + period, period);
+                    interval.set_missed_tick_behavior(behavior);
+                }
+            }
+        }
+    }
+}
+
+/// Result of an attempt made by a [`Runner`].
+#[derive(Clone, Debug)]
+pub struct ProbeResult {
+    pub origin: Origin,
+    pub attempt: Attempt,
+    /// Whether the attempt was made during warmup, so it should not count towards statistics.
+    pub warmup: bool,
+    /// Number of ticks the worker skipped since its previous result, because it already had
+    /// `--max-in-flight` attempts, with `--when-busy skip`.
+    pub skipped: u64,
+}
+
+/// Runs workers that each make an attempt with a probe on every interval, yielding the result of
+/// every attempt as a stream, until stopped or the configured count or duration is reached.
+/// Workers are aborted when the runner is dropped.
+pub struct Runner {
+    results: mpsc::UnboundedReceiver<ProbeResult>,
+    stop: CancellationToken,
+    _workers: JoinSet<()>,
+}
+
+impl Runner {
+    /// Starts the workers of the probe.
+    pub fn start<P: Probe>(probe: P, args: &CommonArgs) -> Self {
+        let probe = Arc::new(probe);
+        let target = probe.target();
+        if args.count.is_none() && args.duration_s.is_none() {
+            UNBOUNDED.store(true, Ordering::Relaxed);
+        }
+        let count = args.count.unwrap_or(u64::MAX);
+        let deadline = args
+            .duration_s
+            .map(|duration| Instant::now() + Duration::from_secs(duration));
+        assert!(args.max_in_flight > 0, "--max-in-flight must be at least 1");
+        assert!(args.burst > 0, "--burst must be at least 1");
+        let start = Instant::now();
+        let plan = Plan {
+            count,
+            deadline,
+            limits: Limits {
+                max_latency: args.max_latency_ms.map(Duration::from_millis),
+                deadline: args.attempt_deadline_ms.map(Duration::from_millis),
+            },
+            max_in_flight: args.max_in_flight,
+            burst: args.burst,
+            when_busy: args.when_busy,
+            jitter: args.jitter_ms,
+            warmup_count: args.warmup_count,
+            warmup_until: args
+                .warmup_s
+                .map(|warmup| start + Duration::from_secs(warmup)),
+            missed_tick_behavior: args.missed_tick_behavior.into(),
+        };
+        let profile = args.rps_profile.as_ref().map(|profile| {
+            profile
+                .parse::<Profile>()
+                .unwrap_or_else(|e| panic!("{}", e))
+        });
+        let rps = args
+            .rps
+            .or(profile.map(|profile| profile.rps(Duration::ZERO)));
+        let period = match rps {
+            Some(rps) => paced_period(rps),
+            None => Duration::from_millis(args.interval_ms),
+        };
+        let shared = rps.map(|_| {
+            let mut interval = time::interval(period);
+            interval.set_missed_tick_behavior(plan.missed_tick_behavior);
+            Arc::new(Mutex::new(interval))
+        });
+        let period = watch::Sender::new(period);
+        let adaptive = args.min_interval_ms.map(|min| {
+            let min = Duration::from_millis(min);
+            let base = *period.borrow();
+            assert!(
+                min < base,
+                "--min-interval-ms must be less than --interval-ms"
+            );
+            Arc::new(Adaptive {
+                min,
+                degraded_latency: args.degraded_latency_ms.map(Duration::from_millis),
+                base: std::sync::Mutex::new(base),
+                period: period.clone(),
+            })
+        });
+        let paced = profile.map(|profile| {
+            Arc::new(Paced {
+                profile,
+                start,
+                period: period.clone(),
+            })
+        });
+        let stop = scope().child_token();
+        let (sender, results) = mpsc::unbounded_channel();
+
+        let mut workers = Workers {
+            origin: Origin {
+                probe: probe.kind(),
+                name: args.name.clone(),
+                labels: args.labels(),
+                target: target.clone(),
+                worker: 0,
+            },
+            probe,
+            plan,
+            retry: Retry {
+                retries: args.retries,
+                backoff: Duration::from_millis(args.retry_backoff_ms),
+                on: args.retry_on.clone().into(),
+            },
+            expect: args.expect_failure.clone().map(Into::into),
+            shared,
+            period,
+            adaptive,
+            paced,
+            paused: watch::Sender::new(false),
+            sender,
+            stop: stop.clone(),
+            running: vec![],
+            tasks: JoinSet::new(),
+        };
+        for worker in 0..args.parallel {
+            let ramp_up =
+                Duration::from_millis(args.ramp_up_ms) * worker as u32 / args.parallel as u32;
+            workers.spawn(start + ramp_up);
+        }
+
+        let commands = control::register(workers.origin.probe, args.name.clone(), target);
+        let mut supervisor = JoinSet::new();
+        supervisor.spawn(workers.run(commands));
+        Runner {
+            results,
+            stop,
+            _workers: supervisor,
+        }
+    }
+
+    /// Stops workers from making new attempts. Results of attempts already in flight are still
+    /// yielded.
+    pub fn stop(&self) {
+        self.stop.cancel();
+    }
+
+    /// Waits for the result of the next attempt, or `None` once every worker has finished.
+    pub async fn next(&mut self) -> Option<ProbeResult> {
+        self.results.recv().await
+    }
+}
+
+impl Stream for Runner {
+    type Item = ProbeResult;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<ProbeResult>> {
+        self.results.poll_recv(cx)
+    }
+}
+
+/// How the workers of a [`Runner`] make their attempts.
+#[derive(Clone, Copy)]
+struct Plan {
+    count: u64,
+    deadline: Option<Instant>,
+    limits: Limits,
+    max_in_flight: usize,
+    burst: usize,
+    when_busy: WhenBusy,
+    jitter: u64,
+    warmup_count: u64,
+    warmup_until: Option<Instant>,
+    missed_tick_behavior: MissedTickBehavior,
+}
+
+/// Workers of a [`Runner`], which are started, stopped and changed by commands from the control
+/// API while they run.
+struct Workers<P> {
+    probe: Arc<P>,
+    /// Origin of the attempts of every worker, but for the worker itself.
+    origin: Origin,
+    plan: Plan,
+    retry: Retry,
+    expect: Expect,
+    shared: Option<Arc<Mutex<Interval>>>,
+    /// Time between ticks, of each worker or of the shared interval with `--rps`.
+    period: watch::Sender<Duration>,
+    adaptive: Option<Arc<Adaptive>>,
+    paced: Option<Arc<Paced>>,
+    /// Whether workers make no attempts on their ticks.
+    paused: watch::Sender<bool>,
+    sender: mpsc::UnboundedSender<ProbeResult>,
+    stop: CancellationToken,
+    /// Tokens stopping each worker still running, by worker.
+    running: Vec<CancellationToken>,
+    tasks: JoinSet<()>,
+}
+
+impl<P: Probe> Workers<P> {
+    /// Carries out the commands sent to the probe until every worker and burst has finished.
+    async fn run(mut self, mut commands: mpsc::UnboundedReceiver<control::Command>) {
+        loop {
+            tokio::select! {
+                Some(command) = commands.recv() => self.command(command),
+                joined = self.tasks.join_next() => match joined {
+                    None => break,
+                    Some(Err(e)) if e.is_panic() => {
+                        error!("{} {}: a worker panicked: {}", self.origin.probe, self.origin.target, e);
+                    }
+                    Some(_) => {}
+                },
+            }
+        }
+    }
+
+    fn command(&mut self, command: control::Command) {
+        use control::Command;
+
+        if self.stop.is_cancelled() {
+            return;
+        }
+        let change = match command {
+            Command::Pause => {
+                self.paused.send_replace(true);
+                "paused".to_string()
+            }
+            Command::Resume => {
+                self.paused.send_replace(false);
+                "resumed".to_string()
+            }
+            Command::Interval { .. } => {
+                let period = command.period().expect("interval command without a period");
+                if let Some(adaptive) = &self.adaptive {
+                    *adaptive.base.lock().unwrap() = period;
+                }
+                self.period.send_replace(period);
+                format!("interval changed to {}ms", period.as_secs_f64() * 1000.0)
+            }
+            Command::Parallel { parallel } => {
+                while self.running.len() < parallel {
+                    self.spawn(Instant::now());
+                }
+                // Stopped workers finish the attempts they have in flight.
+                for stop in self.running.drain(parallel..) {
+                    stop.cancel();
+                }
+                format!("parallel changed to {}", parallel)
+            }
+            Command::Burst { count } => {
+                self.burst(count);
+                format!("bursting {} attempts", count)
+            }
+        };
+        announce(&self.origin, &change);
+    }
+
+    /// Makes attempts at once, spread over the workers running, reporting them like the rest with
+    /// a `burst` detail.
+    fn burst(&mut self, count: u64) {
+        for n in 0..count {
+            let worker = n as usize % self.running.len().max(1);
+            let probe = self.probe.clone();
+            let retry = self.retry.clone();
+            let expect = self.expect.clone();
+            let stop = self.stop.clone();
+            let sender = self.sender.clone();
+            let origin = Origin {
+                worker,
+                ..self.origin.clone()
+            };
+            let limits = self.plan.limits;
+            self.tasks.spawn(async move {
+                let mut attempt = attempt(&*probe, worker, limits, &retry, &expect, &stop).await;
+                attempt.details.push(("burst", count.to_string()));
+                let result = ProbeResult {
+                    origin,
+                    attempt,
+                    warmup: false,
+                    skipped: 0,
+                };
+                let _ = sender.send(result);
+            });
+        }
+    }
+
+    /// Starts another worker, ticking from the given instant.
+    fn spawn(&mut self, begin: Instant) {
+        let worker = self.running.len();
+        let stop = self.stop.child_token();
+        self.running.push(stop.clone());
+        let probe = self.probe.clone();
+        let sender = self.sender.clone();
+        let retry = self.retry.clone();
+        let expect = self.expect.clone();
+        let origin = Origin {
+            worker,
+            ..self.origin.clone()
+        };
+        let paused = self.paused.subscribe();
+        let period = self.period.subscribe();
+        let adaptive = self.adaptive.clone();
+        let paced = self.paced.clone();
+        let Plan {
+            count,
+            deadline,
+            limits,
+            max_in_flight,
+            burst,
+            when_busy,
+            jitter,
+            warmup_count,
+            warmup_until,
+            missed_tick_behavior,
+        } = self.plan;
+        let mut schedule = match &self.shared {
+            Some(interval) => Schedule::Shared(interval.clone(), period, begin),
+            None => {
+                let mut interval = time::interval_at(begin, *period.borrow());
+                interval.set_missed_tick_behavior(missed_tick_behavior);
+                Schedule::Worker(interval, period)
+            }
+        };
+        self.tasks.spawn(async move {
+            let expired = async {
+                match deadline {
+                    Some(deadline) => time::sleep_until(deadline).await,
+                    None => std::future::pending().await,
+                }
+            };
+            tokio::pin!(expired);
+
+            let (probe, retry, expect, stop) = (&*probe, &retry, &expect, &stop);
+            let mut in_flight = FuturesUnordered::new();
+            let mut skipped = 0;
+            // Sends the result of an attempt, returning whether it is still wanted.
+            let send = |mut attempt: Attempt, warmup: bool, skipped: &mut u64| {
+                // Warmup attempts are expected to be slow or fail, so they leave the interval be.
+                if let Some(adaptive) = adaptive.as_deref().filter(|_| !warmup) {
+                    let (interval, change) = adaptive.observe(&attempt);
+                    let interval_ms = interval.as_micros() as f64 / 1000.0;
+                    attempt
+                        .details
+                        .push(("interval_ms", format!("{:.3}", interval_ms)));
+                    if let Some(change) = change {
+                        announce(
+                            &origin,
+                            &format!("interval {} to {}ms", change, interval_ms),
+                        );
+                    }
+                }
+                let result = ProbeResult {
+                    origin: origin.clone(),
+                    attempt,
+                    warmup,
+                    skipped: std::mem::take(skipped),
+                };
+                sender.send(result).is_ok()
+            };
+
+            let mut made = 0;
+            let mut counted = 0;
+            'ticks: while counted < count {
+                let tick = async {
+                    let mut scheduled = schedule.tick().await;
+                    if jitter > 0 {
+                        let offset = rand::thread_rng().gen_range(0..=jitter);
+                        scheduled += Duration::from_millis(offset);
+                        time::sleep_until(scheduled).await;
+                    }
+                    scheduled
+                };
+                tokio::pin!(tick);
+                let scheduled = loop {
+                    tokio::select! {
+                        _ = stop.cancelled() => break 'ticks,
+                        _ = &mut expired => break 'ticks,
+                        Some((attempt, warmup)) = in_flight.next() => {
+                            if !send(attempt, warmup, &mut skipped) {
+                                return;
+                            }
+                        }
+                        scheduled = &mut tick => break scheduled,
+                    }
+                };
+                if *paused.borrow() {
+                    continue;
+                }
+
+                // A burst only starts once there is room for every attempt of it.
+                let room = max_in_flight * burst;
+                if in_flight.len() + burst > room {
+                    if when_busy == WhenBusy::Skip {
+                        skipped += 1;
+                        continue;
+                    }
+                    while in_flight.len() + burst > room {
+                        tokio::select! {
+                            _ = stop.cancelled() => break 'ticks,
+                            _ = &mut expired => break 'ticks,
+                            Some((attempt, warmup)) = in_flight.next() => {
+                                if !send(attempt, warmup, &mut skipped) {
+                                    return;
+                                }
+                            }
+                        }
+                    }
+                }
+
+                // The rate the profile asks for from this tick on, reported with its attempts.
+                let target_rps = paced.as_deref().map(|paced| paced.follow(scheduled));
+                let started = Instant::now();
+                // How late the attempts start after their tick, e.g. because the runtime is
+                // overloaded or attempts in flight were waited for.
+                let drift = started.saturating_duration_since(scheduled);
+                for _ in 0..burst {
+                    if counted >= count {
+                        break;
+                    }
+                    let warmup =
+                        made < warmup_count || warmup_until.is_some_and(|until| started < until);
+                    made += 1;
+                    if !warmup {
+                        counted += 1;
+                    }
+                    in_flight.push(async move {
+                        let mut attempt = attempt(probe, worker, limits, retry, expect, stop).await;
+                        attempt.details.push((
+                            "drift_ms",
+                            format!("{:.3}", drift.as_micros() as f64 / 1000.0),
+                        ));
+                        if burst > 1 {
+                            attempt.details.push(("burst", burst.to_string()));
+                        }
+                        if let Some(rps) = target_rps {
+                            attempt.details.push(("target_rps", format!("{:.3}", rps)));
+                        }
+                        (attempt, warmup)
+                    });
+                }
+            }
+
+            while let Some((attempt, warmup)) = in_flight.next().await {
+                if !send(attempt, warmup, &mut skipped) {
+                    return;
+                }
+            }
+        });
+    }
+}
+
+/// Logs a change to how a probe makes its attempts.
+fn announce(origin: &Origin, change: &str) {
+    match &origin.name {
+        Some(name) => info!("{} {} {}: {}", origin.probe, name, origin.target, change),
+        None => info!("{} {}: {}", origin.probe, origin.target, change),
+    }
+}
+
+/// Kinds of error attempts are expected to fail with, with `--expect-failure`. Any kind is
+/// expected when there are none.
+type Expect = Option<Arc<[String]>>;
+
+/// Judges a try that is expected to fail, counting it as successful if it failed with an
+/// expected kind of error, and as failed otherwise.
+fn expected(mut attempt: Attempt, kinds: &[String]) -> Attempt {
+    attempt.error = match attempt.error.take() {
+        Some(e) if kinds.is_empty() || kinds.iter().any(|kind| kind == e.kind) => {
+            attempt.details.push(("expected_error", e.kind.to_string()));
+            None
+        }
+        Some(e) => Some(ProbeError::new(
+            "unexpected_error",
+            format!(
+                "failed with {} rather than {}: {}",
+                e.kind,
+                kinds.join(" or "),
+                e.message
+            ),
+        )),
+        None => Some(ProbeError::new(
+            "unexpected_success",
+            "succeeded but was expected to fail",
+        )),
+    };
+    attempt
+}
+
+/// Accepts the kinds of error for `--expect-failure` like [`one_or_many`], when given.
+fn some_one_or_many<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Vec<String>>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
+    }
+
+    Ok(match Option::<OneOrMany>::deserialize(deserializer)? {
+        Some(OneOrMany::One(one)) => Some(vec![one]),
+        Some(OneOrMany::Many(many)) => Some(many),
+        None => None,
+    })
+}
+
+/// Limits on how long a single try of an attempt may take.
+#[derive(Clone, Copy)]
+struct Limits {
+    /// Longest a try can take and still count as successful.
+    max_latency: Option<Duration>,
+    /// Longest a try is waited for before it is abandoned.
+    deadline: Option<Duration>,
+}
+
+/// Makes an attempt, trying again while it fails and retries are left. The attempt reported is
+/// the last try, with the number of tries and the kinds of error retried as details.
+async fn attempt<P: Probe>(
+    probe: &P,
+    worker: usize,
+    limits: Limits,
+    retry: &Retry,
+    expect: &Expect,
+    stop: &CancellationToken,
+) -> Attempt {
+    let try_once = || async {
+        let mut attempt = match limits.deadline {
+            Some(deadline) => match time::timeout(deadline, probe.attempt(worker)).await {
+                Ok(attempt) => attempt,
+                Err(_) => Attempt {
+                    duration: deadline,
+                    phases: vec![],
+                    details: vec![],
+                    error: Some(ProbeError::new(
+                        "deadline",
+                        format!("abandoned after {}ms", deadline.as_millis()),
+                    )),
+                },
+            },
+            None => probe.attempt(worker).await,
+        };
+        if let Some(max_latency) = limits.max_latency {
+            if attempt.error.is_none() && attempt.duration > max_latency {
+                attempt.error = Some(ProbeError::new(
+                    "latency",
+                    format!("took longer than {}ms", max_latency.as_millis()),
+                ));
+            }
+        }
+        match expect {
+            Some(kinds) => expected(attempt, kinds),
+            None => attempt,
+        }
+    };
+
+    let mut attempt = try_once().await;
+    let mut tries = 1;
+    let mut retried = vec![];
+    while let Some(error) = &attempt.error {
+        if tries > retry.retries || !retry.applies(error) {
+            break;
+        }
+        let kind = error.kind;
+
+        let backoff = retry.backoff.saturating_mul(2u32.saturating_pow(tries - 1));
+        tokio::select! {
+            _ = stop.cancelled() => break,
+            _ = time::sleep(backoff) => {}
+        }
+        retried.push(kind);
+        attempt = try_once().await;
+        tries += 1;
+    }
+
+    if tries > 1 {
+        attempt.details.push(("tries", tries.to_string()));
+        attempt.details.push(("retried", retried.join(",")));
+    }
+    attempt
+}
+
+/// Runs the probe and reports the result of every attempt, until shut down or the configured
+/// count or duration is reached.
+pub async fn run<P: Probe>(probe: P, args: &CommonArgs) {
+    let mut runner = Runner::start(probe, args);
+    while let Some(result) = runner.next().await {
+        if result.skipped > 0 {
+            report::skipped(&result.origin, result.skipped);
+        }
+        match result.warmup {
+            true => report::warmup(&result.origin, &result.attempt),
+            false => report::report(&result.origin, &result.attempt),
+        }
+    }
+}
@@ -0,0 +1,200 @@
+use std::{f64::consts::PI, str::FromStr, time::Duration};
+
+use crate::probe::{MAX_PERIOD, MIN_PERIOD};
+
+/// Rate of attempts that changes over the run, with `--rps-profile`.
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum Profile {
+    /// Starts at a rate, raising it by a step every period, up to an optional maximum.
+    Step {
+        start: f64,
+        step: f64,
+        every: Duration,
+        max: Option<f64>,
+    },
+    /// Holds a base rate, rising to a peak for the last part of every period.
+    Spike {
+        base: f64,
+        peak: f64,
+        every: Duration,
+        length: Duration,
+    },
+    /// Rises from a minimum to a maximum and back over every period.
+    Sine {
+        min: f64,
+        max: f64,
+        period: Duration,
+    },
+}
+
+impl Profile {
+    /// Attempts per second the profile asks for, this far into the run.
+    pub(crate) fn rps(&self, elapsed: Duration) -> f64 {
+        match *self {
+            Profile::Step {
+                start,
+                step,
+                every,
+                max,
+            } => {
+                let steps = (elapsed.as_secs_f64() / every.as_secs_f64()).floor();
+                let rps = start + step * steps;
+                max.map_or(rps, |max| rps.min(max))
+            }
+            Profile::Spike {
+                base,
+                peak,
+                every,
+                length,
+            } => {
+                let into = elapsed.as_secs_f64() % every.as_secs_f64();
+                match into >= (every - length).as_secs_f64() {
+                    true => peak,
+                    false => base,
+                }
+            }
+            Profile::Sine { min, max, period } => {
+                let phase = 2.0 * PI * elapsed.as_secs_f64() / period.as_secs_f64();
+                min + (max - min) * (1.0 - phase.cos()) / 2.0
+            }
+        }
+    }
+}
+
+impl FromStr for Profile {
+    type Err = String;
+
+    /// Parses `step:START,STEP,EVERY_S[,MAX]`, `spike:BASE,PEAK,EVERY_S,LENGTH_S` or
+    /// `sine:MIN,MAX,PERIOD_S`.
+    fn from_str(s: &str) -> Result<Self, String> {
+        let (kind, values) = s
+            .split_once(':')
+            .ok_or("--rps-profile must be step:, spike: or sine:")?;
+        let values = values
+            .split(',')
+            .map(|value| value.trim().parse::<f64>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| format!("invalid --rps-profile {}: {}", s, e))?;
+        if values
+            .iter()
+            .any(|value| !value.is_finite() || *value <= 0.0)
+        {
+            return Err(format!(
+                "--rps-profile {} must only have positive values",
+                s
+            ));
+        }
+        // Periods shorter than the resolution of the timer would round to nothing.
+        let secs = |value: f64| {
+            Duration::try_from_secs_f64(value)
+                .ok()
+                .filter(|period| (MIN_PERIOD..=MAX_PERIOD).contains(period))
+                .ok_or_else(|| {
+                    format!(
+                        "--rps-profile {} must have periods between {}s and {}s",
+                        s,
+                        MIN_PERIOD.as_secs_f64(),
+                        MAX_PERIOD.as_secs()
+                    )
+                })
+        };
+        let profile = match (kind, values.as_slice()) {
+            ("step", &[start, step, every]) => Profile::Step {
+                start,
+                step,
+                every: secs(every)?,
+                max: None,
+            },
+            ("step", &[start, step, every, max]) => Profile::Step {
+                start,
+                step,
+                every: secs(every)?,
+                max: Some(max),
+            },
+            ("spike", &[base, peak, every, length]) if length < every => Profile::Spike {
+                base,
+                peak,
+                every: secs(every)?,
+                length: secs(length)?,
+            },
+            ("sine", &[min, max, period]) if min < max => Profile::Sine {
+                min,
+                max,
+                period: secs(period)?,
+            },
+            _ => {
+                return Err(format!(
+                    "--rps-profile must be step:START,STEP,EVERY_S[,MAX], \
+                     spike:BASE,PEAK,EVERY_S,LENGTH_S with a length shorter than every, or \
+                     sine:MIN,MAX,PERIOD_S with a min below max, not {}",
+                    s
+                ))
+            }
+        };
+        Ok(profile)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn secs(secs: f64) -> Duration {
+        Duration::from_secs_f64(secs)
+    }
+
+    #[test]
+    fn step_rises_every_period_up_to_the_maximum() {
+        let profile: Profile = "step:10,5,2,20".parse().unwrap();
+        assert_eq!(profile.rps(Duration::ZERO), 10.0);
+        assert_eq!(profile.rps(secs(1.999)), 10.0);
+        assert_eq!(profile.rps(secs(2.0)), 15.0);
+        assert_eq!(profile.rps(secs(4.0)), 20.0);
+        assert_eq!(profile.rps(secs(3600.0)), 20.0);
+    }
+
+    #[test]
+    fn step_without_a_maximum_keeps_rising() {
+        let profile: Profile = "step:1,1,1".parse().unwrap();
+        assert_eq!(profile.rps(secs(99.5)), 100.0);
+    }
+
+    #[test]
+    fn spike_peaks_for_the_last_part_of_every_period() {
+        let profile: Profile = "spike:10,100,10,2".parse().unwrap();
+        assert_eq!(profile.rps(Duration::ZERO), 10.0);
+        assert_eq!(profile.rps(secs(7.999)), 10.0);
+        assert_eq!(profile.rps(secs(8.0)), 100.0);
+        assert_eq!(profile.rps(secs(9.999)), 100.0);
+        assert_eq!(profile.rps(secs(10.0)), 10.0);
+        assert_eq!(profile.rps(secs(18.0)), 100.0);
+    }
+
+    #[test]
+    fn sine_starts_at_the_minimum_and_peaks_halfway() {
+        let profile: Profile = "sine:10,30,8".parse().unwrap();
+        assert_eq!(profile.rps(Duration::ZERO), 10.0);
+        assert!((profile.rps(secs(2.0)) - 20.0).abs() < 1e-9);
+        assert_eq!(profile.rps(secs(4.0)), 30.0);
+        assert!((profile.rps(secs(8.0)) - 10.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn periods_below_the_timer_resolution_are_rejected() {
+        for profile in ["step:1,1,0.0001", "spike:1,2,1,0.0001", "sine:1,2,0.0001"] {
+            let error = profile.parse::<Profile>().unwrap_err();
+            assert!(error.contains("must have periods between"), "{}", error);
+        }
+        assert!("step:1,1,1e300".parse::<Profile>().is_err());
+        assert!("step:1,1,0.001".parse::<Profile>().is_ok());
+    }
+
+    #[test]
+    fn malformed_profiles_are_rejected() {
+        assert!("step:1,1".parse::<Profile>().is_err());
+        assert!("spike:1,2,1,1".parse::<Profile>().is_err());
+        assert!("sine:2,1,1".parse::<Profile>().is_err());
+        assert!("sine:1,2,-1".parse::<Profile>().is_err());
+        assert!("ramp:1,2,3".parse::<Profile>().is_err());
+    }
+}
@@ -0,0 +1,259 @@
+use std::{
+    io,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use clap::Parser;
+use percent_encoding::percent_decode_str;
+use rustls::pki_types::ServerName;
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader},
+    time,
+};
+use tokio_rustls::TlsConnector;
+use url::Url;
+
+use crate::{
+    limit,
+    probe::{self, CommonArgs, Probe},
+    report::{self, Attempt, ProbeError},
+    resolve, tls,
+};
+
+#[derive(Parser, Debug, Serialize, Deserialize)]
+pub struct RedisArgs {
+    /// Redis URL to connect to, as `redis://[[username]:password@]host[:port]`.
+    #[arg(long)]
+    url: String,
+
+    /// Set a timeout for only the connect phase of a connection.
+    #[arg(long, default_value_t = 15)]
+    connect_timeout_ms: u64,
+
+    /// Set a timeout for the `PING` round trip, including authentication.
+    #[arg(long, default_value_t = 20)]
+    timeout_ms: u64,
+
+    /// Insecure connection
+    #[arg(long)]
+    insecure: bool,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    pub common: CommonArgs,
+}
+
+trait Stream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Stream for T {}
+
+pub struct RedisProbe {
+    url: Url,
+    host: String,
+    port: u16,
+    tls: Option<TlsConnector>,
+    connect_timeout: Duration,
+    timeout: Duration,
+}
+
+impl RedisProbe {
+    pub fn new(args: &RedisArgs) -> Self {
+        let url = Url::parse(&args.url).expect("invalid redis url");
+        let host = url
+            .host_str()
+            .expect("redis url has no host")
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .to_string();
+        let port = url.port().unwrap_or(6379);
+
+        RedisProbe {
+            url,
+            host,
+            port,
+            tls: (!args.insecure).then(|| TlsConnector::from(Arc::new(tls::client_config()))),
+            connect_timeout: Duration::from_millis(args.connect_timeout_ms),
+            timeout: Duration::from_millis(args.timeout_ms),
+        }
+    }
+}
+
+impl Probe for RedisProbe {
+    fn kind(&self) -> &'static str {
+        "redis"
+    }
+
+    fn target(&self) -> String {
+        report::redact(self.url.as_str())
+    }
+
+    async fn attempt(&self, _worker: usize) -> Attempt {
+        let start = Instant::now();
+        let throttle = limit::acquire().await;
+        let connect_start = Instant::now();
+        let connect = connect(&self.host, self.port, self.tls.as_ref());
+        let result = limit::timeout(self.connect_timeout, connect).await;
+        let mut connected = connect_start.elapsed();
+        let throttled = throttle.release();
+
+        let mut details = vec![];
+        let mut tls_throttle = None;
+        let (phases, error) = match result {
+            Ok(Ok((stream, family, waited))) => {
+                details = family;
+                if let Some((_, waited)) = waited {
+                    connected = connected.saturating_sub(waited);
+                }
+                tls_throttle = waited;
+                match time::timeout(self.timeout, ping(stream, &self.url)).await {
+                    Ok(Ok(rtt)) => (vec![("connect", connected), ("rtt", rtt)], None),
+                    Ok(Err(e)) => (
+                        vec![("connect", connected)],
+                        Some(ProbeError::from_cause("ping", &e)),
+                    ),
+                    Err(_) => (
+                        vec![("connect", connected)],
+                        Some(ProbeError::new(
+                            "timeout",
+                            format!("timed out after {}ms", self.timeout.as_millis()),
+                        )),
+                    ),
+                }
+            }
+            Ok(Err(e)) => (vec![], Some(ProbeError::from_cause("connect", &e))),
+            Err(_) => (
+                vec![],
+                Some(ProbeError::new(
+                    "connect_timeout",
+                    format!("timed out after {}ms", self.connect_timeout.as_millis()),
+                )),
+            ),
+        };
+
+        Attempt {
+            duration: start.elapsed(),
+            phases: throttled
+                .into_iter()
+                .chain(tls_throttle)
+                .chain(phases)
+                .collect(),
+            details,
+            error,
+        }
+    }
+}
+
+pub async fn redis_main(args: RedisArgs) {
+    probe::run(RedisProbe::new(&args), &args.common).await
+}
+
+/// Opens a connection, negotiating TLS when a connector is given, returning it with the details
+/// of its address family and any time spent waiting to start the handshake.
+async fn connect(
+    host: &str,
+    port: u16,
+    connector: Option<&TlsConnector>,
+) -> io::Result<(
+    Box<dyn Stream>,
+    Vec<(&'static str, String)>,
+    Option<(&'static str, Duration)>,
+)> {
+    let conn = resolve::connect(host, port).await?;
+    let family = conn.details();
+
+    match connector {
+        Some(connector) => {
+            let name = ServerName::try_from(host.to_string())
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+            let waited = limit::handshake().await;
+            Ok((
+                Box::new(connector.connect(name, conn.stream).await?),
+                family,
+                waited,
+            ))
+        }
+        None => Ok((Box::new(conn.stream), family, None)),
+    }
+}
+
+/// Authenticates if the URL has credentials, then measures the round trip of a `PING`.
+async fn ping(stream: Box<dyn Stream>, url: &Url) -> io::Result<Duration> {
+    let mut stream = BufReader::new(stream);
+
+    if let Some(password) = url.password() {
+        let password = percent_decode_str(password).decode_utf8_lossy();
+        let username = percent_decode_str(url.username()).decode_utf8_lossy();
+        let auth = if username.is_empty() {
+            command(&["AUTH", &password])
+        } else {
+            command(&["AUTH", &username, &password])
+        };
+        stream.write_all(&auth).await?;
+        read_reply(&mut stream).await?;
+    }
+
+    let start = Instant::now();
+    stream.write_all(&command(&["PING"])).await?;
+    let reply = read_reply(&mut stream).await?;
+    let rtt = start.elapsed();
+
+    if reply != "PONG" {
+        return Err(io::Error::other(format!("unexpected reply: {}", reply)));
+    }
+    Ok(rtt)
+}
+
+/// Encodes a command as a RESP array of bulk strings.
+fn command(args: &[&str]) -> Vec<u8> {
+    let mut buf = format!("*{}\r\n", args.len()).into_bytes();
+    for arg in args {
+        buf.extend_from_slice(format!("${}\r\n{}\r\n", arg.len(), arg).as_bytes());
+    }
+    buf
+}
+
+/// Reads a single-line RESP reply, turning error replies into errors.
+async fn read_reply<S: AsyncRead + Unpin>(stream: &mut BufReader<S>) -> io::Result<String> {
+    let mut line = String::new();
+    if stream.read_line(&mut line).await? == 0 {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    let line = line.trim_end();
+
+    match line.split_at_checked(1) {
+        Some(("+", reply)) => Ok(reply.to_string()),
+        Some(("-", reply)) => Err(io::Error::other(reply.to_string())),
+        _ => Err(io::Error::other(format!("unexpected reply: {}", line))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn connects_to_ipv6_literal() {
+        // A server that answers `PING` with `PONG`.
+        let listener = TcpListener::bind("[::1]:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut line = String::new();
+            let mut reader = BufReader::new(&mut stream);
+            while reader.read_line(&mut line).await.unwrap() > 0 && !line.ends_with("PING\r\n") {}
+            stream.write_all(b"+PONG\r\n").await.unwrap();
+        });
+
+        let url = format!("redis://[::1]:{}", port);
+        let args = RedisArgs::parse_from(["redis", "--url", &url, "--insecure"]);
+        let probe = RedisProbe::new(&args);
+        assert_eq!(probe.host, "::1");
+
+        let (stream, _, _) = connect(&probe.host, probe.port, None).await.unwrap();
+        ping(stream, &probe.url).await.unwrap();
+    }
+}
@@ -0,0 +1,103 @@
+use std::{
+    collections::BTreeMap,
+    future::Future,
+    pin::Pin,
+    sync::{Arc, LazyLock, Mutex},
+};
+
+use clap::{error::ErrorKind, CommandFactory, Parser};
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{
+    cli::{self, Cli},
+    probe::{self, CommonArgs, Probe},
+};
+
+/// Probes registered by the program running the command line, by subcommand.
+static PROBES: LazyLock<Mutex<BTreeMap<String, Registered>>> = LazyLock::new(Default::default);
+
+type Run = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// Parses the command line of a probe, merging `ARTEMISS_` environment variables over it if asked
+/// to, into the run of the probe.
+type Parse = dyn Fn(&[String], bool) -> Result<Run, clap::Error> + Send + Sync;
+
+/// How to run a registered probe from its command line.
+#[derive(Clone)]
+struct Registered {
+    about: String,
+    parse: Arc<Parse>,
+}
+
+/// Adds a probe of a custom kind, run by the command line as the subcommand `name` with the
+/// options of `A`, and by config files with `type` set to `name`. The probe is built from the
+/// parsed options by `build`, which also gives back the options every probe takes, and is then
+/// scheduled, reported and alerted on like any other probe. Register probes before calling
+/// [`cli::main`].
+///
+/// Panics if `name` is already a subcommand.
+pub fn register<A, P, F>(name: &str, build: F)
+where
+    A: Parser + Serialize + DeserializeOwned + Send + 'static,
+    P: Probe,
+    F: Fn(A) -> (P, CommonArgs) + Send + Sync + 'static,
+{
+    assert!(
+        Cli::command().find_subcommand(name).is_none(),
+        "probe {} is already a subcommand",
+        name
+    );
+    let about = A::command()
+        .get_about()
+        .map(ToString::to_string)
+        .unwrap_or_default();
+    let build = Arc::new(build);
+    let parse = move |argv: &[String], env: bool| {
+        let args = A::try_parse_from(argv)?;
+        let args = if env { cli::extract_config(args) } else { args };
+        // The probe is only built once it runs, so that parsing has no effects.
+        let build = build.clone();
+        Ok(Box::pin(async move {
+            let (probe, common) = build(args);
+            probe::run(probe, &common).await
+        }) as Run)
+    };
+
+    let mut probes = PROBES.lock().unwrap();
+    let registered = Registered {
+        about,
+        parse: Arc::new(parse),
+    };
+    assert!(
+        probes.insert(name.to_string(), registered).is_none(),
+        "probe {} is registered twice",
+        name
+    );
+}
+
+/// Parses the command line of a registered probe, starting with its subcommand, into its run.
+pub(crate) fn parse(argv: &[String], env: bool) -> Result<Run, clap::Error> {
+    let name = argv.first().map(String::as_str).unwrap_or_default();
+    let registered = PROBES.lock().unwrap().get(name).cloned();
+    match registered {
+        Some(registered) => (registered.parse)(argv, env),
+        None => Err(Cli::command().error(
+            ErrorKind::InvalidSubcommand,
+            format!("unrecognized subcommand '{}'", name),
+        )),
+    }
+}
+
+/// Lists the registered probes after the help of the command line, if there are any.
+pub(crate) fn augment(command: clap::Command) -> clap::Command {
+    let probes = PROBES.lock().unwrap();
+    if probes.is_empty() {
+        return command;
+    }
+    let width = probes.keys().map(String::len).max().unwrap_or_default();
+    let mut help = "Custom probes:".to_string();
+    for (name, registered) in probes.iter() {
+        help.push_str(&format!("\n  {:width$}  {}", name, registered.about));
+    }
+    command.after_help(help)
+}
@@ -0,0 +1,821 @@
+use std::{
+    collections::BTreeMap,
+    error::Error,
+    fmt::Write,
+    io,
+    net::SocketAddr,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, OnceLock,
+    },
+    time::{Duration, Instant, SystemTime},
+};
+
+use clap::{Args, ValueEnum};
+use hickory_resolver::error::ResolveError;
+use log::{debug, info, log, warn, Level};
+use serde::{Deserialize, Serialize};
+use tokio::time;
+use url::Url;
+
+use crate::{
+    ab, agent, alert, api, export, logger, metrics, otlp, pcap, ports, secret, socket, stats,
+    store, tui,
+};
+
+static OUTPUT: OnceLock<Output> = OnceLock::new();
+/// Whether connections are reported when they open and close.
+static CONNECTION_EVENTS: AtomicBool = AtomicBool::new(false);
+/// Number of connections opened so far, to identify each one by.
+static CONNECTIONS: AtomicU64 = AtomicU64::new(0);
+/// Number of results reported so far, to order each one by.
+static RESULTS: AtomicU64 = AtomicU64::new(0);
+/// When reporting started, to time results from with a clock that NTP does not adjust.
+static STARTED: OnceLock<Instant> = OnceLock::new();
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Output {
+    /// Log lines through the logger.
+    #[default]
+    Text,
+    /// One JSON object per attempt on stdout.
+    Json,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogSink {
+    /// Standard error.
+    #[default]
+    Stderr,
+    /// The local syslog daemon, under the `daemon` facility.
+    Syslog,
+    /// The systemd journal, with the fields of each attempt as fields of its entry.
+    Journald,
+}
+
+#[derive(Args, Debug, Serialize, Deserialize)]
+pub struct ReportArgs {
+    /// Address to serve Prometheus metrics on at `/metrics`.
+    #[arg(long, global = true)]
+    metrics_addr: Option<SocketAddr>,
+
+    /// Address to serve the control API on, with `/healthz`, `/status` for the statistics of every
+    /// target, `/targets` for the latest attempt against each, and `/metrics`. Running probes
+    /// are paused, resumed, resized and burst with a JSON command posted to `/control`, like
+    /// `{"command": "burst", "count": 20, "name": "api"}`.
+    #[arg(long, global = true)]
+    listen_addr: Option<SocketAddr>,
+
+    /// Format to report probe attempts in.
+    #[arg(long, global = true, value_enum, default_value_t)]
+    output: Output,
+
+    /// Where to send log lines, including the reports of attempts with `--output text`. Failed
+    /// attempts are logged at the `err` severity, and failed warmups, alerts and connections
+    /// closed by errors at `warning`.
+    #[arg(long, global = true, value_enum, default_value_t)]
+    log_sink: LogSink,
+
+    /// Export each attempt as a trace span, along with latency and error metrics, to this OTLP
+    /// gRPC endpoint, e.g. `http://localhost:4317`.
+    #[arg(long, global = true)]
+    otlp_endpoint: Option<String>,
+
+    /// Show a live dashboard of every target in the terminal instead of reporting each attempt.
+    /// Errors and log lines are shown on the dashboard, and the summary is still printed at the
+    /// end.
+    #[arg(long, global = true)]
+    tui: bool,
+
+    /// Print a summary of the attempts against every target since the last one this often, or
+    /// never if `0`.
+    #[arg(long, global = true, default_value_t = 10)]
+    report_interval_s: u64,
+
+    /// Also append every attempt to this file, as CSV or Parquet by its extension, e.g.
+    /// `results.parquet`, and the periodic summaries to a file next to it, e.g.
+    /// `results.intervals.parquet`. Parquet files are only complete once the run ends.
+    #[arg(long, global = true)]
+    out: Option<PathBuf>,
+
+    /// Also write every attempt to this SQLite database, creating it if needed, to look into
+    /// past runs with `artemiss report`.
+    #[arg(long, global = true)]
+    store: Option<PathBuf>,
+
+    /// Report every connection probes open and close, with its socket addresses, how long it was
+    /// open and the error that closed it, if any.
+    #[arg(long, global = true)]
+    connection_events: bool,
+}
+
+impl ReportArgs {
+    /// Takes the store, for `artemiss report` to read rather than write.
+    pub(crate) fn take_store(&mut self) -> Option<PathBuf> {
+        self.store.take()
+    }
+}
+
+/// Starts logging and reporting on the configured outputs.
+pub(crate) fn init(args: ReportArgs) {
+    // Log lines sent elsewhere are kept off the dashboard.
+    logger::init(
+        args.log_sink,
+        args.tui && matches!(args.log_sink, LogSink::Stderr),
+    );
+
+    OUTPUT
+        .set(args.output)
+        .expect("reporting already initialised");
+    STARTED.get_or_init(Instant::now);
+    CONNECTION_EVENTS.store(args.connection_events, Ordering::Relaxed);
+
+    if let Some(addr) = args.metrics_addr {
+        metrics::serve(addr);
+    }
+    if let Some(addr) = args.listen_addr {
+        api::serve(addr);
+    }
+    if let Some(endpoint) = &args.otlp_endpoint {
+        otlp::init(endpoint);
+    }
+    if let Some(path) = &args.out {
+        export::init(path);
+    }
+    if let Some(path) = &args.store {
+        store::init(path);
+    }
+    if args.tui {
+        tui::start();
+    }
+    if args.report_interval_s > 0 {
+        tokio::spawn(report_periodically(Duration::from_secs(
+            args.report_interval_s,
+        )));
+    }
+}
+
+/// Prints a summary of the attempts made in every interval, unless the dashboard is showing.
+async fn report_periodically(period: Duration) {
+    let mut interval = time::interval_at(time::Instant::now() + period, period);
+    loop {
+        interval.tick().await;
+        let summaries = stats::interval_summaries();
+        if summaries.is_empty() {
+            continue;
+        }
+        export::interval(&summaries, period.as_secs());
+        if tui::enabled() {
+            continue;
+        }
+
+        match OUTPUT.get().copied().unwrap_or_default() {
+            Output::Text => {
+                println!("--- artemiss last {}s ---", period.as_secs());
+                for summary in &summaries {
+                    println!("{}", summary_line(summary));
+                }
+            }
+            Output::Json => {
+                #[derive(Serialize)]
+                struct Record<'a> {
+                    timestamp: String,
+                    interval_s: u64,
+                    interval: &'a [stats::Summary],
+                }
+                let record = Record {
+                    timestamp: humantime::format_rfc3339_micros(SystemTime::now()).to_string(),
+                    interval_s: period.as_secs(),
+                    interval: &summaries,
+                };
+                print_json(&record);
+            }
+        }
+    }
+}
+
+/// Labels attached to a probe's output, by key.
+pub type Labels = Arc<BTreeMap<String, String>>;
+
+/// Where a probe attempt was made from.
+#[derive(Clone, Debug)]
+pub struct Origin {
+    /// Kind of probe that made the attempt.
+    pub probe: &'static str,
+    /// Name given to the probe.
+    pub name: Option<String>,
+    /// Labels given to the probe with `--label`.
+    pub labels: Labels,
+    /// What the attempt was made against.
+    pub target: String,
+    /// Worker that made the attempt.
+    pub worker: usize,
+}
+
+/// Result of a single probe attempt.
+#[derive(Clone, Debug)]
+pub struct Attempt {
+    /// Time taken by the whole attempt.
+    pub duration: Duration,
+    /// Time taken by each phase of the attempt, e.g. `connect`.
+    pub phases: Vec<(&'static str, Duration)>,
+    /// Probe specific facts about the attempt, e.g. a response status.
+    pub details: Vec<(&'static str, String)>,
+    /// Error the attempt failed with.
+    pub error: Option<ProbeError>,
+}
+
+impl Attempt {
+    /// Time taken by the named phase, if the attempt went through it.
+    pub fn phase(&self, name: &str) -> Option<Duration> {
+        self.phases
+            .iter()
+            .find(|(phase, _)| *phase == name)
+            .map(|(_, duration)| *duration)
+    }
+
+    /// Value of the named detail, if the probe recorded it.
+    pub fn detail(&self, name: &str) -> Option<&str> {
+        self.details
+            .iter()
+            .find(|(detail, _)| *detail == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// Error a probe attempt failed with.
+#[derive(Clone, Debug)]
+pub struct ProbeError {
+    /// Machine readable class of the error. Kinds shared between probes are:
+    ///
+    /// - `dns`: the name of the target could not be resolved.
+    /// - `connect_refused`: nothing was listening on the target.
+    /// - `connect_timeout`: the connection was not open within the connect timeout.
+    /// - `connect`: the connection could not be opened any other way.
+    /// - `tls`: the TLS handshake or a TLS record failed.
+    /// - `reset_by_peer`: the connection was reset or closed under the probe.
+    /// - `timeout` or `request_timeout`: there was no answer within the timeout.
+    /// - `http_status` and `body_mismatch`: an HTTP response was not the one expected.
+    /// - `db_auth` and `db_handshake`: the database rejected the credentials or the session.
+    ///
+    /// Probes add their own kinds for the steps of their protocol, e.g. `not_serving`.
+    pub kind: &'static str,
+    pub message: String,
+}
+
+impl ProbeError {
+    pub fn new(kind: &'static str, message: impl ToString) -> Self {
+        ProbeError {
+            kind,
+            message: message.to_string(),
+        }
+    }
+
+    /// Creates an error from the error it was caused by, refining the kind when the cause is
+    /// recognised, e.g. `connect` becomes `connect_refused`.
+    pub fn from_cause(kind: &'static str, cause: &(dyn Error + 'static)) -> Self {
+        ProbeError::new(classify(kind, cause), cause)
+    }
+}
+
+/// Refines a generic error kind by looking through the chain of causes of an error for the
+/// transport failures shared by every probe.
+pub fn classify(kind: &'static str, error: &(dyn Error + 'static)) -> &'static str {
+    let mut cause = Some(error);
+    while let Some(error) = cause {
+        if error.is::<rustls::Error>() {
+            return "tls";
+        }
+        if error.is::<ResolveError>() {
+            return "dns";
+        }
+        // hyper does not expose the type of errors from its connector.
+        if error.to_string().starts_with("dns error") {
+            return "dns";
+        }
+
+        if let Some(e) = error.downcast_ref::<io::Error>() {
+            match e.kind() {
+                io::ErrorKind::ConnectionRefused => return "connect_refused",
+                io::ErrorKind::ConnectionReset
+                | io::ErrorKind::ConnectionAborted
+                | io::ErrorKind::BrokenPipe => return "reset_by_peer",
+                io::ErrorKind::TimedOut if kind == "connect" => return "connect_timeout",
+                // Connecting fails like this once there is no local port left to connect from,
+                // e.g. with every ephemeral port in TIME_WAIT, and so does binding to
+                // `--bind-addr`, which otherwise is refused for not being a local address.
+                io::ErrorKind::AddrNotAvailable
+                    if kind == "connect" && socket::bind_addr().is_none() =>
+                {
+                    return ports::EXHAUSTED
+                }
+                io::ErrorKind::AddrInUse if kind == "connect" => return ports::EXHAUSTED,
+                _ => {}
+            }
+            // The standard library reports failed lookups without a kind of their own.
+            if e.to_string()
+                .starts_with("failed to lookup address information")
+            {
+                return "dns";
+            }
+            // Errors wrapped in an I/O error, e.g. by TLS streams, are not its source.
+            if let Some(inner) = e.get_ref() {
+                let refined = classify(kind, inner);
+                if refined != kind {
+                    return refined;
+                }
+            }
+        }
+
+        cause = error.source();
+    }
+    kind
+}
+
+/// A connection opened by a probe, identified across the attempts it serves, which is reported
+/// when it opens and closes with `--connection-events`.
+#[derive(Debug)]
+pub struct Lifecycle {
+    pub id: u64,
+    pub local_addr: Option<SocketAddr>,
+    pub remote_addr: Option<SocketAddr>,
+    origin: Origin,
+    opened: Instant,
+}
+
+#[derive(Serialize)]
+struct ConnectionRecord<'a> {
+    timestamp: String,
+    event: &'static str,
+    probe: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    name: Option<&'a str>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    labels: &'a BTreeMap<String, String>,
+    target: &'a str,
+    worker: usize,
+    connection_id: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    local_addr: Option<SocketAddr>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    remote_addr: Option<SocketAddr>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    age_ms: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    attempts: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error_kind: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error_message: Option<&'a str>,
+}
+
+impl Lifecycle {
+    /// Numbers a new connection between the addresses and reports it opening.
+    pub fn opened(
+        origin: &Origin,
+        local_addr: Option<SocketAddr>,
+        remote_addr: Option<SocketAddr>,
+    ) -> Self {
+        let lifecycle = Lifecycle {
+            id: CONNECTIONS.fetch_add(1, Ordering::Relaxed) + 1,
+            origin: origin.clone(),
+            local_addr,
+            remote_addr,
+            opened: Instant::now(),
+        };
+        lifecycle.event("open", None, None);
+        lifecycle
+    }
+
+    /// Reports the connection closing, after serving a number of attempts if the probe counts
+    /// them, and the error that closed it, if any.
+    pub fn closed(&self, attempts: Option<usize>, error: Option<&ProbeError>) {
+        self.event("close", attempts, error);
+    }
+
+    /// Detail identifying the connection in the attempts it serves.
+    pub fn detail(&self) -> (&'static str, String) {
+        ("connection_id", self.id.to_string())
+    }
+
+    fn event(&self, event: &'static str, attempts: Option<usize>, error: Option<&ProbeError>) {
+        if !CONNECTION_EVENTS.load(Ordering::Relaxed) || tui::enabled() {
+            return;
+        }
+
+        let origin = &self.origin;
+        let age = (event == "close").then(|| self.opened.elapsed());
+        match OUTPUT.get().copied().unwrap_or_default() {
+            Output::Text => {
+                let mut fields = String::new();
+                if let Some(name) = &origin.name {
+                    let _ = write!(fields, "name={} ", name);
+                }
+                write_labels(&mut fields, &origin.labels);
+                let _ = write!(
+                    fields,
+                    "target={} worker={} connection_id={}",
+                    origin.target, origin.worker, self.id
+                );
+                if let Some(addr) = self.local_addr {
+                    let _ = write!(fields, " local_addr={}", addr);
+                }
+                if let Some(addr) = self.remote_addr {
+                    let _ = write!(fields, " remote_addr={}", addr);
+                }
+                if let Some(age) = age {
+                    let _ = write!(fields, " age={:.3}ms", ms(age));
+                }
+                if let Some(attempts) = attempts {
+                    let _ = write!(fields, " attempts={}", attempts);
+                }
+
+                match error {
+                    None => info!("{} connection {}. {}", origin.probe, event, fields),
+                    Some(e) => warn!(
+                        "{} connection {} on {} error: {}. {}",
+                        origin.probe, event, e.kind, e.message, fields
+                    ),
+                }
+            }
+            Output::Json => {
+                let record = ConnectionRecord {
+                    timestamp: humantime::format_rfc3339_micros(SystemTime::now()).to_string(),
+                    event,
+                    probe: origin.probe,
+                    name: origin.name.as_deref(),
+                    labels: &origin.labels,
+                    target: &origin.target,
+                    worker: origin.worker,
+                    connection_id: self.id,
+                    local_addr: self.local_addr,
+                    remote_addr: self.remote_addr,
+                    age_ms: age.map(ms),
+                    attempts,
+                    error_kind: error.map(|e| e.kind),
+                    error_message: error.map(|e| e.message.as_str()),
+                };
+                print_json(&record);
+            }
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Record<'a> {
+    /// Position of the result among every result of the run.
+    seq: u64,
+    timestamp: String,
+    /// Time since the start of the run, from the monotonic clock.
+    elapsed_ms: f64,
+    probe: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    name: Option<&'a str>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    labels: &'a BTreeMap<String, String>,
+    target: &'a str,
+    worker: usize,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    warmup: bool,
+    outcome: &'static str,
+    latency_ms: f64,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    phases_ms: BTreeMap<&'static str, f64>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    details: BTreeMap<&'static str, &'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error_kind: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error_message: Option<&'a str>,
+}
+
+/// Records the result of a probe attempt.
+pub(crate) fn report(origin: &Origin, attempt: &Attempt) {
+    metrics::observe(origin, attempt);
+    stats::record(origin, attempt);
+    api::observe(origin, attempt);
+    otlp::observe(origin, attempt);
+    export::observe(origin, attempt);
+    store::observe(origin, attempt);
+    alert::observe(origin, attempt);
+    agent::observe(origin, attempt);
+    tui::observe(origin, attempt);
+    let seq = print(origin, attempt, false);
+    pcap::observe(origin, attempt, seq);
+}
+
+/// Records ticks a worker skipped rather than make an attempt on.
+pub(crate) fn skipped(origin: &Origin, ticks: u64) {
+    metrics::skipped(origin, ticks);
+    stats::skipped(origin, ticks);
+    debug!(
+        "{} skipped {} ticks with attempts in flight. target={} worker={}",
+        origin.probe, ticks, origin.target, origin.worker
+    );
+}
+
+/// Prints the result of a warmup attempt, without recording it anywhere else.
+pub(crate) fn warmup(origin: &Origin, attempt: &Attempt) {
+    print(origin, attempt, true);
+}
+
+/// Prints the result of an attempt, returning the position it was given among every result.
+fn print(origin: &Origin, attempt: &Attempt, warmup: bool) -> u64 {
+    let seq = RESULTS.fetch_add(1, Ordering::Relaxed);
+    // The dashboard covers the terminal in place of reporting each attempt.
+    if tui::enabled() {
+        return seq;
+    }
+    let elapsed = STARTED.get_or_init(Instant::now).elapsed();
+    match OUTPUT.get().copied().unwrap_or_default() {
+        Output::Text => log(origin, attempt, warmup, seq, elapsed),
+        Output::Json => {
+            let record = Record {
+                seq,
+                timestamp: humantime::format_rfc3339_micros(SystemTime::now()).to_string(),
+                elapsed_ms: ms(elapsed),
+                probe: origin.probe,
+                name: origin.name.as_deref(),
+                labels: &origin.labels,
+                target: &origin.target,
+                worker: origin.worker,
+                warmup,
+                outcome: if attempt.error.is_none() {
+                    "success"
+                } else {
+                    "failure"
+                },
+                latency_ms: ms(attempt.duration),
+                phases_ms: attempt
+                    .phases
+                    .iter()
+                    .map(|(name, duration)| (*name, ms(*duration)))
+                    .collect(),
+                details: attempt
+                    .details
+                    .iter()
+                    .map(|(name, value)| (*name, value.as_str()))
+                    .collect(),
+                error_kind: attempt.error.as_ref().map(|e| e.kind),
+                error_message: attempt.error.as_ref().map(|e| e.message.as_str()),
+            };
+            print_json(&record);
+        }
+    }
+    seq
+}
+
+/// Prints a summary of every attempt made during the run.
+pub(crate) fn summary(summaries: &[stats::Summary]) {
+    // Leave the dashboard so the summary ends up on the normal screen.
+    tui::stop();
+
+    let comparisons = ab::comparisons();
+    match OUTPUT.get().copied().unwrap_or_default() {
+        Output::Text => {
+            println!("--- artemiss summary ---");
+            for summary in summaries {
+                println!("{}", summary_line(summary));
+            }
+            for comparison in &comparisons {
+                println!("{}", comparison_line(comparison));
+            }
+        }
+        Output::Json => {
+            #[derive(Serialize)]
+            struct Record<'a> {
+                summary: &'a [stats::Summary],
+                #[serde(skip_serializing_if = "<[_]>::is_empty")]
+                comparisons: &'a [ab::Comparison],
+            }
+            let record = Record {
+                summary: summaries,
+                comparisons: &comparisons,
+            };
+            print_json(&record);
+        }
+    }
+}
+
+/// Describes the paired differences between two targets on one line.
+fn comparison_line(comparison: &ab::Comparison) -> String {
+    let significance = |significant: Option<bool>| match significant {
+        Some(true) => "significant",
+        Some(false) => "not significant",
+        None => "too few to tell",
+    };
+    let mut line = format!("{} ", comparison.probe);
+    if let Some(name) = &comparison.name {
+        let _ = write!(line, "{} ", name);
+    }
+    let _ = write!(
+        line,
+        "a={} b={}: pairs={} errors a={:.2}% b={:.2}% ({})",
+        comparison.a,
+        comparison.b,
+        comparison.pairs,
+        comparison.a_error_rate,
+        comparison.b_error_rate,
+        significance(comparison.error_rate_significant)
+    );
+    if let (Some(a), Some(b)) = (comparison.a_mean_ms, comparison.b_mean_ms) {
+        let _ = write!(line, " mean a={:.3}ms b={:.3}ms", a, b);
+    }
+    if let Some(difference) = &comparison.difference_ms {
+        let _ = write!(
+            line,
+            " b-a mean={:+.3}ms p50={:+.3}ms p90={:+.3}ms t={:.2} ({})",
+            difference.mean,
+            difference.p50,
+            difference.p90,
+            difference.t,
+            significance(difference.significant)
+        );
+    }
+    secret::redact(&line).into_owned()
+}
+
+/// Describes the statistics of a target on one line.
+fn summary_line(summary: &stats::Summary) -> String {
+    let mut line = format!("{} ", summary.probe);
+    if let Some(name) = &summary.name {
+        let _ = write!(line, "{} ", name);
+    }
+    write_labels(&mut line, &summary.labels);
+    let _ = write!(
+        line,
+        "{}: attempts={} failures={} success={:.2}%",
+        summary.target, summary.attempts, summary.failures, summary.success_rate
+    );
+    if let Some(latency) = &summary.latency_ms {
+        let _ = write!(
+            line,
+            " p50={:.3}ms p90={:.3}ms p95={:.3}ms p99={:.3}ms p99.9={:.3}ms max={:.3}ms",
+            latency.p50, latency.p90, latency.p95, latency.p99, latency.p999, latency.max
+        );
+    }
+    if summary.recovered > 0 {
+        let _ = write!(line, " recovered={}", summary.recovered);
+    }
+    if summary.skipped > 0 {
+        let _ = write!(line, " skipped={}", summary.skipped);
+    }
+    for (kind, count) in &summary.errors {
+        let _ = write!(line, " {}={}", kind, count);
+    }
+    match &summary.health {
+        Some(health) if health.flapping => {
+            let _ = write!(
+                line,
+                " flapping transitions={} {}",
+                health.transitions, health.state
+            );
+        }
+        Some(health) if health.state == "unhealthy" => line.push_str(" unhealthy"),
+        _ => {}
+    }
+    // Only worth breaking down when both families were used, or one had to stand in for the other.
+    let fell_back = summary.families.values().any(|family| family.fallbacks > 0);
+    if summary.families.len() > 1 || fell_back {
+        for (name, family) in &summary.families {
+            let _ = write!(
+                line,
+                " [{} attempts={} failures={}",
+                name, family.attempts, family.failures
+            );
+            if let Some(latency) = &family.latency_ms {
+                let _ = write!(line, " p50={:.3}ms p99={:.3}ms", latency.p50, latency.p99);
+            }
+            if family.fallbacks > 0 {
+                let _ = write!(line, " fallbacks={}", family.fallbacks);
+            }
+            line.push(']');
+        }
+    }
+    // Only worth breaking down when requests failed, to tell which connections they failed on.
+    if summary
+        .connections
+        .values()
+        .any(|connection| connection.failures > 0)
+    {
+        for (name, connection) in &summary.connections {
+            let _ = write!(
+                line,
+                " [{} attempts={} failures={} dropped={}]",
+                name, connection.attempts, connection.failures, connection.dropped
+            );
+        }
+    }
+    secret::redact(&line).into_owned()
+}
+
+/// Waits for reported attempts to be exported before exiting.
+pub(crate) async fn flush() {
+    export::shutdown();
+    store::shutdown();
+    alert::flush().await;
+    pcap::flush().await;
+    agent::flush().await;
+    otlp::shutdown().await;
+}
+
+fn log(origin: &Origin, attempt: &Attempt, warmup: bool, seq: u64, elapsed: Duration) {
+    let mut fields = format!("seq={} elapsed={:.3}ms ", seq, ms(elapsed));
+    if let Some(name) = &origin.name {
+        let _ = write!(fields, "name={} ", name);
+    }
+    write_labels(&mut fields, &origin.labels);
+    let _ = write!(
+        fields,
+        "target={} worker={} latency={:.3}ms",
+        origin.target,
+        origin.worker,
+        ms(attempt.duration)
+    );
+    for (name, duration) in &attempt.phases {
+        let _ = write!(fields, " {}={:.3}ms", name, ms(*duration));
+    }
+    for (name, value) in &attempt.details {
+        let _ = write!(fields, " {}={}", name, value);
+    }
+
+    // Warmup failures are expected, so they are not logged as errors.
+    let (level, message) = match &attempt.error {
+        None if warmup => (
+            Level::Debug,
+            format!("{} warmup successful. {}", origin.probe, fields),
+        ),
+        Some(e) if warmup => (
+            Level::Warn,
+            format!(
+                "{} warmup {} error: {}. {}",
+                origin.probe, e.kind, e.message, fields
+            ),
+        ),
+        None => (
+            Level::Debug,
+            format!("{} successful. {}", origin.probe, fields),
+        ),
+        Some(e) => (
+            Level::Error,
+            format!(
+                "{} {} error: {}. {}",
+                origin.probe, e.kind, e.message, fields
+            ),
+        ),
+    };
+    // The fields are also given apart for sinks that keep them structured.
+    log!(
+        level,
+        probe = origin.probe,
+        name = origin.name.as_deref().unwrap_or_default(),
+        target = origin.target.as_str(),
+        worker = origin.worker,
+        seq = seq,
+        latency_ms = ms(attempt.duration),
+        error_kind = attempt.error.as_ref().map_or("", |e| e.kind);
+        "{}",
+        message
+    );
+}
+
+/// Prints a record as a line of JSON, with any secrets in it redacted.
+fn print_json(record: &impl Serialize) {
+    let json = serde_json::to_string(record).unwrap();
+    println!("{}", secret::redact(&json));
+}
+
+/// Writes labels out as fields of a log line, each followed by a space.
+pub(crate) fn write_labels(fields: &mut String, labels: &BTreeMap<String, String>) {
+    for (key, value) in labels {
+        let _ = write!(fields, "label.{}={} ", key, value);
+    }
+}
+
+/// Milliseconds with microsecond precision.
+fn ms(duration: Duration) -> f64 {
+    duration.as_micros() as f64 / 1000.0
+}
+
+/// Strips passwords from a connection string so it can be used as a target label.
+pub(crate) fn redact(target: &str) -> String {
+    if let Ok(mut url) = Url::parse(target) {
+        if url.password().is_some() {
+            let _ = url.set_password(Some("***"));
+        }
+        return url.to_string();
+    }
+
+    // Key-value connection strings, e.g. `host=localhost password=secret`.
+    target
+        .split_whitespace()
+        .map(|pair| match pair.split_once('=') {
+            Some((key, _)) if key.eq_ignore_ascii_case("password") => format!("{}=***", key),
+            _ => pair.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
@@ -0,0 +1,401 @@
+use std::{
+    io,
+    net::{IpAddr, SocketAddr},
+    sync::OnceLock,
+    time::Duration,
+};
+
+use clap::Args;
+use hickory_resolver::{
+    config::{NameServerConfigGroup, ResolverConfig, ResolverOpts},
+    TokioAsyncResolver,
+};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    net::{self, TcpStream},
+    task::JoinSet,
+    time,
+};
+
+use crate::{http::one_or_many, socket};
+
+/// Time to wait for a connection to an address before racing one to the next address, as
+/// recommended for Happy Eyeballs by RFC 8305.
+const ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
+/// Address family every connection is restricted to, if any.
+static FAMILY: OnceLock<Option<Family>> = OnceLock::new();
+
+/// Options restricting connections to one address family.
+#[derive(Args, Debug, Default, Serialize, Deserialize)]
+pub struct FamilyArgs {
+    /// Only connect to IPv4 addresses.
+    #[arg(long, global = true, conflicts_with = "ipv6")]
+    ipv4: bool,
+
+    /// Only connect to IPv6 addresses.
+    #[arg(long, global = true)]
+    ipv6: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Family {
+    V4,
+    V6,
+}
+
+impl Family {
+    fn of(addr: &SocketAddr) -> Self {
+        match addr {
+            SocketAddr::V4(_) => Family::V4,
+            SocketAddr::V6(_) => Family::V6,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Family::V4 => "ipv4",
+            Family::V6 => "ipv6",
+        }
+    }
+}
+
+/// Restricts connections to the address family chosen, if any.
+pub(crate) fn init(args: FamilyArgs) {
+    let family = match (args.ipv4, args.ipv6) {
+        (true, _) => Some(Family::V4),
+        (_, true) => Some(Family::V6),
+        _ => None,
+    };
+    FAMILY.set(family).expect("address family already set");
+}
+
+fn family() -> Option<Family> {
+    FAMILY.get().copied().flatten()
+}
+
+/// Whether connections are restricted to one address family with `--ipv4` or `--ipv6`.
+pub fn is_restricted() -> bool {
+    family().is_some()
+}
+
+/// Whether connecting to the address is allowed by the address family chosen, if any.
+pub fn allows(ip: IpAddr) -> bool {
+    family().is_none_or(|family| family == Family::of(&SocketAddr::new(ip, 0)))
+}
+
+/// Refuses to run a probe whose client library resolves hosts itself, so that its connections
+/// cannot be restricted to the address family chosen, if any.
+pub fn ensure_unrestricted(probe: &str, reason: &str) {
+    if let Some(family) = family() {
+        panic!(
+            "--{} is not supported by {}, as {}",
+            family.name(),
+            probe,
+            reason
+        );
+    }
+}
+
+/// Resolves a host to the first of its addresses that may be connected to, for a client library
+/// to connect to in place of the host.
+pub async fn first_addr(host: &str, port: u16) -> io::Result<SocketAddr> {
+    Resolver::default()
+        .lookup(host, port)
+        .await?
+        .into_iter()
+        .next()
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{} has no addresses", host),
+            )
+        })
+}
+
+/// A TCP connection, and which address family it was made over.
+pub struct Connection {
+    pub stream: TcpStream,
+    pub family: &'static str,
+    /// Family of the first address tried, if the connection had to fall back from it to the
+    /// other family.
+    pub fallback_from: Option<&'static str>,
+}
+
+impl Connection {
+    /// Details describing the socket addresses and address family of the connection.
+    pub fn details(&self) -> Vec<(&'static str, String)> {
+        let mut details = vec![];
+        if let Ok(addr) = self.stream.local_addr() {
+            details.push(("local_addr", addr.to_string()));
+            details.push(("local_port", addr.port().to_string()));
+        }
+        if let Ok(addr) = self.stream.peer_addr() {
+            details.push(("remote_addr", addr.to_string()));
+        }
+        details.push(("family", self.family.to_string()));
+        if let Some(family) = self.fallback_from {
+            details.push(("fallback_from", family.to_string()));
+        }
+        details
+    }
+}
+
+/// Connects to the host with the system resolver, racing its addresses with Happy Eyeballs.
+pub async fn connect(host: &str, port: u16) -> io::Result<Connection> {
+    Resolver::default().connect(host, port).await
+}
+
+/// Connects to one of the addresses, preferring the first, and starting a connection to the next
+/// address, alternating between families, whenever the last has not connected within the attempt
+/// delay. The first connection made wins.
+pub async fn connect_any(addrs: &[SocketAddr]) -> io::Result<Connection> {
+    let Some(first) = addrs.first() else {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            "no addresses resolved",
+        ));
+    };
+    let preferred = Family::of(first);
+    let (same, other): (Vec<_>, Vec<_>) = addrs
+        .iter()
+        .copied()
+        .partition(|addr| Family::of(addr) == preferred);
+    let mut order = vec![];
+    let (mut same, mut other) = (same.into_iter(), other.into_iter());
+    loop {
+        match (same.next(), other.next()) {
+            (None, None) => break,
+            (a, b) => order.extend(a.into_iter().chain(b)),
+        }
+    }
+
+    let mut remaining = order.into_iter();
+    let mut attempts = JoinSet::new();
+    let mut last_err = None;
+    loop {
+        if attempts.is_empty() {
+            match remaining.next() {
+                Some(addr) => {
+                    attempts.spawn(async move { (addr, socket::connect(addr).await) });
+                }
+                None => return Err(last_err.expect("an attempt was made")),
+            }
+        }
+
+        let delay = time::sleep(ATTEMPT_DELAY);
+        tokio::select! {
+            Some(joined) = attempts.join_next() => {
+                let (addr, result) = joined.map_err(io::Error::other)?;
+                match result {
+                    Ok(stream) => {
+                        let family = Family::of(&addr);
+                        return Ok(Connection {
+                            stream,
+                            family: family.name(),
+                            fallback_from: (family != preferred).then(|| preferred.name()),
+                        });
+                    }
+                    Err(e) => {
+                        last_err = Some(e);
+                        // Move on to the next address straight away rather than after the delay.
+                        if let Some(addr) = remaining.next() {
+                            attempts.spawn(async move { (addr, socket::connect(addr).await) });
+                        }
+                    }
+                }
+            }
+            _ = delay => {
+                if let Some(addr) = remaining.next() {
+                    attempts.spawn(async move { (addr, socket::connect(addr).await) });
+                }
+            }
+        }
+    }
+}
+
+/// Options for controlling how hosts are resolved to addresses.
+#[derive(Args, Debug, Default, Serialize, Deserialize)]
+pub struct ResolveArgs {
+    /// Resolve a host and port to these addresses instead of looking them up, as
+    /// `host:port:addr[,addr]` like curl, e.g. `example.com:443:10.0.0.1`. The port can be `*` to
+    /// match any. Can be repeated.
+    #[arg(long)]
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub resolve: Vec<String>,
+
+    /// Look hosts up with this nameserver, as `ip` or `ip:port`, instead of the system resolver.
+    #[arg(long)]
+    pub dns_server: Option<String>,
+}
+
+/// Resolves hosts to addresses, preferring static overrides, then a custom nameserver, then the
+/// system resolver, keeping only addresses of the family chosen with `--ipv4` or `--ipv6`.
+#[derive(Default)]
+pub struct Resolver {
+    overrides: Vec<Override>,
+    nameserver: Option<TokioAsyncResolver>,
+    /// Host resolved to a single instance whatever the port, taking precedence over the rest.
+    pinned: Option<(String, SocketAddr)>,
+}
+
+struct Override {
+    host: String,
+    /// Port the override applies to, or any port if `None`.
+    port: Option<u16>,
+    addrs: Vec<IpAddr>,
+}
+
+impl Resolver {
+    pub fn new(args: &ResolveArgs) -> Self {
+        let overrides = args
+            .resolve
+            .iter()
+            .map(|entry| parse_override(entry))
+            .collect();
+
+        let nameserver = args.dns_server.as_deref().map(|nameserver| {
+            let addr = parse_nameserver(nameserver);
+            let group = NameServerConfigGroup::from_ips_clear(&[addr.ip()], addr.port(), true);
+            let config = ResolverConfig::from_parts(None, vec![], group);
+            // Every lookup should reach the nameserver, so that its latency is measured.
+            let mut opts = ResolverOpts::default();
+            opts.cache_size = 0;
+            opts.use_hosts_file = false;
+            TokioAsyncResolver::tokio(config, opts)
+        });
+
+        Resolver {
+            overrides,
+            nameserver,
+            pinned: None,
+        }
+    }
+
+    /// Resolves the host to the address of an instance behind it on any port, e.g. one found
+    /// with `--discover`.
+    pub(crate) fn pin(mut self, host: &str, addr: SocketAddr) -> Self {
+        self.pinned = Some((host.to_string(), addr));
+        self
+    }
+
+    /// Looks up the SRV records of a name, as the host and port of each target, with the custom
+    /// nameserver if there is one.
+    pub(crate) async fn srv(&self, name: &str) -> io::Result<Vec<(String, u16)>> {
+        let system;
+        let resolver = match &self.nameserver {
+            Some(resolver) => resolver,
+            None => {
+                system = TokioAsyncResolver::tokio_from_system_conf()?;
+                &system
+            }
+        };
+        let lookup = resolver.srv_lookup(name).await.map_err(io::Error::other)?;
+        Ok(lookup
+            .iter()
+            .map(|srv| {
+                let host = srv.target().to_utf8();
+                (host.trim_end_matches('.').to_string(), srv.port())
+            })
+            .collect())
+    }
+
+    /// Resolves a host to the addresses to connect to on the port.
+    pub async fn lookup(&self, host: &str, port: u16) -> io::Result<Vec<SocketAddr>> {
+        let mut addrs = self.lookup_any(host, port).await?;
+        if let Some(family) = family() {
+            addrs.retain(|addr| Family::of(addr) == family);
+            if addrs.is_empty() {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("{} has no {} addresses", host, family.name()),
+                ));
+            }
+        }
+        if let Some(bind) = socket::bind_addr() {
+            addrs.retain(|addr| addr.is_ipv4() == bind.is_ipv4());
+            if addrs.is_empty() {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("{} has no addresses of the family of {}", host, bind),
+                ));
+            }
+        }
+        Ok(addrs)
+    }
+
+    /// Resolves a host and connects to it, racing its addresses with Happy Eyeballs.
+    pub async fn connect(&self, host: &str, port: u16) -> io::Result<Connection> {
+        connect_any(&self.lookup(host, port).await?).await
+    }
+
+    async fn lookup_any(&self, host: &str, port: u16) -> io::Result<Vec<SocketAddr>> {
+        let pinned = self.pinned.as_ref();
+        if let Some((_, addr)) = pinned.filter(|(pinned, _)| pinned.eq_ignore_ascii_case(host)) {
+            return Ok(vec![*addr]);
+        }
+        if let Ok(ip) = host.parse::<IpAddr>() {
+            return Ok(vec![SocketAddr::new(ip, port)]);
+        }
+
+        let found = self.overrides.iter().find(|entry| {
+            entry.host.eq_ignore_ascii_case(host) && entry.port.is_none_or(|p| p == port)
+        });
+        if let Some(entry) = found {
+            return Ok(entry
+                .addrs
+                .iter()
+                .map(|ip| SocketAddr::new(*ip, port))
+                .collect());
+        }
+
+        match &self.nameserver {
+            Some(resolver) => {
+                let lookup = resolver.lookup_ip(host).await.map_err(io::Error::other)?;
+                Ok(lookup.iter().map(|ip| SocketAddr::new(ip, port)).collect())
+            }
+            None => Ok(net::lookup_host((host, port)).await?.collect()),
+        }
+    }
+}
+
+/// Parses a nameserver address like `10.0.0.2` or `10.0.0.2:5353`, defaulting to port 53.
+pub fn parse_nameserver(nameserver: &str) -> SocketAddr {
+    nameserver
+        .parse::<SocketAddr>()
+        .or_else(|_| {
+            nameserver
+                .parse::<IpAddr>()
+                .map(|ip| SocketAddr::new(ip, 53))
+        })
+        .expect("invalid nameserver address")
+}
+
+/// Parses an override like `example.com:443:10.0.0.1,[::1]`.
+fn parse_override(entry: &str) -> Override {
+    let mut parts = entry.splitn(3, ':');
+    let (Some(host), Some(port), Some(addrs)) = (parts.next(), parts.next(), parts.next()) else {
+        panic!("--resolve must be `host:port:addr`, not {:?}", entry);
+    };
+
+    let port = match port {
+        "*" => None,
+        port => Some(port.parse().expect("invalid port in --resolve")),
+    };
+    let addrs = addrs
+        .split(',')
+        .map(|addr| {
+            addr.trim()
+                .trim_start_matches('[')
+                .trim_end_matches(']')
+                .parse()
+                .expect("invalid address in --resolve")
+        })
+        .collect();
+
+    Override {
+        host: host.to_string(),
+        port,
+        addrs,
+    }
+}
@@ -0,0 +1,489 @@
+use std::{
+    env,
+    error::Error,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use chrono::Utc;
+use clap::{Parser, ValueEnum};
+use hyper::{
+    body::Bytes,
+    header::{HeaderValue, HOST},
+    Body, Client, Method, Request, StatusCode, Uri,
+};
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use ring::{digest, hmac};
+use serde::{Deserialize, Serialize};
+use tokio::time;
+
+use crate::{
+    http::{ConnectTimeout, ConnectionInfo, HttpVersion, Proxy, TimingConnector},
+    probe::{self, CommonArgs, Probe},
+    report::{Attempt, Origin, ProbeError},
+    resolve::{ResolveArgs, Resolver},
+    tls::TlsArgs,
+};
+
+/// Characters left as they are in the paths signed, besides `/` between segments.
+const UNRESERVED: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+/// Request made against the bucket on every attempt, in the order listed here.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Operation {
+    /// `HEAD` the bucket, checking that it exists and can be accessed.
+    HeadBucket,
+    /// `PUT` a small object to `--key`.
+    Put,
+    /// `GET` `--key`, checking it matches what was put if `put` is also made.
+    Get,
+}
+
+impl Operation {
+    fn name(self) -> &'static str {
+        match self {
+            Operation::HeadBucket => "head_bucket",
+            Operation::Put => "put",
+            Operation::Get => "get",
+        }
+    }
+
+    fn status_detail(self) -> &'static str {
+        match self {
+            Operation::HeadBucket => "head_bucket_status",
+            Operation::Put => "put_status",
+            Operation::Get => "get_status",
+        }
+    }
+}
+
+#[derive(Parser, Debug, Serialize, Deserialize)]
+pub struct S3Args {
+    /// Endpoint of the S3-compatible service, e.g. `https://minio.internal:9000`. Defaults to
+    /// `AWS_ENDPOINT_URL_S3` or `AWS_ENDPOINT_URL` from the environment, or else the AWS endpoint
+    /// of the region.
+    #[arg(long)]
+    endpoint: Option<String>,
+
+    /// Region to sign requests for. Defaults to `AWS_REGION` or `AWS_DEFAULT_REGION` from the
+    /// environment, or else `us-east-1`.
+    #[arg(long)]
+    region: Option<String>,
+
+    /// Bucket to make requests against.
+    #[arg(long)]
+    bucket: String,
+
+    /// Key of the object to put and get.
+    #[arg(long, default_value = "artemiss/canary")]
+    key: String,
+
+    /// Requests to make on every attempt. Can be repeated or separated by commas.
+    #[arg(long, value_enum, value_delimiter = ',', default_values_t = [Operation::HeadBucket])]
+    #[serde(default)]
+    operation: Vec<Operation>,
+
+    /// Size in bytes of the object to put.
+    #[arg(long, default_value_t = 1024)]
+    object_size: usize,
+
+    /// Address the bucket in the path of the endpoint, as most S3-compatible services expect,
+    /// rather than as a subdomain of it.
+    #[arg(long)]
+    path_style: bool,
+
+    /// Set a timeout for only the connect phase of a `Client`.
+    #[arg(long, default_value_t = 1000)]
+    connect_timeout_ms: u64,
+
+    /// Set a timeout for all the requests of an attempt.
+    #[arg(long, default_value_t = 5000)]
+    timeout_ms: u64,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    tls: TlsArgs,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    resolve: ResolveArgs,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    pub common: CommonArgs,
+}
+
+/// Credentials requests are signed with, from the environment as the AWS tools read them.
+pub(crate) struct Credentials {
+    access_key_id: String,
+    secret_access_key: String,
+    session_token: Option<String>,
+}
+
+impl Credentials {
+    pub(crate) fn from_env() -> Self {
+        let var = |name| {
+            env::var(name)
+                .ok()
+                .filter(|value: &String| !value.is_empty())
+        };
+        Credentials {
+            access_key_id: var("AWS_ACCESS_KEY_ID").expect("AWS_ACCESS_KEY_ID is not set"),
+            secret_access_key: var("AWS_SECRET_ACCESS_KEY")
+                .expect("AWS_SECRET_ACCESS_KEY is not set"),
+            session_token: var("AWS_SESSION_TOKEN"),
+        }
+    }
+}
+
+/// Makes the requests chosen against a bucket on every attempt, signed with Signature Version 4,
+/// over a new connection for every attempt.
+pub struct S3Probe {
+    connectors: Vec<TimingConnector>,
+    scheme: String,
+    /// Host, and port if not the default, of the bucket with virtual-hosted-style requests.
+    authority: String,
+    /// Path of the bucket, which is empty with virtual-hosted-style requests.
+    bucket_path: String,
+    key: String,
+    region: String,
+    credentials: Credentials,
+    operations: Vec<Operation>,
+    object: Bytes,
+    target: String,
+    timeout: Duration,
+}
+
+impl S3Probe {
+    pub fn new(args: &S3Args) -> Self {
+        let var = |name| {
+            env::var(name)
+                .ok()
+                .filter(|value: &String| !value.is_empty())
+        };
+        let region = args
+            .region
+            .clone()
+            .or_else(|| var("AWS_REGION"))
+            .or_else(|| var("AWS_DEFAULT_REGION"))
+            .unwrap_or_else(|| "us-east-1".to_string());
+        let endpoint = args
+            .endpoint
+            .clone()
+            .or_else(|| var("AWS_ENDPOINT_URL_S3"))
+            .or_else(|| var("AWS_ENDPOINT_URL"))
+            .unwrap_or_else(|| format!("https://s3.{}.amazonaws.com", region));
+        let endpoint: Uri = endpoint.parse().expect("invalid s3 endpoint");
+        let scheme = endpoint.scheme_str().unwrap_or("https").to_string();
+        let host = endpoint
+            .authority()
+            .expect("s3 endpoint has no host")
+            .as_str();
+        let bucket = encode(&args.bucket);
+        let (authority, bucket_path) = if args.path_style {
+            (host.to_string(), format!("/{}", bucket))
+        } else {
+            (format!("{}.{}", bucket, host), String::new())
+        };
+
+        let mut operations = args.operation.clone();
+        operations.sort_unstable();
+        operations.dedup();
+        assert!(!operations.is_empty(), "no --operation to make");
+
+        let uri: Uri = format!("{}://{}/", scheme, authority)
+            .parse()
+            .expect("invalid s3 bucket");
+        let proxy = Proxy::for_uri(&uri, None, None, &[]);
+        let resolver = Arc::new(Resolver::new(&args.resolve));
+        let target = format!("{}://{}{}", scheme, authority, bucket_path);
+        // Create a connector for every worker so that their connections are reported apart.
+        let connectors = (0..args.common.parallel)
+            .map(|worker| {
+                let origin = Origin {
+                    probe: "s3",
+                    name: args.common.name.clone(),
+                    labels: args.common.labels(),
+                    target: target.clone(),
+                    worker,
+                };
+                TimingConnector::new(
+                    Duration::from_millis(args.connect_timeout_ms),
+                    &args.tls,
+                    HttpVersion::Http1,
+                    proxy.clone(),
+                    resolver.clone(),
+                    origin,
+                )
+            })
+            .collect();
+
+        S3Probe {
+            connectors,
+            scheme,
+            authority,
+            bucket_path,
+            key: args
+                .key
+                .split('/')
+                .map(encode)
+                .collect::<Vec<_>>()
+                .join("/"),
+            region,
+            credentials: Credentials::from_env(),
+            operations,
+            object: Bytes::from(vec![b'a'; args.object_size]),
+            target,
+            timeout: Duration::from_millis(args.timeout_ms),
+        }
+    }
+
+    /// Builds the signed request for an operation.
+    fn request(&self, operation: Operation) -> Request<Body> {
+        let (method, path, body) = match operation {
+            Operation::HeadBucket => (Method::HEAD, format!("{}/", self.bucket_path), Bytes::new()),
+            Operation::Put => (
+                Method::PUT,
+                format!("{}/{}", self.bucket_path, self.key),
+                self.object.clone(),
+            ),
+            Operation::Get => (
+                Method::GET,
+                format!("{}/{}", self.bucket_path, self.key),
+                Bytes::new(),
+            ),
+        };
+        // Buckets are addressed without the trailing slash in the path style.
+        let path = match path.strip_suffix('/') {
+            Some(bucket) if !bucket.is_empty() => bucket.to_string(),
+            _ => path,
+        };
+
+        let mut headers = vec![("host", self.authority.clone())];
+        sign_v4(
+            &self.credentials,
+            "s3",
+            &self.region,
+            &method,
+            &path,
+            &mut headers,
+            &body,
+        );
+
+        let mut req = Request::new(Body::from(body));
+        *req.method_mut() = method;
+        *req.uri_mut() = format!("{}://{}{}", self.scheme, self.authority, path)
+            .parse()
+            .expect("invalid s3 key");
+        let values = req.headers_mut();
+        for (name, value) in headers {
+            let value = HeaderValue::try_from(value).expect("invalid s3 header");
+            match name {
+                "host" => values.insert(HOST, value),
+                name => values.insert(name, value),
+            };
+        }
+        req
+    }
+}
+
+impl Probe for S3Probe {
+    fn kind(&self) -> &'static str {
+        "s3"
+    }
+
+    fn target(&self) -> String {
+        self.target.clone()
+    }
+
+    async fn attempt(&self, worker: usize) -> Attempt {
+        // Operations of an attempt share a connection, which is closed along with the client.
+        let connector = self.connectors[worker % self.connectors.len()].clone();
+        let client = Client::builder().build::<_, Body>(connector);
+        let mut phases = vec![];
+        let mut details = vec![];
+
+        let start = Instant::now();
+        let operations = async {
+            let mut put = false;
+            for &operation in &self.operations {
+                let sent = Instant::now();
+                let res = client.request(self.request(operation)).await?;
+                let status = res.status();
+                let info = res.extensions().get::<ConnectionInfo>().cloned();
+                let body = hyper::body::to_bytes(res.into_body()).await?;
+
+                let mut ready = sent;
+                if let Some(info) = info.filter(ConnectionInfo::first_use) {
+                    phases.push(("dns", info.dns));
+                    phases.push(("connect", info.connect));
+                    if let Some(tunnel) = info.tunnel {
+                        phases.push(("tunnel", tunnel));
+                    }
+                    if let Some(tls) = info.tls {
+                        phases.push(("tls", tls));
+                    }
+                    if let Some(addr) = info.lifecycle.remote_addr {
+                        details.push(("remote_addr", addr.to_string()));
+                    }
+                    details.push(("family", info.family.to_string()));
+                    ready = ready.max(info.established_at);
+                }
+                phases.push((operation.name(), ready.elapsed()));
+                details.push((operation.status_detail(), status.as_u16().to_string()));
+
+                if !status.is_success() {
+                    let (code, message) = error_code(status, &body);
+                    let error = ProbeError::new(
+                        "s3_error",
+                        format!("{} failed with {}: {}", operation.name(), status, message),
+                    );
+                    details.push(("error_code", code));
+                    return Ok(Some(error));
+                }
+                match operation {
+                    Operation::Put => put = true,
+                    Operation::Get if put && body != self.object => {
+                        return Ok(Some(ProbeError::new(
+                            "body_mismatch",
+                            format!(
+                                "got {} bytes that differ from the {} bytes put",
+                                body.len(),
+                                self.object.len()
+                            ),
+                        )));
+                    }
+                    _ => {}
+                }
+            }
+            Ok::<_, hyper::Error>(None)
+        };
+
+        let error = match time::timeout(self.timeout, operations).await {
+            Ok(Ok(error)) => error,
+            Ok(Err(e)) => {
+                let kind = if !e.is_connect() {
+                    "request"
+                } else if e.source().is_some_and(|e| e.is::<ConnectTimeout>()) {
+                    "connect_timeout"
+                } else {
+                    "connect"
+                };
+                Some(ProbeError::from_cause(kind, &e))
+            }
+            Err(_) => Some(ProbeError::new(
+                "request_timeout",
+                format!("timed out after {}ms", self.timeout.as_millis()),
+            )),
+        };
+
+        Attempt {
+            duration: start.elapsed(),
+            phases,
+            details,
+            error,
+        }
+    }
+}
+
+pub async fn s3_main(args: S3Args) {
+    probe::run(S3Probe::new(&args), &args.common).await
+}
+
+/// The error code and message of a failed response, from its XML body, or else from its status
+/// for responses to `HEAD` requests, which have none.
+fn error_code(status: StatusCode, body: &[u8]) -> (String, String) {
+    let body = String::from_utf8_lossy(body);
+    let element = |name: &str| {
+        let start = body.find(&format!("<{}>", name))? + name.len() + 2;
+        let end = body[start..].find(&format!("</{}>", name))?;
+        Some(body[start..start + end].to_string())
+    };
+    let code = element("Code").unwrap_or_else(|| match status {
+        StatusCode::NOT_FOUND => "NotFound".to_string(),
+        StatusCode::FORBIDDEN => "Forbidden".to_string(),
+        status => status.as_u16().to_string(),
+    });
+    let message = match element("Message") {
+        Some(message) => format!("{} {}", code, message),
+        None => code.clone(),
+    };
+    (code, message)
+}
+
+/// Signs a request to an AWS service with Signature Version 4, adding the headers it is signed
+/// with, and the `authorization` header, to the headers given, which include `host`.
+pub(crate) fn sign_v4(
+    credentials: &Credentials,
+    service: &str,
+    region: &str,
+    method: &Method,
+    path: &str,
+    headers: &mut Vec<(&'static str, String)>,
+    body: &[u8],
+) {
+    let now = Utc::now();
+    let date = now.format("%Y%m%d").to_string();
+    let timestamp = now.format("%Y%m%dT%H%M%SZ").to_string();
+    let payload_hash = hex(digest::digest(&digest::SHA256, body).as_ref());
+
+    headers.push(("x-amz-content-sha256", payload_hash.clone()));
+    headers.push(("x-amz-date", timestamp.clone()));
+    if let Some(token) = &credentials.session_token {
+        headers.push(("x-amz-security-token", token.clone()));
+    }
+    // Headers are signed in order of their names.
+    headers.sort_by_key(|(name, _)| *name);
+    let signed_headers = headers
+        .iter()
+        .map(|(name, _)| *name)
+        .collect::<Vec<_>>()
+        .join(";");
+    let canonical_headers: String = headers
+        .iter()
+        .map(|(name, value)| format!("{}:{}\n", name, value.trim()))
+        .collect();
+    let canonical_request = format!(
+        "{}\n{}\n\n{}\n{}\n{}",
+        method, path, canonical_headers, signed_headers, payload_hash
+    );
+
+    let scope = format!("{}/{}/{}/aws4_request", date, region, service);
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+        timestamp,
+        scope,
+        hex(digest::digest(&digest::SHA256, canonical_request.as_bytes()).as_ref())
+    );
+    let secret = format!("AWS4{}", credentials.secret_access_key);
+    let key = [date.as_str(), region, service, "aws4_request"]
+        .iter()
+        .fold(secret.into_bytes(), |key, part| sign(&key, part.as_bytes()));
+    let signature = hex(&sign(&key, string_to_sign.as_bytes()));
+    headers.push((
+        "authorization",
+        format!(
+            "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+            credentials.access_key_id, scope, signed_headers, signature
+        ),
+    ));
+}
+
+/// Encodes a segment of a path the way it is signed.
+fn encode(segment: &str) -> String {
+    utf8_percent_encode(segment, UNRESERVED).to_string()
+}
+
+fn sign(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let key = hmac::Key::new(hmac::HMAC_SHA256, key);
+    hmac::sign(&key, data).as_ref().to_vec()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
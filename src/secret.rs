@@ -0,0 +1,251 @@
+use std::{
+    borrow::Cow,
+    future::Future,
+    sync::{LazyLock, RwLock},
+    time::Duration,
+};
+
+use tokio::runtime::{self, Handle, RuntimeFlavor};
+
+#[cfg(any(feature = "vault", feature = "aws-secrets"))]
+use hyper::{Body, Request};
+#[cfg(any(feature = "vault", feature = "aws-secrets"))]
+use serde_json::Value;
+
+#[cfg(any(feature = "vault", feature = "aws-secrets"))]
+use crate::{
+    http,
+    report::{self, Labels, Origin},
+};
+
+/// Time to wait for a secret manager to answer.
+const FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Secrets given so far, replaced with `***` wherever they could be printed.
+static SECRETS: LazyLock<RwLock<Vec<String>>> = LazyLock::new(Default::default);
+
+/// Reads a secret from a file, without its trailing newline, or from a secret manager: with the
+/// `vault` feature from `vault:PATH#FIELD`, and with the `aws-secrets` feature from
+/// `aws-sm:ID`, or `aws-sm:ID#FIELD` for a field of a secret holding JSON. The secret is redacted
+/// from then on.
+///
+/// Panics if the secret cannot be read.
+pub(crate) fn read(source: &str) -> String {
+    let secret = if let Some(reference) = source.strip_prefix("vault:") {
+        fetch(source, vault(reference))
+    } else if let Some(reference) = source.strip_prefix("aws-sm:") {
+        fetch(source, aws(reference))
+    } else {
+        let secret = std::fs::read_to_string(source)
+            .unwrap_or_else(|e| panic!("unable to read secret {}: {}", source, e));
+        secret.trim_end_matches(['\r', '\n']).to_string()
+    };
+    register(&secret);
+    secret
+}
+
+/// Redacts a secret given some other way, like in a connection string, from then on.
+pub(crate) fn register(secret: &str) {
+    if secret.is_empty() {
+        return;
+    }
+    let mut secrets = SECRETS.write().unwrap();
+    if !secrets.iter().any(|known| known == secret) {
+        secrets.push(secret.to_string());
+    }
+}
+
+/// Replaces every secret in the text with `***`.
+pub(crate) fn redact(text: &str) -> Cow<'_, str> {
+    let secrets = SECRETS.read().unwrap();
+    let mut text = Cow::Borrowed(text);
+    for secret in secrets.iter() {
+        if text.contains(secret.as_str()) {
+            text = Cow::Owned(text.replace(secret.as_str(), "***"));
+        }
+    }
+    text
+}
+
+/// Whether any secrets are redacted, so that text need not be checked for them otherwise.
+pub(crate) fn any() -> bool {
+    !SECRETS.read().unwrap().is_empty()
+}
+
+/// Waits for a secret to be fetched, from code that cannot.
+fn fetch(source: &str, secret: impl Future<Output = Result<String, String>> + Send) -> String {
+    let secret = tokio::time::timeout(FETCH_TIMEOUT, secret);
+    let secret = match Handle::try_current() {
+        Ok(handle) if handle.runtime_flavor() == RuntimeFlavor::MultiThread => {
+            tokio::task::block_in_place(|| handle.block_on(secret))
+        }
+        // A runtime on a single thread cannot be blocked in place, so fetch on a thread of its own.
+        _ => std::thread::scope(|scope| {
+            scope
+                .spawn(|| {
+                    runtime::Builder::new_current_thread()
+                        .enable_all()
+                        .build()
+                        .expect("unable to start a runtime to read secrets with")
+                        .block_on(secret)
+                })
+                .join()
+                .expect("reading a secret panicked")
+        }),
+    };
+    match secret {
+        Ok(Ok(secret)) => secret,
+        Ok(Err(e)) => panic!("unable to read secret {}: {}", source, e),
+        Err(_) => panic!(
+            "unable to read secret {}: the secret manager did not answer",
+            source
+        ),
+    }
+}
+
+/// Sends a request to a secret manager, returning the JSON it answers with.
+#[cfg(any(feature = "vault", feature = "aws-secrets"))]
+async fn request(probe: &'static str, req: Request<Body>) -> Result<Value, String> {
+    let uri = req.uri().clone();
+    let origin = Origin {
+        probe,
+        name: None,
+        labels: Labels::default(),
+        target: report::redact(&uri.to_string()),
+        worker: 0,
+    };
+    let res = http::client(&uri, origin)
+        .request(req)
+        .await
+        .map_err(|e| e.to_string())?;
+    let status = res.status();
+    let body = hyper::body::to_bytes(res.into_body())
+        .await
+        .map_err(|e| e.to_string())?;
+    if !status.is_success() {
+        return Err(format!(
+            "answered {}: {}",
+            status,
+            String::from_utf8_lossy(&body).trim()
+        ));
+    }
+    serde_json::from_slice(&body).map_err(|e| e.to_string())
+}
+
+/// The field of a JSON object, as it is if a string and as JSON otherwise.
+#[cfg(any(feature = "vault", feature = "aws-secrets"))]
+fn field(object: &Value, name: &str) -> Option<String> {
+    match object.get(name)? {
+        Value::String(value) => Some(value.clone()),
+        value => Some(value.to_string()),
+    }
+}
+
+/// Reads a field of a secret from Vault at `VAULT_ADDR`, with the token in `VAULT_TOKEN` or
+/// `~/.vault-token`, from either version of the key-value engine.
+#[cfg(feature = "vault")]
+async fn vault(reference: &str) -> Result<String, String> {
+    use std::env;
+
+    let (path, name) = reference
+        .rsplit_once('#')
+        .ok_or("vault secrets are given as vault:PATH#FIELD")?;
+    let addr = env::var("VAULT_ADDR").unwrap_or_else(|_| "http://127.0.0.1:8200".to_string());
+    let token = match env::var("VAULT_TOKEN") {
+        Ok(token) => token,
+        Err(_) => {
+            let home = env::var("HOME").map_err(|_| "VAULT_TOKEN is not set")?;
+            std::fs::read_to_string(format!("{}/.vault-token", home))
+                .map_err(|_| "VAULT_TOKEN is not set and there is no ~/.vault-token")?
+                .trim()
+                .to_string()
+        }
+    };
+    let url = format!(
+        "{}/v1/{}",
+        addr.trim_end_matches('/'),
+        path.trim_start_matches('/')
+    );
+    let mut req = Request::get(&url).header("x-vault-token", token);
+    if let Ok(namespace) = env::var("VAULT_NAMESPACE") {
+        req = req.header("x-vault-namespace", namespace);
+    }
+    let req = req.body(Body::empty()).map_err(|e| e.to_string())?;
+
+    let secret = request("vault", req).await?;
+    // Version 2 of the key-value engine nests the fields of a secret a level further.
+    let data = &secret["data"];
+    field(&data["data"], name)
+        .or_else(|| field(data, name))
+        .ok_or_else(|| format!("{} has no field {}", path, name))
+}
+
+#[cfg(not(feature = "vault"))]
+async fn vault(_reference: &str) -> Result<String, String> {
+    Err("built without the `vault` feature".to_string())
+}
+
+/// Reads a secret, or a field of it, from AWS Secrets Manager, in the region of the ARN of the
+/// secret or else of the environment, with the credentials of the environment.
+#[cfg(feature = "aws-secrets")]
+async fn aws(reference: &str) -> Result<String, String> {
+    use std::env;
+
+    use hyper::Method;
+
+    use crate::s3::{self, Credentials};
+
+    let (id, name) = match reference.split_once('#') {
+        Some((id, name)) => (id, Some(name)),
+        None => (reference, None),
+    };
+    let region = match id.split(':').nth(3) {
+        Some(region) if id.starts_with("arn:") => region.to_string(),
+        _ => env::var("AWS_REGION")
+            .or_else(|_| env::var("AWS_DEFAULT_REGION"))
+            .unwrap_or_else(|_| "us-east-1".to_string()),
+    };
+    let endpoint = env::var("AWS_ENDPOINT_URL_SECRETS_MANAGER")
+        .unwrap_or_else(|_| format!("https://secretsmanager.{}.amazonaws.com", region));
+    let uri: hyper::Uri = endpoint
+        .parse()
+        .map_err(|e| format!("{}: {}", endpoint, e))?;
+    let authority = uri.authority().ok_or("endpoint has no host")?.to_string();
+
+    let body = serde_json::json!({ "SecretId": id }).to_string();
+    let mut headers = vec![
+        ("content-type", "application/x-amz-json-1.1".to_string()),
+        ("host", authority),
+        ("x-amz-target", "secretsmanager.GetSecretValue".to_string()),
+    ];
+    s3::sign_v4(
+        &Credentials::from_env(),
+        "secretsmanager",
+        &region,
+        &Method::POST,
+        "/",
+        &mut headers,
+        body.as_bytes(),
+    );
+    let mut req = Request::post(format!("{}/", endpoint.trim_end_matches('/')));
+    for (name, value) in headers {
+        req = req.header(name, value);
+    }
+    let req = req.body(Body::from(body)).map_err(|e| e.to_string())?;
+
+    let secret = request("aws-sm", req).await?;
+    let value = field(&secret, "SecretString").ok_or("secret has no SecretString")?;
+    match name {
+        Some(name) => {
+            let fields: Value = serde_json::from_str(&value)
+                .map_err(|_| format!("secret {} does not hold JSON", id))?;
+            field(&fields, name).ok_or_else(|| format!("secret {} has no field {}", id, name))
+        }
+        None => Ok(value),
+    }
+}
+
+#[cfg(not(feature = "aws-secrets"))]
+async fn aws(_reference: &str) -> Result<String, String> {
+    Err("built without the `aws-secrets` feature".to_string())
+}
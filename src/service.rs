@@ -0,0 +1,259 @@
+use std::{
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+
+use clap::Args;
+use serde::{Deserialize, Serialize};
+#[cfg(windows)]
+use windows_service::service::ServiceState;
+
+/// Whether the process runs as a service.
+static SERVICE: AtomicBool = AtomicBool::new(false);
+
+/// Options for running as a service of systemd or Windows.
+#[derive(Args, Debug, Serialize, Deserialize)]
+pub struct ServiceArgs {
+    /// Run as a long-running service. Under systemd, with `Type=notify`, tell it once probes have
+    /// started, when the config is reloaded and when stopping, and ping its watchdog if
+    /// `WatchdogSec` is set. On Windows, run under the service control manager as the service
+    /// `artemiss`, e.g. once registered with `sc.exe create artemiss binPath= "C:\artemiss.exe
+    /// --service run --config C:\artemiss.yaml"`, stopping gracefully when it asks to.
+    #[arg(long, global = true)]
+    service: bool,
+}
+
+/// Connects to the service manager, if running as a service.
+pub(crate) fn init(args: ServiceArgs) {
+    if !args.service {
+        return;
+    }
+    SERVICE.store(true, Ordering::Relaxed);
+    #[cfg(windows)]
+    windows::start();
+    #[cfg(not(any(target_os = "linux", windows)))]
+    panic!("--service is only supported with systemd or on windows");
+}
+
+/// Tells the service manager that probes have started, or carry on after a reload, starting to
+/// ping its watchdog the first time.
+pub(crate) fn ready() {
+    if !SERVICE.load(Ordering::Relaxed) {
+        return;
+    }
+    #[cfg(target_os = "linux")]
+    systemd::ready();
+    #[cfg(windows)]
+    windows::set(ServiceState::Running, Duration::ZERO);
+}
+
+/// Tells the service manager that the config is being reloaded.
+pub(crate) fn reloading() {
+    #[cfg(target_os = "linux")]
+    if SERVICE.load(Ordering::Relaxed) {
+        systemd::reloading();
+    }
+}
+
+/// Tells the service manager that probes are stopping, and how long they may take to.
+pub(crate) fn stopping(grace: Duration) {
+    if !SERVICE.load(Ordering::Relaxed) {
+        return;
+    }
+    #[cfg(target_os = "linux")]
+    systemd::stopping(grace);
+    #[cfg(windows)]
+    windows::set(ServiceState::StopPending, grace);
+}
+
+/// Tells the service manager that the process is about to exit.
+pub(crate) fn stopped() {
+    #[cfg(windows)]
+    if SERVICE.load(Ordering::Relaxed) {
+        windows::set(ServiceState::Stopped, Duration::ZERO);
+    }
+}
+
+/// Completes when the service manager asks the process to stop, besides by a signal.
+pub(crate) async fn stop_requested() {
+    #[cfg(windows)]
+    if SERVICE.load(Ordering::Relaxed) {
+        return windows::STOP.cancelled().await;
+    }
+    std::future::pending().await
+}
+
+#[cfg(target_os = "linux")]
+mod systemd {
+    use std::{
+        io,
+        os::{
+            linux::net::SocketAddrExt,
+            unix::net::{SocketAddr, UnixDatagram},
+        },
+        sync::Once,
+        time::Duration,
+    };
+
+    use log::{debug, warn};
+    use tokio::time;
+
+    /// Starts pinging the watchdog along with the first notice of being ready.
+    static WATCHDOG: Once = Once::new();
+
+    pub(super) fn ready() {
+        notify("READY=1\nSTATUS=probing");
+        WATCHDOG.call_once(|| {
+            if let Some(period) = watchdog() {
+                debug!(
+                    "pinging the systemd watchdog every {}ms",
+                    period.as_millis()
+                );
+                tokio::spawn(ping(period));
+            }
+        });
+    }
+
+    pub(super) fn reloading() {
+        // SAFETY: the time is written to by the call, and the clock always exists.
+        let mut now: libc::timespec = unsafe { std::mem::zeroed() };
+        unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut now) };
+        let usec = now.tv_sec as u64 * 1_000_000 + now.tv_nsec as u64 / 1_000;
+        notify(&format!(
+            "RELOADING=1\nMONOTONIC_USEC={}\nSTATUS=reloading config",
+            usec
+        ));
+    }
+
+    pub(super) fn stopping(grace: Duration) {
+        // Asks for the grace on top of the usual stop timeout, so that in-flight attempts
+        // finish before being killed.
+        notify(&format!(
+            "STOPPING=1\nEXTEND_TIMEOUT_USEC={}\nSTATUS=waiting for in-flight attempts",
+            grace.as_micros()
+        ));
+    }
+
+    /// Pings the watchdog for as long as the runtime keeps running tasks.
+    async fn ping(period: Duration) {
+        let mut interval = time::interval(period);
+        loop {
+            interval.tick().await;
+            notify("WATCHDOG=1");
+        }
+    }
+
+    /// Half the time systemd waits for a watchdog ping, if it watches this process.
+    fn watchdog() -> Option<Duration> {
+        let usec: u64 = std::env::var("WATCHDOG_USEC").ok()?.parse().ok()?;
+        // Watchdogs set for another process, like the shell of an `ExecStart`, are left alone.
+        if let Ok(pid) = std::env::var("WATCHDOG_PID") {
+            if pid.parse() != Ok(std::process::id()) {
+                return None;
+            }
+        }
+        Some(Duration::from_micros(usec) / 2)
+    }
+
+    /// Sends a state to systemd over `NOTIFY_SOCKET`, if it gave one.
+    fn notify(state: &str) {
+        let Some(path) = std::env::var_os("NOTIFY_SOCKET") else {
+            warn!("--service is not running under systemd with Type=notify");
+            return;
+        };
+        let send = || {
+            // Sockets starting with `@` are in the abstract namespace.
+            let addr = match path.as_encoded_bytes().strip_prefix(b"@") {
+                Some(name) => SocketAddr::from_abstract_name(name)?,
+                None => SocketAddr::from_pathname(&path)?,
+            };
+            UnixDatagram::unbound()?.send_to_addr(state.as_bytes(), &addr)?;
+            Ok::<_, io::Error>(())
+        };
+        if let Err(e) = send() {
+            warn!("cannot notify systemd: {}", e);
+        }
+    }
+}
+
+#[cfg(windows)]
+mod windows {
+    use std::{ffi::OsString, sync::OnceLock, time::Duration};
+
+    use log::error;
+    use tokio_util::sync::CancellationToken;
+    use windows_service::{
+        define_windows_service,
+        service::{
+            ServiceControl, ServiceControlAccept, ServiceExitCode, ServiceState, ServiceStatus,
+            ServiceType,
+        },
+        service_control_handler::{self, ServiceControlHandlerResult, ServiceStatusHandle},
+        service_dispatcher,
+    };
+
+    /// Name the service is registered under.
+    const NAME: &str = "artemiss";
+
+    /// Cancelled when the service control manager asks the service to stop.
+    pub(super) static STOP: std::sync::LazyLock<CancellationToken> =
+        std::sync::LazyLock::new(CancellationToken::new);
+    static STATUS: OnceLock<ServiceStatusHandle> = OnceLock::new();
+
+    define_windows_service!(ffi_service_main, service_main);
+
+    /// Connects to the service control manager, which runs the service on a thread of its own.
+    pub(super) fn start() {
+        std::thread::spawn(|| {
+            if let Err(e) = service_dispatcher::start(NAME, ffi_service_main) {
+                error!(
+                    "--service is not running under the service control manager: {}",
+                    e
+                );
+                std::process::exit(1);
+            }
+        });
+    }
+
+    fn service_main(_arguments: Vec<OsString>) {
+        let handler = |control| match control {
+            ServiceControl::Stop | ServiceControl::Shutdown => {
+                STOP.cancel();
+                ServiceControlHandlerResult::NoError
+            }
+            ServiceControl::Interrogate => ServiceControlHandlerResult::NoError,
+            _ => ServiceControlHandlerResult::NotImplemented,
+        };
+        match service_control_handler::register(NAME, handler) {
+            Ok(status) => {
+                let _ = STATUS.set(status);
+                // Probes are already starting by the time the service is registered.
+                set(ServiceState::Running, Duration::ZERO);
+            }
+            Err(e) => error!("cannot register the service control handler: {}", e),
+        }
+    }
+
+    /// Reports the state of the service, and how long it may stay in it when pending.
+    pub(super) fn set(state: ServiceState, wait_hint: Duration) {
+        let Some(status) = STATUS.get() else {
+            return;
+        };
+        let controls_accepted = match state {
+            ServiceState::Running => ServiceControlAccept::STOP | ServiceControlAccept::SHUTDOWN,
+            _ => ServiceControlAccept::empty(),
+        };
+        let result = status.set_service_status(ServiceStatus {
+            service_type: ServiceType::OWN_PROCESS,
+            current_state: state,
+            controls_accepted,
+            exit_code: ServiceExitCode::Win32(0),
+            checkpoint: 0,
+            wait_hint,
+            process_id: None,
+        });
+        if let Err(e) = result {
+            error!("cannot set the service status: {}", e);
+        }
+    }
+}
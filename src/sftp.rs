@@ -0,0 +1,324 @@
+use std::{
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use clap::Parser;
+use russh::{
+    client::{self, AuthResult, Handle},
+    keys::{self, PrivateKey},
+    Disconnect,
+};
+use russh_sftp::client::SftpSession;
+use serde::{Deserialize, Serialize};
+use tokio::{io::AsyncWriteExt, time};
+
+use crate::{
+    ftp::{self, Transfer},
+    limit,
+    probe::{self, CommonArgs, Probe},
+    report::{Attempt, ProbeError},
+    resolve::{ResolveArgs, Resolver},
+    secret,
+    ssh::{self, Client},
+};
+
+#[derive(Parser, Debug, Serialize, Deserialize)]
+pub struct SftpArgs {
+    /// Host to connect to.
+    #[arg(long)]
+    host: String,
+
+    /// Port to connect to.
+    #[arg(long, default_value_t = 22)]
+    port: u16,
+
+    /// Fail unless the host key has this SHA-256 fingerprint, as printed by `ssh-keygen -l`, e.g.
+    /// `SHA256:...`.
+    #[arg(long)]
+    host_key_fingerprint: Option<String>,
+
+    /// User to authenticate as.
+    #[arg(long)]
+    user: String,
+
+    /// Private key in OpenSSH format to authenticate with.
+    #[arg(long, required_unless_present_any = ["password", "password_file"])]
+    key: Option<PathBuf>,
+
+    /// Passphrase of `--key`, if it is encrypted.
+    #[arg(long, requires = "key")]
+    key_passphrase: Option<String>,
+
+    /// Authenticate with this password rather than a key.
+    #[arg(long, conflicts_with = "key")]
+    password: Option<String>,
+
+    /// Password to authenticate with, read from a file or a `vault:` or `aws-sm:` source.
+    #[arg(long, conflicts_with_all = ["key", "password"])]
+    password_file: Option<String>,
+
+    /// What to transfer on every attempt once authenticated.
+    #[arg(long, value_enum, default_value_t)]
+    transfer: Transfer,
+
+    /// Path of the directory to list or of the file to download or upload, relative to the
+    /// directory the server starts the session in.
+    #[arg(long, required_if_eq_any = [("transfer", "download"), ("transfer", "upload")])]
+    path: Option<String>,
+
+    /// Size of the file written by `--transfer upload`.
+    #[arg(long, default_value_t = 1024)]
+    upload_bytes: usize,
+
+    /// Set a timeout for the connect phase of a socket.
+    #[arg(long, default_value_t = 1000)]
+    connect_timeout_ms: u64,
+
+    /// Set a timeout for the rest of the session after connecting, including the transfer.
+    #[arg(long, default_value_t = 5000)]
+    timeout_ms: u64,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    resolve: ResolveArgs,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    pub common: CommonArgs,
+}
+
+/// How to authenticate to the server.
+enum Credentials {
+    Key(Arc<PrivateKey>),
+    Password(String),
+}
+
+/// Opens a connection on every attempt, authenticates and starts the SFTP subsystem, then lists a
+/// directory, downloads a file or uploads one before disconnecting.
+pub struct SftpProbe {
+    host: String,
+    port: u16,
+    resolver: Resolver,
+    config: Arc<client::Config>,
+    fingerprint: Option<String>,
+    user: String,
+    credentials: Credentials,
+    transfer: Transfer,
+    path: Option<String>,
+    upload_bytes: usize,
+    connect_timeout: Duration,
+    timeout: Duration,
+}
+
+impl SftpProbe {
+    pub fn new(args: &SftpArgs) -> Self {
+        let credentials = match (&args.key, &args.password, &args.password_file) {
+            (Some(path), _, _) => {
+                let key = keys::load_secret_key(path, args.key_passphrase.as_deref())
+                    .expect("unable to load ssh key");
+                Credentials::Key(Arc::new(key))
+            }
+            (_, Some(password), _) => {
+                secret::register(password);
+                Credentials::Password(password.clone())
+            }
+            (_, _, Some(file)) => Credentials::Password(secret::read(file)),
+            _ => panic!("--key, --password or --password-file is required"),
+        };
+
+        SftpProbe {
+            host: args.host.clone(),
+            port: args.port,
+            resolver: Resolver::new(&args.resolve),
+            config: Arc::new(client::Config::default()),
+            fingerprint: args.host_key_fingerprint.clone(),
+            user: args.user.clone(),
+            credentials,
+            transfer: args.transfer,
+            path: args.path.clone(),
+            upload_bytes: args.upload_bytes,
+            connect_timeout: Duration::from_millis(args.connect_timeout_ms),
+            timeout: Duration::from_millis(args.timeout_ms),
+        }
+    }
+
+    /// Runs the session over the connection, recording the time of each phase.
+    async fn session(
+        &self,
+        stream: tokio::net::TcpStream,
+        phases: &mut Vec<(&'static str, Duration)>,
+        details: &mut Vec<(&'static str, String)>,
+    ) -> Result<(), ProbeError> {
+        let start = Instant::now();
+        let host_key = Arc::new(Mutex::new(None));
+        let handler = Client {
+            host_key: host_key.clone(),
+        };
+        let result = client::connect_stream(self.config.clone(), stream, handler).await;
+        let host_key = host_key.lock().unwrap().take();
+        if let Some((algorithm, fingerprint)) = &host_key {
+            details.push(("host_key", algorithm.clone()));
+            details.push(("fingerprint", fingerprint.clone()));
+        }
+        let mut handle = result.map_err(|e| ProbeError::from_cause("handshake", &e))?;
+        ssh::check_host_key(self.fingerprint.as_deref(), host_key.as_ref())?;
+        phases.push(("handshake", start.elapsed()));
+
+        let start = Instant::now();
+        self.authenticate(&mut handle).await?;
+        phases.push(("auth", start.elapsed()));
+
+        let start = Instant::now();
+        let channel = handle
+            .channel_open_session()
+            .await
+            .map_err(|e| ProbeError::from_cause("sftp", &e))?;
+        channel
+            .request_subsystem(true, "sftp")
+            .await
+            .map_err(|e| ProbeError::from_cause("sftp", &e))?;
+        let sftp = SftpSession::new(channel.into_stream())
+            .await
+            .map_err(|e| ProbeError::new("sftp", e))?;
+        phases.push(("sftp", start.elapsed()));
+
+        let start = Instant::now();
+        let bytes = self.transfer(&sftp, details).await?;
+        let elapsed = start.elapsed();
+        phases.push(("transfer", elapsed));
+        details.extend(ftp::transfer_details(self.transfer, bytes, elapsed));
+
+        // The connection is closed once the handle is dropped in any case.
+        let _ = sftp.close().await;
+        let _ = handle.disconnect(Disconnect::ByApplication, "", "en").await;
+        Ok(())
+    }
+
+    async fn authenticate(&self, handle: &mut Handle<Client>) -> Result<(), ProbeError> {
+        let password = match &self.credentials {
+            Credentials::Key(key) => {
+                return ssh::authenticate(handle, &self.user, key.clone()).await
+            }
+            Credentials::Password(password) => password,
+        };
+        let result = handle
+            .authenticate_password(&self.user, password)
+            .await
+            .map_err(|e| ProbeError::from_cause("auth", &e))?;
+        match result {
+            AuthResult::Success => Ok(()),
+            AuthResult::Failure { .. } => Err(ProbeError::new(
+                "auth",
+                format!("password authentication as {} was rejected", self.user),
+            )),
+        }
+    }
+
+    /// Runs the transfer, returning how many bytes of file data or names went through it.
+    async fn transfer(
+        &self,
+        sftp: &SftpSession,
+        details: &mut Vec<(&'static str, String)>,
+    ) -> Result<u64, ProbeError> {
+        let path = self.path.clone();
+        let transfer_error = |e| ProbeError::new("transfer", e);
+        match self.transfer {
+            Transfer::List => {
+                let entries = sftp
+                    .read_dir(path.unwrap_or_else(|| ".".to_string()))
+                    .await
+                    .map_err(transfer_error)?;
+                let names: Vec<_> = entries
+                    .map(|entry| entry.file_name())
+                    .filter(|name| name != "." && name != "..")
+                    .collect();
+                details.push(("entries", names.len().to_string()));
+                Ok(names.iter().map(|name| name.len() as u64).sum())
+            }
+            Transfer::Download => {
+                let data = sftp
+                    .read(path.expect("--path is required to download"))
+                    .await
+                    .map_err(transfer_error)?;
+                Ok(data.len() as u64)
+            }
+            Transfer::Upload => {
+                let payload = ftp::payload(self.upload_bytes);
+                let mut file = sftp
+                    .create(path.expect("--path is required to upload"))
+                    .await
+                    .map_err(transfer_error)?;
+                file.write_all(&payload)
+                    .await
+                    .map_err(|e| ProbeError::from_cause("transfer", &e))?;
+                // Closing waits for the server to acknowledge every write.
+                file.close()
+                    .await
+                    .map_err(|e| ProbeError::from_cause("transfer", &e))?;
+                Ok(payload.len() as u64)
+            }
+        }
+    }
+}
+
+impl Probe for SftpProbe {
+    fn kind(&self) -> &'static str {
+        "sftp"
+    }
+
+    fn target(&self) -> String {
+        format!("{}:{}", self.host, self.port)
+    }
+
+    async fn attempt(&self, _worker: usize) -> Attempt {
+        let start = Instant::now();
+        let throttle = limit::acquire().await;
+        let connect_start = Instant::now();
+        let connect = self.resolver.connect(&self.host, self.port);
+        let connected = time::timeout(self.connect_timeout, connect).await;
+        let throttled = throttle.release();
+        let conn = match connected {
+            Ok(Ok(conn)) => conn,
+            Ok(Err(e)) => return failed(start, ProbeError::from_cause("connect", &e)),
+            Err(_) => {
+                let message = format!("timed out after {}ms", self.connect_timeout.as_millis());
+                return failed(start, ProbeError::new("connect_timeout", message));
+            }
+        };
+        let mut phases: Vec<_> = throttled.into_iter().collect();
+        phases.push(("connect", connect_start.elapsed()));
+        let mut details = conn.details();
+
+        let session = self.session(conn.stream, &mut phases, &mut details);
+        let error = match time::timeout(self.timeout, session).await {
+            Ok(Ok(())) => None,
+            Ok(Err(e)) => Some(e),
+            Err(_) => Some(ProbeError::new(
+                "timeout",
+                format!("timed out after {}ms", self.timeout.as_millis()),
+            )),
+        };
+
+        Attempt {
+            duration: start.elapsed(),
+            phases,
+            details,
+            error,
+        }
+    }
+}
+
+pub async fn sftp_main(args: SftpArgs) {
+    probe::run(SftpProbe::new(&args), &args.common).await
+}
+
+fn failed(start: Instant, error: ProbeError) -> Attempt {
+    Attempt {
+        duration: start.elapsed(),
+        phases: vec![],
+        details: vec![],
+        error: Some(error),
+    }
+}
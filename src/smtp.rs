@@ -0,0 +1,398 @@
+use std::{
+    io,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use clap::{Parser, ValueEnum};
+use rustls::pki_types::ServerName;
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio_rustls::TlsConnector;
+
+use crate::{
+    limit,
+    probe::{self, CommonArgs, Probe},
+    report::{Attempt, ProbeError},
+    resolve, secret, socket,
+    tls::{self, TlsArgs},
+};
+
+/// How TLS is negotiated with the server.
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpTls {
+    /// Upgrade the connection with `STARTTLS` after the first `EHLO`.
+    #[default]
+    Starttls,
+    /// Negotiate TLS as soon as the connection is open, as on port 465.
+    Implicit,
+    /// Stay in cleartext.
+    None,
+}
+
+#[derive(Parser, Debug, Serialize, Deserialize)]
+pub struct SmtpArgs {
+    /// Host of the mail server.
+    #[arg(long)]
+    host: String,
+
+    /// Port of the mail server.
+    #[arg(long, default_value_t = 25)]
+    port: u16,
+
+    /// How to negotiate TLS.
+    #[arg(long, value_enum, default_value_t)]
+    smtp_tls: SmtpTls,
+
+    /// Name to introduce the client with in `EHLO`.
+    #[arg(long, default_value = "localhost")]
+    ehlo_name: String,
+
+    /// Authenticate with `AUTH PLAIN` as this user after the handshake.
+    #[arg(long, requires = "password")]
+    username: Option<String>,
+
+    /// Password for `--username`.
+    #[arg(long, requires = "username")]
+    password: Option<String>,
+
+    /// Count the attempt as successful only if authentication is rejected, to check that the
+    /// server is reachable and verifying credentials using dummy ones.
+    #[arg(long, requires = "username")]
+    expect_auth_rejected: bool,
+
+    /// Set a timeout for only the connect phase of a connection, including implicit TLS.
+    #[arg(long, default_value_t = 1000)]
+    connect_timeout_ms: u64,
+
+    /// Set a timeout for the rest of the session after connecting.
+    #[arg(long, default_value_t = 1000)]
+    timeout_ms: u64,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    tls: TlsArgs,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    pub common: CommonArgs,
+}
+
+trait Stream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Stream for T {}
+
+/// Goes through the greeting, `EHLO`, `STARTTLS` and optionally `AUTH` on every attempt, then
+/// says `QUIT`.
+pub struct SmtpProbe {
+    host: String,
+    port: u16,
+    mode: SmtpTls,
+    tls: TlsConnector,
+    sni: Option<String>,
+    ehlo_name: String,
+    credentials: Option<(String, String)>,
+    expect_auth_rejected: bool,
+    connect_timeout: Duration,
+    timeout: Duration,
+}
+
+impl SmtpProbe {
+    pub fn new(args: &SmtpArgs) -> Self {
+        SmtpProbe {
+            host: args.host.clone(),
+            port: args.port,
+            mode: args.smtp_tls,
+            tls: TlsConnector::from(Arc::new(tls::configure(&args.tls))),
+            sni: args.tls.sni.clone(),
+            ehlo_name: args.ehlo_name.clone(),
+            credentials: args
+                .username
+                .clone()
+                .zip(args.password.clone())
+                .inspect(|(_, password)| secret::register(password)),
+            expect_auth_rejected: args.expect_auth_rejected,
+            connect_timeout: Duration::from_millis(args.connect_timeout_ms),
+            timeout: Duration::from_millis(args.timeout_ms),
+        }
+    }
+
+    /// Negotiates TLS over the connection.
+    async fn handshake(&self, stream: Box<dyn Stream>) -> Result<Box<dyn Stream>, ProbeError> {
+        let name = ServerName::try_from(self.sni.as_ref().unwrap_or(&self.host).clone())
+            .map_err(|e| ProbeError::new("tls", e))?;
+        let stream = self
+            .tls
+            .connect(name, stream)
+            .await
+            .map_err(|e| ProbeError::from_cause("tls", &e))?;
+        Ok(Box::new(stream))
+    }
+
+    /// Opens a connection, negotiating TLS straight away in implicit mode.
+    async fn connect(
+        &self,
+        phases: &mut Vec<(&'static str, Duration)>,
+        details: &mut Vec<(&'static str, String)>,
+    ) -> Result<Box<dyn Stream>, ProbeError> {
+        let start = Instant::now();
+        let conn = resolve::connect(&self.host, self.port)
+            .await
+            .map_err(|e| ProbeError::from_cause("connect", &e))?;
+        details.extend(conn.details());
+        let stream = conn.stream;
+        stream
+            .set_nodelay(socket::nodelay(true))
+            .map_err(|e| ProbeError::from_cause("connect", &e))?;
+        phases.push(("connect", start.elapsed()));
+
+        if self.mode != SmtpTls::Implicit {
+            return Ok(Box::new(stream));
+        }
+        phases.extend(limit::handshake().await);
+        let start = Instant::now();
+        let stream = self.handshake(Box::new(stream)).await?;
+        phases.push(("tls", start.elapsed()));
+        Ok(stream)
+    }
+
+    /// Runs the session after connecting, up to and including `QUIT`.
+    async fn session(
+        &self,
+        stream: Box<dyn Stream>,
+        phases: &mut Vec<(&'static str, Duration)>,
+        details: &mut Vec<(&'static str, String)>,
+    ) -> Result<(), ProbeError> {
+        let mut stream = BufReader::new(stream);
+
+        let start = Instant::now();
+        let (_, banner) = expect(&mut stream, None, 220, "greeting").await?;
+        phases.push(("greeting", start.elapsed()));
+        details.push(("banner", banner.first().cloned().unwrap_or_default()));
+
+        let ehlo = format!("EHLO {}\r\n", self.ehlo_name);
+        let start = Instant::now();
+        let (_, mut extensions) = expect(&mut stream, Some(&ehlo), 250, "ehlo").await?;
+        phases.push(("ehlo", start.elapsed()));
+
+        if self.mode == SmtpTls::Starttls {
+            if !has_extension(&extensions, "STARTTLS") {
+                return Err(ProbeError::new(
+                    "starttls",
+                    "server does not offer STARTTLS",
+                ));
+            }
+
+            phases.extend(limit::handshake().await);
+            let start = Instant::now();
+            expect(&mut stream, Some("STARTTLS\r\n"), 220, "starttls").await?;
+            // Anything sent before the handshake could have been injected in cleartext.
+            if !stream.buffer().is_empty() {
+                return Err(ProbeError::new(
+                    "starttls",
+                    "server sent data before the tls handshake",
+                ));
+            }
+            stream = BufReader::new(self.handshake(stream.into_inner()).await?);
+            phases.push(("starttls", start.elapsed()));
+
+            // The server forgets everything from before the upgrade, so introduce the client again.
+            (_, extensions) = expect(&mut stream, Some(&ehlo), 250, "ehlo").await?;
+        }
+
+        if let Some((username, password)) = &self.credentials {
+            if !has_extension(&extensions, "AUTH") {
+                return Err(ProbeError::new("auth", "server does not offer AUTH"));
+            }
+
+            let token = STANDARD.encode(format!("\0{}\0{}", username, password));
+            let auth = format!("AUTH PLAIN {}\r\n", token);
+            let start = Instant::now();
+            let (code, reply) = send(&mut stream, Some(&auth)).await?;
+            phases.push(("auth", start.elapsed()));
+            details.push(("auth_code", code.to_string()));
+
+            let accepted = code == 235;
+            if accepted == self.expect_auth_rejected {
+                let expected = if accepted { "rejected" } else { "accepted" };
+                return Err(ProbeError::new(
+                    "auth",
+                    format!(
+                        "expected credentials to be {}: {} {}",
+                        expected,
+                        code,
+                        reply.join(" ")
+                    ),
+                ));
+            }
+        }
+
+        expect(&mut stream, Some("QUIT\r\n"), 221, "quit").await?;
+        Ok(())
+    }
+}
+
+impl Probe for SmtpProbe {
+    fn kind(&self) -> &'static str {
+        "smtp"
+    }
+
+    fn target(&self) -> String {
+        format!("{}:{}", self.host, self.port)
+    }
+
+    async fn attempt(&self, _worker: usize) -> Attempt {
+        let start = Instant::now();
+        let mut phases = vec![];
+        let mut details = vec![("tls_mode", format!("{:?}", self.mode).to_lowercase())];
+
+        let throttle = limit::acquire().await;
+        phases.extend(throttle.phase());
+        let connect = self.connect(&mut phases, &mut details);
+        let connected = limit::timeout(self.connect_timeout, connect).await;
+        drop(throttle);
+        let result = match connected {
+            Ok(Ok(stream)) => {
+                let session = self.session(stream, &mut phases, &mut details);
+                match limit::timeout(self.timeout, session).await {
+                    Ok(result) => result,
+                    Err(_) => Err(ProbeError::new(
+                        "timeout",
+                        format!("timed out after {}ms", self.timeout.as_millis()),
+                    )),
+                }
+            }
+            Ok(Err(e)) => Err(e),
+            Err(_) => Err(ProbeError::new(
+                "connect_timeout",
+                format!("timed out after {}ms", self.connect_timeout.as_millis()),
+            )),
+        };
+
+        Attempt {
+            duration: start.elapsed(),
+            phases,
+            details,
+            error: result.err(),
+        }
+    }
+}
+
+pub async fn smtp_main(args: SmtpArgs) {
+    probe::run(SmtpProbe::new(&args), &args.common).await
+}
+
+/// Sends a command, if any, and reads the reply.
+async fn send<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut BufReader<S>,
+    command: Option<&str>,
+) -> Result<(u16, Vec<String>), ProbeError> {
+    if let Some(command) = command {
+        stream
+            .write_all(command.as_bytes())
+            .await
+            .map_err(|e| ProbeError::from_cause("send", &e))?;
+    }
+    read_reply(stream)
+        .await
+        .map_err(|e| ProbeError::from_cause("receive", &e))
+}
+
+/// Sends a command, if any, and fails with the given kind unless the reply has the expected code.
+async fn expect<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut BufReader<S>,
+    command: Option<&str>,
+    code: u16,
+    kind: &'static str,
+) -> Result<(u16, Vec<String>), ProbeError> {
+    let (actual, lines) = send(stream, command).await?;
+    if actual != code {
+        return Err(ProbeError::new(
+            kind,
+            format!("unexpected reply: {} {}", actual, lines.join(" ")),
+        ));
+    }
+    Ok((actual, lines))
+}
+
+/// Reads a possibly multiline reply, returning its code and the text of every line.
+async fn read_reply<S: AsyncRead + Unpin>(
+    stream: &mut BufReader<S>,
+) -> io::Result<(u16, Vec<String>)> {
+    let mut lines = vec![];
+    loop {
+        let mut line = String::new();
+        if stream.read_line(&mut line).await? == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        let line = line.trim_end();
+
+        let code = line
+            .get(..3)
+            .and_then(|code| code.parse().ok())
+            .ok_or_else(|| io::Error::other(format!("malformed reply: {}", line)))?;
+        lines.push(line.get(4..).unwrap_or_default().to_string());
+        if line.as_bytes().get(3) != Some(&b'-') {
+            return Ok((code, lines));
+        }
+    }
+}
+
+/// Whether the `EHLO` reply advertises the extension.
+fn has_extension(lines: &[String], extension: &str) -> bool {
+    lines.iter().any(|line| {
+        line.split_whitespace()
+            .next()
+            .is_some_and(|keyword| keyword.eq_ignore_ascii_case(extension))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn connect_limit_only_covers_opening_connections() {
+        // A server that accepts connections but never greets, holding every session open.
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port().to_string();
+        tokio::spawn(async move {
+            let mut held = vec![];
+            while let Ok((stream, _)) = listener.accept().await {
+                held.push(stream);
+            }
+        });
+        limit::init(serde_json::from_str(r#"{"max_concurrent_connects":1}"#).unwrap());
+
+        let args = SmtpArgs::parse_from([
+            "smtp",
+            "--host",
+            "127.0.0.1",
+            "--port",
+            &port,
+            "--smtp-tls",
+            "none",
+            "--timeout-ms",
+            "500",
+        ]);
+        let probe = SmtpProbe::new(&args);
+        let (first, second) = tokio::join!(probe.attempt(0), probe.attempt(1));
+
+        let throttle = |attempt: &Attempt| {
+            let phase = attempt
+                .phases
+                .iter()
+                .find(|(phase, _)| *phase == "throttle");
+            phase.map(|(_, waited)| *waited).unwrap()
+        };
+        // Whichever worker connected second only waited for the first to connect, not for its
+        // session to time out.
+        assert!(throttle(&first).max(throttle(&second)) < Duration::from_millis(100));
+        assert_eq!(first.error.unwrap().kind, "timeout");
+        assert_eq!(second.error.unwrap().kind, "timeout");
+    }
+}
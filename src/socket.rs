@@ -0,0 +1,204 @@
+use std::{
+    io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::OnceLock,
+    time::Duration,
+};
+
+use clap::Args;
+use serde::{Deserialize, Serialize};
+use socket2::{SockRef, TcpKeepalive};
+use tokio::net::{TcpSocket, TcpStream, UdpSocket};
+
+use crate::pcap;
+
+/// Options applied to every socket probes open themselves.
+static OPTIONS: OnceLock<SocketArgs> = OnceLock::new();
+
+/// Options for the sockets probes open. Probes connecting with a client library apply those it
+/// takes, and refuse to start with any other set.
+#[derive(Args, Clone, Debug, Default, Serialize, Deserialize)]
+pub struct SocketArgs {
+    /// Open sockets from this local address, e.g. to probe one egress path of a multi-homed host.
+    /// Only addresses of its family are connected to.
+    #[arg(long, global = true)]
+    bind_addr: Option<IpAddr>,
+
+    /// Open sockets on this network interface, e.g. `eth1`. Only supported on Linux, where it
+    /// needs `CAP_NET_RAW`.
+    #[arg(long, global = true)]
+    interface: Option<String>,
+
+    /// Set `TCP_NODELAY` on connections, overriding what the probe sets. HTTP, WebSocket, gRPC and
+    /// SMTP connections set it unless this is `false`.
+    #[arg(long, global = true)]
+    tcp_nodelay: Option<bool>,
+
+    /// Send TCP keepalives once a connection has been idle for this long.
+    #[arg(long, global = true)]
+    tcp_keepalive_s: Option<u64>,
+
+    /// Time between TCP keepalives while they go unanswered.
+    #[arg(long, global = true, requires = "tcp_keepalive_s")]
+    tcp_keepalive_interval_s: Option<u64>,
+
+    /// Number of unanswered TCP keepalives after which the connection is dropped. Only supported
+    /// on Linux.
+    #[arg(long, global = true, requires = "tcp_keepalive_s")]
+    tcp_keepalive_retries: Option<u32>,
+
+    /// Set `SO_LINGER` on connections, so that closing them waits up to this long for unsent
+    /// data, or resets them straight away with `0`.
+    #[arg(long, global = true)]
+    linger_s: Option<u64>,
+}
+
+impl SocketArgs {
+    fn keepalive(&self) -> Option<TcpKeepalive> {
+        let time = self.tcp_keepalive_s?;
+        let mut keepalive = TcpKeepalive::new().with_time(Duration::from_secs(time));
+        if let Some(interval) = self.tcp_keepalive_interval_s {
+            keepalive = keepalive.with_interval(Duration::from_secs(interval));
+        }
+        #[cfg(target_os = "linux")]
+        if let Some(retries) = self.tcp_keepalive_retries {
+            keepalive = keepalive.with_retries(retries);
+        }
+        Some(keepalive)
+    }
+
+    /// Flags of the options set.
+    fn flags(&self) -> Vec<&'static str> {
+        let set = [
+            ("--bind-addr", self.bind_addr.is_some()),
+            ("--interface", self.interface.is_some()),
+            ("--tcp-nodelay", self.tcp_nodelay.is_some()),
+            ("--tcp-keepalive-s", self.tcp_keepalive_s.is_some()),
+            (
+                "--tcp-keepalive-interval-s",
+                self.tcp_keepalive_interval_s.is_some(),
+            ),
+            (
+                "--tcp-keepalive-retries",
+                self.tcp_keepalive_retries.is_some(),
+            ),
+            ("--linger-s", self.linger_s.is_some()),
+        ];
+        set.into_iter()
+            .filter(|(_, set)| *set)
+            .map(|(flag, _)| flag)
+            .collect()
+    }
+}
+
+/// Sets the options applied to every socket.
+pub(crate) fn init(args: SocketArgs) {
+    OPTIONS.set(args).expect("socket options already set");
+}
+
+fn options() -> &'static SocketArgs {
+    OPTIONS.get_or_init(SocketArgs::default)
+}
+
+/// Local address sockets are opened from, if set.
+pub fn bind_addr() -> Option<IpAddr> {
+    options().bind_addr
+}
+
+/// Network interface sockets are opened on, if set.
+pub fn interface() -> Option<&'static str> {
+    options().interface.as_deref()
+}
+
+/// Whether to set `TCP_NODELAY` on a connection, for a probe that otherwise would or would not.
+pub fn nodelay(default: bool) -> bool {
+    options().tcp_nodelay.unwrap_or(default)
+}
+
+/// Whether any socket option is set.
+pub fn is_set() -> bool {
+    !options().flags().is_empty()
+}
+
+/// Time a connection is idle for before sending TCP keepalives, if set.
+pub fn keepalive_time() -> Option<Duration> {
+    options().tcp_keepalive_s.map(Duration::from_secs)
+}
+
+/// Time closing a connection waits for unsent data, if set.
+pub fn linger() -> Option<Duration> {
+    options().linger_s.map(Duration::from_secs)
+}
+
+/// Refuses to run a probe connecting with its client library while any socket option is set that
+/// the library cannot apply, other than those of the flags given.
+pub fn ensure_supported(probe: &str, supported: &[&str]) {
+    let unsupported: Vec<_> = options()
+        .flags()
+        .into_iter()
+        .filter(|flag| !supported.contains(flag))
+        .collect();
+    if !unsupported.is_empty() {
+        panic!(
+            "{} cannot be used with {}, whose client library opens its own sockets",
+            unsupported.join(", "),
+            probe
+        );
+    }
+}
+
+/// Opens a TCP connection to the address with the socket options.
+pub async fn connect(addr: SocketAddr) -> io::Result<TcpStream> {
+    pcap::watch(addr.ip());
+    let options = options();
+    let socket = match addr {
+        SocketAddr::V4(_) => TcpSocket::new_v4()?,
+        SocketAddr::V6(_) => TcpSocket::new_v6()?,
+    };
+    if let Some(ip) = options.bind_addr {
+        socket.bind(SocketAddr::new(ip, 0))?;
+    }
+    if let Some(interface) = &options.interface {
+        bind_device(SockRef::from(&socket), interface)?;
+    }
+    if let Some(keepalive) = options.keepalive() {
+        SockRef::from(&socket).set_tcp_keepalive(&keepalive)?;
+    }
+    if let Some(linger) = options.linger_s {
+        SockRef::from(&socket).set_linger(Some(Duration::from_secs(linger)))?;
+    }
+
+    let stream = socket.connect(addr).await?;
+    if let Some(nodelay) = options.tcp_nodelay {
+        stream.set_nodelay(nodelay)?;
+    }
+    Ok(stream)
+}
+
+/// Binds a UDP socket to send to the address from, with the socket options.
+pub async fn bind_udp(addr: SocketAddr) -> io::Result<UdpSocket> {
+    pcap::watch(addr.ip());
+    let options = options();
+    let ip = options.bind_addr.unwrap_or(match addr {
+        SocketAddr::V4(_) => Ipv4Addr::UNSPECIFIED.into(),
+        SocketAddr::V6(_) => Ipv6Addr::UNSPECIFIED.into(),
+    });
+    let socket = UdpSocket::bind(SocketAddr::new(ip, 0)).await?;
+    if let Some(interface) = &options.interface {
+        bind_device(SockRef::from(&socket), interface)?;
+    }
+    Ok(socket)
+}
+
+#[cfg(target_os = "linux")]
+fn bind_device(socket: SockRef<'_>, interface: &str) -> io::Result<()> {
+    socket.bind_device(Some(interface.as_bytes()))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn bind_device(_socket: SockRef<'_>, _interface: &str) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "--interface is only supported on linux",
+    ))
+}
@@ -0,0 +1,340 @@
+use std::{
+    io,
+    path::PathBuf,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+use clap::Parser;
+use russh::{
+    client::{self, AuthResult, Handle},
+    keys::{self, HashAlg, PrivateKey, PrivateKeyWithHashAlg, PublicKeyOrCertificate},
+    Disconnect,
+};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    time,
+};
+
+use crate::{
+    limit,
+    probe::{self, CommonArgs, Probe},
+    report::{Attempt, ProbeError},
+    resolve::{ResolveArgs, Resolver},
+};
+
+/// Longest identification line, with the lines a server may send before it, that is looked for.
+const MAX_BANNER: usize = 8192;
+
+#[derive(Parser, Debug, Serialize, Deserialize)]
+pub struct SshArgs {
+    /// Host to connect to.
+    #[arg(long)]
+    host: String,
+
+    /// Port to connect to.
+    #[arg(long, default_value_t = 22)]
+    port: u16,
+
+    /// Fail unless the host key has this SHA-256 fingerprint, as printed by `ssh-keygen -l`, e.g.
+    /// `SHA256:...`.
+    #[arg(long)]
+    host_key_fingerprint: Option<String>,
+
+    /// Authenticate as this user after the key exchange, with `--key`.
+    #[arg(long, requires = "key")]
+    user: Option<String>,
+
+    /// Private key in OpenSSH format to authenticate with.
+    #[arg(long, requires = "user")]
+    key: Option<PathBuf>,
+
+    /// Passphrase of `--key`, if it is encrypted.
+    #[arg(long, requires = "key")]
+    key_passphrase: Option<String>,
+
+    /// Set a timeout for the connect phase of a socket.
+    #[arg(long, default_value_t = 1000)]
+    connect_timeout_ms: u64,
+
+    /// Set a timeout for the SSH handshake, including authentication.
+    #[arg(long, default_value_t = 2000)]
+    timeout_ms: u64,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    resolve: ResolveArgs,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    pub common: CommonArgs,
+}
+
+/// Opens a connection on every attempt and completes the SSH identification exchange and key
+/// exchange, optionally authenticating with a key, before disconnecting.
+pub struct SshProbe {
+    host: String,
+    port: u16,
+    resolver: Resolver,
+    config: Arc<client::Config>,
+    fingerprint: Option<String>,
+    auth: Option<(String, Arc<PrivateKey>)>,
+    connect_timeout: Duration,
+    timeout: Duration,
+}
+
+impl SshProbe {
+    pub fn new(args: &SshArgs) -> Self {
+        let auth = args
+            .user
+            .clone()
+            .zip(args.key.as_ref())
+            .map(|(user, path)| {
+                let key = keys::load_secret_key(path, args.key_passphrase.as_deref())
+                    .expect("unable to load ssh key");
+                (user, Arc::new(key))
+            });
+
+        SshProbe {
+            host: args.host.clone(),
+            port: args.port,
+            resolver: Resolver::new(&args.resolve),
+            config: Arc::new(client::Config::default()),
+            fingerprint: args.host_key_fingerprint.clone(),
+            auth,
+            connect_timeout: Duration::from_millis(args.connect_timeout_ms),
+            timeout: Duration::from_millis(args.timeout_ms),
+        }
+    }
+
+    /// Completes the handshake over the connection, recording the time of each phase and what the
+    /// server identified itself with.
+    async fn handshake(
+        &self,
+        stream: tokio::net::TcpStream,
+        phases: &mut Vec<(&'static str, Duration)>,
+        details: &mut Vec<(&'static str, String)>,
+    ) -> Result<(), ProbeError> {
+        let start = Instant::now();
+        let banner = Arc::new(Mutex::new(Banner::default()));
+        let stream = BannerStream {
+            inner: stream,
+            banner: banner.clone(),
+        };
+        let host_key = Arc::new(Mutex::new(None));
+        let handler = Client {
+            host_key: host_key.clone(),
+        };
+        let result = client::connect_stream(self.config.clone(), stream, handler).await;
+
+        let seen = banner.lock().unwrap().line.take();
+        if let Some((received, line)) = seen {
+            phases.push(("banner", received - start));
+            phases.push(("kex", received.elapsed()));
+            details.push(("banner", line));
+        }
+        let host_key = host_key.lock().unwrap().take();
+        if let Some((algorithm, fingerprint)) = &host_key {
+            details.push(("host_key", algorithm.clone()));
+            details.push(("fingerprint", fingerprint.clone()));
+        }
+        let mut handle = result.map_err(|e| ProbeError::from_cause("handshake", &e))?;
+
+        check_host_key(self.fingerprint.as_deref(), host_key.as_ref())?;
+
+        if let Some((user, key)) = &self.auth {
+            let start = Instant::now();
+            authenticate(&mut handle, user, key.clone()).await?;
+            phases.push(("auth", start.elapsed()));
+        }
+
+        // The connection is closed once the handle is dropped in any case.
+        let _ = handle.disconnect(Disconnect::ByApplication, "", "en").await;
+        Ok(())
+    }
+}
+
+impl Probe for SshProbe {
+    fn kind(&self) -> &'static str {
+        "ssh"
+    }
+
+    fn target(&self) -> String {
+        format!("{}:{}", self.host, self.port)
+    }
+
+    async fn attempt(&self, _worker: usize) -> Attempt {
+        let start = Instant::now();
+        let throttle = limit::acquire().await;
+        let connect_start = Instant::now();
+        let connect = self.resolver.connect(&self.host, self.port);
+        let connected = time::timeout(self.connect_timeout, connect).await;
+        let throttled = throttle.release();
+        let conn = match connected {
+            Ok(Ok(conn)) => conn,
+            Ok(Err(e)) => return failed(start, ProbeError::from_cause("connect", &e)),
+            Err(_) => {
+                let message = format!("timed out after {}ms", self.connect_timeout.as_millis());
+                return failed(start, ProbeError::new("connect_timeout", message));
+            }
+        };
+        let mut phases: Vec<_> = throttled.into_iter().collect();
+        phases.push(("connect", connect_start.elapsed()));
+        let mut details = conn.details();
+
+        let handshake = self.handshake(conn.stream, &mut phases, &mut details);
+        let error = match time::timeout(self.timeout, handshake).await {
+            Ok(Ok(())) => None,
+            Ok(Err(e)) => Some(e),
+            Err(_) => Some(ProbeError::new(
+                "timeout",
+                format!("timed out after {}ms", self.timeout.as_millis()),
+            )),
+        };
+
+        Attempt {
+            duration: start.elapsed(),
+            phases,
+            details,
+            error,
+        }
+    }
+}
+
+pub async fn ssh_main(args: SshArgs) {
+    probe::run(SshProbe::new(&args), &args.common).await
+}
+
+fn failed(start: Instant, error: ProbeError) -> Attempt {
+    Attempt {
+        duration: start.elapsed(),
+        phases: vec![],
+        details: vec![],
+        error: Some(error),
+    }
+}
+
+/// Fails unless the host key seen, if any, has the fingerprint expected, if any.
+pub(crate) fn check_host_key(
+    expected: Option<&str>,
+    host_key: Option<&(String, String)>,
+) -> Result<(), ProbeError> {
+    if let (Some(expected), Some((_, fingerprint))) = (expected, host_key) {
+        if expected != fingerprint {
+            return Err(ProbeError::new(
+                "host_key",
+                format!("host key {} does not match {}", fingerprint, expected),
+            ));
+        }
+    }
+    Ok(())
+}
+
+pub(crate) async fn authenticate(
+    handle: &mut Handle<Client>,
+    user: &str,
+    key: Arc<PrivateKey>,
+) -> Result<(), ProbeError> {
+    let hash = handle
+        .best_supported_rsa_hash()
+        .await
+        .map_err(|e| ProbeError::from_cause("auth", &e))?
+        .flatten();
+    let result = handle
+        .authenticate_publickey(user, PrivateKeyWithHashAlg::new(key, hash))
+        .await
+        .map_err(|e| ProbeError::from_cause("auth", &e))?;
+    match result {
+        AuthResult::Success => Ok(()),
+        AuthResult::Failure { .. } => Err(ProbeError::new(
+            "auth",
+            format!("public key authentication as {} was rejected", user),
+        )),
+    }
+}
+
+/// Accepts any host key, recording it so that it can be reported and checked against the
+/// fingerprint expected.
+pub(crate) struct Client {
+    pub(crate) host_key: Arc<Mutex<Option<(String, String)>>>,
+}
+
+impl client::Handler for Client {
+    type Error = russh::Error;
+
+    async fn check_server_key(
+        &mut self,
+        key: &PublicKeyOrCertificate,
+    ) -> Result<bool, Self::Error> {
+        let key = match key {
+            PublicKeyOrCertificate::PublicKey { key, .. } => key.key_data(),
+            PublicKeyOrCertificate::Certificate(cert) => cert.public_key(),
+        };
+        let algorithm = key.algorithm().to_string();
+        let fingerprint = key.fingerprint(HashAlg::Sha256).to_string();
+        *self.host_key.lock().unwrap() = Some((algorithm, fingerprint));
+        Ok(true)
+    }
+}
+
+#[derive(Default)]
+struct Banner {
+    /// Bytes read so far while looking for the identification line.
+    read: Vec<u8>,
+    /// When the identification line of the server was received, and the line.
+    line: Option<(Instant, String)>,
+}
+
+/// Passes a stream through, watching what is read for the identification line of the server.
+struct BannerStream<S> {
+    inner: S,
+    banner: Arc<Mutex<Banner>>,
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for BannerStream<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let filled = buf.filled().len();
+        let poll = Pin::new(&mut self.inner).poll_read(cx, buf);
+
+        let mut banner = self.banner.lock().unwrap();
+        if banner.line.is_none() && banner.read.len() < MAX_BANNER {
+            banner.read.extend_from_slice(&buf.filled()[filled..]);
+            // Servers can send other lines before the one starting with `SSH-`.
+            while let Some(end) = banner.read.iter().position(|&b| b == b'\n') {
+                let line: Vec<u8> = banner.read.drain(..=end).collect();
+                if line.starts_with(b"SSH-") {
+                    let line = String::from_utf8_lossy(&line).trim_end().to_string();
+                    banner.line = Some((Instant::now(), line));
+                    banner.read = vec![];
+                    break;
+                }
+            }
+        }
+        poll
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for BannerStream<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
@@ -0,0 +1,263 @@
+use std::{
+    collections::BTreeMap,
+    sync::{LazyLock, Mutex},
+    time::Duration,
+};
+
+use hdrhistogram::Histogram;
+use serde::Serialize;
+
+use crate::{
+    alert,
+    report::{Attempt, Labels, Origin},
+};
+
+/// Attempts recorded for each probe target, keyed by probe kind, name and target.
+static STATS: LazyLock<Mutex<BTreeMap<Key, Stats>>> = LazyLock::new(Default::default);
+/// Attempts recorded for each probe target since the last periodic report.
+static INTERVAL: LazyLock<Mutex<BTreeMap<Key, Stats>>> = LazyLock::new(Default::default);
+
+type Key = (&'static str, Option<String>, String);
+
+/// Highest latency told apart in histograms, in microseconds, above which latencies are counted
+/// as this.
+const MAX_LATENCY_US: u64 = 3_600_000_000;
+
+#[derive(Default)]
+struct Stats {
+    labels: Labels,
+    attempts: u64,
+    recovered: u64,
+    skipped: u64,
+    latencies: Latencies,
+    errors: BTreeMap<&'static str, u64>,
+    families: BTreeMap<String, FamilyStats>,
+    connections: BTreeMap<String, ConnectionSummary>,
+}
+
+/// Attempts recorded over connections of one address family.
+#[derive(Default)]
+struct FamilyStats {
+    attempts: u64,
+    failures: u64,
+    fallbacks: u64,
+    latencies: Latencies,
+}
+
+/// Histogram of latencies, to take percentiles from without keeping every sample. Latencies are
+/// counted in microseconds to three significant digits, so percentiles are within 0.1%.
+pub struct Latencies(Histogram<u64>);
+
+/// Statistics of every attempt made against a probe target.
+#[derive(Serialize)]
+pub struct Summary {
+    pub probe: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// Labels given to the probe with `--label`.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub labels: BTreeMap<String, String>,
+    pub target: String,
+    pub attempts: u64,
+    pub failures: u64,
+    /// Number of successful attempts that were retried after failing at first.
+    pub recovered: u64,
+    /// Number of ticks skipped because a worker already had `--max-in-flight` attempts.
+    pub skipped: u64,
+    /// Percentage of attempts that succeeded.
+    pub success_rate: f64,
+    /// Latency of the successful attempts.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub latency_ms: Option<Percentiles>,
+    /// Number of failed attempts by error kind.
+    pub errors: BTreeMap<&'static str, u64>,
+    /// Statistics of the attempts made over each address family, for probes that report it.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub families: BTreeMap<String, FamilySummary>,
+    /// Statistics of the attempts made over connections that were new or reused, for probes that
+    /// report it.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub connections: BTreeMap<String, ConnectionSummary>,
+    /// Health of the target, as alerting tracks it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub health: Option<HealthSummary>,
+}
+
+/// Health of a target, by the consecutive attempts that failed and succeeded.
+#[derive(Serialize)]
+pub struct HealthSummary {
+    /// `healthy` or `unhealthy`.
+    pub state: &'static str,
+    /// Number of times the target became unhealthy or recovered within `--flap-window-s`.
+    pub transitions: usize,
+    pub flapping: bool,
+}
+
+/// Statistics of the attempts made over connections of one address family.
+#[derive(Serialize)]
+pub struct FamilySummary {
+    pub attempts: u64,
+    pub failures: u64,
+    /// Number of attempts whose connection fell back to this family from the other.
+    pub fallbacks: u64,
+    /// Latency of the successful attempts.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub latency_ms: Option<Percentiles>,
+}
+
+/// Statistics of the attempts made over connections that were new or reused.
+#[derive(Clone, Default, Serialize)]
+pub struct ConnectionSummary {
+    pub attempts: u64,
+    pub failures: u64,
+    /// Number of failed attempts whose request was in flight when the connection failed.
+    pub dropped: u64,
+}
+
+/// Latency percentiles in milliseconds.
+#[derive(Serialize)]
+pub struct Percentiles {
+    pub p50: f64,
+    pub p90: f64,
+    pub p95: f64,
+    pub p99: f64,
+    pub p999: f64,
+    pub max: f64,
+}
+
+/// Records a probe attempt in the statistics.
+pub fn record(origin: &Origin, attempt: &Attempt) {
+    let key = (origin.probe, origin.name.clone(), origin.target.clone());
+
+    for stats in [&STATS, &INTERVAL] {
+        let mut stats = stats.lock().unwrap();
+        let stats = stats.entry(key.clone()).or_default();
+        stats.labels = origin.labels.clone();
+        stats.attempts += 1;
+        if attempt.error.is_none() && attempt.detail("tries").is_some() {
+            stats.recovered += 1;
+        }
+        match &attempt.error {
+            None => stats.latencies.record(attempt.duration),
+            Some(e) => *stats.errors.entry(e.kind).or_default() += 1,
+        }
+
+        if let Some(family) = attempt.detail("family") {
+            let family = stats.families.entry(family.to_string()).or_default();
+            family.attempts += 1;
+            if attempt.detail("fallback_from").is_some() {
+                family.fallbacks += 1;
+            }
+            match &attempt.error {
+                None => family.latencies.record(attempt.duration),
+                Some(_) => family.failures += 1,
+            }
+        }
+
+        if let Some(connection) = attempt.detail("connection") {
+            let connection = stats.connections.entry(connection.to_string()).or_default();
+            connection.attempts += 1;
+            if attempt.error.is_some() {
+                connection.failures += 1;
+            }
+            if attempt.detail("dropped").is_some() {
+                connection.dropped += 1;
+            }
+        }
+    }
+}
+
+/// Records ticks skipped by a worker in the statistics.
+pub fn skipped(origin: &Origin, ticks: u64) {
+    let key = (origin.probe, origin.name.clone(), origin.target.clone());
+    for stats in [&STATS, &INTERVAL] {
+        let mut stats = stats.lock().unwrap();
+        let stats = stats.entry(key.clone()).or_default();
+        stats.labels = origin.labels.clone();
+        stats.skipped += ticks;
+    }
+}
+
+/// Summarises the attempts recorded so far.
+pub fn summaries() -> Vec<Summary> {
+    let stats = STATS.lock().unwrap();
+    stats.iter().map(summarise).collect()
+}
+
+/// Summarises the attempts recorded since this was last called, starting a new interval.
+pub fn interval_summaries() -> Vec<Summary> {
+    let stats = std::mem::take(&mut *INTERVAL.lock().unwrap());
+    stats.iter().map(summarise).collect()
+}
+
+fn summarise(((probe, name, target), stats): (&Key, &Stats)) -> Summary {
+    let failures = stats.errors.values().sum();
+    let families = stats
+        .families
+        .iter()
+        .map(|(family, stats)| {
+            let summary = FamilySummary {
+                attempts: stats.attempts,
+                failures: stats.failures,
+                fallbacks: stats.fallbacks,
+                latency_ms: stats.latencies.percentiles(),
+            };
+            (family.clone(), summary)
+        })
+        .collect();
+
+    Summary {
+        probe,
+        name: name.clone(),
+        labels: (*stats.labels).clone(),
+        target: target.clone(),
+        attempts: stats.attempts,
+        failures,
+        recovered: stats.recovered,
+        skipped: stats.skipped,
+        success_rate: if stats.attempts == 0 {
+            0.0
+        } else {
+            (stats.attempts - failures) as f64 * 100.0 / stats.attempts as f64
+        },
+        latency_ms: stats.latencies.percentiles(),
+        errors: stats.errors.clone(),
+        families,
+        connections: stats.connections.clone(),
+        health: alert::health(probe, name, target),
+    }
+}
+
+impl Default for Latencies {
+    fn default() -> Self {
+        Latencies(Histogram::new_with_bounds(1, MAX_LATENCY_US, 3).unwrap())
+    }
+}
+
+impl Latencies {
+    pub fn record(&mut self, latency: Duration) {
+        let us = (latency.as_micros() as u64).clamp(1, MAX_LATENCY_US);
+        self.0.saturating_record(us);
+    }
+
+    /// Percentiles of the latencies, if there are any.
+    pub fn percentiles(&self) -> Option<Percentiles> {
+        (!self.0.is_empty()).then(|| {
+            let ms = |q| self.0.value_at_quantile(q) as f64 / 1000.0;
+            Percentiles {
+                p50: ms(0.5),
+                p90: ms(0.9),
+                p95: ms(0.95),
+                p99: ms(0.99),
+                p999: ms(0.999),
+                max: self.0.max() as f64 / 1000.0,
+            }
+        })
+    }
+}
+
+/// Nearest-rank percentile of non-empty sorted samples.
+pub fn percentile(sorted: &[Duration], q: f64) -> Duration {
+    let rank = (q * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
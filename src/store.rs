@@ -0,0 +1,343 @@
+use std::{
+    collections::BTreeMap,
+    path::Path,
+    sync::{mpsc, Mutex},
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use clap::Parser;
+use log::error;
+use rusqlite::{params, Connection};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    report::{Attempt, Origin},
+    stats::Latencies,
+};
+
+/// Most attempts written to the store in one transaction.
+const BATCH: usize = 1000;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS attempts (
+    timestamp_us INTEGER NOT NULL,
+    probe TEXT NOT NULL,
+    name TEXT,
+    target TEXT NOT NULL,
+    worker INTEGER NOT NULL,
+    outcome TEXT NOT NULL,
+    latency_ms REAL NOT NULL,
+    error_kind TEXT,
+    error_message TEXT,
+    phases_ms TEXT NOT NULL,
+    details TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS attempts_timestamp ON attempts (timestamp_us);
+";
+
+static STORE: Mutex<Option<Store>> = Mutex::new(None);
+
+/// Attempts waiting to be written to the store, and the thread writing them.
+struct Store {
+    sender: mpsc::Sender<Row>,
+    writer: thread::JoinHandle<()>,
+}
+
+struct Row {
+    timestamp: SystemTime,
+    probe: &'static str,
+    name: Option<String>,
+    target: String,
+    worker: usize,
+    latency_ms: f64,
+    error_kind: Option<&'static str>,
+    error_message: Option<String>,
+    phases_ms: String,
+    details: String,
+}
+
+#[derive(Parser, Debug, Serialize, Deserialize)]
+pub struct QueryArgs {
+    /// Only report attempts made since then, as a time like `2026-10-14T02:10:00Z` or a duration
+    /// ago like `1h`.
+    #[arg(long)]
+    since: Option<String>,
+
+    /// Only report attempts made before then, as a time or a duration ago like `--since`.
+    #[arg(long)]
+    until: Option<String>,
+
+    /// Only report attempts of this probe, e.g. `http`.
+    #[arg(long)]
+    probe: Option<String>,
+
+    /// Only report attempts against targets containing this.
+    #[arg(long)]
+    target: Option<String>,
+
+    /// Length of the buckets the timeline of errors is counted in.
+    #[arg(long, default_value_t = 60)]
+    bucket_s: u64,
+}
+
+/// Starts writing every attempt to the SQLite database, creating it if needed, so that results
+/// from several runs accumulate.
+pub fn init(path: &Path) {
+    let conn = open(path);
+    conn.execute_batch(SCHEMA)
+        .expect("unable to create store tables");
+    let (sender, receiver) = mpsc::channel();
+    let writer = thread::spawn(move || write(conn, receiver));
+    *STORE.lock().unwrap() = Some(Store { sender, writer });
+}
+
+fn open(path: &Path) -> Connection {
+    let conn = Connection::open(path).expect("unable to open store");
+    // Readers, like `artemiss report`, do not block the run writing to the store.
+    conn.pragma_update(None, "journal_mode", "WAL")
+        .expect("unable to open store");
+    conn
+}
+
+/// Writes attempts to the store as they arrive, batching those that arrive together.
+fn write(mut conn: Connection, receiver: mpsc::Receiver<Row>) {
+    while let Ok(row) = receiver.recv() {
+        let mut rows = vec![row];
+        rows.extend(receiver.try_iter().take(BATCH - 1));
+        if let Err(e) = insert(&mut conn, &rows) {
+            error!("error writing results to store: {}", e);
+        }
+    }
+}
+
+fn insert(conn: &mut Connection, rows: &[Row]) -> rusqlite::Result<()> {
+    let tx = conn.transaction()?;
+    {
+        let mut insert = tx.prepare_cached(
+            "INSERT INTO attempts VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+        )?;
+        for row in rows {
+            let outcome = match row.error_kind {
+                None => "success",
+                Some(_) => "failure",
+            };
+            insert.execute(params![
+                micros(row.timestamp),
+                row.probe,
+                row.name,
+                row.target,
+                row.worker as i64,
+                outcome,
+                row.latency_ms,
+                row.error_kind,
+                row.error_message,
+                row.phases_ms,
+                row.details,
+            ])?;
+        }
+    }
+    tx.commit()
+}
+
+fn micros(at: SystemTime) -> i64 {
+    at.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_micros() as i64
+}
+
+/// Writes a probe attempt to the store, if storing.
+pub fn observe(origin: &Origin, attempt: &Attempt) {
+    let store = STORE.lock().unwrap();
+    let Some(store) = &*store else {
+        return;
+    };
+
+    let phases: BTreeMap<_, _> = attempt
+        .phases
+        .iter()
+        .map(|(name, duration)| (*name, duration.as_micros() as f64 / 1000.0))
+        .collect();
+    let details: BTreeMap<_, _> = attempt
+        .details
+        .iter()
+        .map(|(name, value)| (*name, value.as_str()))
+        .collect();
+    let row = Row {
+        timestamp: SystemTime::now(),
+        probe: origin.probe,
+        name: origin.name.clone(),
+        target: origin.target.clone(),
+        worker: origin.worker,
+        latency_ms: attempt.duration.as_micros() as f64 / 1000.0,
+        error_kind: attempt.error.as_ref().map(|e| e.kind),
+        error_message: attempt.error.as_ref().map(|e| e.message.clone()),
+        phases_ms: serde_json::to_string(&phases).unwrap(),
+        details: serde_json::to_string(&details).unwrap(),
+    };
+    // The writer only stops once the store is shut down, which takes the sender with it.
+    let _ = store.sender.send(row);
+}
+
+/// Waits for every attempt to be written to the store.
+pub fn shutdown() {
+    let Some(store) = STORE.lock().unwrap().take() else {
+        return;
+    };
+    drop(store.sender);
+    if store.writer.join().is_err() {
+        error!("error writing results to store");
+    }
+}
+
+pub(crate) type Key = (String, Option<String>, String);
+
+/// Attempts against a target found in the store.
+#[derive(Default)]
+struct Found {
+    attempts: u64,
+    latencies: Latencies,
+    errors: BTreeMap<String, u64>,
+    /// Attempts and errors by kind in every bucket of the timeline, keyed by when it starts.
+    buckets: BTreeMap<i64, (u64, BTreeMap<String, u64>)>,
+}
+
+/// Prints a summary of the attempts in the store against every target, and the timeline of their
+/// errors.
+pub fn report_main(path: &Path, args: QueryArgs) {
+    let now = SystemTime::now();
+    let since = args.since.as_deref().map_or(UNIX_EPOCH, |since| {
+        parse_time(since, now).expect("invalid --since")
+    });
+    let until = args.until.as_deref().map_or(now, |until| {
+        parse_time(until, now).expect("invalid --until")
+    });
+    assert!(args.bucket_s > 0, "--bucket-s must be positive");
+    let bucket_us = args.bucket_s as i64 * 1_000_000;
+
+    let conn = open(path);
+    let mut query = conn
+        .prepare(
+            "SELECT timestamp_us, probe, name, target, latency_ms, error_kind FROM attempts
+             WHERE timestamp_us >= ?1 AND timestamp_us < ?2
+             AND (?3 IS NULL OR probe = ?3) AND (?4 IS NULL OR instr(target, ?4) > 0)
+             ORDER BY timestamp_us",
+        )
+        .expect("unable to query store");
+    let rows = query
+        .query_map(
+            params![micros(since), micros(until), args.probe, args.target],
+            |row| {
+                Ok((
+                    row.get::<_, i64>(0)?,
+                    (row.get(1)?, row.get(2)?, row.get(3)?),
+                    row.get::<_, f64>(4)?,
+                    row.get::<_, Option<String>>(5)?,
+                ))
+            },
+        )
+        .expect("unable to query store");
+
+    let mut found: BTreeMap<Key, Found> = BTreeMap::new();
+    for row in rows {
+        let (timestamp_us, key, latency_ms, error_kind) = row.expect("unable to read store");
+        let found = found.entry(key).or_default();
+        found.attempts += 1;
+        let bucket = found
+            .buckets
+            .entry(timestamp_us - timestamp_us.rem_euclid(bucket_us))
+            .or_default();
+        bucket.0 += 1;
+        match error_kind {
+            None => found
+                .latencies
+                .record(Duration::from_secs_f64(latency_ms.max(0.0) / 1000.0)),
+            Some(kind) => {
+                *found.errors.entry(kind.clone()).or_default() += 1;
+                *bucket.1.entry(kind).or_default() += 1;
+            }
+        }
+    }
+
+    println!(
+        "--- artemiss store {} to {} ---",
+        humantime::format_rfc3339_seconds(since),
+        humantime::format_rfc3339_seconds(until)
+    );
+    if found.is_empty() {
+        println!("no attempts found");
+    }
+    for ((probe, name, target), found) in &found {
+        let failures: u64 = found.errors.values().sum();
+        let mut line = format!("{} ", probe);
+        if let Some(name) = name {
+            line.push_str(name);
+            line.push(' ');
+        }
+        line.push_str(&format!(
+            "{}: attempts={} failures={} success={:.2}%",
+            target,
+            found.attempts,
+            failures,
+            (found.attempts - failures) as f64 * 100.0 / found.attempts as f64
+        ));
+        if let Some(latency) = found.latencies.percentiles() {
+            line.push_str(&format!(
+                " p50={:.3}ms p90={:.3}ms p95={:.3}ms p99={:.3}ms p99.9={:.3}ms max={:.3}ms",
+                latency.p50, latency.p90, latency.p95, latency.p99, latency.p999, latency.max
+            ));
+        }
+        for (kind, count) in &found.errors {
+            line.push_str(&format!(" {}={}", kind, count));
+        }
+        println!("{}", line);
+
+        // Only buckets with errors are listed, so that the timeline shows when they happened.
+        for (start, (attempts, errors)) in &found.buckets {
+            if errors.is_empty() {
+                continue;
+            }
+            let start = UNIX_EPOCH + Duration::from_micros(*start as u64);
+            let mut line = format!(
+                "  {} attempts={} failures={}",
+                humantime::format_rfc3339_seconds(start),
+                attempts,
+                errors.values().sum::<u64>()
+            );
+            for (kind, count) in errors {
+                line.push_str(&format!(" {}={}", kind, count));
+            }
+            println!("{}", line);
+        }
+    }
+}
+
+/// Reads every attempt in the store, with the target it was made against, its latency and the
+/// kind of its error if it failed.
+pub(crate) fn attempts(path: &Path, mut each: impl FnMut(Key, f64, Option<String>)) {
+    let conn = open(path);
+    let mut query = conn
+        .prepare("SELECT probe, name, target, latency_ms, error_kind FROM attempts")
+        .expect("unable to query store");
+    let rows = query
+        .query_map([], |row| {
+            Ok((
+                (row.get(0)?, row.get(1)?, row.get(2)?),
+                row.get::<_, f64>(3)?,
+                row.get::<_, Option<String>>(4)?,
+            ))
+        })
+        .expect("unable to query store");
+    for row in rows {
+        let (key, latency_ms, error_kind) = row.expect("unable to read store");
+        each(key, latency_ms, error_kind);
+    }
+}
+
+/// Parses a time like `2026-10-14T02:10:00Z`, or a duration like `1h` before `now`.
+fn parse_time(time: &str, now: SystemTime) -> Result<SystemTime, String> {
+    match humantime::parse_duration(time) {
+        Ok(ago) => Ok(now - ago),
+        Err(_) => humantime::parse_rfc3339_weak(time).map_err(|e| format!("{}: {}", time, e)),
+    }
+}
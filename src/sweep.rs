@@ -0,0 +1,105 @@
+use std::{
+    fmt::Write,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+
+use clap::Args;
+use serde::{Deserialize, Serialize};
+
+use crate::report::ProbeError;
+
+/// Options for sweeping the size of payloads to find the largest that gets through.
+#[derive(Args, Debug, Default, Serialize, Deserialize)]
+pub struct SweepArgs {
+    /// Send a payload of each of these sizes in bytes on every attempt, e.g.
+    /// `64,1400,1472,1500,8972`, and wait for a reply to each, reporting the largest size that
+    /// got through. Sizes that fail while smaller ones succeed point to a path MTU black hole.
+    /// Can be repeated or separated by commas.
+    #[arg(long, value_delimiter = ',')]
+    #[serde(default)]
+    pub sweep_sizes: Vec<usize>,
+}
+
+/// Sizes to sweep, and the largest size every attempt so far got through.
+pub struct Sweep {
+    sizes: Vec<usize>,
+    /// `usize::MAX` until the first attempt.
+    consistent: AtomicUsize,
+}
+
+/// A payload of the size, filled with a pattern that an echo can be checked against.
+pub fn payload(size: usize) -> Vec<u8> {
+    (0..size).map(|i| b'a' + (i % 26) as u8).collect()
+}
+
+impl Sweep {
+    /// Sweeps the sizes given, if any.
+    pub fn new(args: &SweepArgs) -> Option<Self> {
+        let mut sizes = args.sweep_sizes.clone();
+        sizes.sort_unstable();
+        sizes.dedup();
+        assert!(!sizes.contains(&0), "--sweep-sizes must be at least 1");
+
+        (!sizes.is_empty()).then(|| Sweep {
+            sizes,
+            consistent: AtomicUsize::new(usize::MAX),
+        })
+    }
+
+    /// Sizes to send on every attempt, smallest first.
+    pub fn sizes(&self) -> &[usize] {
+        &self.sizes
+    }
+
+    /// Reports the outcome of sending each size in an attempt as details, failing the attempt if
+    /// any size failed.
+    pub fn report(
+        &self,
+        results: Vec<(usize, Result<Duration, ProbeError>)>,
+        details: &mut Vec<(&'static str, String)>,
+    ) -> Option<ProbeError> {
+        // Sizes only count as through while every smaller one also got through.
+        let max = results
+            .iter()
+            .take_while(|(_, result)| result.is_ok())
+            .last()
+            .map_or(0, |(size, _)| *size);
+        let consistent = self.consistent.fetch_min(max, Ordering::Relaxed).min(max);
+
+        let mut rtts = String::new();
+        for (size, result) in &results {
+            match result {
+                Ok(rtt) => write!(rtts, "{}={:.3}ms ", size, rtt.as_secs_f64() * 1000.0),
+                Err(e) => write!(rtts, "{}={} ", size, e.kind),
+            }
+            .unwrap();
+        }
+        details.push(("sizes", rtts.trim_end().to_string()));
+        details.push(("max_size", max.to_string()));
+        details.push(("consistent_max_size", consistent.to_string()));
+
+        let mut failed = results
+            .into_iter()
+            .filter_map(|(size, result)| match result {
+                Ok(_) => None,
+                Err(e) => Some((size, e)),
+            });
+        let (size, error) = failed.next()?;
+        // The target is unreachable altogether, rather than only for large payloads.
+        if max == 0 {
+            return Some(error);
+        }
+        let mut sizes = size.to_string();
+        for (size, _) in failed {
+            write!(sizes, ", {}", size).unwrap();
+        }
+        Some(ProbeError::new(
+            "mtu",
+            format!(
+                "sizes {} failed, the first with {}: {}; the largest size through was {}",
+                sizes, error.kind, error.message, max
+            ),
+        ))
+    }
+}
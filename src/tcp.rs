@@ -0,0 +1,468 @@
+use std::time::{Duration, Instant};
+
+use clap::Parser;
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{tcp::OwnedWriteHalf, TcpStream},
+    sync::{oneshot, Mutex},
+    time,
+};
+
+use crate::{
+    hold::{self, Death, HoldArgs},
+    http::{Proxy, TunnelRefused},
+    limit,
+    misbehave::{Misbehave, MisbehaveArgs},
+    ports,
+    probe::{self, CommonArgs, Probe},
+    report::{Attempt, Labels, Lifecycle, Origin, ProbeError},
+    resolve::{Connection, ResolveArgs, Resolver},
+    sweep::{self, Sweep, SweepArgs},
+};
+
+#[derive(Parser, Debug, Serialize, Deserialize)]
+pub struct TcpArgs {
+    /// Host to connect to.
+    #[arg(long)]
+    host: String,
+
+    /// Port to connect to.
+    #[arg(long)]
+    port: u16,
+
+    /// Connect through this proxy, as `socks5://[user:password@]host:port`, `socks5h://` for the
+    /// proxy to resolve the host itself, or `http://` for an HTTP proxy opening tunnels with
+    /// `CONNECT`.
+    #[arg(long)]
+    proxy: Option<String>,
+
+    /// Credentials for the proxy, as `user:password`.
+    #[arg(long, requires = "proxy")]
+    proxy_auth: Option<String>,
+
+    /// Set a timeout for the connect phase of a socket, including opening a tunnel through the
+    /// proxy.
+    #[arg(long, default_value_t = 15)]
+    connect_timeout_ms: u64,
+
+    /// Set a timeout for each payload to be echoed back with `--sweep-sizes`, or written with
+    /// `--hold-payload`.
+    #[arg(long, default_value_t = 1000)]
+    timeout_ms: u64,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    sweep: SweepArgs,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    hold: HoldArgs,
+
+    /// Write this on every attempt over a held connection, e.g. a newline or a no-op of the
+    /// protocol, so that middleboxes see traffic on it. Anything read back is discarded.
+    #[arg(long, requires = "hold")]
+    hold_payload: Option<String>,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    misbehave: MisbehaveArgs,
+
+    /// Send this over connections misbehaved on, e.g. the start of a request of the protocol.
+    #[arg(long, requires = "misbehave")]
+    misbehave_payload: Option<String>,
+
+    /// Open and close this many connections one after another on every attempt rather than one,
+    /// closing each from this end so that it lingers in TIME_WAIT, e.g. to find the rate of new
+    /// connections at which the host runs out of ephemeral ports. Attempts report how many were
+    /// opened, the local ports they used and how much of the ephemeral port range is in use.
+    /// Connections that fail for want of a local port fail with `ports_exhausted`.
+    #[arg(long, conflicts_with_all = ["hold", "sweep_sizes", "misbehave"])]
+    churn: Option<usize>,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    resolve: ResolveArgs,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    pub common: CommonArgs,
+}
+
+pub struct TcpProbe {
+    host: String,
+    port: u16,
+    name: Option<String>,
+    labels: Labels,
+    resolver: Resolver,
+    proxy: Option<Proxy>,
+    connect_timeout: Duration,
+    /// Payload sizes to have echoed back after connecting.
+    sweep: Option<Sweep>,
+    /// Connection held open by every worker, with `--hold`.
+    held: Option<Vec<Mutex<Option<Held>>>>,
+    hold_payload: Option<Vec<u8>>,
+    /// How to misbehave on every connection and what to send over it, with `--misbehave`.
+    misbehave: Option<(Misbehave, Vec<u8>)>,
+    /// Connections to open and close on every attempt, with `--churn`.
+    churn: Option<usize>,
+    timeout: Duration,
+}
+
+/// A connection held open by a worker, read from in the background to notice it die as soon
+/// as it does.
+struct Held {
+    writer: OwnedWriteHalf,
+    died: oneshot::Receiver<Death>,
+    opened: Instant,
+    /// Details of the connection reported with every attempt over it.
+    details: Vec<(&'static str, String)>,
+    lifecycle: Lifecycle,
+    attempts: usize,
+}
+
+impl TcpProbe {
+    /// Connects to the target, through the proxy if there is one, returning how long each phase
+    /// took.
+    async fn connect(&self) -> Result<(Connection, Vec<(&'static str, Duration)>), ProbeError> {
+        let connect = async {
+            let (host, port) = match &self.proxy {
+                Some(proxy) => (proxy.host.as_str(), proxy.port),
+                None => (self.host.as_str(), self.port),
+            };
+            let start = Instant::now();
+            let mut conn = self
+                .resolver
+                .connect(host, port)
+                .await
+                .map_err(|e| ProbeError::from_cause("connect", &e))?;
+            let mut phases = vec![("connect", start.elapsed())];
+
+            if let Some(proxy) = &self.proxy {
+                let start = Instant::now();
+                let tunnel = proxy.tunnel(&mut conn.stream, &self.host, self.port, &self.resolver);
+                if let Err(e) = tunnel.await {
+                    let kind = e
+                        .downcast_ref::<TunnelRefused>()
+                        .map_or("proxy", |e| e.kind);
+                    return Err(ProbeError::from_cause(kind, &*e));
+                }
+                phases.push(("tunnel", start.elapsed()));
+            }
+            Ok((conn, phases))
+        };
+
+        let throttle = limit::acquire().await;
+        match time::timeout(self.connect_timeout, connect).await {
+            Ok(Ok((conn, mut phases))) => {
+                phases.splice(0..0, throttle.phase());
+                Ok((conn, phases))
+            }
+            Ok(Err(e)) => Err(e),
+            Err(_) => {
+                let message = format!("timed out after {}ms", self.connect_timeout.as_millis());
+                Err(ProbeError::new("connect_timeout", message))
+            }
+        }
+    }
+
+    /// Details of a connection, naming the proxy it goes through, if any.
+    fn details(&self, conn: &Connection) -> Vec<(&'static str, String)> {
+        let mut details = conn.details();
+        if let Some(proxy) = &self.proxy {
+            details.push(("proxy", proxy.addr()));
+        }
+        details
+    }
+
+    /// Opens a connection for a worker to hold, returning how long each phase took.
+    async fn open(
+        &self,
+        worker: usize,
+    ) -> Result<(Held, Vec<(&'static str, Duration)>), ProbeError> {
+        let (conn, phases) = self.connect().await?;
+
+        let origin = Origin {
+            probe: self.kind(),
+            name: self.name.clone(),
+            labels: self.labels.clone(),
+            target: self.target(),
+            worker,
+        };
+        let lifecycle = Lifecycle::opened(
+            &origin,
+            conn.stream.local_addr().ok(),
+            conn.stream.peer_addr().ok(),
+        );
+        let mut details = self.details(&conn);
+        details.push(lifecycle.detail());
+
+        let opened = Instant::now();
+        let (mut reader, writer) = conn.stream.into_split();
+        let (sender, died) = oneshot::channel();
+        tokio::spawn(async move {
+            let mut buf = [0; 4096];
+            let error = loop {
+                match reader.read(&mut buf).await {
+                    Ok(0) => break None,
+                    Ok(_) => continue,
+                    Err(e) => break Some(e),
+                }
+            };
+            let _ = sender.send(Death::new(opened, error.as_ref()));
+        });
+
+        let held = Held {
+            writer,
+            died,
+            opened,
+            details,
+            lifecycle,
+            attempts: 1,
+        };
+        Ok((held, phases))
+    }
+
+    /// Checks that the connection held by a worker is still alive, writing `--hold-payload`
+    /// over it, or opens one if it has none.
+    async fn hold(&self, worker: usize, held: &Mutex<Option<Held>>) -> Attempt {
+        let mut held = held.lock().await;
+        let start = Instant::now();
+        let open = match &mut *held {
+            Some(open) => open,
+            None => {
+                let (phases, details, error) = match self.open(worker).await {
+                    Ok((open, phases)) => {
+                        let details = open.details.clone();
+                        *held = Some(open);
+                        (phases, details, None)
+                    }
+                    Err(error) => (vec![], vec![], Some(error)),
+                };
+                return Attempt {
+                    duration: start.elapsed(),
+                    phases,
+                    details,
+                    error,
+                };
+            }
+        };
+
+        open.attempts += 1;
+        let mut phases = vec![];
+        let mut death = open.died.try_recv().ok();
+        if let (None, Some(payload)) = (&death, &self.hold_payload) {
+            let send = Instant::now();
+            match time::timeout(self.timeout, open.writer.write_all(payload)).await {
+                Ok(Ok(())) => phases.push(("send", send.elapsed())),
+                Ok(Err(e)) => death = Some(Death::new(open.opened, Some(&e))),
+                Err(_) => {
+                    let message = format!("write blocked for {}ms", self.timeout.as_millis());
+                    let error = ProbeError::new("timeout", message);
+                    death = Some(Death::with_error(open.opened, error));
+                }
+            }
+        }
+
+        let mut details = open.details.clone();
+        let error = match death {
+            Some(death) => {
+                open.lifecycle
+                    .closed(Some(open.attempts), Some(&death.error));
+                details.extend(death.details());
+                *held = None;
+                Some(death.error)
+            }
+            None => {
+                details.push(hold::age(open.opened.elapsed()));
+                None
+            }
+        };
+        Attempt {
+            duration: start.elapsed(),
+            phases,
+            details,
+            error,
+        }
+    }
+
+    /// Opens connections one after another, closing each as soon as it is open, until as many as
+    /// to churn have been or one fails.
+    async fn churn(&self, count: usize) -> (Vec<(&'static str, String)>, Option<ProbeError>) {
+        let mut opened = 0;
+        let mut range: Option<(u16, u16)> = None;
+        let mut remote = None;
+        let mut error = None;
+        while opened < count {
+            match self.connect().await {
+                Ok((conn, _)) => {
+                    if let Ok(addr) = conn.stream.local_addr() {
+                        let (low, high) = range.unwrap_or((addr.port(), addr.port()));
+                        range = Some((low.min(addr.port()), high.max(addr.port())));
+                    }
+                    remote = remote.or(conn.stream.peer_addr().ok());
+                    opened += 1;
+                }
+                Err(e) => {
+                    let message = format!(
+                        "after opening {} of {} connections: {}",
+                        opened, count, e.message
+                    );
+                    error = Some(ProbeError::new(e.kind, message));
+                    break;
+                }
+            }
+        }
+
+        let mut details = vec![("churned", opened.to_string())];
+        if let Some((low, high)) = range {
+            details.push(("local_ports", format!("{}-{}", low, high)));
+        }
+        details.extend(ports::details(remote));
+        (details, error)
+    }
+
+    /// Writes a payload of each size to sweep over the connection, waiting for it to be echoed
+    /// back.
+    async fn sweep(
+        &self,
+        sweep: &Sweep,
+        mut stream: TcpStream,
+    ) -> Vec<(usize, Result<Duration, ProbeError>)> {
+        let mut results = vec![];
+        for &size in sweep.sizes() {
+            let echo = time::timeout(self.timeout, echo(&mut stream, size)).await;
+            let result = match echo {
+                Ok(result) => result,
+                Err(_) => Err(ProbeError::new(
+                    "timeout",
+                    format!("no echo within {}ms", self.timeout.as_millis()),
+                )),
+            };
+            // The connection is left in an unknown state, so larger sizes fail without trying.
+            let failed = result.is_err();
+            results.push((size, result));
+            if failed {
+                break;
+            }
+        }
+        results
+    }
+}
+
+impl Probe for TcpProbe {
+    fn kind(&self) -> &'static str {
+        "tcp"
+    }
+
+    fn target(&self) -> String {
+        format!("{}:{}", self.host, self.port)
+    }
+
+    async fn attempt(&self, worker: usize) -> Attempt {
+        if let Some(held) = &self.held {
+            return self.hold(worker, &held[worker % held.len()]).await;
+        }
+
+        let start = Instant::now();
+        if let Some(count) = self.churn {
+            let (details, error) = self.churn(count).await;
+            return Attempt {
+                duration: start.elapsed(),
+                phases: vec![],
+                details,
+                error,
+            };
+        }
+        let (phases, details, error) = match self.connect().await {
+            Ok((conn, mut phases)) => match (&self.sweep, &self.misbehave) {
+                (_, Some((misbehave, payload))) => {
+                    let mut details = self.details(&conn);
+                    let misbehaved = misbehave.run(conn.stream, payload).await;
+                    phases.push(("held", misbehaved.held));
+                    details.extend(misbehaved.details(misbehave));
+                    (phases, details, misbehaved.error())
+                }
+                (Some(sweep), None) => {
+                    let mut details = self.details(&conn);
+                    let results = self.sweep(sweep, conn.stream).await;
+                    let error = sweep.report(results, &mut details);
+                    (phases, details, error)
+                }
+                (None, None) => (phases, self.details(&conn), None),
+            },
+            Err(error) if error.kind == ports::EXHAUSTED => {
+                (vec![], ports::details(None), Some(error))
+            }
+            Err(error) => (vec![], vec![], Some(error)),
+        };
+
+        Attempt {
+            duration: start.elapsed(),
+            phases,
+            details,
+            error,
+        }
+    }
+}
+
+pub async fn tcp_main(args: TcpArgs) {
+    assert!(
+        !args.hold.hold || args.sweep.sweep_sizes.is_empty(),
+        "--hold cannot be used with --sweep-sizes"
+    );
+    assert!(
+        args.misbehave.misbehave.is_none()
+            || (!args.hold.hold && args.sweep.sweep_sizes.is_empty()),
+        "--misbehave cannot be used with --hold or --sweep-sizes"
+    );
+    let probe = TcpProbe {
+        host: args.host,
+        port: args.port,
+        name: args.common.name.clone(),
+        labels: args.common.labels(),
+        resolver: Resolver::new(&args.resolve),
+        proxy: args
+            .proxy
+            .as_deref()
+            .map(|proxy| Proxy::new(proxy, args.proxy_auth.as_deref())),
+        connect_timeout: Duration::from_millis(args.connect_timeout_ms),
+        sweep: Sweep::new(&args.sweep),
+        held: args.hold.hold.then(|| {
+            (0..args.common.parallel)
+                .map(|_| Mutex::new(None))
+                .collect()
+        }),
+        hold_payload: args.hold_payload.map(String::into_bytes),
+        misbehave: Misbehave::new(&args.misbehave).map(|misbehave| {
+            let payload = args.misbehave_payload.unwrap_or_default();
+            (misbehave, payload.into_bytes())
+        }),
+        churn: args.churn,
+        timeout: Duration::from_millis(args.timeout_ms),
+    };
+
+    probe::run(probe, &args.common).await
+}
+
+/// Writes a payload of the size and reads it back, returning the round trip.
+async fn echo(stream: &mut TcpStream, size: usize) -> Result<Duration, ProbeError> {
+    let payload = sweep::payload(size);
+    let start = Instant::now();
+    stream
+        .write_all(&payload)
+        .await
+        .map_err(|e| ProbeError::from_cause("send", &e))?;
+    let mut reply = vec![0; size];
+    stream
+        .read_exact(&mut reply)
+        .await
+        .map_err(|e| ProbeError::from_cause("receive", &e))?;
+    if reply != payload {
+        return Err(ProbeError::new(
+            "unexpected_reply",
+            format!("echo of {} bytes does not match what was sent", size),
+        ));
+    }
+    Ok(start.elapsed())
+}
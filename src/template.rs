@@ -0,0 +1,320 @@
+use std::{
+    collections::BTreeMap,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use rand::Rng;
+
+/// Text with placeholders expanded on every attempt:
+///
+/// - `{{seq}}`: number of the attempt, counting from 0 for the probe.
+/// - `{{uuid}}`: a random UUID.
+/// - `{{timestamp}}`: milliseconds since the Unix epoch.
+/// - `{{rand_int(min,max)}}`: a random integer between `min` and `max` inclusive.
+///
+/// Placeholders other than `rand_int` expand to the same value everywhere in an attempt. Probes
+/// that set variables of their own during an attempt also expand `{{name}}` to their values.
+#[derive(Debug)]
+pub struct Template {
+    parts: Vec<Part>,
+}
+
+#[derive(Debug, PartialEq)]
+enum Part {
+    Literal(Vec<u8>),
+    Seq,
+    Uuid,
+    Timestamp,
+    RandInt(i64, i64),
+    Var(String),
+}
+
+/// Values placeholders expand to in one attempt.
+pub struct Vars {
+    seq: u64,
+    uuid: String,
+    timestamp: u128,
+    values: BTreeMap<String, String>,
+}
+
+impl Vars {
+    /// Values for the next attempt of a probe, counting attempts with its sequence.
+    pub fn next(sequence: &AtomicU64) -> Self {
+        let mut uuid: [u8; 16] = rand::thread_rng().gen();
+        // Version 4, variant 1.
+        uuid[6] = (uuid[6] & 0x0f) | 0x40;
+        uuid[8] = (uuid[8] & 0x3f) | 0x80;
+        let hex: String = uuid.iter().map(|byte| format!("{:02x}", byte)).collect();
+
+        Vars {
+            seq: sequence.fetch_add(1, Ordering::Relaxed),
+            uuid: format!(
+                "{}-{}-{}-{}-{}",
+                &hex[..8],
+                &hex[8..12],
+                &hex[12..16],
+                &hex[16..20],
+                &hex[20..]
+            ),
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis(),
+            values: BTreeMap::new(),
+        }
+    }
+
+    /// Sets a variable, for `{{name}}` to expand to from then on.
+    pub fn set(&mut self, name: &str, value: String) {
+        self.values.insert(name.to_string(), value);
+    }
+}
+
+impl Template {
+    /// Parses the placeholders in the text, failing on any that is unknown.
+    pub fn parse(text: impl Into<Vec<u8>>) -> Result<Self, String> {
+        Self::parse_with_vars(text, &[])
+    }
+
+    /// Parses the placeholders in the text, which may also name the variables given.
+    pub fn parse_with_vars(text: impl Into<Vec<u8>>, vars: &[String]) -> Result<Self, String> {
+        let mut rest: &[u8] = &text.into();
+        let mut parts = vec![];
+        let mut literal = vec![];
+        while let Some(mut open) = find(rest, b"{{") {
+            // The innermost braces open the placeholder, e.g. in `{{{seq}}}` for a JSON object.
+            while rest.get(open + 2) == Some(&b'{') {
+                open += 1;
+            }
+            let Some(close) = find(&rest[open + 2..], b"}}") else {
+                break;
+            };
+            literal.extend_from_slice(&rest[..open]);
+            let name = String::from_utf8_lossy(&rest[open + 2..open + 2 + close]);
+            let name = name.trim();
+            let part = match vars.iter().any(|var| var == name) {
+                true => Part::Var(name.to_string()),
+                false => placeholder(name)?,
+            };
+            if !literal.is_empty() {
+                parts.push(Part::Literal(std::mem::take(&mut literal)));
+            }
+            parts.push(part);
+            rest = &rest[open + 2 + close + 2..];
+        }
+        literal.extend_from_slice(rest);
+        if !literal.is_empty() {
+            parts.push(Part::Literal(literal));
+        }
+        Ok(Template { parts })
+    }
+
+    /// Whether the text has no placeholders, so it renders the same every time.
+    pub fn is_static(&self) -> bool {
+        self.parts
+            .iter()
+            .all(|part| matches!(part, Part::Literal(_)))
+    }
+
+    /// Expands the placeholders with the values for an attempt.
+    pub fn render(&self, vars: &Vars) -> Vec<u8> {
+        let mut rendered = vec![];
+        for part in &self.parts {
+            match part {
+                Part::Literal(literal) => rendered.extend_from_slice(literal),
+                Part::Seq => rendered.extend_from_slice(vars.seq.to_string().as_bytes()),
+                Part::Uuid => rendered.extend_from_slice(vars.uuid.as_bytes()),
+                Part::Timestamp => {
+                    rendered.extend_from_slice(vars.timestamp.to_string().as_bytes())
+                }
+                Part::RandInt(min, max) => {
+                    let value = rand::thread_rng().gen_range(*min..=*max);
+                    rendered.extend_from_slice(value.to_string().as_bytes());
+                }
+                Part::Var(name) => {
+                    if let Some(value) = vars.values.get(name) {
+                        rendered.extend_from_slice(value.as_bytes());
+                    }
+                }
+            }
+        }
+        rendered
+    }
+
+    /// Expands the placeholders of text that was valid UTF-8.
+    pub fn render_string(&self, vars: &Vars) -> String {
+        String::from_utf8_lossy(&self.render(vars)).into_owned()
+    }
+}
+
+fn placeholder(name: &str) -> Result<Part, String> {
+    match name {
+        "seq" => return Ok(Part::Seq),
+        "uuid" => return Ok(Part::Uuid),
+        "timestamp" => return Ok(Part::Timestamp),
+        _ => {}
+    }
+    let args = name
+        .strip_prefix("rand_int(")
+        .and_then(|args| args.strip_suffix(')'))
+        .ok_or_else(|| format!("unknown placeholder {{{{{}}}}}", name))?;
+    let (min, max) = args
+        .split_once(',')
+        .ok_or_else(|| format!("rand_int takes a min and a max: {{{{{}}}}}", name))?;
+    let parse = |bound: &str| {
+        bound
+            .trim()
+            .parse::<i64>()
+            .map_err(|e| format!("invalid bound {:?} of rand_int: {}", bound.trim(), e))
+    };
+    let (min, max) = (parse(min)?, parse(max)?);
+    if min > max {
+        return Err(format!("rand_int min {} is greater than max {}", min, max));
+    }
+    Ok(Part::RandInt(min, max))
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(template: &str, seq: u64) -> String {
+        let template = Template::parse(template).unwrap();
+        template.render_string(&Vars::next(&AtomicU64::new(seq)))
+    }
+
+    #[test]
+    fn text_without_placeholders_is_static() {
+        let template = Template::parse(r#"{"a":{"b":1}}"#).unwrap();
+        assert!(template.is_static());
+        assert_eq!(render(r#"{"a":{"b":1}}"#, 0), r#"{"a":{"b":1}}"#);
+    }
+
+    #[test]
+    fn expands_builtins() {
+        assert_eq!(render("id={{seq}}", 7), "id=7");
+        let uuid = render("{{uuid}}", 0);
+        assert_eq!(uuid.len(), 36);
+        assert_eq!(&uuid[14..15], "4");
+        assert!(render("{{timestamp}}", 0).parse::<u128>().unwrap() > 1_600_000_000_000);
+    }
+
+    #[test]
+    fn builtins_expand_to_the_same_value_within_an_attempt() {
+        let rendered = render("{{uuid}} {{uuid}}", 0);
+        let (first, second) = rendered.split_once(' ').unwrap();
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn vars_count_attempts() {
+        let sequence = AtomicU64::new(0);
+        let template = Template::parse("{{seq}}").unwrap();
+        assert_eq!(template.render_string(&Vars::next(&sequence)), "0");
+        assert_eq!(template.render_string(&Vars::next(&sequence)), "1");
+    }
+
+    #[test]
+    fn whitespace_inside_placeholders_is_ignored() {
+        assert_eq!(render("{{ seq }}", 3), "3");
+        assert_eq!(render("{{\tseq\n}}", 3), "3");
+        assert_eq!(render("{{ rand_int( 4 , 4 ) }}", 0), "4");
+    }
+
+    #[test]
+    fn unclosed_placeholders_are_left_as_text() {
+        let template = Template::parse("a {{seq").unwrap();
+        assert!(template.is_static());
+        assert_eq!(render("a {{seq", 0), "a {{seq");
+        assert_eq!(render("{{seq}} {{seq", 2), "2 {{seq");
+    }
+
+    #[test]
+    fn braces_around_placeholders_are_kept() {
+        assert_eq!(render("{{{seq}}}", 5), "{5}");
+        assert_eq!(render(r#"{"id":{{seq}}}"#, 5), r#"{"id":5}"#);
+        assert_eq!(render(r#"{"a":{"id":{{seq}}}}"#, 5), r#"{"a":{"id":5}}"#);
+    }
+
+    #[test]
+    fn nested_placeholders_are_rejected() {
+        let error = Template::parse("{{ {{seq}} }}").unwrap_err();
+        assert!(error.contains("unknown placeholder"), "{}", error);
+    }
+
+    #[test]
+    fn unknown_placeholders_are_rejected() {
+        assert_eq!(
+            Template::parse("{{nope}}").unwrap_err(),
+            "unknown placeholder {{nope}}"
+        );
+        assert!(Template::parse("{{}}").is_err());
+    }
+
+    #[test]
+    fn rand_int_stays_within_its_bounds() {
+        let template = Template::parse("{{rand_int(-2,2)}}").unwrap();
+        let sequence = AtomicU64::new(0);
+        for _ in 0..200 {
+            let value: i64 = template
+                .render_string(&Vars::next(&sequence))
+                .parse()
+                .unwrap();
+            assert!((-2..=2).contains(&value));
+        }
+        assert_eq!(render("{{rand_int(9,9)}}", 0), "9");
+    }
+
+    #[test]
+    fn rand_int_rejects_bad_bounds() {
+        assert_eq!(
+            Template::parse("{{rand_int(5,1)}}").unwrap_err(),
+            "rand_int min 5 is greater than max 1"
+        );
+        let error = Template::parse("{{rand_int(a,1)}}").unwrap_err();
+        assert!(
+            error.starts_with(r#"invalid bound "a" of rand_int"#),
+            "{}",
+            error
+        );
+        let error = Template::parse("{{rand_int(1,99999999999999999999)}}").unwrap_err();
+        assert!(error.starts_with("invalid bound"), "{}", error);
+        let error = Template::parse("{{rand_int(1)}}").unwrap_err();
+        assert!(
+            error.starts_with("rand_int takes a min and a max"),
+            "{}",
+            error
+        );
+        assert!(Template::parse("{{rand_int(1,2}}").is_err());
+        assert!(Template::parse("{{rand_int(1,2,3)}}").is_err());
+    }
+
+    #[test]
+    fn vars_are_expanded_and_take_precedence_over_builtins() {
+        let vars = ["token".to_string(), "seq".to_string()];
+        let template = Template::parse_with_vars("{{token}}/{{seq}}/{{uuid}}", &vars).unwrap();
+        let mut values = Vars::next(&AtomicU64::new(1));
+        values.set("token", "abc".to_string());
+        values.set("seq", "mine".to_string());
+        let rendered = template.render_string(&values);
+        assert!(rendered.starts_with("abc/mine/"), "{}", rendered);
+    }
+
+    #[test]
+    fn unset_vars_expand_to_nothing() {
+        let template = Template::parse_with_vars("[{{token}}]", &["token".to_string()]).unwrap();
+        assert!(!template.is_static());
+        assert_eq!(
+            template.render_string(&Vars::next(&AtomicU64::new(0))),
+            "[]"
+        );
+        assert!(Template::parse("{{token}}").is_err());
+    }
+}
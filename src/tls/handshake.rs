@@ -0,0 +1,227 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use clap::Parser;
+use log::warn;
+use rustls::pki_types::ServerName;
+use serde::{Deserialize, Serialize};
+use tokio::time;
+use tokio_rustls::TlsConnector;
+use x509_parser::{certificate::X509Certificate, prelude::FromDer};
+
+use super::TlsArgs;
+use crate::{
+    limit,
+    probe::{self, CommonArgs, Probe},
+    report::{Attempt, ProbeError},
+    resolve,
+};
+
+const DAY: u64 = 24 * 60 * 60;
+
+#[derive(Parser, Debug, Serialize, Deserialize)]
+pub struct HandshakeArgs {
+    /// Host to connect to.
+    #[arg(long)]
+    host: String,
+
+    /// Port to connect to.
+    #[arg(long, default_value_t = 443)]
+    port: u16,
+
+    /// Offer these protocols with ALPN, e.g. `h2`. Can be repeated.
+    #[arg(long)]
+    alpn: Vec<String>,
+
+    /// Warn when the leaf certificate expires within this many days.
+    #[arg(long)]
+    cert_expiry_warn_days: Option<u64>,
+
+    /// Count the attempt as failed, rather than warning, when the leaf certificate expires within
+    /// `--cert-expiry-warn-days`.
+    #[arg(long, requires = "cert_expiry_warn_days")]
+    fail_on_cert_expiry: bool,
+
+    /// Set a timeout for the connect phase of a socket.
+    #[arg(long, default_value_t = 15)]
+    connect_timeout_ms: u64,
+
+    /// Set a timeout for the TLS handshake.
+    #[arg(long, default_value_t = 20)]
+    timeout_ms: u64,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    tls: TlsArgs,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    pub common: CommonArgs,
+}
+
+/// Completes a TLS handshake on every attempt and inspects the certificates presented.
+pub struct HandshakeProbe {
+    host: String,
+    port: u16,
+    connector: TlsConnector,
+    sni: Option<String>,
+    expiry_warning: Option<Duration>,
+    fail_on_expiry: bool,
+    warned: AtomicBool,
+    connect_timeout: Duration,
+    timeout: Duration,
+}
+
+impl HandshakeProbe {
+    pub fn new(args: &HandshakeArgs) -> Self {
+        let mut config = super::configure(&args.tls);
+        config.alpn_protocols = args.alpn.iter().map(|p| p.as_bytes().to_vec()).collect();
+
+        HandshakeProbe {
+            host: args.host.clone(),
+            port: args.port,
+            connector: TlsConnector::from(Arc::new(config)),
+            sni: args.tls.sni.clone(),
+            expiry_warning: args
+                .cert_expiry_warn_days
+                .map(|days| Duration::from_secs(days * DAY)),
+            fail_on_expiry: args.fail_on_cert_expiry,
+            warned: AtomicBool::new(false),
+            connect_timeout: Duration::from_millis(args.connect_timeout_ms),
+            timeout: Duration::from_millis(args.timeout_ms),
+        }
+    }
+
+    /// Warns about, or fails on, a leaf certificate expiring within the warning period.
+    fn check_expiry(&self, expires_in: Duration, not_after: &str) -> Option<ProbeError> {
+        let warning = self.expiry_warning?;
+        if expires_in > warning {
+            return None;
+        }
+
+        let message = format!(
+            "certificate of {} expires in {} days, at {}",
+            self.target(),
+            expires_in.as_secs() / DAY,
+            not_after
+        );
+        if self.fail_on_expiry {
+            return Some(ProbeError::new("cert_expiry", message));
+        }
+        // Only warn once, rather than on every attempt.
+        if !self.warned.swap(true, Ordering::Relaxed) {
+            warn!("{}", message);
+        }
+        None
+    }
+}
+
+impl Probe for HandshakeProbe {
+    fn kind(&self) -> &'static str {
+        "tls"
+    }
+
+    fn target(&self) -> String {
+        format!("{}:{}", self.host, self.port)
+    }
+
+    async fn attempt(&self, _worker: usize) -> Attempt {
+        let start = Instant::now();
+        let mut phases = vec![];
+        let mut details = vec![];
+
+        let throttle = limit::acquire().await;
+        phases.extend(throttle.phase());
+        let connect_start = Instant::now();
+        let connect = resolve::connect(&self.host, self.port);
+        let tcp = match time::timeout(self.connect_timeout, connect).await {
+            Ok(Ok(conn)) => {
+                details.extend(conn.details());
+                conn.stream
+            }
+            Ok(Err(e)) => return failed(start, phases, ProbeError::from_cause("connect", &e)),
+            Err(_) => {
+                let message = format!("timed out after {}ms", self.connect_timeout.as_millis());
+                return failed(start, phases, ProbeError::new("connect_timeout", message));
+            }
+        };
+        phases.push(("connect", connect_start.elapsed()));
+
+        let name = match ServerName::try_from(self.sni.as_ref().unwrap_or(&self.host).clone()) {
+            Ok(name) => name,
+            Err(e) => return failed(start, phases, ProbeError::new("tls", e)),
+        };
+        phases.extend(limit::handshake().await);
+        let handshake_start = Instant::now();
+        let handshake = self.connector.connect(name, tcp);
+        let stream = match time::timeout(self.timeout, handshake).await {
+            Ok(Ok(stream)) => stream,
+            Ok(Err(e)) => return failed(start, phases, ProbeError::from_cause("tls", &e)),
+            Err(_) => {
+                let message = format!("timed out after {}ms", self.timeout.as_millis());
+                return failed(start, phases, ProbeError::new("timeout", message));
+            }
+        };
+        phases.push(("tls", handshake_start.elapsed()));
+        let duration = start.elapsed();
+        drop(throttle);
+
+        let (_, conn) = stream.get_ref();
+        if let Some(version) = conn.protocol_version() {
+            details.push(("protocol", format!("{:?}", version)));
+        }
+        if let Some(suite) = conn.negotiated_cipher_suite() {
+            details.push(("cipher", format!("{:?}", suite.suite())));
+        }
+        if let Some(alpn) = conn.alpn_protocol() {
+            details.push(("alpn", String::from_utf8_lossy(alpn).into_owned()));
+        }
+
+        let chain = conn.peer_certificates().unwrap_or_default();
+        details.push(("chain", chain.len().to_string()));
+        let mut error = None;
+        if let Some(leaf) = chain.first() {
+            match X509Certificate::from_der(leaf) {
+                Ok((_, cert)) => {
+                    let not_after = cert.validity().not_after;
+                    let expires_at = UNIX_EPOCH
+                        + Duration::from_secs(not_after.timestamp().try_into().unwrap_or(0));
+                    let expires_in = expires_at
+                        .duration_since(SystemTime::now())
+                        .unwrap_or_default();
+                    details.push(("subject", cert.subject().to_string()));
+                    details.push(("issuer", cert.issuer().to_string()));
+                    details.push(("not_after", not_after.to_string()));
+                    details.push(("expires_in_days", (expires_in.as_secs() / DAY).to_string()));
+                    error = self.check_expiry(expires_in, &not_after.to_string());
+                }
+                Err(e) => error = Some(ProbeError::new("certificate", e)),
+            }
+        }
+
+        Attempt {
+            duration,
+            phases,
+            details,
+            error,
+        }
+    }
+}
+
+pub async fn tls_main(args: HandshakeArgs) {
+    probe::run(HandshakeProbe::new(&args), &args.common).await
+}
+
+fn failed(start: Instant, phases: Vec<(&'static str, Duration)>, error: ProbeError) -> Attempt {
+    Attempt {
+        duration: start.elapsed(),
+        phases,
+        details: vec![],
+        error: Some(error),
+    }
+}
@@ -0,0 +1,151 @@
+mod handshake;
+
+use std::{
+    path::PathBuf,
+    sync::{Arc, OnceLock},
+};
+
+use clap::Args;
+use rustls::{
+    client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+    crypto::{self, CryptoProvider},
+    pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer, ServerName, UnixTime},
+    ClientConfig, DigitallySignedStruct, RootCertStore, SignatureScheme,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::secret;
+
+pub use handshake::{tls_main, HandshakeArgs, HandshakeProbe};
+
+/// Options for customizing TLS connections.
+#[derive(Args, Debug, Default, Serialize, Deserialize)]
+pub struct TlsArgs {
+    /// Trust the certificates in this PEM file instead of the bundled web PKI roots.
+    #[arg(long)]
+    pub ca_cert: Option<PathBuf>,
+
+    /// Present the certificate chain in this PEM file to the server.
+    #[arg(long, requires = "client_key")]
+    pub client_cert: Option<PathBuf>,
+
+    /// Private key in PEM format for `--client-cert`. With the `vault` or `aws-secrets` features,
+    /// the key can instead be read from `vault:PATH#FIELD` or `aws-sm:ID[#FIELD]`.
+    #[arg(long, requires = "client_cert")]
+    pub client_key: Option<PathBuf>,
+
+    /// Skip verifying the server's certificate.
+    #[arg(long)]
+    pub insecure: bool,
+
+    /// Send this server name in the handshake and verify the certificate against it,
+    /// instead of the host being connected to.
+    #[arg(long)]
+    pub sni: Option<String>,
+
+    /// Key of `--client-key` once read, for every connection made with these options to share
+    /// rather than fetching it from a secret manager again.
+    #[arg(skip)]
+    #[serde(skip)]
+    client_key_der: OnceLock<PrivateKeyDer<'static>>,
+}
+
+/// Builds a TLS client configuration trusting the bundled web PKI roots.
+pub fn client_config() -> ClientConfig {
+    configure(&TlsArgs::default())
+}
+
+/// Builds a TLS client configuration with the given customizations.
+pub fn configure(args: &TlsArgs) -> ClientConfig {
+    let provider = Arc::new(crypto::ring::default_provider());
+
+    let mut roots = RootCertStore::empty();
+    match &args.ca_cert {
+        Some(path) => {
+            for cert in CertificateDer::pem_file_iter(path).expect("unable to read ca cert") {
+                roots
+                    .add(cert.expect("invalid ca cert"))
+                    .expect("invalid ca cert");
+            }
+        }
+        None => roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned()),
+    }
+
+    let builder = ClientConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()
+        .expect("error configuring tls")
+        .with_root_certificates(roots);
+
+    let mut config = match (&args.client_cert, &args.client_key) {
+        (Some(cert), Some(key)) => {
+            let chain = CertificateDer::pem_file_iter(cert)
+                .expect("unable to read client cert")
+                .collect::<Result<_, _>>()
+                .expect("invalid client cert");
+            let key = args.client_key_der.get_or_init(|| {
+                let key = secret::read(&key.to_string_lossy());
+                PrivateKeyDer::from_pem_slice(key.as_bytes()).expect("invalid client key")
+            });
+            builder
+                .with_client_auth_cert(chain, key.clone_key())
+                .expect("invalid client cert or key")
+        }
+        _ => builder.with_no_client_auth(),
+    };
+
+    if args.insecure {
+        config
+            .dangerous()
+            .set_certificate_verifier(Arc::new(NoVerifier(provider)));
+    }
+    config
+}
+
+/// Accepts any server certificate, while still checking handshake signatures.
+#[derive(Debug)]
+struct NoVerifier(Arc<CryptoProvider>);
+
+impl ServerCertVerifier for NoVerifier {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        crypto::verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        crypto::verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
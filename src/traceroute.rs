@@ -0,0 +1,239 @@
+use std::{
+    fmt, io,
+    net::IpAddr,
+    time::{Duration, Instant},
+};
+
+use serde::Serialize;
+
+/// Destination port of the probe for the first hop, incremented for every hop after it, as
+/// traceroute does.
+#[cfg(target_os = "linux")]
+const BASE_PORT: u16 = 33434;
+
+/// A hop on the path to a destination, and the round trip of the probe it answered, or neither
+/// if it did not answer in time.
+#[derive(Clone, Debug, Serialize)]
+pub struct Hop {
+    pub ttl: u8,
+    pub addr: Option<IpAddr>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rtt_ms: Option<f64>,
+}
+
+impl fmt::Display for Hop {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (self.addr, self.rtt_ms) {
+            (Some(addr), Some(rtt)) => write!(f, "{} {} {:.3}ms", self.ttl, addr, rtt),
+            _ => write!(f, "{} *", self.ttl),
+        }
+    }
+}
+
+/// Discovers the path to the destination by sending UDP probes with increasing TTLs, up to the
+/// maximum number of hops, until one reaches it. Hops that do not answer within the timeout are
+/// recorded without an address.
+pub async fn trace(dest: IpAddr, max_hops: u8, timeout: Duration) -> io::Result<Vec<Hop>> {
+    tokio::task::spawn_blocking(move || trace_blocking(dest, max_hops, timeout))
+        .await
+        .map_err(io::Error::other)?
+}
+
+/// Sends the probes from an unprivileged UDP socket, reading the ICMP errors they cause from its
+/// error queue, like `tracepath`.
+#[cfg(target_os = "linux")]
+fn trace_blocking(dest: IpAddr, max_hops: u8, timeout: Duration) -> io::Result<Vec<Hop>> {
+    use std::{net::SocketAddr, os::fd::AsRawFd};
+
+    use socket2::{Domain, Protocol, Socket, Type};
+
+    let domain = match dest {
+        IpAddr::V4(_) => Domain::IPV4,
+        IpAddr::V6(_) => Domain::IPV6,
+    };
+    let socket = Socket::new(domain, Type::DGRAM, Some(Protocol::UDP))?;
+    let (level, option) = match dest {
+        IpAddr::V4(_) => (libc::SOL_IP, libc::IP_RECVERR),
+        IpAddr::V6(_) => (libc::SOL_IPV6, libc::IPV6_RECVERR),
+    };
+    let enable: libc::c_int = 1;
+    // SAFETY: the option value points to a c_int of the length given, for an open socket.
+    let result = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            level,
+            option,
+            &enable as *const _ as *const libc::c_void,
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if result != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let mut hops = vec![];
+    for ttl in 1..=max_hops {
+        match dest {
+            IpAddr::V4(_) => socket.set_ttl_v4(ttl.into())?,
+            IpAddr::V6(_) => socket.set_unicast_hops_v6(ttl.into())?,
+        }
+        let port = BASE_PORT + u16::from(ttl);
+        let start = Instant::now();
+        socket.send_to(&[0; 32], &SocketAddr::new(dest, port).into())?;
+
+        let (addr, reached) = match wait(&socket, port, start + timeout)? {
+            Some((addr, reached)) => (Some(addr), reached),
+            None => (None, false),
+        };
+        hops.push(Hop {
+            ttl,
+            addr,
+            rtt_ms: addr.map(|_| start.elapsed().as_secs_f64() * 1000.0),
+        });
+        if reached {
+            break;
+        }
+    }
+    Ok(hops)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn trace_blocking(_dest: IpAddr, _max_hops: u8, _timeout: Duration) -> io::Result<Vec<Hop>> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "traceroute is only supported on linux",
+    ))
+}
+
+/// Waits for the error caused by the probe sent to the port, returning the address of the hop
+/// that sent it, and whether it was the destination.
+#[cfg(target_os = "linux")]
+fn wait(
+    socket: &socket2::Socket,
+    port: u16,
+    deadline: Instant,
+) -> io::Result<Option<(IpAddr, bool)>> {
+    use std::os::fd::AsRawFd;
+
+    loop {
+        let Some(remaining) = deadline.checked_duration_since(Instant::now()) else {
+            return Ok(None);
+        };
+        let mut poll = libc::pollfd {
+            fd: socket.as_raw_fd(),
+            events: libc::POLLIN | libc::POLLERR,
+            revents: 0,
+        };
+        let millis = remaining.as_millis().max(1) as libc::c_int;
+        // SAFETY: one valid pollfd is passed.
+        match unsafe { libc::poll(&mut poll, 1, millis) } {
+            0 => return Ok(None),
+            n if n < 0 => {
+                let e = io::Error::last_os_error();
+                if e.kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+                return Err(e);
+            }
+            _ => {}
+        }
+
+        if poll.revents & libc::POLLIN != 0 {
+            // A reply from the destination itself, which is rare for UDP.
+            let mut buf = [std::mem::MaybeUninit::uninit(); 512];
+            let (_, from) = socket.recv_from(&mut buf)?;
+            if let Some(from) = from.as_socket() {
+                return Ok(Some((from.ip(), true)));
+            }
+            continue;
+        }
+
+        let mut data = [0u8; 512];
+        let mut control = [0u8; 512];
+        // SAFETY: zeroed storage is a valid, empty socket address.
+        let mut name: libc::sockaddr_storage = unsafe { std::mem::zeroed() };
+        let mut iov = libc::iovec {
+            iov_base: data.as_mut_ptr() as *mut libc::c_void,
+            iov_len: data.len(),
+        };
+        // SAFETY: zeroed is a valid msghdr, whose buffers are set below.
+        let mut msg: libc::msghdr = unsafe { std::mem::zeroed() };
+        msg.msg_name = &mut name as *mut _ as *mut libc::c_void;
+        msg.msg_namelen = std::mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+        msg.msg_controllen = control.len() as _;
+        // SAFETY: every buffer the message points to outlives the call.
+        let read = unsafe { libc::recvmsg(socket.as_raw_fd(), &mut msg, libc::MSG_ERRQUEUE) };
+        if read < 0 {
+            let e = io::Error::last_os_error();
+            if e.kind() == io::ErrorKind::WouldBlock {
+                continue;
+            }
+            return Err(e);
+        }
+
+        // The name is the destination of the probe that caused the error, so errors for probes
+        // of earlier hops that answered late can be ignored.
+        // SAFETY: the kernel wrote the address into the storage.
+        let original = unsafe { socket_addr(&name as *const _ as *const libc::sockaddr) };
+        if original.map(|addr| addr.port()) != Some(port) {
+            continue;
+        }
+
+        // SAFETY: the control messages were written by the kernel into the buffer, and the macros
+        // only read within the length it set.
+        unsafe {
+            let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+            while !cmsg.is_null() {
+                let level = (*cmsg).cmsg_level;
+                let kind = (*cmsg).cmsg_type;
+                if (level == libc::SOL_IP && kind == libc::IP_RECVERR)
+                    || (level == libc::SOL_IPV6 && kind == libc::IPV6_RECVERR)
+                {
+                    let err = libc::CMSG_DATA(cmsg) as *const libc::sock_extended_err;
+                    let (time_exceeded, unreachable) = match (*err).ee_origin {
+                        libc::SO_EE_ORIGIN_ICMP => (11, 3),
+                        libc::SO_EE_ORIGIN_ICMP6 => (3, 1),
+                        _ => {
+                            cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+                            continue;
+                        }
+                    };
+                    if let Some(from) = socket_addr(libc::SO_EE_OFFENDER(err)) {
+                        let ee_type = (*err).ee_type;
+                        if ee_type == time_exceeded || ee_type == unreachable {
+                            return Ok(Some((from.ip(), ee_type == unreachable)));
+                        }
+                    }
+                }
+                cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+            }
+        }
+    }
+}
+
+/// Reads an IPv4 or IPv6 socket address.
+///
+/// # Safety
+///
+/// The pointer must point to a socket address as large as its family says.
+#[cfg(target_os = "linux")]
+unsafe fn socket_addr(addr: *const libc::sockaddr) -> Option<std::net::SocketAddr> {
+    use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+
+    match (*addr).sa_family as libc::c_int {
+        libc::AF_INET => {
+            let addr = &*(addr as *const libc::sockaddr_in);
+            let ip = Ipv4Addr::from(u32::from_be(addr.sin_addr.s_addr));
+            Some(SocketAddr::new(ip.into(), u16::from_be(addr.sin_port)))
+        }
+        libc::AF_INET6 => {
+            let addr = &*(addr as *const libc::sockaddr_in6);
+            let ip = Ipv6Addr::from(addr.sin6_addr.s6_addr);
+            Some(SocketAddr::new(ip.into(), u16::from_be(addr.sin6_port)))
+        }
+        _ => None,
+    }
+}
@@ -0,0 +1,334 @@
+use std::{
+    collections::{BTreeMap, VecDeque},
+    io::{self, IsTerminal},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        LazyLock, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use chrono::Local;
+use ratatui::{
+    crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers},
+    layout::{Constraint, Layout},
+    style::{Color, Modifier, Style},
+    symbols::bar,
+    text::{Line, Span},
+    widgets::{Block, Paragraph, Row, Table},
+    Frame,
+};
+use tokio::sync::Notify;
+
+use crate::{
+    report::{Attempt, Origin},
+    stats,
+};
+
+/// Attempts made within this long make up the rolling statistics.
+const WINDOW: Duration = Duration::from_secs(60);
+/// Number of one second columns in each latency sparkline.
+const SPARKLINE: usize = 30;
+/// Number of recent errors kept to display.
+const ERRORS: usize = 100;
+/// Time between redraws, which is also the longest a key press waits to be handled.
+const REFRESH: Duration = Duration::from_millis(250);
+
+const BARS: [&str; 8] = [
+    bar::ONE_EIGHTH,
+    bar::ONE_QUARTER,
+    bar::THREE_EIGHTHS,
+    bar::HALF,
+    bar::FIVE_EIGHTHS,
+    bar::THREE_QUARTERS,
+    bar::SEVEN_EIGHTHS,
+    bar::FULL,
+];
+
+static RUNNING: AtomicBool = AtomicBool::new(false);
+static RENDERER: Mutex<Option<JoinHandle<()>>> = Mutex::new(None);
+static DASHBOARD: LazyLock<Mutex<Dashboard>> = LazyLock::new(|| {
+    Mutex::new(Dashboard {
+        started: Instant::now(),
+        targets: BTreeMap::new(),
+        errors: VecDeque::new(),
+    })
+});
+/// Notified every time the dashboard is asked to quit.
+static QUIT: Notify = Notify::const_new();
+
+/// Keyed by probe kind, name and target, like the statistics.
+type Key = (&'static str, Option<String>, String);
+
+struct Dashboard {
+    started: Instant,
+    targets: BTreeMap<Key, Target>,
+    /// Most recent errors, newest last.
+    errors: VecDeque<String>,
+}
+
+#[derive(Default)]
+struct Target {
+    attempts: u64,
+    failures: u64,
+    /// When each attempt within the window finished, and its latency if it succeeded.
+    recent: VecDeque<(Instant, Option<Duration>)>,
+}
+
+impl Dashboard {
+    fn push_error(&mut self, error: String) {
+        if self.errors.len() == ERRORS {
+            self.errors.pop_front();
+        }
+        self.errors.push_back(error);
+    }
+}
+
+/// Takes over the terminal with a dashboard of every probe target, redrawn in place.
+pub fn start() {
+    assert!(
+        io::stdout().is_terminal(),
+        "--tui needs stdout to be a terminal"
+    );
+
+    LazyLock::force(&DASHBOARD);
+    RUNNING.store(true, Ordering::Relaxed);
+    let renderer = thread::spawn(render);
+    *RENDERER.lock().unwrap() = Some(renderer);
+}
+
+/// Whether the dashboard is running in place of the usual output.
+pub fn enabled() -> bool {
+    RUNNING.load(Ordering::Relaxed)
+}
+
+/// Restores the terminal, leaving the dashboard.
+pub fn stop() {
+    RUNNING.store(false, Ordering::Relaxed);
+    if let Some(renderer) = RENDERER.lock().unwrap().take() {
+        let _ = renderer.join();
+    }
+}
+
+/// Completes when the dashboard is asked to quit, by `q`, Esc or Ctrl-C.
+pub async fn quit() {
+    QUIT.notified().await;
+}
+
+/// Adds a probe attempt to the dashboard.
+pub fn observe(origin: &Origin, attempt: &Attempt) {
+    if !enabled() {
+        return;
+    }
+
+    let now = Instant::now();
+    let key = (origin.probe, origin.name.clone(), origin.target.clone());
+    let mut dashboard = DASHBOARD.lock().unwrap();
+    let dashboard = &mut *dashboard;
+
+    let target = dashboard.targets.entry(key).or_default();
+    target.attempts += 1;
+    target
+        .recent
+        .push_back((now, attempt.error.is_none().then_some(attempt.duration)));
+    while target
+        .recent
+        .front()
+        .is_some_and(|(at, _)| now.duration_since(*at) > WINDOW)
+    {
+        target.recent.pop_front();
+    }
+
+    if let Some(e) = &attempt.error {
+        target.failures += 1;
+        let mut error = format!("{} {} ", Local::now().format("%H:%M:%S"), origin.probe);
+        if let Some(name) = &origin.name {
+            error.push_str(name);
+            error.push(' ');
+        }
+        error.push_str(&format!("{} {}: {}", origin.target, e.kind, e.message));
+        dashboard.push_error(error);
+    }
+}
+
+/// Shows log lines with the recent errors, since the dashboard covers the terminal they would
+/// otherwise be written to.
+pub struct LogWriter;
+
+impl io::Write for LogWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut dashboard = DASHBOARD.lock().unwrap();
+        for line in String::from_utf8_lossy(buf).lines() {
+            if !line.trim().is_empty() {
+                dashboard.push_error(line.to_string());
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Redraws the dashboard and handles key presses until stopped.
+fn render() {
+    let mut terminal = ratatui::init();
+    while enabled() {
+        if terminal.draw(draw).is_err() {
+            break;
+        }
+        if !event::poll(REFRESH).unwrap_or(false) {
+            continue;
+        }
+        if let Ok(Event::Key(key)) = event::read() {
+            // The terminal swallows Ctrl-C as a key press rather than sending a signal.
+            let ctrl_c =
+                key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('c');
+            if key.kind == KeyEventKind::Press
+                && (ctrl_c || matches!(key.code, KeyCode::Char('q') | KeyCode::Esc))
+            {
+                QUIT.notify_one();
+            }
+        }
+    }
+    ratatui::restore();
+}
+
+fn draw(frame: &mut Frame) {
+    let now = Instant::now();
+    let dashboard = DASHBOARD.lock().unwrap();
+
+    let [header, targets, errors] = Layout::vertical([
+        Constraint::Length(1),
+        Constraint::Length(dashboard.targets.len() as u16 + 3),
+        Constraint::Fill(1),
+    ])
+    .areas(frame.area());
+
+    let elapsed = Duration::from_secs(now.duration_since(dashboard.started).as_secs());
+    let title = format!(
+        "artemiss: running for {}, rolling statistics over the last {}s, press q to quit",
+        humantime::format_duration(elapsed),
+        WINDOW.as_secs()
+    );
+    frame.render_widget(Paragraph::new(title).style(bold()), header);
+
+    let rows = dashboard
+        .targets
+        .iter()
+        .map(|((probe, name, target), seen)| {
+            let window: Vec<_> = seen
+                .recent
+                .iter()
+                .filter(|(at, _)| now.duration_since(*at) <= WINDOW)
+                .collect();
+            let mut latencies: Vec<_> = window.iter().filter_map(|(_, latency)| *latency).collect();
+            latencies.sort_unstable();
+
+            let success = match window.len() {
+                0 => "-".to_string(),
+                attempts => format!("{:.2}%", latencies.len() as f64 * 100.0 / attempts as f64),
+            };
+            let percentile = |q| match latencies.is_empty() {
+                true => "-".to_string(),
+                false => format!("{:.3}ms", ms(stats::percentile(&latencies, q))),
+            };
+            let target = match name {
+                Some(name) => format!("{} {}", name, target),
+                None => target.clone(),
+            };
+
+            Row::new(vec![
+                Line::from(*probe),
+                Line::from(target),
+                Line::from(seen.attempts.to_string()),
+                Line::from(seen.failures.to_string()),
+                Line::from(success),
+                Line::from(percentile(0.5)),
+                Line::from(percentile(0.9)),
+                Line::from(percentile(0.99)),
+                sparkline(&seen.recent, now),
+            ])
+        });
+    let table = Table::new(
+        rows,
+        [
+            Constraint::Length(6),
+            Constraint::Fill(1),
+            Constraint::Length(9),
+            Constraint::Length(7),
+            Constraint::Length(8),
+            Constraint::Length(11),
+            Constraint::Length(11),
+            Constraint::Length(11),
+            Constraint::Length(SPARKLINE as u16),
+        ],
+    )
+    .header(
+        Row::new([
+            "probe",
+            "target",
+            "attempts",
+            "failed",
+            "success",
+            "p50",
+            "p90",
+            "p99",
+            "latency per second",
+        ])
+        .style(bold()),
+    )
+    .block(Block::bordered().title("Targets"));
+    frame.render_widget(table, targets);
+
+    let lines: Vec<_> = dashboard.errors.iter().rev().map(Line::raw).collect();
+    let errors_block = Block::bordered().title("Recent errors");
+    frame.render_widget(Paragraph::new(lines).block(errors_block), errors);
+}
+
+/// Mean latency of the successful attempts in each of the last seconds, scaled to the slowest,
+/// with seconds that had failures in red.
+fn sparkline(recent: &VecDeque<(Instant, Option<Duration>)>, now: Instant) -> Line<'static> {
+    let mut seconds = [(Duration::ZERO, 0u32, false); SPARKLINE];
+    for (at, latency) in recent {
+        let age = now.duration_since(*at).as_secs() as usize;
+        let Some(second) = SPARKLINE.checked_sub(age + 1) else {
+            continue;
+        };
+        match latency {
+            Some(latency) => {
+                seconds[second].0 += *latency;
+                seconds[second].1 += 1;
+            }
+            None => seconds[second].2 = true,
+        }
+    }
+
+    let means = seconds.map(|(total, count, _)| (count > 0).then(|| total / count));
+    let slowest = means.iter().flatten().max().copied().unwrap_or_default();
+    let spans = means.iter().zip(seconds).map(|(mean, (_, _, failed))| {
+        let symbol = match mean {
+            Some(mean) if !slowest.is_zero() => {
+                let level = (mean.as_secs_f64() / slowest.as_secs_f64() * 8.0).ceil() as usize;
+                BARS[level.clamp(1, 8) - 1]
+            }
+            Some(_) => BARS[0],
+            None if failed => bar::FULL,
+            None => " ",
+        };
+        let color = if failed { Color::Red } else { Color::Green };
+        Span::styled(symbol, Style::new().fg(color))
+    });
+    Line::from_iter(spans)
+}
+
+fn bold() -> Style {
+    Style::new().add_modifier(Modifier::BOLD)
+}
+
+/// Milliseconds with microsecond precision.
+fn ms(duration: Duration) -> f64 {
+    duration.as_micros() as f64 / 1000.0
+}
@@ -0,0 +1,291 @@
+use std::{
+    fs, io,
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+
+use clap::Parser;
+use serde::{Deserialize, Serialize};
+use tokio::{net::UdpSocket, time};
+
+use crate::{
+    probe::{self, CommonArgs, Probe},
+    report::{Attempt, ProbeError},
+    resolve::{ResolveArgs, Resolver},
+    socket,
+    sweep::{self, Sweep, SweepArgs},
+};
+
+/// Largest datagram a reply can be read into.
+const MAX_DATAGRAM: usize = 65536;
+
+#[derive(Parser, Debug, Serialize, Deserialize)]
+pub struct UdpArgs {
+    /// Host to send datagrams to.
+    #[arg(long)]
+    host: String,
+
+    /// Port to send datagrams to.
+    #[arg(long)]
+    port: u16,
+
+    /// Payload to send on every attempt.
+    #[arg(long, default_value = "", conflicts_with_all = ["payload_hex", "payload_file"])]
+    payload: String,
+
+    /// Payload to send on every attempt, in hex, e.g. `deadbeef`.
+    #[arg(long, conflicts_with = "payload_file")]
+    payload_hex: Option<String>,
+
+    /// Send the contents of this file on every attempt.
+    #[arg(long)]
+    payload_file: Option<String>,
+
+    /// Wait for a reply to every datagram, counting the attempt as failed if none arrives within
+    /// the timeout.
+    #[arg(long)]
+    expect_reply: bool,
+
+    /// Count a reply as failed unless it contains this, implying `--expect-reply`.
+    #[arg(long, conflicts_with = "expect_hex")]
+    expect: Option<String>,
+
+    /// Count a reply as failed unless it contains these bytes, in hex, implying `--expect-reply`.
+    #[arg(long)]
+    expect_hex: Option<String>,
+
+    /// Set a timeout for receiving a reply.
+    #[arg(long, default_value_t = 1000)]
+    timeout_ms: u64,
+
+    /// Payloads of each size, which replace `--payload`, are sent with fragmentation prohibited,
+    /// so that sizes larger than the path MTU fail rather than being fragmented.
+    #[command(flatten)]
+    #[serde(flatten)]
+    sweep: SweepArgs,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    resolve: ResolveArgs,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    pub common: CommonArgs,
+}
+
+/// Sends a datagram from a new socket on every attempt, optionally waiting for a reply and
+/// matching it against the bytes expected.
+pub struct UdpProbe {
+    host: String,
+    port: u16,
+    resolver: Resolver,
+    payload: Vec<u8>,
+    expect_reply: bool,
+    expect: Option<Vec<u8>>,
+    sweep: Option<Sweep>,
+    timeout: Duration,
+}
+
+impl UdpProbe {
+    pub fn new(args: &UdpArgs) -> Self {
+        let payload = match (&args.payload_hex, &args.payload_file) {
+            (Some(hex), _) => parse_hex(hex).expect("invalid --payload-hex"),
+            (_, Some(path)) => fs::read(path).expect("unable to read payload file"),
+            _ => args.payload.clone().into_bytes(),
+        };
+        let expect = match (&args.expect, &args.expect_hex) {
+            (Some(expect), _) => Some(expect.clone().into_bytes()),
+            (_, Some(hex)) => Some(parse_hex(hex).expect("invalid --expect-hex")),
+            _ => None,
+        };
+
+        UdpProbe {
+            host: args.host.clone(),
+            port: args.port,
+            resolver: Resolver::new(&args.resolve),
+            payload,
+            expect_reply: args.expect_reply || expect.is_some(),
+            expect,
+            sweep: Sweep::new(&args.sweep),
+            timeout: Duration::from_millis(args.timeout_ms),
+        }
+    }
+
+    /// Sends the payload and, if a reply is expected, waits for one, returning its round trip and
+    /// the reply.
+    async fn exchange(
+        &self,
+        socket: &UdpSocket,
+        payload: &[u8],
+        expect_reply: bool,
+    ) -> Result<Option<(Duration, Vec<u8>)>, ProbeError> {
+        let start = Instant::now();
+        socket
+            .send(payload)
+            .await
+            .map_err(|e| ProbeError::from_cause("send", &e))?;
+        if !expect_reply {
+            return Ok(None);
+        }
+
+        let mut buf = vec![0; MAX_DATAGRAM];
+        match time::timeout(self.timeout, socket.recv(&mut buf)).await {
+            Ok(Ok(len)) => {
+                buf.truncate(len);
+                Ok(Some((start.elapsed(), buf)))
+            }
+            // An ICMP port unreachable is reported as the connection being refused.
+            Ok(Err(e)) => Err(ProbeError::from_cause("receive", &e)),
+            Err(_) => Err(ProbeError::new(
+                "timeout",
+                format!("no reply within {}ms", self.timeout.as_millis()),
+            )),
+        }
+    }
+}
+
+impl Probe for UdpProbe {
+    fn kind(&self) -> &'static str {
+        "udp"
+    }
+
+    fn target(&self) -> String {
+        format!("{}:{}", self.host, self.port)
+    }
+
+    async fn attempt(&self, _worker: usize) -> Attempt {
+        let start = Instant::now();
+        let mut phases = vec![];
+        let mut details = vec![];
+
+        let result = async {
+            let addr = *self
+                .resolver
+                .lookup(&self.host, self.port)
+                .await
+                .map_err(|e| ProbeError::from_cause("resolve", &e))?
+                .first()
+                .ok_or_else(|| ProbeError::new("resolve", "no addresses resolved"))?;
+            let socket = bind(addr)
+                .await
+                .map_err(|e| ProbeError::from_cause("bind", &e))?;
+            if let Ok(local) = socket.local_addr() {
+                details.push(("local_addr", local.to_string()));
+            }
+            details.push(("remote_addr", addr.to_string()));
+            let family = if addr.is_ipv4() { "ipv4" } else { "ipv6" };
+            details.push(("family", family.to_string()));
+
+            if let Some(sweep) = &self.sweep {
+                dont_fragment(&socket, addr).map_err(|e| ProbeError::from_cause("bind", &e))?;
+                let mut results = vec![];
+                for &size in sweep.sizes() {
+                    // Late replies to smaller sizes would otherwise pass for this one.
+                    let mut buf = vec![0; MAX_DATAGRAM];
+                    while socket.try_recv(&mut buf).is_ok() {}
+                    let exchange = self.exchange(&socket, &sweep::payload(size), true).await;
+                    results.push((size, exchange.map(|reply| reply.unwrap_or_default().0)));
+                }
+                return sweep.report(results, &mut details).map_or(Ok(()), Err);
+            }
+
+            details.push(("sent_bytes", self.payload.len().to_string()));
+            let exchange = self.exchange(&socket, &self.payload, self.expect_reply);
+            if let Some((rtt, reply)) = exchange.await? {
+                phases.push(("rtt", rtt));
+                details.push(("received_bytes", reply.len().to_string()));
+                if let Some(expect) = &self.expect {
+                    if !contains(&reply, expect) {
+                        return Err(ProbeError::new(
+                            "unexpected_reply",
+                            format!(
+                                "reply does not contain the expected bytes: {}",
+                                String::from_utf8_lossy(&reply)
+                            ),
+                        ));
+                    }
+                }
+            }
+            Ok(())
+        }
+        .await;
+
+        Attempt {
+            duration: start.elapsed(),
+            phases,
+            details,
+            error: result.err(),
+        }
+    }
+}
+
+pub async fn udp_main(args: UdpArgs) {
+    probe::run(UdpProbe::new(&args), &args.common).await
+}
+
+/// Binds a socket of the same family as the address and connects it, so that only replies from
+/// the address are received.
+pub(crate) async fn bind(addr: SocketAddr) -> io::Result<UdpSocket> {
+    let socket = socket::bind_udp(addr).await?;
+    socket.connect(addr).await?;
+    Ok(socket)
+}
+
+/// Prohibits datagrams sent from the socket being fragmented, so that they fail to be sent, or
+/// are dropped by a router, if larger than the path MTU.
+#[cfg(target_os = "linux")]
+fn dont_fragment(socket: &UdpSocket, addr: SocketAddr) -> io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    let (level, option, value) = match addr {
+        SocketAddr::V4(_) => (libc::SOL_IP, libc::IP_MTU_DISCOVER, libc::IP_PMTUDISC_DO),
+        SocketAddr::V6(_) => (
+            libc::SOL_IPV6,
+            libc::IPV6_MTU_DISCOVER,
+            libc::IPV6_PMTUDISC_DO,
+        ),
+    };
+    // SAFETY: the option value points to a c_int of the length given, for an open socket.
+    let result = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            level,
+            option,
+            &value as *const _ as *const libc::c_void,
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if result != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Datagrams are sent with the default of the platform, which usually sets the don't fragment
+/// bit already.
+#[cfg(not(target_os = "linux"))]
+fn dont_fragment(_socket: &UdpSocket, _addr: SocketAddr) -> io::Result<()> {
+    Ok(())
+}
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    needle.is_empty()
+        || haystack
+            .windows(needle.len())
+            .any(|window| window == needle)
+}
+
+/// Parses bytes written in hex, ignoring whitespace.
+fn parse_hex(hex: &str) -> Result<Vec<u8>, String> {
+    let digits: Vec<u8> = hex.bytes().filter(|b| !b.is_ascii_whitespace()).collect();
+    if !digits.len().is_multiple_of(2) {
+        return Err("odd number of hex digits".to_string());
+    }
+    digits
+        .chunks(2)
+        .map(|pair| {
+            let pair = std::str::from_utf8(pair).map_err(|e| e.to_string())?;
+            u8::from_str_radix(pair, 16).map_err(|e| format!("{}: {}", pair, e))
+        })
+        .collect()
+}
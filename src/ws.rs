@@ -0,0 +1,291 @@
+use std::{
+    io,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use clap::Parser;
+use futures_util::{SinkExt, StreamExt};
+use rustls::pki_types::ServerName;
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    sync::Mutex,
+    time,
+};
+use tokio_rustls::TlsConnector;
+use tokio_tungstenite::{tungstenite::Message, WebSocketStream};
+use url::Url;
+
+use crate::{
+    limit,
+    probe::{self, CommonArgs, Probe},
+    report::{self, Attempt, Labels, Lifecycle, Origin, ProbeError},
+    resolve, socket,
+    tls::{self, TlsArgs},
+};
+
+#[derive(Parser, Debug, Serialize, Deserialize)]
+pub struct WsArgs {
+    /// WebSocket URL to connect to, as `ws://` or `wss://`.
+    #[arg(long)]
+    url: String,
+
+    /// Set a timeout for opening a connection, including the WebSocket handshake.
+    #[arg(long, default_value_t = 15)]
+    connect_timeout_ms: u64,
+
+    /// Set a timeout for the reply to each ping or message.
+    #[arg(long, default_value_t = 20)]
+    timeout_ms: u64,
+
+    /// Send this text message on every attempt and wait for any reply, instead of a ping.
+    #[arg(long)]
+    message: Option<String>,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    tls: TlsArgs,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    pub common: CommonArgs,
+}
+
+trait Stream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Stream for T {}
+
+type Connection = WebSocketStream<Box<dyn Stream>>;
+
+/// A connection kept open by a worker.
+struct Open {
+    ws: Connection,
+    /// Details of the connection reported with every attempt over it.
+    details: Vec<(&'static str, String)>,
+    lifecycle: Lifecycle,
+    attempts: usize,
+}
+
+/// Keeps a connection open for every worker, sending a ping or message on each attempt.
+/// A connection is reopened on the next attempt after any failure.
+pub struct WsProbe {
+    url: Url,
+    host: String,
+    port: u16,
+    tls: Option<TlsConnector>,
+    sni: Option<String>,
+    message: Option<String>,
+    name: Option<String>,
+    labels: Labels,
+    connections: Vec<Mutex<Option<Open>>>,
+    connect_timeout: Duration,
+    timeout: Duration,
+}
+
+impl WsProbe {
+    pub fn new(args: &WsArgs) -> Self {
+        let url = Url::parse(&args.url).expect("invalid websocket url");
+        let secure = match url.scheme() {
+            "wss" => true,
+            "ws" => false,
+            scheme => panic!("unsupported scheme {}", scheme),
+        };
+        let host = url
+            .host_str()
+            .expect("websocket url has no host")
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .to_string();
+        let port = url
+            .port_or_known_default()
+            .expect("websocket url has no port");
+
+        WsProbe {
+            url,
+            host,
+            port,
+            tls: secure.then(|| TlsConnector::from(Arc::new(tls::configure(&args.tls)))),
+            sni: args.tls.sni.clone(),
+            message: args.message.clone(),
+            name: args.common.name.clone(),
+            labels: args.common.labels(),
+            connections: (0..args.common.parallel)
+                .map(|_| Mutex::new(None))
+                .collect(),
+            connect_timeout: Duration::from_millis(args.connect_timeout_ms),
+            timeout: Duration::from_millis(args.timeout_ms),
+        }
+    }
+
+    /// Opens a connection for a worker and completes the WebSocket handshake, returning the time
+    /// of each phase.
+    async fn connect(
+        &self,
+        worker: usize,
+    ) -> Result<(Open, Vec<(&'static str, Duration)>), ProbeError> {
+        let mut phases = vec![];
+
+        let start = Instant::now();
+        let conn = resolve::connect(&self.host, self.port)
+            .await
+            .map_err(|e| ProbeError::from_cause("connect", &e))?;
+        let mut details = conn.details();
+        let (local_addr, remote_addr) =
+            (conn.stream.local_addr().ok(), conn.stream.peer_addr().ok());
+        let tcp = conn.stream;
+        tcp.set_nodelay(socket::nodelay(true))
+            .map_err(|e| ProbeError::from_cause("connect", &e))?;
+        phases.push(("connect", start.elapsed()));
+
+        let stream: Box<dyn Stream> = match &self.tls {
+            Some(connector) => {
+                phases.extend(limit::handshake().await);
+                let start = Instant::now();
+                let name = ServerName::try_from(self.sni.as_ref().unwrap_or(&self.host).clone())
+                    .map_err(|e| ProbeError::new("tls", e))?;
+                let stream = connector
+                    .connect(name, tcp)
+                    .await
+                    .map_err(|e| ProbeError::from_cause("tls", &e))?;
+                phases.push(("tls", start.elapsed()));
+                Box::new(stream)
+            }
+            None => Box::new(tcp),
+        };
+
+        let start = Instant::now();
+        let (ws, _) = tokio_tungstenite::client_async(self.url.as_str(), stream)
+            .await
+            .map_err(|e| ProbeError::from_cause("handshake", &e))?;
+        phases.push(("handshake", start.elapsed()));
+
+        let origin = Origin {
+            probe: self.kind(),
+            name: self.name.clone(),
+            labels: self.labels.clone(),
+            target: self.target(),
+            worker,
+        };
+        let lifecycle = Lifecycle::opened(&origin, local_addr, remote_addr);
+        details.push(lifecycle.detail());
+        let open = Open {
+            ws,
+            details,
+            lifecycle,
+            attempts: 0,
+        };
+        Ok((open, phases))
+    }
+
+    /// Sends a ping or the configured message and waits for the reply, returning the round trip.
+    async fn exchange(&self, ws: &mut Connection) -> Result<Duration, ProbeError> {
+        let start = Instant::now();
+        let message = match &self.message {
+            Some(message) => Message::text(message.as_str()),
+            None => Message::Ping(Default::default()),
+        };
+        ws.send(message)
+            .await
+            .map_err(|e| ProbeError::from_cause("send", &e))?;
+
+        loop {
+            match ws.next().await {
+                Some(Ok(Message::Pong(_))) if self.message.is_none() => return Ok(start.elapsed()),
+                Some(Ok(Message::Text(_) | Message::Binary(_))) if self.message.is_some() => {
+                    return Ok(start.elapsed())
+                }
+                Some(Ok(Message::Close(frame))) => {
+                    let reason = match frame {
+                        Some(frame) => format!("closed by server: {} {}", frame.code, frame.reason),
+                        None => "closed by server".to_string(),
+                    };
+                    return Err(ProbeError::new("closed", reason));
+                }
+                Some(Ok(_)) => continue,
+                Some(Err(e)) => return Err(ProbeError::from_cause("receive", &e)),
+                None => return Err(ProbeError::new("closed", io::ErrorKind::UnexpectedEof)),
+            }
+        }
+    }
+}
+
+impl Probe for WsProbe {
+    fn kind(&self) -> &'static str {
+        "ws"
+    }
+
+    fn target(&self) -> String {
+        report::redact(self.url.as_str())
+    }
+
+    async fn attempt(&self, worker: usize) -> Attempt {
+        let mut connection = self.connections[worker % self.connections.len()]
+            .lock()
+            .await;
+
+        let start = Instant::now();
+        let mut phases = vec![];
+        let open = match &mut *connection {
+            Some(open) => open,
+            None => {
+                let throttle = limit::acquire().await;
+                phases.extend(throttle.phase());
+                match limit::timeout(self.connect_timeout, self.connect(worker)).await {
+                    Ok(Ok((open, connected))) => {
+                        phases.extend(connected);
+                        connection.insert(open)
+                    }
+                    Ok(Err(error)) => return failed(start, phases, error),
+                    Err(_) => {
+                        let message =
+                            format!("timed out after {}ms", self.connect_timeout.as_millis());
+                        return failed(start, phases, ProbeError::new("connect_timeout", message));
+                    }
+                }
+            }
+        };
+
+        open.attempts += 1;
+        let error = match time::timeout(self.timeout, self.exchange(&mut open.ws)).await {
+            Ok(Ok(rtt)) => {
+                phases.push(("rtt", rtt));
+                None
+            }
+            Ok(Err(error)) => Some(error),
+            Err(_) => {
+                let kind = match self.message {
+                    Some(_) => "reply_timeout",
+                    None => "pong_timeout",
+                };
+                let message = format!("no reply after {}ms", self.timeout.as_millis());
+                Some(ProbeError::new(kind, message))
+            }
+        };
+        let details = open.details.clone();
+        if let Some(error) = &error {
+            open.lifecycle.closed(Some(open.attempts), Some(error));
+            *connection = None;
+        }
+
+        Attempt {
+            duration: start.elapsed(),
+            phases,
+            details,
+            error,
+        }
+    }
+}
+
+pub async fn ws_main(args: WsArgs) {
+    probe::run(WsProbe::new(&args), &args.common).await
+}
+
+fn failed(start: Instant, phases: Vec<(&'static str, Duration)>, error: ProbeError) -> Attempt {
+    Attempt {
+        duration: start.elapsed(),
+        phases,
+        details: vec![],
+        error: Some(error),
+    }
+}